@@ -0,0 +1,105 @@
+//! Config Module
+//!
+//! settings read from a TOML file, so scripts don't need to repeat the
+//! server url and credentials on every call
+//!
+//! ```toml
+//! server_url = "http://localhost:3000"
+//! api_key = "..."
+//! timeout_secs = 10
+//! output = "ndjson"
+//! ```
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::output::OutputFormat;
+
+/// environment variable naming the config file
+pub const CONFIG_ENV: &str = "POST_CLI_CONFIG";
+
+/// Config struct - everything is optional, command-line flags win
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server_url: Option<String>,
+    pub api_key: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub output: Option<OutputFormat>,
+}
+
+/// Config implementation
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// read the config file
+    ///
+    /// an explicitly given path must exist; the default location,
+    /// `~/.config/post-cli/config.toml`, is skipped when missing
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                Ok(Config::default())
+            }
+            Err(e) => Err(format!("{}: {}", path.display(), e)),
+        }
+    }
+}
+
+fn default_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/post-cli/config.toml"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_every_setting() {
+        let config = Config::parse(
+            r#"
+            server_url = "http://posts.internal:3000"
+            api_key = "secret"
+            timeout_secs = 5
+            output = "ndjson"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            Config {
+                server_url: Some("http://posts.internal:3000".to_string()),
+                api_key: Some("secret".to_string()),
+                timeout_secs: Some(5),
+                output: Some(OutputFormat::Ndjson),
+            },
+            config
+        );
+        assert_eq!(Config::default(), Config::parse("").unwrap());
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(Config::parse("server = \"http://localhost:3000\"").is_err());
+        assert!(Config::parse("output = \"xml\"").is_err());
+    }
+
+    #[test]
+    fn explicit_path_must_exist() {
+        assert!(Config::load(Some(Path::new("/nonexistent/post-cli.toml"))).is_err());
+    }
+}
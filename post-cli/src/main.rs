@@ -0,0 +1,534 @@
+//! post-cli
+//!
+//! a command-line client for post-server, built on the `post_lib` client
+
+mod config;
+mod output;
+
+use std::{
+    error::Error,
+    fs,
+    io::{self, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use post_lib::{
+    client::{PostClient, DEFAULT_BASE_URL},
+    AuditAction, AuditQuery, ContentFormat, ModerateRequest, ModerationAction, NewPoll,
+};
+
+use config::{Config, CONFIG_ENV};
+use output::OutputFormat;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+/// Script the post-server API
+#[derive(Parser)]
+#[command(name = "post-cli", version)]
+struct Cli {
+    /// config file [default: ~/.config/post-cli/config.toml]
+    #[arg(long, env = CONFIG_ENV, global = true)]
+    config: Option<PathBuf>,
+
+    /// server url [default: http://localhost:3000]
+    #[arg(long, env = "POST_CLI_SERVER", global = true)]
+    server: Option<String>,
+
+    /// api key sent as a bearer token
+    #[arg(long, env = "POST_CLI_API_KEY", hide_env_values = true, global = true)]
+    api_key: Option<String>,
+
+    /// request timeout in seconds [default: 10]
+    #[arg(long, global = true)]
+    timeout: Option<u64>,
+
+    /// output format [default: table]
+    #[arg(long, short, value_enum, global = true)]
+    output: Option<OutputFormat>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List every post
+    List,
+    /// Show one post
+    Get { post_id: u64 },
+    /// Create a post from an argument, a file or stdin, printing its id
+    Create {
+        #[command(flatten)]
+        content: ContentArgs,
+        /// the content is CommonMark, rendered to HTML by the server
+        #[arg(long)]
+        markdown: bool,
+        #[command(flatten)]
+        poll: PollArgs,
+    },
+    /// Replace the content of a post
+    Update {
+        post_id: u64,
+        #[command(flatten)]
+        content: ContentArgs,
+    },
+    /// Delete a post
+    Delete { post_id: u64 },
+    /// Save a draft, printing it; only you see it until published
+    Draft {
+        #[command(flatten)]
+        content: ContentArgs,
+        /// the content is CommonMark, rendered to HTML by the server
+        #[arg(long)]
+        markdown: bool,
+        /// have the server publish it at this time, e.g. `2026-11-01T09:00:00Z`
+        #[arg(long, value_parser = parse_time)]
+        publish_at: Option<u64>,
+    },
+    /// List your drafts
+    Drafts,
+    /// Publish one of your drafts now, printing the new post's id
+    Publish { draft_id: u64 },
+    /// Vote in a post's poll as you, replacing any earlier vote, and print the results
+    Vote {
+        post_id: u64,
+        /// the numbers of the chosen options, from 0; none takes the vote back
+        choices: Vec<u32>,
+    },
+    /// Report a post to the moderators as you
+    Report {
+        post_id: u64,
+        /// what is wrong with the post
+        reason: String,
+    },
+    /// List reported posts, most reported first
+    Reports,
+    /// Act on a post as a moderator, closing its reports
+    Moderate {
+        post_id: u64,
+        #[arg(value_enum)]
+        action: ActionArg,
+        /// the user to warn, required by `warn`
+        #[arg(long, required_if_eq("action", "warn"))]
+        user: Option<String>,
+        /// why, for the moderation log
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Show the moderation log, oldest first
+    ModerationLog {
+        /// only entries about this post
+        #[arg(long)]
+        post_id: Option<u64>,
+    },
+    /// Show the audit log of every change to a post, draft or webhook, oldest first
+    Audit {
+        /// only changes made by this actor, like `ann` or `key:1a2b3c4d5e6f`
+        #[arg(long)]
+        actor: Option<String>,
+        #[arg(long, value_enum)]
+        action: Option<AuditActionArg>,
+        /// only changes to this post
+        #[arg(long)]
+        post_id: Option<u64>,
+        /// only changes made at or after this RFC 3339 time
+        #[arg(long, value_parser = parse_time)]
+        since: Option<u64>,
+        /// only changes made at or before this RFC 3339 time
+        #[arg(long, value_parser = parse_time)]
+        until: Option<u64>,
+    },
+    /// Pin a post so it is listed first
+    Pin {
+        post_id: u64,
+        /// unpin it instead
+        #[arg(long)]
+        undo: bool,
+    },
+    /// Lock a post against edits
+    Lock {
+        post_id: u64,
+        /// unlock it instead
+        #[arg(long)]
+        undo: bool,
+    },
+    /// Upload files to a post, printing the new attachments
+    Attach {
+        post_id: u64,
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// MIME type of every file [default: guessed by the server from the name]
+        #[arg(long)]
+        content_type: Option<String>,
+    },
+    /// Download an attachment to stdout or a file
+    Download {
+        attachment_id: u64,
+        /// write to this file instead of stdout
+        #[arg(long)]
+        to: Option<PathBuf>,
+    },
+    /// List your notifications, newest first
+    Notifications {
+        /// only list notifications not yet read
+        #[arg(long)]
+        unread: bool,
+        /// mark the listed notifications read afterwards
+        #[arg(long)]
+        mark_read: bool,
+    },
+    /// List posts containing some text, ignoring case
+    Search { text: String },
+    /// Follow live changes until interrupted
+    Tail {
+        /// replay the changes after this event id first
+        #[arg(long)]
+        after: Option<u64>,
+    },
+}
+
+/// an RFC 3339 time as seconds since the unix epoch
+fn parse_time(time: &str) -> std::result::Result<u64, String> {
+    humantime::parse_rfc3339_weak(time)
+        .map_err(|e| e.to_string())?
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .map_err(|_| "must not be before 1970".to_string())
+}
+
+/// what a moderator does about a post
+#[derive(ValueEnum, Clone, Copy)]
+enum ActionArg {
+    /// the reports were unfounded; a hidden post is shown again
+    Dismiss,
+    /// only moderators see the post from now on
+    Hide,
+    Delete,
+    /// tell a user off about the post
+    Warn,
+}
+
+impl From<ActionArg> for ModerationAction {
+    fn from(action: ActionArg) -> Self {
+        match action {
+            ActionArg::Dismiss => ModerationAction::Dismiss,
+            ActionArg::Hide => ModerationAction::Hide,
+            ActionArg::Delete => ModerationAction::Delete,
+            ActionArg::Warn => ModerationAction::Warn,
+        }
+    }
+}
+
+/// a kind of change in the audit log
+#[derive(ValueEnum, Clone, Copy)]
+enum AuditActionArg {
+    Create,
+    Update,
+    Delete,
+    /// pinned, unpinned, locked or unlocked
+    Flag,
+    Attach,
+    React,
+    Unreact,
+    Vote,
+    Hide,
+    /// shown again after being hidden
+    Restore,
+    Dismiss,
+    Warn,
+    CreateDraft,
+    UpdateDraft,
+    DeleteDraft,
+    PublishDraft,
+    CreateWebhook,
+    DeleteWebhook,
+}
+
+impl From<AuditActionArg> for AuditAction {
+    fn from(action: AuditActionArg) -> Self {
+        match action {
+            AuditActionArg::Create => AuditAction::Create,
+            AuditActionArg::Update => AuditAction::Update,
+            AuditActionArg::Delete => AuditAction::Delete,
+            AuditActionArg::Flag => AuditAction::Flag,
+            AuditActionArg::Attach => AuditAction::Attach,
+            AuditActionArg::React => AuditAction::React,
+            AuditActionArg::Unreact => AuditAction::Unreact,
+            AuditActionArg::Vote => AuditAction::Vote,
+            AuditActionArg::Hide => AuditAction::Hide,
+            AuditActionArg::Restore => AuditAction::Restore,
+            AuditActionArg::Dismiss => AuditAction::Dismiss,
+            AuditActionArg::Warn => AuditAction::Warn,
+            AuditActionArg::CreateDraft => AuditAction::CreateDraft,
+            AuditActionArg::UpdateDraft => AuditAction::UpdateDraft,
+            AuditActionArg::DeleteDraft => AuditAction::DeleteDraft,
+            AuditActionArg::PublishDraft => AuditAction::PublishDraft,
+            AuditActionArg::CreateWebhook => AuditAction::CreateWebhook,
+            AuditActionArg::DeleteWebhook => AuditAction::DeleteWebhook,
+        }
+    }
+}
+
+/// a poll to ask with a new post
+#[derive(Args)]
+struct PollArgs {
+    /// ask this question in a poll
+    #[arg(long, requires = "options")]
+    poll: Option<String>,
+
+    /// a poll option; give it once per option
+    #[arg(long = "option", id = "options", requires = "poll")]
+    options: Vec<String>,
+
+    /// let voters pick more than one option
+    #[arg(long, requires = "poll")]
+    multiple: bool,
+
+    /// stop taking votes at this time, e.g. `2026-11-01T09:00:00Z`
+    #[arg(long, value_parser = parse_time, requires = "poll")]
+    closes_at: Option<u64>,
+}
+
+impl PollArgs {
+    fn new_poll(self) -> Option<NewPoll> {
+        let question = self.poll?;
+        Some(NewPoll {
+            question,
+            options: self.options,
+            multiple: self.multiple,
+            closes_at: self.closes_at,
+        })
+    }
+}
+
+/// where new content comes from
+#[derive(Args)]
+struct ContentArgs {
+    /// the content, or `-` to read stdin; stdin is also read when omitted
+    content: Option<String>,
+
+    /// read the content from a file
+    #[arg(long, short, conflicts_with = "content")]
+    file: Option<PathBuf>,
+}
+
+impl ContentArgs {
+    fn read(&self) -> io::Result<String> {
+        let text = match (&self.content, &self.file) {
+            (Some(content), _) if content != "-" => return Ok(content.clone()),
+            (_, Some(path)) => fs::read_to_string(path)?,
+            _ => {
+                let mut text = String::new();
+                io::stdin().read_to_string(&mut text)?;
+                text
+            }
+        };
+        // files and heredocs end in a newline that isn't part of the post
+        Ok(text.strip_suffix('\n').unwrap_or(&text).to_string())
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let config = Config::load(cli.config.as_deref())?;
+    let format = cli.output.or(config.output).unwrap_or_default();
+
+    let mut builder = PostClient::builder()
+        .base_url(
+            cli.server
+                .or(config.server_url)
+                .unwrap_or_else(|| DEFAULT_BASE_URL.to_string()),
+        )
+        .timeout(Duration::from_secs(
+            cli.timeout.or(config.timeout_secs).unwrap_or(10),
+        ));
+    if let Some(api_key) = cli.api_key.or(config.api_key) {
+        builder = builder.api_key(api_key);
+    }
+    let client = builder.build()?;
+
+    let mut out = io::stdout().lock();
+    match cli.command {
+        Command::List => output::posts(&mut out, format, &client.list_posts().await?)?,
+        Command::Get { post_id } => {
+            output::post(&mut out, format, &client.get_post(post_id).await?)?
+        }
+        Command::Create {
+            content,
+            markdown,
+            poll,
+        } => {
+            let content_format = if markdown {
+                ContentFormat::Markdown
+            } else {
+                ContentFormat::Plain
+            };
+            let post_id = match poll.new_poll() {
+                Some(poll) => {
+                    client
+                        .create_post_with_poll(content.read()?, content_format, poll)
+                        .await?
+                }
+                None => {
+                    client
+                        .create_post_as(content.read()?, content_format)
+                        .await?
+                }
+            };
+            output::post_id(&mut out, format, post_id)?
+        }
+        Command::Update { post_id, content } => {
+            let post_id = client.update_post(post_id, content.read()?).await?;
+            output::post_id(&mut out, format, post_id)?
+        }
+        Command::Delete { post_id } => {
+            output::post_id(&mut out, format, client.delete_post(post_id).await?)?
+        }
+        Command::Draft {
+            content,
+            markdown,
+            publish_at,
+        } => {
+            let content_format = if markdown {
+                ContentFormat::Markdown
+            } else {
+                ContentFormat::Plain
+            };
+            let draft = client
+                .create_draft(content.read()?, content_format, publish_at)
+                .await?;
+            output::drafts(&mut out, format, &[draft])?
+        }
+        Command::Drafts => output::drafts(&mut out, format, &client.drafts().await?)?,
+        Command::Publish { draft_id } => {
+            let post_id = client.publish_draft(draft_id).await?;
+            output::post_id(&mut out, format, post_id)?
+        }
+        Command::Vote { post_id, choices } => {
+            output::poll(&mut out, format, &client.vote(post_id, choices).await?)?
+        }
+        Command::Report { post_id, reason } => output::report(
+            &mut out,
+            format,
+            &client.report_post(post_id, reason).await?,
+        )?,
+        Command::Reports => {
+            output::reported_posts(&mut out, format, &client.moderation_queue().await?)?
+        }
+        Command::Moderate {
+            post_id,
+            action,
+            user,
+            note,
+        } => {
+            let entry = client
+                .moderate(ModerateRequest {
+                    post_id,
+                    action: action.into(),
+                    user,
+                    note,
+                })
+                .await?;
+            output::moderation_log(&mut out, format, &[entry])?
+        }
+        Command::ModerationLog { post_id } => {
+            output::moderation_log(&mut out, format, &client.moderation_log(post_id).await?)?
+        }
+        Command::Audit {
+            actor,
+            action,
+            post_id,
+            since,
+            until,
+        } => {
+            let query = AuditQuery {
+                actor,
+                action: action.map(Into::into),
+                post_id,
+                since,
+                until,
+            };
+            output::audit_log(&mut out, format, &client.audit_log(&query).await?)?
+        }
+        Command::Pin { post_id, undo } => {
+            let post = client.update_post_flags(post_id, Some(!undo), None).await?;
+            output::post(&mut out, format, &post)?
+        }
+        Command::Lock { post_id, undo } => {
+            let post = client.update_post_flags(post_id, None, Some(!undo)).await?;
+            output::post(&mut out, format, &post)?
+        }
+        Command::Attach {
+            post_id,
+            files,
+            content_type,
+        } => {
+            let mut attachments = vec![];
+            for path in files {
+                let file_name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                let attachment = client
+                    .upload_attachment(
+                        post_id,
+                        file_name,
+                        content_type.as_deref(),
+                        fs::read(&path)?,
+                    )
+                    .await?;
+                attachments.push(attachment);
+            }
+            output::attachments(&mut out, format, &attachments)?
+        }
+        Command::Download { attachment_id, to } => {
+            let content = client.download_attachment(attachment_id).await?;
+            match to {
+                Some(path) => fs::write(path, content)?,
+                None => out.write_all(&content)?,
+            }
+        }
+        Command::Notifications { unread, mark_read } => {
+            let notifications = client.notifications(unread).await?;
+            output::notifications(&mut out, format, &notifications)?;
+            if mark_read && !notifications.is_empty() {
+                let ids = notifications
+                    .iter()
+                    .map(|notification| notification.notification_id)
+                    .collect();
+                client.mark_notifications_read(Some(ids)).await?;
+            }
+        }
+        Command::Search { text } => {
+            // the server has no search endpoint, so filter here
+            let text = text.to_lowercase();
+            let posts: Vec<_> = client
+                .list_posts()
+                .await?
+                .into_iter()
+                .filter(|post| post.content.to_lowercase().contains(&text))
+                .collect();
+            output::posts(&mut out, format, &posts)?
+        }
+        Command::Tail { after } => {
+            let mut events = client.events(after).await?;
+            while let Some(event) = events.next().await {
+                output::event(&mut out, format, &event?)?;
+            }
+        }
+    }
+    Ok(())
+}
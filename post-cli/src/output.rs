@@ -0,0 +1,634 @@
+//! Output Module
+//!
+//! rendering of results as a table for people, or as JSON / NDJSON for
+//! scripts
+
+use std::{
+    io::{self, Write},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use post_lib::{
+    Attachment, AuditEntry, Draft, ModerationEntry, Notification, Poll, Post, PostEvent, Report,
+    ReportedPost,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// longest content shown in a table cell
+pub const TABLE_CONTENT_WIDTH: usize = 60;
+
+/// How results are printed
+#[derive(ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// aligned columns, content shortened to one line
+    #[default]
+    Table,
+    /// pretty-printed JSON
+    Json,
+    /// one compact JSON value per line
+    Ndjson,
+}
+
+/// a list of posts
+pub fn posts(out: &mut impl Write, format: OutputFormat, posts: &[Post]) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            let width = posts
+                .iter()
+                .map(|post| post.post_id.to_string().len())
+                .max()
+                .unwrap_or(0)
+                .max("ID".len());
+            writeln!(out, "{:<width$}  CONTENT", "ID", width = width)?;
+            for post in posts {
+                writeln!(
+                    out,
+                    "{:<width$}  {}{}",
+                    post.post_id,
+                    flags(post),
+                    one_line(&post.content),
+                    width = width
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => json_pretty(out, &posts),
+        OutputFormat::Ndjson => posts.iter().try_for_each(|post| json_line(out, post)),
+    }
+}
+
+/// a single post
+pub fn post(out: &mut impl Write, format: OutputFormat, post: &Post) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => posts(out, format, std::slice::from_ref(post)),
+        OutputFormat::Json => json_pretty(out, post),
+        OutputFormat::Ndjson => json_line(out, post),
+    }
+}
+
+/// the id of a post that was just created, updated or deleted
+pub fn post_id(out: &mut impl Write, format: OutputFormat, post_id: u64) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => writeln!(out, "{}", post_id),
+        OutputFormat::Json => json_pretty(out, &json!({ "post_id": post_id })),
+        OutputFormat::Ndjson => json_line(out, &json!({ "post_id": post_id })),
+    }
+}
+
+/// a poll's results, option by option
+pub fn poll(out: &mut impl Write, format: OutputFormat, poll: &Poll) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            writeln!(out, "{:<3} {:>6}  OPTION", "#", "VOTES")?;
+            for (index, option) in poll.options.iter().enumerate() {
+                writeln!(
+                    out,
+                    "{:<3} {:>6}  {}",
+                    index,
+                    option.votes,
+                    one_line(&option.text)
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => json_pretty(out, poll),
+        OutputFormat::Ndjson => json_line(out, poll),
+    }
+}
+
+/// drafts, with when they will be published
+pub fn drafts(out: &mut impl Write, format: OutputFormat, drafts: &[Draft]) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            writeln!(out, "{:<6} {:<20}  CONTENT", "ID", "PUBLISH AT")?;
+            for draft in drafts {
+                let publish_at = match draft.publish_at {
+                    Some(publish_at) => humantime::format_rfc3339_seconds(
+                        UNIX_EPOCH + Duration::from_secs(publish_at),
+                    )
+                    .to_string(),
+                    None => "-".to_string(),
+                };
+                writeln!(
+                    out,
+                    "{:<6} {:<20}  {}",
+                    draft.draft_id,
+                    publish_at,
+                    one_line(&draft.content)
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => json_pretty(out, &drafts),
+        OutputFormat::Ndjson => drafts.iter().try_for_each(|draft| json_line(out, draft)),
+    }
+}
+
+/// attachments that were just uploaded
+pub fn attachments(
+    out: &mut impl Write,
+    format: OutputFormat,
+    attachments: &[Attachment],
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            writeln!(out, "{:<6} {:>10}  {:<24}  NAME", "ID", "SIZE", "TYPE")?;
+            for attachment in attachments {
+                writeln!(
+                    out,
+                    "{:<6} {:>10}  {:<24}  {}",
+                    attachment.attachment_id,
+                    attachment.size,
+                    attachment.content_type,
+                    attachment.file_name
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => json_pretty(out, &attachments),
+        OutputFormat::Ndjson => attachments
+            .iter()
+            .try_for_each(|attachment| json_line(out, attachment)),
+    }
+}
+
+/// a user's notifications
+pub fn notifications(
+    out: &mut impl Write,
+    format: OutputFormat,
+    notifications: &[Notification],
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            writeln!(out, "{:<6} {:<6} {:<8} STATE", "ID", "POST", "KIND")?;
+            for notification in notifications {
+                writeln!(
+                    out,
+                    "{:<6} {:<6} {:<8} {}",
+                    notification.notification_id,
+                    notification.post_id,
+                    notification.kind.name(),
+                    if notification.read { "read" } else { "unread" }
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => json_pretty(out, &notifications),
+        OutputFormat::Ndjson => notifications
+            .iter()
+            .try_for_each(|notification| json_line(out, notification)),
+    }
+}
+
+/// a report that was just made
+pub fn report(out: &mut impl Write, format: OutputFormat, report: &Report) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            writeln!(out, "{:<6} {:<6} REASON", "ID", "POST")?;
+            writeln!(
+                out,
+                "{:<6} {:<6} {}",
+                report.report_id,
+                report.post_id,
+                one_line(&report.reason)
+            )
+        }
+        OutputFormat::Json => json_pretty(out, report),
+        OutputFormat::Ndjson => json_line(out, report),
+    }
+}
+
+/// the moderation queue, with how many users reported each post
+pub fn reported_posts(
+    out: &mut impl Write,
+    format: OutputFormat,
+    queue: &[ReportedPost],
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            writeln!(out, "{:<6} {:>7}  CONTENT", "POST", "REPORTS")?;
+            for reported in queue {
+                writeln!(
+                    out,
+                    "{:<6} {:>7}  {}{}",
+                    reported.post.post_id,
+                    reported.report_count,
+                    flags(&reported.post),
+                    one_line(&reported.post.content)
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => json_pretty(out, &queue),
+        OutputFormat::Ndjson => queue
+            .iter()
+            .try_for_each(|reported| json_line(out, reported)),
+    }
+}
+
+/// moderation log entries; hides made by reports have no moderator
+pub fn moderation_log(
+    out: &mut impl Write,
+    format: OutputFormat,
+    entries: &[ModerationEntry],
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            writeln!(
+                out,
+                "{:<6} {:<6} {:<8} {:<12} {:<12} NOTE",
+                "ID", "POST", "ACTION", "MODERATOR", "USER"
+            )?;
+            for entry in entries {
+                writeln!(
+                    out,
+                    "{:<6} {:<6} {:<8} {:<12} {:<12} {}",
+                    entry.entry_id,
+                    entry.post_id,
+                    entry.action.name(),
+                    entry.moderator.as_deref().unwrap_or("-"),
+                    entry.user.as_deref().unwrap_or("-"),
+                    one_line(entry.note.as_deref().unwrap_or_default())
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => json_pretty(out, &entries),
+        OutputFormat::Ndjson => entries.iter().try_for_each(|entry| json_line(out, entry)),
+    }
+}
+
+/// audit log entries; entries made outside any request have no request id
+pub fn audit_log(
+    out: &mut impl Write,
+    format: OutputFormat,
+    entries: &[AuditEntry],
+) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            writeln!(
+                out,
+                "{:<6} {:<16} {:<14} {:<20} REQUEST",
+                "ID", "CHANGED", "ACTION", "ACTOR"
+            )?;
+            for entry in entries {
+                writeln!(
+                    out,
+                    "{:<6} {:<16} {:<14} {:<20} {}",
+                    entry.entry_id,
+                    audited(entry),
+                    entry.action.name(),
+                    entry.actor,
+                    entry.request_id.as_deref().unwrap_or("-")
+                )?;
+            }
+            Ok(())
+        }
+        OutputFormat::Json => json_pretty(out, &entries),
+        OutputFormat::Ndjson => entries.iter().try_for_each(|entry| json_line(out, entry)),
+    }
+}
+
+/// what an audit log entry is about, like `post:3` or `draft:2,post:5`
+fn audited(entry: &AuditEntry) -> String {
+    let ids = [
+        ("post", entry.post_id),
+        ("draft", entry.draft_id),
+        ("webhook", entry.webhook_id),
+    ];
+    ids.iter()
+        .filter_map(|(kind, id)| id.map(|id| format!("{}:{}", kind, id)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// one live change, printed as it arrives
+pub fn event(out: &mut impl Write, format: OutputFormat, event: &PostEvent) -> io::Result<()> {
+    match format {
+        OutputFormat::Table => {
+            let content = match (&event.reaction, &event.post) {
+                (Some(reaction), _) => format!("{} {}", reaction.user, reaction.emoji),
+                (None, Some(post)) => one_line(&post.content),
+                (None, None) => String::new(),
+            };
+            writeln!(
+                out,
+                "{:<6} {:<8} {:<6} {}",
+                event.event_id,
+                event.kind.name(),
+                event.post_id,
+                content
+            )?;
+            out.flush()
+        }
+        OutputFormat::Json => {
+            json_pretty(out, event)?;
+            out.flush()
+        }
+        OutputFormat::Ndjson => {
+            json_line(out, event)?;
+            out.flush()
+        }
+    }
+}
+
+fn json_pretty(out: &mut impl Write, value: &impl Serialize) -> io::Result<()> {
+    serde_json::to_writer_pretty(&mut *out, value)?;
+    writeln!(out)
+}
+
+fn json_line(out: &mut impl Write, value: &impl Serialize) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, value)?;
+    writeln!(out)
+}
+
+/// markers for hidden, pinned, locked and poll posts, shown before their content
+fn flags(post: &Post) -> String {
+    let mut flags = String::new();
+    if post.hidden {
+        flags.push_str("[hidden] ");
+    }
+    if post.pinned_at.is_some() {
+        flags.push_str("[pinned] ");
+    }
+    if post.locked {
+        flags.push_str("[locked] ");
+    }
+    if post.poll.is_some() {
+        flags.push_str("[poll] ");
+    }
+    flags
+}
+
+/// content squashed onto one line and cut to fit a table cell
+fn one_line(content: &str) -> String {
+    let line = content.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() <= TABLE_CONTENT_WIDTH {
+        line
+    } else {
+        let cut: String = line.chars().take(TABLE_CONTENT_WIDTH - 1).collect();
+        format!("{}…", cut)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use post_lib::PostEventKind;
+
+    fn sample() -> Vec<Post> {
+        vec![
+            Post {
+                post_id: 1,
+                content: "first\npost".to_string(),
+                ..Default::default()
+            },
+            Post {
+                post_id: 12,
+                content: "x".repeat(100),
+                ..Default::default()
+            },
+        ]
+    }
+
+    fn render(f: impl FnOnce(&mut Vec<u8>) -> io::Result<()>) -> String {
+        let mut out = vec![];
+        f(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn table_output() {
+        let text = render(|out| posts(out, OutputFormat::Table, &sample()));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!("ID  CONTENT", lines[0]);
+        assert_eq!("1   first post", lines[1]);
+        assert!(lines[2].starts_with("12  xxx"));
+        assert!(lines[2].ends_with('…'));
+        assert_eq!(4 + TABLE_CONTENT_WIDTH, lines[2].chars().count());
+
+        let flagged = Post {
+            pinned_at: Some(10),
+            locked: true,
+            ..sample().remove(0)
+        };
+        let text = render(|out| post(out, OutputFormat::Table, &flagged));
+        assert_eq!(
+            "1   [pinned] [locked] first post",
+            text.lines().nth(1).unwrap()
+        );
+    }
+
+    #[test]
+    fn attachment_table() {
+        let attachment = Attachment {
+            attachment_id: 3,
+            file_name: "app.log".to_string(),
+            content_type: "text/plain".to_string(),
+            size: 2048,
+            sha256: "ab".repeat(32),
+            thumbnail: false,
+        };
+        let text = render(|out| attachments(out, OutputFormat::Table, &[attachment]));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            "ID           SIZE  TYPE                      NAME",
+            lines[0]
+        );
+        assert_eq!(
+            "3            2048  text/plain                app.log",
+            lines[1]
+        );
+    }
+
+    #[test]
+    fn draft_table() {
+        let scheduled = Draft {
+            draft_id: 2,
+            author: "ann".to_string(),
+            content: "launch\nday".to_string(),
+            publish_at: Some(1_800_000_000),
+            ..Default::default()
+        };
+        let unscheduled = Draft {
+            draft_id: 3,
+            publish_at: None,
+            ..scheduled.clone()
+        };
+        let text = render(|out| drafts(out, OutputFormat::Table, &[scheduled, unscheduled]));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!("ID     PUBLISH AT            CONTENT", lines[0]);
+        assert_eq!("2      2027-01-15T08:00:00Z  launch day", lines[1]);
+        assert_eq!("3      -                     launch day", lines[2]);
+    }
+
+    #[test]
+    fn poll_table() {
+        let results = Poll {
+            question: "lunch?".to_string(),
+            options: vec![
+                post_lib::PollOption {
+                    text: "pizza".to_string(),
+                    votes: 12,
+                },
+                post_lib::PollOption {
+                    text: "sushi".to_string(),
+                    votes: 3,
+                },
+            ],
+            voters: 15,
+            ..Default::default()
+        };
+        let text = render(|out| poll(out, OutputFormat::Table, &results));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!("#    VOTES  OPTION", lines[0]);
+        assert_eq!("0       12  pizza", lines[1]);
+        assert_eq!("1        3  sushi", lines[2]);
+
+        let asking = Post {
+            poll: Some(results),
+            ..sample().remove(0)
+        };
+        let text = render(|out| post(out, OutputFormat::Table, &asking));
+        assert_eq!("1   [poll] first post", text.lines().nth(1).unwrap());
+    }
+
+    #[test]
+    fn notification_table() {
+        let notification = Notification {
+            notification_id: 7,
+            user: "alice".to_string(),
+            kind: post_lib::NotificationKind::Mention,
+            post_id: 3,
+            read: false,
+            created_at: 0,
+        };
+        let text = render(|out| notifications(out, OutputFormat::Table, &[notification]));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!("ID     POST   KIND     STATE", lines[0]);
+        assert_eq!("7      3      mention  unread", lines[1]);
+    }
+
+    #[test]
+    fn moderation_tables() {
+        let queue = ReportedPost {
+            post: Post {
+                hidden: true,
+                ..sample().remove(0)
+            },
+            report_count: 5,
+            reports: vec![],
+        };
+        let text = render(|out| reported_posts(out, OutputFormat::Table, &[queue]));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!("POST   REPORTS  CONTENT", lines[0]);
+        assert_eq!("1            5  [hidden] first post", lines[1]);
+
+        let entries = [
+            ModerationEntry {
+                entry_id: 1,
+                post_id: 1,
+                moderator: None,
+                action: post_lib::ModerationAction::Hide,
+                user: None,
+                note: Some("reported by 5 users".to_string()),
+                created_at: 0,
+            },
+            ModerationEntry {
+                entry_id: 2,
+                post_id: 1,
+                moderator: Some("mod".to_string()),
+                action: post_lib::ModerationAction::Warn,
+                user: Some("bob".to_string()),
+                note: None,
+                created_at: 0,
+            },
+        ];
+        let text = render(|out| moderation_log(out, OutputFormat::Table, &entries));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            "ID     POST   ACTION   MODERATOR    USER         NOTE",
+            lines[0]
+        );
+        assert_eq!(
+            "1      1      hide     -            -            reported by 5 users",
+            lines[1]
+        );
+        assert_eq!(
+            "2      1      warn     mod          bob          ",
+            lines[2]
+        );
+
+        let entries = [
+            AuditEntry {
+                entry_id: 1,
+                actor: "key:1a2b3c4d5e6f".to_string(),
+                action: post_lib::AuditAction::Create,
+                post_id: Some(1),
+                draft_id: None,
+                webhook_id: None,
+                before: None,
+                after: Some("ab".repeat(32)),
+                request_id: Some("req-1".to_string()),
+                created_at: 0,
+            },
+            AuditEntry {
+                entry_id: 2,
+                actor: "scheduler".to_string(),
+                action: post_lib::AuditAction::PublishDraft,
+                post_id: Some(2),
+                draft_id: Some(4),
+                webhook_id: None,
+                before: Some("cd".repeat(32)),
+                after: None,
+                request_id: None,
+                created_at: 0,
+            },
+        ];
+        let text = render(|out| audit_log(out, OutputFormat::Table, &entries));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            "ID     CHANGED          ACTION         ACTOR                REQUEST",
+            lines[0]
+        );
+        assert_eq!(
+            "1      post:1           create         key:1a2b3c4d5e6f     req-1",
+            lines[1]
+        );
+        assert_eq!(
+            "2      post:2,draft:4   publish_draft  scheduler            -",
+            lines[2]
+        );
+    }
+
+    #[test]
+    fn json_outputs() {
+        let text = render(|out| posts(out, OutputFormat::Ndjson, &sample()));
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(2, lines.len());
+        assert_eq!(r#"{"post_id":1,"content":"first\npost"}"#, lines[0]);
+
+        let text = render(|out| posts(out, OutputFormat::Json, &sample()));
+        let parsed: Vec<Post> = serde_json::from_str(&text).unwrap();
+        assert_eq!(sample(), parsed);
+
+        let text = render(|out| post_id(out, OutputFormat::Ndjson, 3));
+        assert_eq!("{\"post_id\":3}\n", text);
+    }
+
+    #[test]
+    fn event_rows() {
+        let event = PostEvent {
+            event_id: 4,
+            kind: PostEventKind::Deleted,
+            post_id: 1,
+            post: None,
+            reaction: None,
+        };
+        let text = render(|out| super::event(out, OutputFormat::Table, &event));
+        assert_eq!("4      deleted  1      \n", text);
+    }
+}
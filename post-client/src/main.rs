@@ -24,6 +24,58 @@ pub struct PostClient {
     info: Option<String>,
 }
 
+/// markdown posts come with HTML the server has already sanitized
+fn view_content(post: &Post) -> Html {
+    match &post.content_html {
+        Some(content_html) => {
+            let element = yew::utils::document().create_element("div").unwrap();
+            element.set_inner_html(content_html);
+            Html::VRef(element.into())
+        }
+        None => html! { <span>{ post.content.clone() }</span> },
+    }
+}
+
+/// badges for pinned and locked posts
+fn view_flags(post: &Post) -> Html {
+    html! {
+        <>
+            { if post.pinned_at.is_some() { html! { <span class="label">{ "pinned" }</span> } } else { html! {} } }
+            { if post.locked { html! { <span class="label warning">{ "locked" }</span> } } else { html! {} } }
+        </>
+    }
+}
+
+/// a post's poll with its results so far
+fn view_poll(post: &Post) -> Html {
+    match &post.poll {
+        Some(poll) => html! {
+            <div>
+                <span>{ poll.question.clone() }</span>
+                <ul>
+                    { poll.options.iter().map(|option| html! {
+                        <li>{ format!("{}: {}", option.text, option.votes) }</li>
+                    }).collect::<Html>() }
+                </ul>
+            </div>
+        },
+        None => html! {},
+    }
+}
+
+/// links to a post's attachments, with thumbnails for images
+fn view_attachments(post: &Post) -> Html {
+    post.attachments.iter().map(|attachment| {
+        let url = format!("http://localhost:3000/attachment/{}", attachment.attachment_id);
+        let label = if attachment.thumbnail {
+            html! { <img src={format!("{}/thumbnail", url)} alt={attachment.file_name.clone()}/> }
+        } else {
+            html! { { attachment.file_name.clone() } }
+        };
+        html! { <a href={url}>{ label }</a> }
+    }).collect::<Html>()
+}
+
 impl PostClient {
     fn view_post_list(&self) -> Html {
         match self.posts {
@@ -64,7 +116,11 @@ impl PostClient {
                                 {
                                     post_list.iter().map(|post| html! {
                                         <div>
-                                            <span>{ format!("{}: {}", post.post_id.clone(), post.content.clone()) }</span>
+                                            <span>{ format!("{}: ", post.post_id) }</span>
+                                            { view_flags(post) }
+                                            { view_content(post) }
+                                            { view_poll(post) }
+                                            { view_attachments(post) }
                                             <button class="warning" onclick={delete_post_callback(post.post_id)}>{"delete post"}</button>
                                         </div>
                                     }).collect::<Html>()
@@ -150,7 +206,7 @@ impl Component for PostClient {
                 true
             }
             AddPost(content) => {
-                let body = CreatePostRequest { content };
+                let body = CreatePostRequest { content, ..Default::default() };
 
                 let request = Request::post("http://localhost:3000/addPost")
                     .header("Content-Type", "application/json")
@@ -164,7 +220,7 @@ impl Component for PostClient {
                             match data {
                                 Ok(id) => PostMsg::SetInfo(format!(
                                     "Added new post id {}",
-                                    id.to_string()
+                                    id
                                 )),
                                 Err(error) => PostMsg::SetInfo(error.to_string()),
                             }
@@ -188,9 +244,9 @@ impl Component for PostClient {
                             match data {
                                 Ok(id) => PostMsg::SetInfo(format!(
                                     "Deleted Post id {}", 
-                                    id.to_string()
+                                    id
                                 )),
-                                Err(error) => PostMsg::SetInfo(format!("ERROR! {}", error.to_string()))
+                                Err(error) => PostMsg::SetInfo(format!("ERROR! {}", error))
                             }
                         });
                 let task = FetchService::fetch(request, callback).expect("failed to start request");
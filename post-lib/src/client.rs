@@ -0,0 +1,660 @@
+//! Client Module
+//!
+//! an async client for post-server, enabled with the `client` feature
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), post_lib::client::ClientError> {
+//! use post_lib::client::PostClient;
+//!
+//! let client = PostClient::builder()
+//!     .base_url("http://localhost:3000")
+//!     .build()?;
+//! let post_id = client.create_post("hello").await?;
+//! let post = client.get_post(post_id).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use reqwest::{
+    multipart::{Form, Part},
+    RequestBuilder, Response,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    ApiError, Attachment, AuditEntry, AuditQuery, ContentFormat, CreateDraftRequest,
+    CreatePostRequest, Draft, DraftRequest, MarkNotificationsReadRequest, ModerateRequest,
+    ModerationEntry, NewPoll, Notification, Poll, Post, PostEvent, PostFlagsRequest, Reaction,
+    ReactionCount, ReactionRequest, Report, ReportRequest, ReportedPost, UpdateDraftRequest,
+    UpdatePostRequest, VoteRequest,
+};
+
+/// where the server listens unless told otherwise
+pub const DEFAULT_BASE_URL: &str = "http://localhost:3000";
+
+/// Errors returned by [`PostClient`]
+#[derive(Debug)]
+pub enum ClientError {
+    /// the server answered with an error status
+    Api { status: u16, error: ApiError },
+    /// the request could not be sent, or the response could not be read
+    Http(reqwest::Error),
+    /// the server sent something that is not the expected JSON
+    Decode(serde_json::Error),
+}
+
+impl ClientError {
+    /// the HTTP status the server answered with, if it answered
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            ClientError::Api { status, .. } => Some(*status),
+            ClientError::Http(e) => e.status().map(|status| status.as_u16()),
+            ClientError::Decode(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Api { status, error } => {
+                write!(f, "server returned {}: {}", status, error)
+            }
+            ClientError::Http(e) => write!(f, "request failed: {}", e),
+            ClientError::Decode(e) => write!(f, "unexpected response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ClientError::Api { error, .. } => Some(error),
+            ClientError::Http(e) => Some(e),
+            ClientError::Decode(e) => Some(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(e: reqwest::Error) -> Self {
+        ClientError::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(e: serde_json::Error) -> Self {
+        ClientError::Decode(e)
+    }
+}
+
+/// PostClientBuilder struct - settings for a [`PostClient`]
+#[derive(Debug, Clone)]
+pub struct PostClientBuilder {
+    base_url: String,
+    api_key: Option<String>,
+    timeout: Duration,
+    retries: u32,
+    retry_backoff: Duration,
+}
+
+/// PostClientBuilder default implementation
+impl Default for PostClientBuilder {
+    fn default() -> Self {
+        PostClientBuilder {
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_key: None,
+            timeout: Duration::from_secs(10),
+            retries: 2,
+            retry_backoff: Duration::from_millis(200),
+        }
+    }
+}
+
+/// PostClientBuilder implementation
+impl PostClientBuilder {
+    /// the server to talk to, e.g. `http://localhost:3000`
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    /// sent as a bearer token with every request
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// how long a single attempt may take, including reading the body
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// how many times a failed request is retried before giving up
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// the wait before the first retry, doubling for each one after
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    pub fn build(self) -> Result<PostClient, ClientError> {
+        let http = reqwest::Client::builder().timeout(self.timeout).build()?;
+        // event streams stay open indefinitely, so only connecting is timed
+        let streaming = reqwest::Client::builder()
+            .connect_timeout(self.timeout)
+            .build()?;
+        Ok(PostClient {
+            http,
+            streaming,
+            base_url: self.base_url.trim_end_matches('/').to_string(),
+            api_key: self.api_key,
+            retries: self.retries,
+            retry_backoff: self.retry_backoff,
+        })
+    }
+}
+
+/// PostClient struct - a typed client for the post-server REST API
+///
+/// reads are retried on connection errors, timeouts and 5xx responses;
+/// writes are only retried when the connection could not be made, so a
+/// write is never sent twice
+#[derive(Debug, Clone)]
+pub struct PostClient {
+    http: reqwest::Client,
+    streaming: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    retries: u32,
+    retry_backoff: Duration,
+}
+
+/// PostClient implementation
+impl PostClient {
+    /// a client for `base_url` with the default settings
+    pub fn new(base_url: impl Into<String>) -> Result<Self, ClientError> {
+        Self::builder().base_url(base_url).build()
+    }
+
+    pub fn builder() -> PostClientBuilder {
+        PostClientBuilder::default()
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// every post
+    pub async fn list_posts(&self) -> Result<Vec<Post>, ClientError> {
+        let url = self.url("/posts");
+        self.execute(true, || self.http.get(&url)).await
+    }
+
+    /// a single post by id
+    pub async fn get_post(&self, post_id: u64) -> Result<Post, ClientError> {
+        let url = self.url(&format!("/post/{}", post_id));
+        self.execute(true, || self.http.get(&url)).await
+    }
+
+    /// create a plain text post, returning its id
+    pub async fn create_post(&self, content: impl Into<String>) -> Result<u64, ClientError> {
+        self.create_post_as(content, ContentFormat::Plain).await
+    }
+
+    /// create a post written in `format`, returning its id
+    pub async fn create_post_as(
+        &self,
+        content: impl Into<String>,
+        format: ContentFormat,
+    ) -> Result<u64, ClientError> {
+        let url = self.url("/addPost");
+        let request = CreatePostRequest {
+            content: content.into(),
+            format,
+            poll: None,
+        };
+        self.execute(false, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// create a post written in `format` asking `poll`, returning its id
+    pub async fn create_post_with_poll(
+        &self,
+        content: impl Into<String>,
+        format: ContentFormat,
+        poll: NewPoll,
+    ) -> Result<u64, ClientError> {
+        let url = self.url("/addPost");
+        let request = CreatePostRequest {
+            content: content.into(),
+            format,
+            poll: Some(poll),
+        };
+        self.execute(false, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// vote in a post's poll as the signed in user, replacing any earlier
+    /// vote, and return the poll's results
+    ///
+    /// a vote replaces rather than adds, so these requests are retried like reads
+    pub async fn vote(&self, post_id: u64, choices: Vec<u32>) -> Result<Poll, ClientError> {
+        let url = self.url("/vote");
+        let request = VoteRequest { post_id, choices };
+        self.execute(true, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// replace the content of a post, returning its id
+    pub async fn update_post(
+        &self,
+        post_id: u64,
+        content: impl Into<String>,
+    ) -> Result<u64, ClientError> {
+        let url = self.url("/updatePost");
+        let request = UpdatePostRequest {
+            post_id,
+            updated_content: content.into(),
+        };
+        self.execute(false, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// delete a post, returning its id
+    pub async fn delete_post(&self, post_id: u64) -> Result<u64, ClientError> {
+        let url = self.url(&format!("/deletePost/{}", post_id));
+        self.execute(false, || self.http.post(&url)).await
+    }
+
+    /// save a draft as the signed in user, published by the server at
+    /// `publish_at` (seconds since the unix epoch) if given
+    pub async fn create_draft(
+        &self,
+        content: impl Into<String>,
+        format: ContentFormat,
+        publish_at: Option<u64>,
+    ) -> Result<Draft, ClientError> {
+        let url = self.url("/addDraft");
+        let request = CreateDraftRequest {
+            content: content.into(),
+            format,
+            publish_at,
+        };
+        self.execute(false, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// replace the content and publish time of one of the signed in user's
+    /// drafts
+    pub async fn update_draft(
+        &self,
+        draft_id: u64,
+        content: impl Into<String>,
+        publish_at: Option<u64>,
+    ) -> Result<Draft, ClientError> {
+        let url = self.url("/updateDraft");
+        let request = UpdateDraftRequest {
+            draft_id,
+            content: content.into(),
+            publish_at,
+        };
+        self.execute(false, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// the signed in user's drafts, oldest first
+    pub async fn drafts(&self) -> Result<Vec<Draft>, ClientError> {
+        let url = self.url("/drafts");
+        self.execute(true, || self.http.get(&url)).await
+    }
+
+    /// publish one of the signed in user's drafts now, returning the new
+    /// post's id
+    pub async fn publish_draft(&self, draft_id: u64) -> Result<u64, ClientError> {
+        let url = self.url("/publishDraft");
+        let request = DraftRequest { draft_id };
+        self.execute(false, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// delete one of the signed in user's drafts, returning its id
+    pub async fn delete_draft(&self, draft_id: u64) -> Result<u64, ClientError> {
+        let url = self.url("/deleteDraft");
+        let request = DraftRequest { draft_id };
+        self.execute(false, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// pin or unpin and lock or unlock a post, leaving `None` flags as they
+    /// are, returning the post
+    ///
+    /// setting flags is idempotent, so these requests are retried like reads
+    pub async fn update_post_flags(
+        &self,
+        post_id: u64,
+        pinned: Option<bool>,
+        locked: Option<bool>,
+    ) -> Result<Post, ClientError> {
+        let url = self.url("/admin/updatePostFlags");
+        let request = PostFlagsRequest {
+            post_id,
+            pinned,
+            locked,
+        };
+        self.execute(true, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// report a post to the moderators as the signed in user, returning
+    /// the report
+    ///
+    /// only a user's first report of a post counts, so these requests are
+    /// retried like reads
+    pub async fn report_post(
+        &self,
+        post_id: u64,
+        reason: impl Into<String>,
+    ) -> Result<Report, ClientError> {
+        let url = self.url(&format!("/posts/{}/report", post_id));
+        let request = ReportRequest {
+            reason: reason.into(),
+        };
+        self.execute(true, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// every post with open reports, most reported first
+    pub async fn moderation_queue(&self) -> Result<Vec<ReportedPost>, ClientError> {
+        let url = self.url("/admin/reports");
+        self.execute(true, || self.http.get(&url)).await
+    }
+
+    /// act on a post as the signed in moderator, returning the new moderation
+    /// log entry
+    ///
+    /// every action is logged, so these requests are not retried
+    pub async fn moderate(&self, request: ModerateRequest) -> Result<ModerationEntry, ClientError> {
+        let url = self.url("/admin/moderate");
+        self.execute(false, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// the moderation log, oldest first, only about `post_id` if given
+    pub async fn moderation_log(
+        &self,
+        post_id: Option<u64>,
+    ) -> Result<Vec<ModerationEntry>, ClientError> {
+        let url = self.url("/admin/moderationLog");
+        self.execute(true, || {
+            let request = self.http.get(&url);
+            match post_id {
+                Some(post_id) => request.query(&[("post_id", post_id)]),
+                None => request,
+            }
+        })
+        .await
+    }
+
+    /// the audit log entries passing `query`, oldest first, as the signed in
+    /// moderator
+    pub async fn audit_log(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, ClientError> {
+        let url = self.url("/admin/auditLog");
+        self.execute(true, || self.http.get(&url).query(query))
+            .await
+    }
+
+    /// react to a post as the signed in user, returning the post's reaction counts
+    ///
+    /// reactions are idempotent, so these requests are retried like reads
+    pub async fn add_reaction(
+        &self,
+        post_id: u64,
+        emoji: impl Into<String>,
+    ) -> Result<Vec<ReactionCount>, ClientError> {
+        let url = self.url("/addReaction");
+        let request = ReactionRequest {
+            post_id,
+            emoji: emoji.into(),
+        };
+        self.execute(true, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// take back the signed in user's reaction to a post, returning the
+    /// post's reaction counts
+    pub async fn remove_reaction(
+        &self,
+        post_id: u64,
+        emoji: impl Into<String>,
+    ) -> Result<Vec<ReactionCount>, ClientError> {
+        let url = self.url("/removeReaction");
+        let request = ReactionRequest {
+            post_id,
+            emoji: emoji.into(),
+        };
+        self.execute(true, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// who reacted to a post and with what
+    pub async fn reactions(&self, post_id: u64) -> Result<Vec<Reaction>, ClientError> {
+        let url = self.url(&format!("/post/{}/reactions", post_id));
+        self.execute(true, || self.http.get(&url)).await
+    }
+
+    /// the notifications of the user the api key signs in as, newest
+    /// first, optionally only unread ones
+    pub async fn notifications(&self, unread_only: bool) -> Result<Vec<Notification>, ClientError> {
+        let url = self.url("/notifications");
+        let unread = unread_only.to_string();
+        self.execute(true, || {
+            self.http.get(&url).query(&[("unread", unread.as_str())])
+        })
+        .await
+    }
+
+    /// mark the signed in user's notifications read, all of them unless
+    /// `notification_ids` says which, returning how many were unread
+    ///
+    /// marking is idempotent, so these requests are retried like reads
+    pub async fn mark_notifications_read(
+        &self,
+        notification_ids: Option<Vec<u64>>,
+    ) -> Result<u64, ClientError> {
+        let url = self.url("/markNotificationsRead");
+        let request = MarkNotificationsReadRequest { notification_ids };
+        self.execute(true, || self.http.post(&url).json(&request))
+            .await
+    }
+
+    /// upload a file to a post, returning the new attachment
+    ///
+    /// without a `content_type` the server guesses one from `file_name`
+    pub async fn upload_attachment(
+        &self,
+        post_id: u64,
+        file_name: impl Into<String>,
+        content_type: Option<&str>,
+        content: Vec<u8>,
+    ) -> Result<Attachment, ClientError> {
+        let url = self.url(&format!("/addAttachment/{}", post_id));
+        let file_name = file_name.into();
+        if let Some(content_type) = content_type {
+            // fail here rather than inside the retried request builder
+            Part::bytes(Vec::new()).mime_str(content_type)?;
+        }
+        let mut attachments: Vec<Attachment> = self
+            .execute(false, || {
+                let mut part = Part::bytes(content.clone()).file_name(file_name.clone());
+                if let Some(content_type) = content_type {
+                    part = part.mime_str(content_type).expect("checked above");
+                }
+                self.http
+                    .post(&url)
+                    .multipart(Form::new().part("file", part))
+            })
+            .await?;
+        Ok(attachments.remove(0))
+    }
+
+    /// the content of an attachment
+    pub async fn download_attachment(&self, attachment_id: u64) -> Result<Vec<u8>, ClientError> {
+        let url = self.url(&format!("/attachment/{}", attachment_id));
+        let response = self.send(true, || self.http.get(&url)).await?;
+        Ok(response.bytes().await?.to_vec())
+    }
+
+    /// follow live post changes, replaying those after `last_event_id`
+    /// when it is given
+    pub async fn events(&self, last_event_id: Option<u64>) -> Result<EventStream, ClientError> {
+        let url = self.url("/events");
+        let response = self
+            .send(true, || {
+                let request = self.streaming.get(&url);
+                match last_event_id {
+                    Some(id) => request.header("last-event-id", id.to_string()),
+                    None => request,
+                }
+            })
+            .await?;
+        Ok(EventStream {
+            response,
+            buffer: vec![],
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    async fn execute<T, F>(&self, idempotent: bool, request: F) -> Result<T, ClientError>
+    where
+        T: DeserializeOwned,
+        F: Fn() -> RequestBuilder,
+    {
+        let response = self.send(idempotent, request).await?;
+        Ok(response.json().await?)
+    }
+
+    /// send the request built by `request`, retrying as the policy allows
+    async fn send<F>(&self, idempotent: bool, request: F) -> Result<Response, ClientError>
+    where
+        F: Fn() -> RequestBuilder,
+    {
+        let mut attempt = 0;
+        loop {
+            let mut builder = request();
+            if let Some(api_key) = &self.api_key {
+                builder = builder.bearer_auth(api_key);
+            }
+            let result = builder.send().await;
+            let retryable = match &result {
+                Ok(response) => idempotent && response.status().is_server_error(),
+                Err(e) => e.is_connect() || (idempotent && e.is_timeout()),
+            };
+            if retryable && attempt < self.retries {
+                tokio::time::sleep(self.retry_backoff * 2u32.saturating_pow(attempt)).await;
+                attempt += 1;
+                continue;
+            }
+            return check_status(result?).await;
+        }
+    }
+}
+
+/// pass successful responses through, turning the rest into errors
+async fn check_status(response: Response) -> Result<Response, ClientError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let body = response.bytes().await?;
+    let error = serde_json::from_slice(&body).unwrap_or_else(|_| {
+        ApiError::new(
+            status
+                .canonical_reason()
+                .unwrap_or("error")
+                .to_lowercase()
+                .replace(' ', "_"),
+            String::from_utf8_lossy(&body),
+        )
+    });
+    Err(ClientError::Api {
+        status: status.as_u16(),
+        error,
+    })
+}
+
+/// EventStream struct - post change events read from `/events`
+pub struct EventStream {
+    response: Response,
+    buffer: Vec<u8>,
+}
+
+/// EventStream implementation
+impl EventStream {
+    /// the next event, or `None` once the server closes the stream
+    pub async fn next(&mut self) -> Option<Result<PostEvent, ClientError>> {
+        loop {
+            if let Some(end) = find_blank_line(&self.buffer) {
+                let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+                match sse_data(&String::from_utf8_lossy(&block)) {
+                    Some(data) => return Some(serde_json::from_str(&data).map_err(Into::into)),
+                    // keep-alive comments carry no data
+                    None => continue,
+                }
+            }
+            match self.response.chunk().await {
+                Ok(Some(chunk)) => self.buffer.extend_from_slice(&chunk),
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+fn find_blank_line(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(2).position(|window| window == b"\n\n")
+}
+
+/// the `data:` lines of one server-sent event, joined by newlines
+fn sse_data(block: &str) -> Option<String> {
+    let lines: Vec<&str> = block
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_sse_data_lines() {
+        assert_eq!(
+            Some("{\"a\":1}".to_string()),
+            sse_data("event:created\ndata:{\"a\":1}\nid:1\n\n")
+        );
+        assert_eq!(
+            Some("one\ntwo".to_string()),
+            sse_data("data: one\ndata: two\n\n")
+        );
+        assert_eq!(None, sse_data(":\n\n"));
+        assert_eq!(Some(3), find_blank_line(b"a:b\n\nc"));
+    }
+}
@@ -1,21 +1,1288 @@
 //! Shared structs for client and server
+//!
+//! every type sent over the wire is defined here, so the server and its
+//! clients agree on one JSON representation
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[cfg(feature = "client")]
+pub mod client;
+
+/// a request to create a post with the given content
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct CreatePostRequest {
     pub content: String,
+    /// how `content` is written, plain text unless given
+    #[serde(default, skip_serializing_if = "ContentFormat::is_plain")]
+    pub format: ContentFormat,
+    /// a poll to attach to the post
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<NewPoll>,
+}
+
+/// How a post's content is written
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[serde(rename_all = "snake_case")]
+pub enum ContentFormat {
+    /// shown as is
+    #[default]
+    Plain,
+    /// CommonMark, rendered by the server into `content_html`
+    Markdown,
+}
+
+impl ContentFormat {
+    pub fn is_plain(&self) -> bool {
+        *self == ContentFormat::Plain
+    }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// A post, as returned by the server
+///
+/// the timestamps are seconds since the unix epoch, kept by the server
+/// for feeds and filtering and not part of the JSON representation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
 pub struct Post {
     pub post_id: u64,
     pub content: String,
+    #[serde(default, skip_serializing_if = "ContentFormat::is_plain")]
+    pub format: ContentFormat,
+    /// sanitized HTML rendering of markdown content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_html: Option<String>,
+    /// how many users reacted with each emoji, most used first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<ReactionCount>,
+    /// files uploaded to the post, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attachments: Vec<Attachment>,
+    /// the poll the post asks, with its results so far
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub poll: Option<Poll>,
+    /// when a moderator pinned the post, in seconds since the unix epoch;
+    /// pinned posts are listed first, longest pinned first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_at: Option<u64>,
+    /// a moderator locked the post, so it can no longer be edited
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub locked: bool,
+    /// a moderator, or enough reports, hid the post; only moderators see it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+    /// seconds since the unix epoch
+    #[serde(skip)]
+    pub created_at: u64,
+    /// seconds since the unix epoch
+    #[serde(skip)]
+    pub updated_at: u64,
+}
+
+/// A poll asked by a post, with its results so far
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Poll {
+    pub question: String,
+    /// the choices, in the order they were given; votes name them by index
+    pub options: Vec<PollOption>,
+    /// whether each voter may pick more than one option
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub multiple: bool,
+    /// when voting ends, in seconds since the unix epoch; open for good if missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closes_at: Option<u64>,
+    /// how many users voted, each counted once however many options they picked
+    #[serde(default)]
+    pub voters: u64,
+}
+
+impl Poll {
+    /// whether votes are still taken at `now`, in seconds since the unix epoch
+    pub fn is_open(&self, now: u64) -> bool {
+        self.closes_at.is_none_or(|closes_at| now < closes_at)
+    }
+}
+
+/// One choice in a poll and how many users picked it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct PollOption {
+    pub text: String,
+    #[serde(default)]
+    pub votes: u64,
+}
+
+/// the poll to attach to a new post
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::InputObject))]
+pub struct NewPoll {
+    pub question: String,
+    pub options: Vec<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    #[cfg_attr(feature = "graphql", graphql(default))]
+    pub multiple: bool,
+    /// seconds since the unix epoch, in the future
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closes_at: Option<u64>,
+}
+
+/// the signed in user's request to vote in a post's poll
+///
+/// voting again replaces the user's earlier choices, and no choices at
+/// all takes the vote back
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct VoteRequest {
+    pub post_id: u64,
+    /// indexes into the poll's options; at most one unless the poll allows more
+    pub choices: Vec<u32>,
 }
 
 /// a request to update a post, given an id and updated content
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct UpdatePostRequest {
     pub post_id: u64,
     pub updated_content: String,
 }
+
+/// An unpublished post, only shown to its author
+///
+/// a draft with `publish_at` is published by the server once that time
+/// comes; timestamps are seconds since the unix epoch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Draft {
+    pub draft_id: u64,
+    pub author: String,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "ContentFormat::is_plain")]
+    pub format: ContentFormat,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publish_at: Option<u64>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// a request to save a new draft for the signed in caller, published at
+/// `publish_at` if given
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CreateDraftRequest {
+    pub content: String,
+    #[serde(default, skip_serializing_if = "ContentFormat::is_plain")]
+    pub format: ContentFormat,
+    /// seconds since the unix epoch; a time already past publishes right away
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publish_at: Option<u64>,
+}
+
+/// a request to replace a draft's content and publish time
+///
+/// leaving out `publish_at` leaves the draft unscheduled
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UpdateDraftRequest {
+    pub draft_id: u64,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub publish_at: Option<u64>,
+}
+
+/// a request to publish or delete one of the signed in caller's drafts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DraftRequest {
+    pub draft_id: u64,
+}
+
+/// a moderator's request to pin or lock a post; flags left out stay as they are
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PostFlagsRequest {
+    pub post_id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked: Option<bool>,
+}
+
+/// the signed in user's request to flag a post for moderators
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReportRequest {
+    /// what is wrong with the post
+    pub reason: String,
+}
+
+/// One user's report of a post, waiting for a moderator
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Report {
+    pub report_id: u64,
+    pub post_id: u64,
+    pub user: String,
+    pub reason: String,
+    /// seconds since the unix epoch
+    pub created_at: u64,
+}
+
+/// A post in the moderation queue with the reports against it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReportedPost {
+    /// the post, even if hidden
+    pub post: Post,
+    /// how many users reported the post
+    pub report_count: u64,
+    /// the reports, oldest first
+    pub reports: Vec<Report>,
+}
+
+/// What a moderator did about a post
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ModerationAction {
+    /// the reports were unfounded; a hidden post is shown again
+    Dismiss,
+    /// only moderators see the post from now on
+    Hide,
+    Delete,
+    /// the user was told off about the post, which stays as it is
+    Warn,
+}
+
+impl ModerationAction {
+    /// name as written on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModerationAction::Dismiss => "dismiss",
+            ModerationAction::Hide => "hide",
+            ModerationAction::Delete => "delete",
+            ModerationAction::Warn => "warn",
+        }
+    }
+}
+
+/// a moderator's decision about a post, which takes it out of the queue;
+/// the moderator is the signed in caller
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ModerateRequest {
+    pub post_id: u64,
+    pub action: ModerationAction,
+    /// the user to warn, required by `warn`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// why, for the moderation log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// One entry in the moderation log
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ModerationEntry {
+    pub entry_id: u64,
+    pub post_id: u64,
+    /// missing when reports crossing the threshold hid the post
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub moderator: Option<String>,
+    pub action: ModerationAction,
+    /// the warned user, for `warn`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// seconds since the unix epoch
+    pub created_at: u64,
+}
+
+/// A change recorded in the audit log
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Delete,
+    /// pinned, unpinned, locked or unlocked
+    Flag,
+    Attach,
+    React,
+    Unreact,
+    Vote,
+    Hide,
+    /// a hidden post was shown again
+    Restore,
+    /// a moderator closed the reports against a post left as it was
+    Dismiss,
+    Warn,
+    CreateDraft,
+    /// the content or publish time changed, including the scheduler
+    /// unscheduling a draft the content filters rejected
+    UpdateDraft,
+    DeleteDraft,
+    /// the draft became the post named by the entry
+    PublishDraft,
+    CreateWebhook,
+    DeleteWebhook,
+}
+
+impl AuditAction {
+    /// name as written on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditAction::Create => "create",
+            AuditAction::Update => "update",
+            AuditAction::Delete => "delete",
+            AuditAction::Flag => "flag",
+            AuditAction::Attach => "attach",
+            AuditAction::React => "react",
+            AuditAction::Unreact => "unreact",
+            AuditAction::Vote => "vote",
+            AuditAction::Hide => "hide",
+            AuditAction::Restore => "restore",
+            AuditAction::Dismiss => "dismiss",
+            AuditAction::Warn => "warn",
+            AuditAction::CreateDraft => "create_draft",
+            AuditAction::UpdateDraft => "update_draft",
+            AuditAction::DeleteDraft => "delete_draft",
+            AuditAction::PublishDraft => "publish_draft",
+            AuditAction::CreateWebhook => "create_webhook",
+            AuditAction::DeleteWebhook => "delete_webhook",
+        }
+    }
+}
+
+/// One entry in the audit log, which is only ever added to
+///
+/// each entry is about a post, a draft or a webhook, naming it by id;
+/// publishing a draft names both the draft and the new post
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AuditEntry {
+    pub entry_id: u64,
+    /// the signed in user who made the change, else the client that made
+    /// it (`key:` and a fingerprint of an unknown API key, or `ip:` and its
+    /// address), else `system` or `scheduler`
+    pub actor: String,
+    pub action: AuditAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub draft_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_id: Option<u64>,
+    /// hex SHA-256 of the changed thing's JSON before the change, missing
+    /// for new ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    /// hex SHA-256 of the changed thing's JSON after the change, missing
+    /// for deleted ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    /// the `X-Request-Id` of the request that made the change
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// seconds since the unix epoch
+    pub created_at: u64,
+}
+
+/// Filters for the audit log, each left out matching everything
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::IntoParams))]
+#[cfg_attr(feature = "openapi", into_params(parameter_in = Query))]
+pub struct AuditQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actor: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub action: Option<AuditAction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_id: Option<u64>,
+    /// only entries made at or after this time, in seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<u64>,
+    /// only entries made at or before this time, in seconds since the epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<u64>,
+}
+
+impl AuditQuery {
+    /// whether `entry` passes every filter
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.actor
+            .as_ref()
+            .is_none_or(|actor| *actor == entry.actor)
+            && self.action.is_none_or(|action| action == entry.action)
+            && self
+                .post_id
+                .is_none_or(|post_id| Some(post_id) == entry.post_id)
+            && self.since.is_none_or(|since| entry.created_at >= since)
+            && self.until.is_none_or(|until| entry.created_at <= until)
+    }
+}
+
+/// A file attached to a post
+///
+/// the content is downloaded from `/attachment/{attachment_id}`, and images
+/// have a PNG preview at `/attachment/{attachment_id}/thumbnail`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Attachment {
+    pub attachment_id: u64,
+    pub file_name: String,
+    pub content_type: String,
+    /// size in bytes
+    pub size: u64,
+    /// hex SHA-256 of the content, shared by attachments with the same content
+    pub sha256: String,
+    #[serde(default)]
+    pub thumbnail: bool,
+}
+
+/// the signed in user's request to add or remove their emoji reaction to a post
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReactionRequest {
+    pub post_id: u64,
+    pub emoji: String,
+}
+
+/// One user's reaction to a post
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Reaction {
+    pub emoji: String,
+    pub user: String,
+}
+
+/// The number of users who reacted to a post with one emoji
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct ReactionCount {
+    pub emoji: String,
+    pub count: u64,
+}
+
+/// The kind of change that happened to a post
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[serde(rename_all = "snake_case")]
+pub enum PostEventKind {
+    Created,
+    Updated,
+    Deleted,
+    ReactionAdded,
+    ReactionRemoved,
+    /// a vote changed a poll's results
+    PollVoted,
+    /// a moderator, or enough reports, hid the post; `post` is missing
+    Hidden,
+    /// a moderator showed a hidden post again
+    Unhidden,
+}
+
+impl PostEventKind {
+    /// name used for the SSE `event:` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            PostEventKind::Created => "created",
+            PostEventKind::Updated => "updated",
+            PostEventKind::Deleted => "deleted",
+            PostEventKind::ReactionAdded => "reaction_added",
+            PostEventKind::ReactionRemoved => "reaction_removed",
+            PostEventKind::PollVoted => "poll_voted",
+            PostEventKind::Hidden => "hidden",
+            PostEventKind::Unhidden => "unhidden",
+        }
+    }
+}
+
+/// A single change to a post
+///
+/// `post` holds the post as it looks after the change,
+/// and is `None` for deleted and hidden posts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct PostEvent {
+    pub event_id: u64,
+    pub kind: PostEventKind,
+    pub post_id: u64,
+    /// the post after the change, missing for deletes and hides
+    pub post: Option<Post>,
+    /// the reaction added or removed, for reaction events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reaction: Option<Reaction>,
+}
+
+/// Why a user was notified
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::Enum))]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// the user was `@mentioned` in a post
+    Mention,
+    /// a moderator warned the user about a post
+    Warning,
+}
+
+impl NotificationKind {
+    /// name as written on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotificationKind::Mention => "mention",
+            NotificationKind::Warning => "warning",
+        }
+    }
+}
+
+/// A notification for one user
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct Notification {
+    pub notification_id: u64,
+    /// the notified user, lowercased
+    pub user: String,
+    pub kind: NotificationKind,
+    pub post_id: u64,
+    pub read: bool,
+    /// seconds since the unix epoch
+    pub created_at: u64,
+}
+
+/// a request to mark the caller's notifications as read
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MarkNotificationsReadRequest {
+    /// the notifications to mark, or every one of the caller's when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notification_ids: Option<Vec<u64>>,
+}
+
+/// A webhook subscription
+///
+/// the secret is only used to sign payloads and is never returned
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Webhook {
+    pub webhook_id: u64,
+    pub url: String,
+    #[serde(skip_serializing, default)]
+    pub secret: String,
+    pub events: Vec<PostEventKind>,
+}
+
+impl Webhook {
+    /// an empty event list subscribes to every event
+    pub fn wants(&self, kind: PostEventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+/// a request to subscribe a url to post events
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CreateWebhookRequest {
+    pub url: String,
+    pub secret: String,
+    #[serde(default)]
+    pub events: Vec<PostEventKind>,
+}
+
+/// Outcome of a single webhook delivery attempt
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Delivered,
+    Failed,
+}
+
+/// One attempt at delivering an event to a webhook
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DeliveryAttempt {
+    pub delivery_id: u64,
+    pub webhook_id: u64,
+    pub event_id: u64,
+    pub attempt: u32,
+    pub status: DeliveryStatus,
+    pub response_status: Option<u16>,
+    pub error: Option<String>,
+}
+
+/// A webhook delivery that failed on every attempt
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DeadLetter {
+    pub delivery_id: u64,
+    pub webhook_id: u64,
+    pub url: String,
+    pub attempts: u32,
+    pub last_error: String,
+    pub event: PostEvent,
+}
+
+/// Whether the server, or one of its checks, is usable
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Unavailable,
+}
+
+/// One check behind a health or readiness answer
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: HealthStatus,
+    /// what was found, e.g. why the check failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// the body of `/healthz` and `/readyz`: `ok` only when every check is
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    pub fn new(checks: Vec<HealthCheck>) -> Self {
+        let status = if checks.iter().all(|check| check.status == HealthStatus::Ok) {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Unavailable
+        };
+        HealthReport { status, checks }
+    }
+}
+
+/// the body the server returns when a request fails
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ApiError {
+    /// a stable, machine readable error code, e.g. `not_found`
+    pub error: String,
+    /// a human readable description of what went wrong
+    pub message: String,
+    /// the `X-Request-Id` of the failed request, for matching up server logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// what is wrong with each invalid field, for `invalid` errors
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<FieldError>,
+}
+
+/// one problem with one field of a request
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FieldError {
+    /// the field as named in the request body, e.g. `content`
+    pub field: String,
+    /// a stable, machine readable code, e.g. `too_long`
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(error: impl Into<String>, message: impl Into<String>) -> Self {
+        ApiError {
+            error: error.into(),
+            message: message.into(),
+            request_id: None,
+            fields: vec![],
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new("not_found", message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new("unauthorized", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new("forbidden", message)
+    }
+
+    pub fn locked(message: impl Into<String>) -> Self {
+        Self::new("locked", message)
+    }
+
+    pub fn closed(message: impl Into<String>) -> Self {
+        Self::new("closed", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new("internal", message)
+    }
+
+    pub fn invalid(fields: Vec<FieldError>) -> Self {
+        ApiError {
+            fields,
+            ..Self::new("invalid", "the request has invalid fields")
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.error, self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+
+    /// `value` serializes to exactly `expected` and reads back unchanged
+    fn round_trip<T>(value: T, expected: Value)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        let serialized = serde_json::to_value(&value).unwrap();
+        assert_eq!(expected, serialized);
+        let deserialized: T = serde_json::from_value(serialized).unwrap();
+        assert_eq!(value, deserialized);
+    }
+
+    fn post() -> Post {
+        Post {
+            post_id: 1,
+            content: "this is some content".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn post_requests_round_trip() {
+        round_trip(
+            CreatePostRequest {
+                content: "this is some content".to_string(),
+                ..Default::default()
+            },
+            json!({ "content": "this is some content" }),
+        );
+        round_trip(
+            CreatePostRequest {
+                content: "*some* content".to_string(),
+                format: ContentFormat::Markdown,
+                ..Default::default()
+            },
+            json!({ "content": "*some* content", "format": "markdown" }),
+        );
+        round_trip(
+            UpdatePostRequest {
+                post_id: 1,
+                updated_content: "updated".to_string(),
+            },
+            json!({ "post_id": 1, "updated_content": "updated" }),
+        );
+        round_trip(
+            ApiError::not_found("no post with id 1"),
+            json!({ "error": "not_found", "message": "no post with id 1" }),
+        );
+        round_trip(
+            ApiError {
+                request_id: Some("abc".to_string()),
+                ..ApiError::internal("the post store is unavailable")
+            },
+            json!({
+                "error": "internal",
+                "message": "the post store is unavailable",
+                "request_id": "abc"
+            }),
+        );
+        round_trip(
+            ApiError::invalid(vec![FieldError {
+                field: "content".to_string(),
+                code: "too_short".to_string(),
+                message: "must not be empty".to_string(),
+            }]),
+            json!({
+                "error": "invalid",
+                "message": "the request has invalid fields",
+                "fields": [{
+                    "field": "content",
+                    "code": "too_short",
+                    "message": "must not be empty"
+                }]
+            }),
+        );
+    }
+
+    #[test]
+    fn post_timestamps_stay_off_the_wire() {
+        round_trip(
+            post(),
+            json!({ "post_id": 1, "content": "this is some content" }),
+        );
+
+        let stamped = Post {
+            created_at: 10,
+            updated_at: 20,
+            ..post()
+        };
+        assert_eq!(
+            json!({ "post_id": 1, "content": "this is some content" }),
+            serde_json::to_value(stamped).unwrap()
+        );
+    }
+
+    #[test]
+    fn moderation_flags_round_trip() {
+        round_trip(
+            Post {
+                pinned_at: Some(30),
+                locked: true,
+                ..post()
+            },
+            json!({
+                "post_id": 1,
+                "content": "this is some content",
+                "pinned_at": 30,
+                "locked": true
+            }),
+        );
+        round_trip(
+            PostFlagsRequest {
+                post_id: 1,
+                locked: Some(false),
+                ..Default::default()
+            },
+            json!({ "post_id": 1, "locked": false }),
+        );
+    }
+
+    #[test]
+    fn drafts_round_trip() {
+        round_trip(
+            Draft {
+                draft_id: 4,
+                author: "ann".to_string(),
+                content: "*soon*".to_string(),
+                format: ContentFormat::Markdown,
+                publish_at: Some(100),
+                created_at: 10,
+                updated_at: 20,
+            },
+            json!({
+                "draft_id": 4,
+                "author": "ann",
+                "content": "*soon*",
+                "format": "markdown",
+                "publish_at": 100,
+                "created_at": 10,
+                "updated_at": 20
+            }),
+        );
+        round_trip(
+            CreateDraftRequest {
+                content: "later".to_string(),
+                ..Default::default()
+            },
+            json!({ "content": "later" }),
+        );
+    }
+
+    #[test]
+    fn polls_round_trip() {
+        round_trip(
+            Post {
+                poll: Some(Poll {
+                    question: "lunch?".to_string(),
+                    options: vec![
+                        PollOption {
+                            text: "pizza".to_string(),
+                            votes: 2,
+                        },
+                        PollOption {
+                            text: "sushi".to_string(),
+                            votes: 0,
+                        },
+                    ],
+                    multiple: false,
+                    closes_at: Some(100),
+                    voters: 2,
+                }),
+                ..post()
+            },
+            json!({
+                "post_id": 1,
+                "content": "this is some content",
+                "poll": {
+                    "question": "lunch?",
+                    "options": [
+                        { "text": "pizza", "votes": 2 },
+                        { "text": "sushi", "votes": 0 }
+                    ],
+                    "closes_at": 100,
+                    "voters": 2
+                }
+            }),
+        );
+        round_trip(
+            CreatePostRequest {
+                content: "vote please".to_string(),
+                poll: Some(NewPoll {
+                    question: "lunch?".to_string(),
+                    options: vec!["pizza".to_string(), "sushi".to_string()],
+                    multiple: true,
+                    closes_at: None,
+                }),
+                ..Default::default()
+            },
+            json!({
+                "content": "vote please",
+                "poll": { "question": "lunch?", "options": ["pizza", "sushi"], "multiple": true }
+            }),
+        );
+        round_trip(
+            VoteRequest {
+                post_id: 1,
+                choices: vec![0],
+            },
+            json!({ "post_id": 1, "choices": [0] }),
+        );
+
+        let poll = Poll {
+            closes_at: Some(100),
+            ..Default::default()
+        };
+        assert!(poll.is_open(99));
+        assert!(!poll.is_open(100));
+        assert!(Poll::default().is_open(u64::MAX));
+    }
+
+    #[test]
+    fn audit_round_trip() {
+        let entry = AuditEntry {
+            entry_id: 1,
+            actor: "mod".to_string(),
+            action: AuditAction::Hide,
+            post_id: Some(3),
+            draft_id: None,
+            webhook_id: None,
+            before: Some("ab".to_string()),
+            after: Some("cd".to_string()),
+            request_id: None,
+            created_at: 10,
+        };
+        round_trip(
+            entry.clone(),
+            json!({
+                "entry_id": 1,
+                "actor": "mod",
+                "action": "hide",
+                "post_id": 3,
+                "before": "ab",
+                "after": "cd",
+                "created_at": 10
+            }),
+        );
+        let query = AuditQuery {
+            action: Some(AuditAction::Hide),
+            since: Some(10),
+            ..Default::default()
+        };
+        round_trip(query.clone(), json!({ "action": "hide", "since": 10 }));
+        assert!(query.matches(&entry));
+        assert!(!AuditQuery {
+            until: Some(9),
+            ..query.clone()
+        }
+        .matches(&entry));
+        let webhook_entry = AuditEntry {
+            action: AuditAction::CreateWebhook,
+            post_id: None,
+            webhook_id: Some(3),
+            ..entry
+        };
+        assert!(!AuditQuery {
+            post_id: Some(3),
+            ..Default::default()
+        }
+        .matches(&webhook_entry));
+    }
+
+    #[test]
+    fn moderation_round_trip() {
+        round_trip(
+            ReportedPost {
+                post: Post {
+                    hidden: true,
+                    ..post()
+                },
+                report_count: 1,
+                reports: vec![Report {
+                    report_id: 3,
+                    post_id: 1,
+                    user: "ann".to_string(),
+                    reason: "spam".to_string(),
+                    created_at: 10,
+                }],
+            },
+            json!({
+                "post": { "post_id": 1, "content": "this is some content", "hidden": true },
+                "report_count": 1,
+                "reports": [{
+                    "report_id": 3,
+                    "post_id": 1,
+                    "user": "ann",
+                    "reason": "spam",
+                    "created_at": 10
+                }]
+            }),
+        );
+        round_trip(
+            ModerateRequest {
+                post_id: 1,
+                action: ModerationAction::Warn,
+                user: Some("bob".to_string()),
+                note: None,
+            },
+            json!({ "post_id": 1, "action": "warn", "user": "bob" }),
+        );
+        round_trip(
+            ModerationEntry {
+                entry_id: 1,
+                post_id: 1,
+                moderator: None,
+                action: ModerationAction::Hide,
+                user: None,
+                note: Some("reported by 3 users".to_string()),
+                created_at: 20,
+            },
+            json!({
+                "entry_id": 1,
+                "post_id": 1,
+                "action": "hide",
+                "note": "reported by 3 users",
+                "created_at": 20
+            }),
+        );
+    }
+
+    #[test]
+    fn attachments_round_trip() {
+        round_trip(
+            Post {
+                attachments: vec![Attachment {
+                    attachment_id: 7,
+                    file_name: "screen.png".to_string(),
+                    content_type: "image/png".to_string(),
+                    size: 1024,
+                    sha256: "ab".repeat(32),
+                    thumbnail: true,
+                }],
+                ..post()
+            },
+            json!({
+                "post_id": 1,
+                "content": "this is some content",
+                "attachments": [{
+                    "attachment_id": 7,
+                    "file_name": "screen.png",
+                    "content_type": "image/png",
+                    "size": 1024,
+                    "sha256": "ab".repeat(32),
+                    "thumbnail": true
+                }]
+            }),
+        );
+    }
+
+    #[test]
+    fn notifications_round_trip() {
+        round_trip(
+            Notification {
+                notification_id: 2,
+                user: "alice".to_string(),
+                kind: NotificationKind::Mention,
+                post_id: 1,
+                read: false,
+                created_at: 30,
+            },
+            json!({
+                "notification_id": 2,
+                "user": "alice",
+                "kind": "mention",
+                "post_id": 1,
+                "read": false,
+                "created_at": 30
+            }),
+        );
+        round_trip(
+            MarkNotificationsReadRequest {
+                notification_ids: Some(vec![2]),
+            },
+            json!({ "notification_ids": [2] }),
+        );
+        round_trip(MarkNotificationsReadRequest::default(), json!({}));
+    }
+
+    #[test]
+    fn events_round_trip() {
+        round_trip(
+            PostEvent {
+                event_id: 3,
+                kind: PostEventKind::Updated,
+                post_id: 1,
+                post: Some(post()),
+                reaction: None,
+            },
+            json!({
+                "event_id": 3,
+                "kind": "updated",
+                "post_id": 1,
+                "post": { "post_id": 1, "content": "this is some content" }
+            }),
+        );
+        round_trip(
+            PostEvent {
+                event_id: 4,
+                kind: PostEventKind::Deleted,
+                post_id: 1,
+                post: None,
+                reaction: None,
+            },
+            json!({ "event_id": 4, "kind": "deleted", "post_id": 1, "post": null }),
+        );
+        round_trip(
+            PostEvent {
+                event_id: 5,
+                kind: PostEventKind::ReactionAdded,
+                post_id: 1,
+                post: Some(Post {
+                    reactions: vec![ReactionCount {
+                        emoji: "👍".to_string(),
+                        count: 1,
+                    }],
+                    ..post()
+                }),
+                reaction: Some(Reaction {
+                    emoji: "👍".to_string(),
+                    user: "ann".to_string(),
+                }),
+            },
+            json!({
+                "event_id": 5,
+                "kind": "reaction_added",
+                "post_id": 1,
+                "post": {
+                    "post_id": 1,
+                    "content": "this is some content",
+                    "reactions": [{ "emoji": "👍", "count": 1 }]
+                },
+                "reaction": { "emoji": "👍", "user": "ann" }
+            }),
+        );
+    }
+
+    #[test]
+    fn webhooks_round_trip() {
+        round_trip(
+            CreateWebhookRequest {
+                url: "http://localhost:9000/hook".to_string(),
+                secret: "shh".to_string(),
+                events: vec![PostEventKind::Created],
+            },
+            json!({ "url": "http://localhost:9000/hook", "secret": "shh", "events": ["created"] }),
+        );
+        round_trip(
+            Webhook {
+                webhook_id: 1,
+                url: "http://localhost:9000/hook".to_string(),
+                secret: String::new(),
+                events: vec![],
+            },
+            json!({ "webhook_id": 1, "url": "http://localhost:9000/hook", "events": [] }),
+        );
+        round_trip(
+            DeadLetter {
+                delivery_id: 2,
+                webhook_id: 1,
+                url: "http://localhost:9000/hook".to_string(),
+                attempts: 5,
+                last_error: "connection refused".to_string(),
+                event: PostEvent {
+                    event_id: 4,
+                    kind: PostEventKind::Deleted,
+                    post_id: 1,
+                    post: None,
+                    reaction: None,
+                },
+            },
+            json!({
+                "delivery_id": 2,
+                "webhook_id": 1,
+                "url": "http://localhost:9000/hook",
+                "attempts": 5,
+                "last_error": "connection refused",
+                "event": { "event_id": 4, "kind": "deleted", "post_id": 1, "post": null }
+            }),
+        );
+        round_trip(
+            DeliveryAttempt {
+                delivery_id: 2,
+                webhook_id: 1,
+                event_id: 4,
+                attempt: 1,
+                status: DeliveryStatus::Failed,
+                response_status: Some(500),
+                error: None,
+            },
+            json!({
+                "delivery_id": 2,
+                "webhook_id": 1,
+                "event_id": 4,
+                "attempt": 1,
+                "status": "failed",
+                "response_status": 500,
+                "error": null
+            }),
+        );
+    }
+
+    #[test]
+    fn webhook_secret_is_never_returned() {
+        let webhook = Webhook {
+            webhook_id: 1,
+            url: "http://localhost:9000/hook".to_string(),
+            secret: "shh".to_string(),
+            events: vec![],
+        };
+        assert!(serde_json::to_value(webhook)
+            .unwrap()
+            .get("secret")
+            .is_none());
+    }
+}
@@ -0,0 +1,826 @@
+//! Attachments Module
+//!
+//! files uploaded to posts are kept under `attachments.dir`, named by the
+//! SHA-256 of their content so identical uploads share one file. Images
+//! get a PNG thumbnail, downloads honor `Range`, and files no post refers
+//! to any more are removed after posts are deleted
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Extension, Multipart, Path as UrlPath},
+    http::{header, HeaderMap, HeaderValue, Response, StatusCode},
+    Json,
+};
+use futures::{Stream, StreamExt};
+use post_lib::{ApiError, FieldError};
+use sha2::{Digest, Sha256};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::broadcast::error::RecvError,
+    task::JoinHandle,
+};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    config::Config,
+    post_db::{Attachment, PostDb, PostDbStatus, PostEventKind},
+};
+
+/// image types that get thumbnails, with the format their bytes must be in
+const THUMBNAIL_TYPES: &[(&str, image::ImageFormat)] = &[
+    ("image/png", image::ImageFormat::Png),
+    ("image/jpeg", image::ImageFormat::Jpeg),
+    ("image/gif", image::ImageFormat::Gif),
+    ("image/webp", image::ImageFormat::WebP),
+];
+
+/// room for multipart boundaries and part headers on top of the file itself
+pub const MULTIPART_OVERHEAD: usize = 16 * 1024;
+
+/// BlobStore struct - a content-addressed directory of uploaded files
+///
+/// `blobs/` holds the files, `thumbnails/` their previews and `tmp/`
+/// uploads still being written
+pub struct BlobStore {
+    dir: PathBuf,
+    /// blobs written but not yet attached to a post, which sweeps leave alone
+    pending: Mutex<HashMap<String, usize>>,
+}
+
+/// BlobStore implementation
+impl BlobStore {
+    /// a store in `dir`, created on the first upload
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        BlobStore {
+            dir: dir.into(),
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// a store in `dir`, creating it and dropping uploads a previous run
+    /// left half written
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let store = Self::new(dir);
+        match fs::remove_dir_all(store.dir.join("tmp")) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        store.create_dirs()?;
+        Ok(store)
+    }
+
+    fn create_dirs(&self) -> io::Result<()> {
+        for sub in ["blobs", "thumbnails", "tmp"] {
+            fs::create_dir_all(self.dir.join(sub))?;
+        }
+        Ok(())
+    }
+
+    pub fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("blobs").join(sha256)
+    }
+
+    pub fn thumbnail_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("thumbnails").join(format!("{}.png", sha256))
+    }
+
+    /// write `chunks` to the store, failing once more than `max_bytes`
+    /// have arrived
+    ///
+    /// the blob is kept from sweeps until the returned [`StoredBlob`] is
+    /// dropped, which should be after it is attached to a post
+    pub async fn put<S, E>(&self, chunks: S, max_bytes: u64) -> Result<StoredBlob<'_>, PutError>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+        E: fmt::Display,
+    {
+        self.create_dirs()?;
+        let tmp = self.dir.join("tmp").join(uuid::Uuid::new_v4().to_string());
+        let written = self.write_tmp(chunks, &tmp, max_bytes).await;
+        let (sha256, size, head) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&tmp).await;
+                return Err(e);
+            }
+        };
+
+        // under the same lock sweeps take, so a blob found here is not
+        // removed before it is marked pending
+        let mut pending = self.pending.lock().unwrap();
+        let path = self.blob_path(&sha256);
+        if path.exists() {
+            fs::remove_file(&tmp)?;
+        } else {
+            fs::rename(&tmp, &path)?;
+        }
+        *pending.entry(sha256.clone()).or_default() += 1;
+        drop(pending);
+
+        Ok(StoredBlob {
+            store: self,
+            sha256,
+            size,
+            head,
+        })
+    }
+
+    async fn write_tmp<S, E>(
+        &self,
+        chunks: S,
+        tmp: &Path,
+        max_bytes: u64,
+    ) -> Result<(String, u64, Vec<u8>), PutError>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+        E: fmt::Display,
+    {
+        futures::pin_mut!(chunks);
+        let mut file = tokio::fs::File::create(tmp).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0u64;
+        let mut head = Vec::new();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|e| PutError::Body(e.to_string()))?;
+            size += chunk.len() as u64;
+            if size > max_bytes {
+                return Err(PutError::TooLarge);
+            }
+            if head.len() < 64 {
+                head.extend(chunk.iter().take(64 - head.len()));
+            }
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok((hex::encode(hasher.finalize()), size, head))
+    }
+
+    /// write a PNG preview of an image blob, at most `size` pixels a side,
+    /// unless one exists already
+    pub fn make_thumbnail(&self, sha256: &str, size: u32) -> image::ImageResult<()> {
+        let path = self.thumbnail_path(sha256);
+        if path.exists() {
+            return Ok(());
+        }
+        // blobs have no extension to go by, so the format is read from the content
+        let thumbnail = image::ImageReader::open(self.blob_path(sha256))?
+            .with_guessed_format()?
+            .decode()?
+            .thumbnail(size, size);
+        let tmp = self
+            .dir
+            .join("tmp")
+            .join(format!("{}.png", uuid::Uuid::new_v4()));
+        thumbnail.save_with_format(&tmp, image::ImageFormat::Png)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// remove every blob and thumbnail no post in `post_db` refers to,
+    /// returning how many blobs went
+    pub fn sweep(&self, post_db: &Mutex<PostDb>) -> io::Result<usize> {
+        // pending before the store, so a blob attached in between is
+        // either still pending or already referenced
+        let pending = self.pending.lock().unwrap();
+        let referenced: HashSet<String> = PostDb::lock(post_db)
+            .map_err(|_| io::Error::other("the post store lock is poisoned"))?
+            .attachment_hashes();
+        let keep = |sha256: &str| referenced.contains(sha256) || pending.contains_key(sha256);
+
+        let mut removed = 0;
+        for (sub, extension) in [("blobs", ""), ("thumbnails", ".png")] {
+            let entries = match fs::read_dir(self.dir.join(sub)) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let sha256 = name.strip_suffix(extension).unwrap_or(&name);
+                if !keep(sha256) {
+                    fs::remove_file(entry.path())?;
+                    if sub == "blobs" {
+                        removed += 1;
+                    }
+                }
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// StoredBlob struct - an uploaded file, safe from sweeps while held
+pub struct StoredBlob<'a> {
+    store: &'a BlobStore,
+    pub sha256: String,
+    pub size: u64,
+    /// the first bytes of the file, to check its type against
+    pub head: Vec<u8>,
+}
+
+impl Drop for StoredBlob<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.store.pending.lock().unwrap();
+        if let Some(count) = pending.get_mut(&self.sha256) {
+            *count -= 1;
+            if *count == 0 {
+                pending.remove(&self.sha256);
+            }
+        }
+    }
+}
+
+/// Ways storing an upload can fail
+#[derive(Debug)]
+pub enum PutError {
+    /// the file is over the size limit
+    TooLarge,
+    /// the request body could not be read
+    Body(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for PutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PutError::TooLarge => write!(f, "the file is too large"),
+            PutError::Body(e) => write!(f, "could not read the upload: {}", e),
+            PutError::Io(e) => write!(f, "could not store the upload: {}", e),
+        }
+    }
+}
+
+impl From<io::Error> for PutError {
+    fn from(e: io::Error) -> Self {
+        PutError::Io(e)
+    }
+}
+
+/// remove orphaned files once at start, then after every post deletion
+pub fn spawn_cleaner(post_db: Arc<Mutex<PostDb>>, blobs: Arc<BlobStore>) -> JoinHandle<()> {
+    let (_, mut receiver) = post_db.lock().unwrap().subscribe(None);
+    tokio::spawn(async move {
+        loop {
+            let (post_db, blobs) = (post_db.clone(), blobs.clone());
+            match tokio::task::spawn_blocking(move || blobs.sweep(&post_db)).await {
+                Ok(Ok(0)) => {}
+                Ok(Ok(removed)) => tracing::info!(removed, "removed orphaned attachments"),
+                Ok(Err(e)) => tracing::error!("error removing orphaned attachments: {}", e),
+                Err(e) => tracing::error!("attachment sweep failed: {}", e),
+            }
+
+            // wait for a deletion; a lagged receiver may have missed one
+            loop {
+                match receiver.recv().await {
+                    Ok(event) if event.kind == PostEventKind::Deleted => break,
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(_)) => break,
+                    Err(RecvError::Closed) => return,
+                }
+            }
+            while receiver.try_recv().is_ok() {}
+        }
+    })
+}
+
+/// the type to store an upload as: the declared one unless it is missing
+/// or generic, in which case it is guessed from the file name
+pub fn content_type(declared: Option<&str>, file_name: &str) -> String {
+    match declared.map(|declared| declared.split(';').next().unwrap().trim()) {
+        Some(declared) if !declared.is_empty() && declared != "application/octet-stream" => {
+            declared.to_ascii_lowercase()
+        }
+        _ => mime_guess::from_path(file_name)
+            .first_or_octet_stream()
+            .essence_str()
+            .to_string(),
+    }
+}
+
+/// the last path component of an uploaded file name, without control
+/// characters or quotes, so it can be echoed back in headers
+pub fn file_name(uploaded: Option<&str>) -> String {
+    let name: String = uploaded
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_control() && *c != '"')
+        .take(255)
+        .collect();
+    match name.trim() {
+        "" | "." | ".." => "attachment".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// a `Range` starting past the end of the file
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unsatisfiable;
+
+/// the inclusive byte range a `Range` header asks for out of `size` bytes
+///
+/// `Ok(None)` means the whole file: no header, another unit, several
+/// ranges or a malformed one
+pub fn byte_range(range: Option<&str>, size: u64) -> Result<Option<(u64, u64)>, Unsatisfiable> {
+    let spec = match range.and_then(|range| range.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Ok(None),
+    };
+    let Some((start, end)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        // the last `end` bytes
+        (Err(_), Ok(suffix)) if start.is_empty() => {
+            if suffix == 0 || size == 0 {
+                return Err(Unsatisfiable);
+            }
+            (size.saturating_sub(suffix), size - 1)
+        }
+        (Ok(start), Err(_)) if end.is_empty() => (start, size.saturating_sub(1)),
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+        _ => return Ok(None),
+    };
+    if range.0 >= size {
+        return Err(Unsatisfiable);
+    }
+    Ok(Some(range))
+}
+
+/// `Content-Disposition` naming the file, inline only for images
+fn disposition(file_name: &str, content_type: &str) -> String {
+    let kind = if content_type.starts_with("image/") {
+        "inline"
+    } else {
+        "attachment"
+    };
+    let ascii: String = file_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let mut encoded = String::new();
+    for byte in file_name.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        kind, ascii, encoded
+    )
+}
+
+/// stream `path` back, or the part of it `Range` asks for
+async fn serve_file(
+    path: &Path,
+    content_type: &str,
+    file_name: &str,
+    etag: &str,
+    request_headers: &HeaderMap,
+) -> Result<Response<Body>, (StatusCode, Json<ApiError>)> {
+    let mut file = tokio::fs::File::open(path).await.map_err(|e| {
+        tracing::error!("could not open {}: {}", path.display(), e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            crate::error_body(ApiError::internal("the attachment is unavailable")),
+        )
+    })?;
+    let size = file.metadata().await.map(|meta| meta.len()).unwrap_or(0);
+
+    let builder = Response::builder()
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, format!("\"{}\"", etag))
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            disposition(file_name, content_type),
+        )
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+
+    let range = request_headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    let (builder, start, len) = match byte_range(range, size) {
+        Ok(None) => (builder.status(StatusCode::OK), 0, size),
+        Ok(Some((start, end))) => (
+            builder.status(StatusCode::PARTIAL_CONTENT).header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, size),
+            ),
+            start,
+            end - start + 1,
+        ),
+        Err(Unsatisfiable) => {
+            return Ok(builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", size))
+                .body(Body::empty())
+                .unwrap())
+        }
+    };
+
+    if start > 0 {
+        file.seek(SeekFrom::Start(start)).await.map_err(|e| {
+            tracing::error!("could not seek in {}: {}", path.display(), e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                crate::error_body(ApiError::internal("the attachment is unavailable")),
+            )
+        })?;
+    }
+    Ok(builder
+        .header(header::CONTENT_LENGTH, HeaderValue::from(len))
+        .body(Body::wrap_stream(ReaderStream::new(file.take(len))))
+        .unwrap())
+}
+
+fn attachment_not_found(attachment_id: u64) -> (StatusCode, Json<ApiError>) {
+    (
+        StatusCode::EXPECTATION_FAILED,
+        crate::error_body(ApiError::not_found(format!(
+            "no attachment with id {}",
+            attachment_id
+        ))),
+    )
+}
+
+fn invalid_file(field: &str, code: &str, message: String) -> (StatusCode, Json<ApiError>) {
+    crate::invalid_request(vec![FieldError {
+        field: field.to_string(),
+        code: code.to_string(),
+        message,
+    }])
+}
+
+/// Upload Files To A Post
+///
+/// every file part of the `multipart/form-data` body becomes an
+/// attachment; either all of them are attached or none are
+#[utoipa::path(
+    post,
+    path = "/addAttachment/{id}",
+    tag = "attachments",
+    params(("id" = u64, Path, description = "post id")),
+    request_body(content = String, description = "one or more file parts", content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "the new attachments", body = [Attachment]),
+        (status = 400, description = "the body is not valid multipart", body = ApiError),
+        (status = 413, description = "a file is over `attachments.max_bytes`", body = ApiError),
+        (status = 417, description = "no post with that id, or it is hidden", body = ApiError),
+        (status = 422, description = "no files, or a file type that is not allowed", body = ApiError),
+        (status = 423, description = "the post is locked", body = ApiError)
+    )
+)]
+pub async fn add_attachment_handler(
+    UrlPath(post_id): UrlPath<u64>,
+    mut multipart: Multipart,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(blobs): Extension<Arc<BlobStore>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> Result<(StatusCode, Json<Vec<Attachment>>), (StatusCode, Json<ApiError>)> {
+    let not_found = || {
+        (
+            StatusCode::EXPECTATION_FAILED,
+            crate::error_body(crate::post_not_found(post_id)),
+        )
+    };
+    {
+        let post_db = PostDb::lock(&post_db).unwrap();
+        if !post_db.is_visible(post_id) {
+            return Err(not_found());
+        }
+        if post_db.is_locked(post_id) {
+            return Err(crate::post_locked(post_id));
+        }
+    }
+    let limits = &config.attachments;
+    let bad_multipart = |e: axum::extract::multipart::MultipartError| {
+        (
+            StatusCode::BAD_REQUEST,
+            crate::error_body(ApiError::new(
+                "invalid_multipart",
+                format!("could not read the upload: {}", e),
+            )),
+        )
+    };
+
+    let mut uploads = vec![];
+    while let Some(field) = multipart.next_field().await.map_err(bad_multipart)? {
+        if field.file_name().is_none() {
+            continue;
+        }
+        let part = field.name().unwrap_or("file").to_string();
+        let file_name = file_name(field.file_name());
+        let content_type = content_type(field.content_type().map(|mime| mime.as_ref()), &file_name);
+        if !limits.allows(&content_type) {
+            return Err(invalid_file(
+                &part,
+                "type_not_allowed",
+                format!("files of type {} are not accepted", content_type),
+            ));
+        }
+
+        let blob = match blobs.put(field, limits.max_bytes).await {
+            Ok(blob) => blob,
+            Err(PutError::TooLarge) => {
+                return Err((
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    crate::error_body(ApiError::new(
+                        "too_large",
+                        format!("files are limited to {} bytes", limits.max_bytes),
+                    )),
+                ))
+            }
+            Err(PutError::Body(e)) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    crate::error_body(ApiError::new("invalid_multipart", e)),
+                ))
+            }
+            Err(e) => {
+                tracing::error!("{}", e);
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    crate::error_body(ApiError::internal("could not store the upload")),
+                ));
+            }
+        };
+
+        let image_format = THUMBNAIL_TYPES
+            .iter()
+            .find(|(image_type, _)| *image_type == content_type)
+            .map(|(_, format)| *format);
+        let mut thumbnail = false;
+        if let Some(format) = image_format {
+            if image::guess_format(&blob.head).ok() != Some(format) {
+                return Err(invalid_file(
+                    &part,
+                    "type_mismatch",
+                    format!("the file content is not {}", content_type),
+                ));
+            }
+            let (store, sha256, size) = (blobs.clone(), blob.sha256.clone(), limits.thumbnail_size);
+            match tokio::task::spawn_blocking(move || store.make_thumbnail(&sha256, size)).await {
+                Ok(Ok(())) => thumbnail = true,
+                Ok(Err(e)) => tracing::warn!(sha256 = %blob.sha256, "no thumbnail: {}", e),
+                Err(e) => tracing::warn!(sha256 = %blob.sha256, "no thumbnail: {}", e),
+            }
+        }
+
+        uploads.push((
+            Attachment {
+                attachment_id: 0,
+                file_name,
+                content_type,
+                size: blob.size,
+                sha256: blob.sha256.clone(),
+                thumbnail,
+            },
+            blob,
+        ));
+    }
+    if uploads.is_empty() {
+        return Err(invalid_file(
+            "file",
+            "missing",
+            "must include at least one file part".to_string(),
+        ));
+    }
+
+    let mut attachments = vec![];
+    {
+        let mut post_db = PostDb::lock(&post_db).unwrap();
+        if !post_db.is_visible(post_id) {
+            return Err(not_found());
+        }
+        // checked again, as it may have been locked during the upload
+        if post_db.is_locked(post_id) {
+            return Err(crate::post_locked(post_id));
+        }
+        for (attachment, _) in &uploads {
+            let response = post_db.add_attachment(post_id, attachment.clone());
+            if response.status == PostDbStatus::Ok {
+                attachments.extend(response.value);
+            }
+        }
+    }
+    // attached now, so the blobs no longer need protecting from sweeps
+    drop(uploads);
+    Ok((StatusCode::OK, Json(attachments)))
+}
+
+/// Download An Attachment
+///
+/// a single `Range` of bytes may be asked for
+#[utoipa::path(
+    get,
+    path = "/attachment/{id}",
+    tag = "attachments",
+    params(
+        ("id" = u64, Path, description = "attachment id"),
+        ("Range" = Option<String>, Header, description = "e.g. `bytes=0-1023`")
+    ),
+    responses(
+        (status = 200, description = "the file", content_type = "application/octet-stream"),
+        (status = 206, description = "the requested range of the file", content_type = "application/octet-stream"),
+        (status = 416, description = "the range is past the end of the file"),
+        (status = 417, description = "no attachment with that id, or its post is hidden", body = ApiError)
+    )
+)]
+pub async fn attachment_handler(
+    UrlPath(attachment_id): UrlPath<u64>,
+    headers: HeaderMap,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(blobs): Extension<Arc<BlobStore>>,
+) -> Result<Response<Body>, (StatusCode, Json<ApiError>)> {
+    let attachment = PostDb::lock(&post_db)
+        .unwrap()
+        .get_attachment(attachment_id)
+        .value
+        .ok_or_else(|| attachment_not_found(attachment_id))?;
+    serve_file(
+        &blobs.blob_path(&attachment.sha256),
+        &attachment.content_type,
+        &attachment.file_name,
+        &attachment.sha256,
+        &headers,
+    )
+    .await
+}
+
+/// Download An Attachment Thumbnail
+#[utoipa::path(
+    get,
+    path = "/attachment/{id}/thumbnail",
+    tag = "attachments",
+    params(("id" = u64, Path, description = "attachment id")),
+    responses(
+        (status = 200, description = "a PNG preview of an image attachment", content_type = "image/png"),
+        (status = 417, description = "no image attachment with that id, or its post is hidden", body = ApiError)
+    )
+)]
+pub async fn thumbnail_handler(
+    UrlPath(attachment_id): UrlPath<u64>,
+    headers: HeaderMap,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(blobs): Extension<Arc<BlobStore>>,
+) -> Result<Response<Body>, (StatusCode, Json<ApiError>)> {
+    let attachment = PostDb::lock(&post_db)
+        .unwrap()
+        .get_attachment(attachment_id)
+        .value
+        .filter(|attachment| attachment.thumbnail)
+        .ok_or_else(|| attachment_not_found(attachment_id))?;
+    let name = format!("{}.png", attachment.file_name);
+    serve_file(
+        &blobs.thumbnail_path(&attachment.sha256),
+        "image/png",
+        &name,
+        &format!("{}-thumbnail", attachment.sha256),
+        &headers,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::stream;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "post-server-attachments-{}-{}",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn chunks(data: &'static [u8]) -> impl Stream<Item = Result<Bytes, io::Error>> {
+        stream::iter(data.chunks(3).map(|chunk| Ok(Bytes::from_static(chunk))))
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(Ok(None), byte_range(None, 10));
+        assert_eq!(Ok(Some((0, 4))), byte_range(Some("bytes=0-4"), 10));
+        assert_eq!(Ok(Some((5, 9))), byte_range(Some("bytes=5-"), 10));
+        assert_eq!(Ok(Some((7, 9))), byte_range(Some("bytes=-3"), 10));
+        assert_eq!(Ok(Some((0, 9))), byte_range(Some("bytes=-30"), 10));
+        assert_eq!(Ok(Some((8, 9))), byte_range(Some("bytes=8-30"), 10));
+        // served whole
+        assert_eq!(Ok(None), byte_range(Some("bytes=0-1,3-4"), 10));
+        assert_eq!(Ok(None), byte_range(Some("items=0-4"), 10));
+        assert_eq!(Ok(None), byte_range(Some("bytes=4-2"), 10));
+        // past the end
+        assert_eq!(Err(Unsatisfiable), byte_range(Some("bytes=10-"), 10));
+        assert_eq!(Err(Unsatisfiable), byte_range(Some("bytes=-0"), 10));
+        assert_eq!(Err(Unsatisfiable), byte_range(Some("bytes=0-"), 0));
+    }
+
+    #[test]
+    fn names_and_types_are_cleaned_up() {
+        assert_eq!("app.log", file_name(Some("C:\\logs\\app.log")));
+        assert_eq!("evil.txt", file_name(Some("../../evil\".txt")));
+        assert_eq!("attachment", file_name(Some("..")));
+        assert_eq!("attachment", file_name(None));
+
+        assert_eq!(
+            "text/plain",
+            content_type(Some("text/plain; charset=utf-8"), "a")
+        );
+        assert_eq!(
+            "image/png",
+            content_type(Some("application/octet-stream"), "a.png")
+        );
+        assert_eq!(
+            "application/octet-stream",
+            content_type(None, "noextension")
+        );
+
+        assert_eq!(
+            "attachment; filename=\"caf_.txt\"; filename*=UTF-8''caf%C3%A9.txt",
+            disposition("café.txt", "text/plain")
+        );
+    }
+
+    #[tokio::test]
+    async fn identical_uploads_share_a_blob() {
+        let dir = temp_dir("dedupe");
+        let store = BlobStore::open(&dir).unwrap();
+
+        let first = store.put(chunks(b"hello world"), 100).await.unwrap();
+        let second = store.put(chunks(b"hello world"), 100).await.unwrap();
+        assert_eq!(first.sha256, second.sha256);
+        assert_eq!(
+            "b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9",
+            first.sha256
+        );
+        assert_eq!(11, first.size);
+        assert_eq!(b"hello world", &first.head[..]);
+        assert_eq!(1, fs::read_dir(dir.join("blobs")).unwrap().count());
+
+        assert!(matches!(
+            store.put(chunks(b"far too long"), 5).await,
+            Err(PutError::TooLarge)
+        ));
+        assert_eq!(0, fs::read_dir(dir.join("tmp")).unwrap().count());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sweeps_keep_referenced_and_pending_blobs() {
+        let dir = temp_dir("sweep");
+        let store = BlobStore::open(&dir).unwrap();
+        let post_db = Mutex::new(PostDb::new());
+        post_db.lock().unwrap().create_post("first".to_string());
+
+        let attached = store.put(chunks(b"attached"), 100).await.unwrap();
+        post_db.lock().unwrap().add_attachment(
+            1,
+            Attachment {
+                attachment_id: 0,
+                file_name: "a.txt".to_string(),
+                content_type: "text/plain".to_string(),
+                size: attached.size,
+                sha256: attached.sha256.clone(),
+                thumbnail: false,
+            },
+        );
+        let attached_path = store.blob_path(&attached.sha256);
+        drop(attached);
+        let pending = store.put(chunks(b"pending"), 100).await.unwrap();
+        let orphan = store.put(chunks(b"orphan"), 100).await.unwrap();
+        let orphan_path = store.blob_path(&orphan.sha256);
+        drop(orphan);
+
+        assert_eq!(1, store.sweep(&post_db).unwrap());
+        assert!(attached_path.exists());
+        assert!(store.blob_path(&pending.sha256).exists());
+        assert!(!orphan_path.exists());
+
+        post_db.lock().unwrap().delete_post(1);
+        drop(pending);
+        assert_eq!(2, store.sweep(&post_db).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+//! Audit Module
+//!
+//! every change to a post, a draft or a webhook is added to an
+//! append-only audit log, naming who made it and the request it came in.
+//! Each request runs with its signed in user as the actor, else a
+//! fingerprint of an unknown API key, else the client's address; nothing
+//! else in the request, like a user named in its body, is trusted.
+//! Moderators can query the log and export it as NDJSON
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::Body,
+    extract::{Extension, Query},
+    http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use post_lib::AuditQuery;
+use sha2::{Digest, Sha256};
+use tower::{Layer, Service};
+
+use crate::{
+    auth::{bearer_token, Auth, Moderator},
+    post_db::PostDb,
+    rate_limit::RateLimiter,
+};
+
+/// content type of the NDJSON export
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// hex digits of an API key's SHA-256 kept in its fingerprint
+const KEY_FINGERPRINT_LEN: usize = 12;
+
+tokio::task_local! {
+    static ACTOR: String;
+}
+
+/// the actor changes are logged as, if called from inside a request or
+/// [`as_actor`]
+pub fn current_actor() -> Option<String> {
+    ACTOR.try_with(|actor| actor.clone()).ok()
+}
+
+/// run `f` with changes logged as made by `actor`, for work no request
+/// asked for, like the scheduler's
+pub fn as_actor<R>(actor: &str, f: impl FnOnce() -> R) -> R {
+    ACTOR.sync_scope(actor.to_string(), f)
+}
+
+/// who is making `request`: the user its API key signs in as, else the
+/// start of an unknown key's SHA-256 so the log never holds the key, else
+/// the client's address
+fn actor<B>(auth: &Auth, rate_limiter: &RateLimiter, request: &Request<B>) -> String {
+    match bearer_token(request.headers()) {
+        Some(key) => match auth.caller_with_key(key) {
+            Some(caller) => caller.user,
+            None => {
+                let digest = hex::encode(Sha256::digest(key.as_bytes()));
+                format!("key:{}", &digest[..KEY_FINGERPRINT_LEN])
+            }
+        },
+        None => rate_limiter.client_addr(request),
+    }
+}
+
+/// AuditLayer struct - wraps the router in [`Audit`]
+#[derive(Clone)]
+pub struct AuditLayer {
+    auth: Arc<Auth>,
+    rate_limiter: Arc<RateLimiter>,
+}
+
+/// AuditLayer implementation
+impl AuditLayer {
+    pub fn new(auth: Arc<Auth>, rate_limiter: Arc<RateLimiter>) -> Self {
+        AuditLayer { auth, rate_limiter }
+    }
+}
+
+impl<S> Layer<S> for AuditLayer {
+    type Service = Audit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Audit {
+            inner,
+            auth: self.auth.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+        }
+    }
+}
+
+/// Audit struct - runs each request with its client as the actor
+#[derive(Clone)]
+pub struct Audit<S> {
+    inner: S,
+    auth: Arc<Auth>,
+    rate_limiter: Arc<RateLimiter>,
+}
+
+impl<S, B> Service<Request<Body>> for Audit<S>
+where
+    S: Service<Request<Body>, Response = Response<B>, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<B>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let actor = actor(&self.auth, &self.rate_limiter, &request);
+        Box::pin(ACTOR.scope(actor, self.inner.call(request)))
+    }
+}
+
+/// Get The Audit Log
+#[utoipa::path(
+    get,
+    path = "/admin/auditLog",
+    tag = "audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "every change passing the filters, oldest first", body = [AuditEntry]),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError)
+    )
+)]
+pub async fn audit_log_handler(
+    _moderator: Moderator,
+    Query(query): Query<AuditQuery>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let log = PostDb::lock(&post_db).unwrap().audit_log(&query);
+    (StatusCode::OK, Json(log))
+}
+
+/// Export The Audit Log
+///
+/// the same entries as `/admin/auditLog`, one JSON object per line
+#[utoipa::path(
+    get,
+    path = "/admin/auditLog.ndjson",
+    tag = "audit",
+    params(AuditQuery),
+    responses(
+        (status = 200, description = "the entries passing the filters as NDJSON, oldest first", content_type = "application/x-ndjson", body = String),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError)
+    )
+)]
+pub async fn audit_export_handler(
+    _moderator: Moderator,
+    Query(query): Query<AuditQuery>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let log = PostDb::lock(&post_db).unwrap().audit_log(&query);
+    let mut body = String::new();
+    for entry in log {
+        body.push_str(&serde_json::to_string(&entry).expect("audit entries serialize"));
+        body.push('\n');
+    }
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(NDJSON_CONTENT_TYPE),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_static("attachment; filename=\"audit.ndjson\""),
+    );
+    (StatusCode::OK, headers, body)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::{AuthConfig, RateLimitConfig};
+
+    #[test]
+    fn actors_come_from_api_keys() {
+        let auth = Auth::new(&AuthConfig {
+            api_keys: [("s3cret-ann".to_string(), "ann".to_string())].into(),
+            ..Default::default()
+        });
+        let limiter = RateLimiter::new(RateLimitConfig {
+            user_header: Some("x-user".to_string()),
+            ..Default::default()
+        });
+        let actor = |key: &str| {
+            let request = Request::builder()
+                .header("authorization", format!("Bearer {}", key))
+                .header("x-user", "mod")
+                .body(())
+                .unwrap();
+            super::actor(&auth, &limiter, &request)
+        };
+        assert_eq!("ann", actor("s3cret-ann"));
+
+        let unknown = actor("secret");
+        assert_eq!(4 + KEY_FINGERPRINT_LEN, unknown.len());
+        assert!(unknown.starts_with("key:") && !unknown.contains("secret"));
+        assert_eq!(unknown, actor("secret"));
+        assert_ne!(unknown, actor("other"));
+
+        // the user header only names who to rate limit, not who is asking
+        let request = Request::builder().header("x-user", "mod").body(()).unwrap();
+        assert_eq!("ip:unknown", super::actor(&auth, &limiter, &request));
+
+        assert_eq!(None, current_actor());
+        assert_eq!(
+            Some("scheduler".to_string()),
+            as_actor("scheduler", current_actor)
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Auth Module
+//!
+//! callers sign in with a bearer API key from `[auth.api_keys]`, which
+//! names the user they act as; the users listed in `auth.moderators` may
+//! also use the `/admin` endpoints. Handlers take a [`User`] to refuse
+//! callers who are not signed in and a [`Moderator`] to refuse everyone
+//! but moderators
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+    http::{header, HeaderMap, StatusCode},
+    Json,
+};
+use post_lib::ApiError;
+
+use crate::config::AuthConfig;
+
+/// Auth struct - the configured API keys and moderators
+#[derive(Debug, Default)]
+pub struct Auth {
+    /// user signed in as, by API key
+    users: HashMap<String, String>,
+    moderators: HashSet<String>,
+}
+
+/// Caller struct - a signed in user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Caller {
+    pub user: String,
+    pub moderator: bool,
+}
+
+/// Auth implementation
+impl Auth {
+    pub fn new(config: &AuthConfig) -> Self {
+        Auth {
+            users: config.api_keys.clone().into_iter().collect(),
+            moderators: config.moderators.iter().cloned().collect(),
+        }
+    }
+
+    /// who sent `headers`, if they carry a known bearer API key
+    pub fn caller(&self, headers: &HeaderMap) -> Option<Caller> {
+        self.caller_with_key(bearer_token(headers)?)
+    }
+
+    /// who signs in with `key`, if anyone
+    pub fn caller_with_key(&self, key: &str) -> Option<Caller> {
+        let user = self.users.get(key)?;
+        Some(Caller {
+            user: user.clone(),
+            moderator: self.moderators.contains(user),
+        })
+    }
+}
+
+/// the token of an `Authorization: Bearer` header
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+}
+
+/// the caller of a request being extracted, if signed in
+fn caller_of<B>(request: &RequestParts<B>) -> Option<Caller> {
+    let auth = request.extensions()?.get::<Arc<Auth>>()?;
+    auth.caller(request.headers()?)
+}
+
+/// User struct - the signed in user making a request
+///
+/// extracting it answers `401` to callers who are not signed in
+pub struct User(pub String);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for User {
+    type Rejection = (StatusCode, Json<ApiError>);
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        caller_of(request)
+            .map(|caller| User(caller.user))
+            .ok_or_else(unauthorized)
+    }
+}
+
+/// Moderator struct - the signed in moderator making a request
+///
+/// extracting it answers `401` to callers who are not signed in and `403`
+/// to ones who are not moderators
+pub struct Moderator(pub String);
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for Moderator {
+    type Rejection = (StatusCode, Json<ApiError>);
+
+    async fn from_request(request: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        match caller_of(request) {
+            Some(Caller {
+                user,
+                moderator: true,
+            }) => Ok(Moderator(user)),
+            Some(_) => Err(forbidden()),
+            None => Err(unauthorized()),
+        }
+    }
+}
+
+fn unauthorized() -> (StatusCode, Json<ApiError>) {
+    (
+        StatusCode::UNAUTHORIZED,
+        crate::error_body(ApiError::unauthorized(
+            "sign in with an `Authorization: Bearer` API key",
+        )),
+    )
+}
+
+fn forbidden() -> (StatusCode, Json<ApiError>) {
+    (
+        StatusCode::FORBIDDEN,
+        crate::error_body(ApiError::forbidden("only moderators may do this")),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn api_keys_name_their_user() {
+        let auth = Auth::new(&AuthConfig {
+            api_keys: [("k1", "ann"), ("k2", "mod")]
+                .into_iter()
+                .map(|(key, user)| (key.to_string(), user.to_string()))
+                .collect(),
+            moderators: vec!["mod".to_string()],
+        });
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+
+        assert_eq!(
+            Some(Caller {
+                user: "ann".to_string(),
+                moderator: false
+            }),
+            auth.caller(&headers("Bearer k1"))
+        );
+        assert!(auth.caller(&headers("Bearer k2")).unwrap().moderator);
+        assert_eq!(None, auth.caller(&headers("Bearer k3")));
+        assert_eq!(None, auth.caller(&headers("Basic k1")));
+        assert_eq!(None, auth.caller(&HeaderMap::new()));
+    }
+}
@@ -0,0 +1,1139 @@
+//! Config Module
+//!
+//! server settings, layered from lowest to highest precedence:
+//! built-in defaults, a TOML file, `POST_SERVER_*` environment variables
+//! and command-line flags
+//!
+//! ```toml
+//! [server]
+//! bind = "127.0.0.1:3000"
+//! public_url = "http://localhost:3000"
+//! allowed_origins = ["http://localhost:8080"]
+//! shutdown_timeout_secs = 30
+//!
+//! [auth]
+//! api_keys = { "s3cret-ann" = "ann", "s3cret-mod" = "mod" }
+//! moderators = ["mod"]
+//!
+//! [storage]
+//! backend = "file"
+//! path = "posts.json"
+//!
+//! [attachments]
+//! dir = "attachments"
+//! max_bytes = 10485760
+//! allowed_types = ["image/*", "text/plain", "application/pdf"]
+//!
+//! [limits]
+//! event_buffer = 256
+//! feed_size = 20
+//! max_body_bytes = 65536
+//!
+//! [limits.content]
+//! min_chars = 1
+//! max_chars = 10000
+//! max_lines = 200
+//!
+//! [moderation]
+//! report_threshold = 5
+//!
+//! [filters.blocked_words]
+//! patterns = ["casino", "v?agra", "*coin"]
+//! action = "reject"
+//!
+//! [filters.links]
+//! max = 5
+//! action = "hold"
+//!
+//! [filters.duplicates]
+//! window_secs = 60
+//! action = "reject"
+//!
+//! [filters.spam]
+//! hold_score = 0.9
+//! reject_score = 1.0
+//! min_trained = 10
+//!
+//! [rate_limit]
+//! read = { per_minute = 600, burst = 120 }
+//! write = { per_minute = 60, burst = 20 }
+//! routes."/addPost" = { write = { per_minute = 10, burst = 5 } }
+//!
+//! [logging]
+//! level = "info"
+//! format = "pretty"
+//!
+//! [logging.modules]
+//! "post_server::webhooks" = "debug"
+//! ```
+
+use std::{
+    collections::BTreeMap, fmt, fs, net::SocketAddr, path::PathBuf, str::FromStr, time::Duration,
+};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+use crate::{feeds::FEED_SIZE, post_db::EVENT_BUFFER_CAPACITY};
+
+/// prefix shared by every environment variable the server reads
+pub const ENV_PREFIX: &str = "POST_SERVER_";
+
+/// Command-line flags, each overriding the matching setting
+#[derive(Parser, Debug, Default)]
+#[command(name = "post-server", version)]
+pub struct Flags {
+    /// TOML config file [env: POST_SERVER_CONFIG]
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// address to listen on, e.g. 0.0.0.0:3000
+    #[arg(long)]
+    pub bind: Option<String>,
+
+    /// the url clients reach the server on, e.g. https://posts.example
+    #[arg(long)]
+    pub public_url: Option<String>,
+
+    /// an origin allowed to make cross-origin requests; repeat for more
+    #[arg(long = "allowed-origin")]
+    pub allowed_origins: Vec<String>,
+
+    /// seconds to let in-flight requests finish when shutting down
+    #[arg(long)]
+    pub shutdown_timeout: Option<u64>,
+
+    /// where posts are kept: memory or file
+    #[arg(long)]
+    pub storage: Option<String>,
+
+    /// snapshot file for the file storage backend
+    #[arg(long)]
+    pub storage_path: Option<PathBuf>,
+
+    /// log filter, e.g. `info` or `post_server=debug,tower_http=warn`
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// log output: pretty or json
+    #[arg(long)]
+    pub log_format: Option<String>,
+
+    /// print the effective configuration as TOML and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+/// Config struct - every server setting
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub auth: AuthConfig,
+    pub storage: StorageConfig,
+    pub attachments: AttachmentsConfig,
+    pub limits: LimitsConfig,
+    pub moderation: ModerationConfig,
+    pub filters: FiltersConfig,
+    pub rate_limit: RateLimitConfig,
+    pub logging: LoggingConfig,
+}
+
+/// where and for whom the server listens
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    /// the url clients reach the server on, used for absolute links like
+    /// the ones in feeds; request headers are never trusted for them
+    pub public_url: String,
+    /// origins allowed by CORS; `*` allows any
+    pub allowed_origins: Vec<String>,
+    /// seconds in-flight requests get to finish after SIGTERM / SIGINT
+    pub shutdown_timeout_secs: u64,
+}
+
+/// ServerConfig default implementation
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "127.0.0.1:3000".to_string(),
+            public_url: "http://localhost:3000".to_string(),
+            allowed_origins: vec!["http://localhost:8080".to_string()],
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+/// who callers are and what they may do
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// bearer API keys, each naming the user it signs its caller in as
+    pub api_keys: BTreeMap<String, String>,
+    /// users who may use the `/admin` endpoints; none by default
+    pub moderators: Vec<String>,
+}
+
+/// Where posts are kept
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// lost on restart
+    #[default]
+    Memory,
+    /// kept in memory and written to a JSON snapshot after every change
+    File,
+}
+
+/// how posts are stored
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    /// snapshot file, required by the file backend
+    pub path: Option<PathBuf>,
+}
+
+/// where uploaded files are kept and which are accepted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentsConfig {
+    /// directory holding the files, named by the SHA-256 of their content
+    pub dir: PathBuf,
+    /// largest file accepted, in bytes
+    pub max_bytes: u64,
+    /// accepted MIME types, either exact or like `image/*`
+    pub allowed_types: Vec<String>,
+    /// longest side of image thumbnails, in pixels
+    pub thumbnail_size: u32,
+}
+
+/// AttachmentsConfig default implementation
+impl Default for AttachmentsConfig {
+    fn default() -> Self {
+        AttachmentsConfig {
+            dir: PathBuf::from("attachments"),
+            max_bytes: 10 * 1024 * 1024,
+            allowed_types: [
+                "image/png",
+                "image/jpeg",
+                "image/gif",
+                "image/webp",
+                "text/plain",
+                "application/json",
+                "application/pdf",
+                "application/gzip",
+                "application/zip",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+            thumbnail_size: 256,
+        }
+    }
+}
+
+/// AttachmentsConfig implementation
+impl AttachmentsConfig {
+    /// whether files of `content_type` may be uploaded
+    pub fn allows(&self, content_type: &str) -> bool {
+        let (kind, _) = content_type.split_once('/').unwrap_or((content_type, ""));
+        self.allowed_types.iter().any(|allowed| {
+            allowed == content_type
+                || allowed
+                    .strip_suffix("/*")
+                    .is_some_and(|allowed_kind| allowed_kind == kind)
+        })
+    }
+}
+
+/// sizes of the server's bounded buffers and listings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// events kept for `Last-Event-ID` replay
+    pub event_buffer: usize,
+    /// posts in the Atom and RSS feeds
+    pub feed_size: usize,
+    /// largest request body accepted, in bytes
+    pub max_body_bytes: usize,
+    pub content: ContentLimits,
+}
+
+/// what post content is accepted, checked after Unicode (NFC) normalization
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContentLimits {
+    /// fewest characters, not counting surrounding whitespace
+    pub min_chars: usize,
+    pub max_chars: usize,
+    pub max_lines: usize,
+}
+
+/// ContentLimits default implementation
+impl Default for ContentLimits {
+    fn default() -> Self {
+        ContentLimits {
+            min_chars: 1,
+            max_chars: 10_000,
+            max_lines: 200,
+        }
+    }
+}
+
+/// LimitsConfig default implementation
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            event_buffer: EVENT_BUFFER_CAPACITY,
+            feed_size: FEED_SIZE,
+            max_body_bytes: 64 * 1024,
+            content: ContentLimits::default(),
+        }
+    }
+}
+
+/// how reported posts are handled
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationConfig {
+    /// users who must report a post before it is hidden until a moderator
+    /// looks at it; 0 leaves every post up
+    pub report_threshold: u64,
+}
+
+/// ModerationConfig default implementation
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        ModerationConfig {
+            report_threshold: 5,
+        }
+    }
+}
+
+/// what a content filter does with a post it catches
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    /// let it through, turning the filter off
+    Allow,
+    /// store it hidden, waiting in the moderation queue
+    Hold,
+    /// refuse it with `422`
+    Reject,
+}
+
+/// the content filters every new post goes through
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FiltersConfig {
+    pub blocked_words: BlockedWordsConfig,
+    pub links: LinksConfig,
+    pub duplicates: DuplicatesConfig,
+    pub spam: SpamConfig,
+}
+
+/// words no post may contain
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlockedWordsConfig {
+    /// whole words, ignoring case, where `*` stands for any run of
+    /// characters and `?` for exactly one
+    pub patterns: Vec<String>,
+    pub action: FilterAction,
+}
+
+/// BlockedWordsConfig default implementation
+impl Default for BlockedWordsConfig {
+    fn default() -> Self {
+        BlockedWordsConfig {
+            patterns: vec![],
+            action: FilterAction::Reject,
+        }
+    }
+}
+
+/// how many links a post may have
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LinksConfig {
+    pub max: usize,
+    pub action: FilterAction,
+}
+
+/// LinksConfig default implementation
+impl Default for LinksConfig {
+    fn default() -> Self {
+        LinksConfig {
+            max: 5,
+            action: FilterAction::Hold,
+        }
+    }
+}
+
+/// posts repeating a recent post
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DuplicatesConfig {
+    /// how far back to look, in seconds; 0 turns the filter off
+    pub window_secs: u64,
+    pub action: FilterAction,
+}
+
+/// DuplicatesConfig default implementation
+impl Default for DuplicatesConfig {
+    fn default() -> Self {
+        DuplicatesConfig {
+            window_secs: 60,
+            action: FilterAction::Reject,
+        }
+    }
+}
+
+/// the spam score learned from moderators, from 0 to 1
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpamConfig {
+    /// posts scoring above this are held; 1 never holds
+    pub hold_score: f64,
+    /// posts scoring above this are rejected; 1 never rejects
+    pub reject_score: f64,
+    /// spam and other posts moderators must have acted on, each, before
+    /// scores are trusted
+    pub min_trained: u64,
+}
+
+/// SpamConfig default implementation
+impl Default for SpamConfig {
+    fn default() -> Self {
+        SpamConfig {
+            hold_score: 0.9,
+            reject_score: 1.0,
+            min_trained: 10,
+        }
+    }
+}
+
+/// a token bucket: refilled at `per_minute`, holding at most `burst`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Limit {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+/// limits for one route, replacing the defaults for that route only
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouteLimits {
+    pub read: Option<Limit>,
+    pub write: Option<Limit>,
+}
+
+/// how many requests each client may make
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// GET, HEAD and OPTIONS requests, shared by routes without their own limit
+    pub read: Limit,
+    /// every other method, shared by routes without their own limit
+    pub write: Limit,
+    /// limits for single routes, keyed by path as registered, e.g. `/post/:id`
+    pub routes: BTreeMap<String, RouteLimits>,
+    /// routes that are never limited
+    pub exempt_routes: Vec<String>,
+    /// header naming the user, set by an authenticating proxy
+    pub user_header: Option<String>,
+    /// key clients by the first `X-Forwarded-For` address instead of the
+    /// peer address; only safe behind a proxy that sets it
+    pub trust_forwarded_for: bool,
+}
+
+/// RateLimitConfig default implementation
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: true,
+            read: Limit {
+                per_minute: 600,
+                burst: 120,
+            },
+            write: Limit {
+                per_minute: 60,
+                burst: 20,
+            },
+            routes: BTreeMap::new(),
+            exempt_routes: vec![
+                "/healthz".to_string(),
+                "/readyz".to_string(),
+                "/metrics".to_string(),
+            ],
+            user_header: None,
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+/// Log output style
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// human readable lines
+    #[default]
+    Pretty,
+    /// one JSON object per line
+    Json,
+}
+
+/// what gets logged and how
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// a `tracing` filter, e.g. `info` or `post_server=debug,hyper=warn`
+    pub level: String,
+    pub format: LogFormat,
+    /// levels for single modules, overriding `level`
+    pub modules: BTreeMap<String, String>,
+}
+
+/// LoggingConfig default implementation
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "info".to_string(),
+            format: LogFormat::Pretty,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+/// LoggingConfig implementation
+impl LoggingConfig {
+    /// `level` and the per-module levels as one `tracing` filter
+    pub fn filter(&self) -> String {
+        let mut directives = vec![self.level.clone()];
+        for (module, level) in &self.modules {
+            directives.push(format!("{}={}", module, level));
+        }
+        directives.join(",")
+    }
+}
+
+/// ConfigError struct - every problem found while building the config
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration")?;
+        for error in &self.errors {
+            write!(f, "\n  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<String> for ConfigError {
+    fn from(error: String) -> Self {
+        ConfigError {
+            errors: vec![error],
+        }
+    }
+}
+
+/// Config implementation
+impl Config {
+    /// build the config from every layer and validate it
+    ///
+    /// `env` looks up environment variables, so tests can pass a map
+    pub fn load(flags: &Flags, env: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+
+        let path = flags
+            .config
+            .clone()
+            .or_else(|| env("POST_SERVER_CONFIG").map(PathBuf::from));
+        if let Some(path) = path {
+            let text =
+                fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            config = Config::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        }
+
+        let mut errors = vec![];
+        config.apply_env(&env, &mut errors);
+        config.apply_flags(flags, &mut errors);
+        errors.extend(config.validate());
+
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigError { errors })
+        }
+    }
+
+    /// parse a TOML config file, filling in defaults for anything missing
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// the config as TOML, as shown by `--print-config`
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("config is always representable as TOML")
+    }
+
+    fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>, errors: &mut Vec<String>) {
+        let var = |name: &str| env(&format!("{}{}", ENV_PREFIX, name));
+
+        if let Some(bind) = var("BIND") {
+            self.server.bind = bind;
+        }
+        if let Some(url) = var("PUBLIC_URL") {
+            self.server.public_url = url;
+        }
+        if let Some(origins) = var("ALLOWED_ORIGINS") {
+            self.server.allowed_origins = origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(String::from)
+                .collect();
+        }
+        if let Some(secs) = var("SHUTDOWN_TIMEOUT") {
+            set_parsed(
+                &mut self.server.shutdown_timeout_secs,
+                "POST_SERVER_SHUTDOWN_TIMEOUT",
+                &secs,
+                errors,
+            );
+        }
+        if let Some(backend) = var("STORAGE") {
+            set_parsed(
+                &mut self.storage.backend,
+                "POST_SERVER_STORAGE",
+                &backend,
+                errors,
+            );
+        }
+        if let Some(path) = var("STORAGE_PATH") {
+            self.storage.path = Some(PathBuf::from(path));
+        }
+        if let Some(dir) = var("ATTACHMENTS_DIR") {
+            self.attachments.dir = PathBuf::from(dir);
+        }
+        if let Some(bytes) = var("ATTACHMENTS_MAX_BYTES") {
+            set_parsed(
+                &mut self.attachments.max_bytes,
+                "POST_SERVER_ATTACHMENTS_MAX_BYTES",
+                &bytes,
+                errors,
+            );
+        }
+        if let Some(size) = var("EVENT_BUFFER") {
+            set_parsed(
+                &mut self.limits.event_buffer,
+                "POST_SERVER_EVENT_BUFFER",
+                &size,
+                errors,
+            );
+        }
+        if let Some(size) = var("FEED_SIZE") {
+            set_parsed(
+                &mut self.limits.feed_size,
+                "POST_SERVER_FEED_SIZE",
+                &size,
+                errors,
+            );
+        }
+        if let Some(bytes) = var("MAX_BODY_BYTES") {
+            set_parsed(
+                &mut self.limits.max_body_bytes,
+                "POST_SERVER_MAX_BODY_BYTES",
+                &bytes,
+                errors,
+            );
+        }
+        if let Some(threshold) = var("REPORT_THRESHOLD") {
+            set_parsed(
+                &mut self.moderation.report_threshold,
+                "POST_SERVER_REPORT_THRESHOLD",
+                &threshold,
+                errors,
+            );
+        }
+        if let Some(enabled) = var("RATE_LIMIT_ENABLED") {
+            set_parsed(
+                &mut self.rate_limit.enabled,
+                "POST_SERVER_RATE_LIMIT_ENABLED",
+                &enabled,
+                errors,
+            );
+        }
+        if let Some(level) = var("LOG_LEVEL") {
+            self.logging.level = level;
+        }
+        if let Some(format) = var("LOG_FORMAT") {
+            set_parsed(
+                &mut self.logging.format,
+                "POST_SERVER_LOG_FORMAT",
+                &format,
+                errors,
+            );
+        }
+    }
+
+    fn apply_flags(&mut self, flags: &Flags, errors: &mut Vec<String>) {
+        if let Some(bind) = &flags.bind {
+            self.server.bind = bind.clone();
+        }
+        if let Some(url) = &flags.public_url {
+            self.server.public_url = url.clone();
+        }
+        if !flags.allowed_origins.is_empty() {
+            self.server.allowed_origins = flags.allowed_origins.clone();
+        }
+        if let Some(secs) = flags.shutdown_timeout {
+            self.server.shutdown_timeout_secs = secs;
+        }
+        if let Some(backend) = &flags.storage {
+            set_parsed(&mut self.storage.backend, "--storage", backend, errors);
+        }
+        if let Some(path) = &flags.storage_path {
+            self.storage.path = Some(path.clone());
+        }
+        if let Some(level) = &flags.log_level {
+            self.logging.level = level.clone();
+        }
+        if let Some(format) = &flags.log_format {
+            set_parsed(&mut self.logging.format, "--log-format", format, errors);
+        }
+    }
+
+    /// every problem with the settings, empty when they are usable
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = vec![];
+
+        if self.server.bind.parse::<SocketAddr>().is_err() {
+            errors.push(format!(
+                "server.bind: `{}` is not an address like 127.0.0.1:3000",
+                self.server.bind
+            ));
+        }
+        let public_url = self.server.public_url.parse::<hyper::Uri>();
+        let valid = public_url.is_ok_and(|url| {
+            matches!(url.scheme_str(), Some("http") | Some("https"))
+                && url.host().is_some_and(|host| !host.is_empty())
+                && !url.authority().is_some_and(|a| a.as_str().contains('@'))
+                && url.query().is_none()
+        });
+        if !valid {
+            errors.push(format!(
+                "server.public_url: `{}` is not a url like https://posts.example",
+                self.server.public_url
+            ));
+        }
+        for origin in &self.server.allowed_origins {
+            let valid = origin == "*"
+                || ((origin.starts_with("http://") || origin.starts_with("https://"))
+                    && !origin.ends_with('/')
+                    && origin.parse::<hyper::header::HeaderValue>().is_ok());
+            if !valid {
+                errors.push(format!(
+                    "server.allowed_origins: `{}` is not `*` or an origin like http://localhost:8080",
+                    origin
+                ));
+            }
+        }
+
+        for (key, user) in &self.auth.api_keys {
+            if key.is_empty() || key.chars().any(|c| c.is_whitespace() || c.is_control()) {
+                errors.push(
+                    "auth.api_keys: keys must be non-empty and have no whitespace".to_string(),
+                );
+            }
+            if crate::validation::user("user", user).ok().as_deref() != Some(user.as_str()) {
+                errors.push(format!("auth.api_keys: `{}` is not a user name", user));
+            }
+        }
+        for moderator in &self.auth.moderators {
+            if !self.auth.api_keys.values().any(|user| user == moderator) {
+                errors.push(format!(
+                    "auth.moderators: `{}` has no API key to sign in with",
+                    moderator
+                ));
+            }
+        }
+
+        if self.storage.backend == StorageBackend::File && self.storage.path.is_none() {
+            errors.push("storage.path: required by the file backend".to_string());
+        }
+
+        if self.attachments.max_bytes == 0 {
+            errors.push("attachments.max_bytes: must be at least 1".to_string());
+        }
+        for allowed in &self.attachments.allowed_types {
+            if allowed.parse::<mime_guess::mime::Mime>().is_err() {
+                errors.push(format!(
+                    "attachments.allowed_types: `{}` is not a MIME type like `image/png` or `image/*`",
+                    allowed
+                ));
+            }
+        }
+        if self.attachments.thumbnail_size == 0 {
+            errors.push("attachments.thumbnail_size: must be at least 1".to_string());
+        }
+
+        if self.limits.event_buffer == 0 {
+            errors.push("limits.event_buffer: must be at least 1".to_string());
+        }
+        if self.limits.feed_size == 0 {
+            errors.push("limits.feed_size: must be at least 1".to_string());
+        }
+        if self.limits.max_body_bytes == 0 {
+            errors.push("limits.max_body_bytes: must be at least 1".to_string());
+        }
+        let content = self.limits.content;
+        if content.max_chars == 0 || content.max_chars < content.min_chars {
+            errors.push(
+                "limits.content.max_chars: must be at least 1 and at least min_chars".to_string(),
+            );
+        }
+        if content.max_lines == 0 {
+            errors.push("limits.content.max_lines: must be at least 1".to_string());
+        }
+
+        for pattern in &self.filters.blocked_words.patterns {
+            if !pattern
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '*' || c == '?')
+                || !pattern.chars().any(char::is_alphanumeric)
+            {
+                errors.push(format!(
+                    "filters.blocked_words.patterns: `{}` is not a word, with `*` and `?` as wildcards",
+                    pattern
+                ));
+            }
+        }
+        let spam = &self.filters.spam;
+        for (name, score) in [
+            ("hold_score", spam.hold_score),
+            ("reject_score", spam.reject_score),
+        ] {
+            if !(0.0..=1.0).contains(&score) {
+                errors.push(format!("filters.spam.{}: must be from 0 to 1", name));
+            }
+        }
+        if spam.min_trained == 0 {
+            errors.push("filters.spam.min_trained: must be at least 1".to_string());
+        }
+
+        let known_routes = crate::route_table();
+        let known = |route: &str| known_routes.routes().iter().any(|(_, path)| path == route);
+        let mut limits = vec![
+            ("rate_limit.read".to_string(), self.rate_limit.read),
+            ("rate_limit.write".to_string(), self.rate_limit.write),
+        ];
+        for (route, route_limits) in &self.rate_limit.routes {
+            if !known(route) {
+                errors.push(format!("rate_limit.routes: `{}` is not a route", route));
+            }
+            let name = format!("rate_limit.routes.\"{}\"", route);
+            limits.extend(
+                route_limits
+                    .read
+                    .map(|limit| (format!("{}.read", name), limit)),
+            );
+            limits.extend(
+                route_limits
+                    .write
+                    .map(|limit| (format!("{}.write", name), limit)),
+            );
+        }
+        for (name, limit) in limits {
+            if limit.per_minute == 0 || limit.burst == 0 {
+                errors.push(format!("{}: per_minute and burst must be at least 1", name));
+            }
+        }
+        for route in &self.rate_limit.exempt_routes {
+            if !known(route) {
+                errors.push(format!(
+                    "rate_limit.exempt_routes: `{}` is not a route",
+                    route
+                ));
+            }
+        }
+
+        if tracing_subscriber::EnvFilter::try_new(&self.logging.level).is_err() {
+            errors.push(format!(
+                "logging.level: `{}` is not a log filter like `info` or `post_server=debug`",
+                self.logging.level
+            ));
+        }
+        for (module, level) in &self.logging.modules {
+            if level
+                .parse::<tracing::level_filters::LevelFilter>()
+                .is_err()
+            {
+                errors.push(format!(
+                    "logging.modules.{}: `{}` is not a level like `debug` or `off`",
+                    module, level
+                ));
+            }
+        }
+
+        errors
+    }
+
+    /// the address to listen on; only valid after [`Config::validate`]
+    pub fn bind_addr(&self) -> SocketAddr {
+        self.server.bind.parse().expect("validated bind address")
+    }
+
+    /// the url absolute links start with, without a trailing `/`
+    pub fn public_url(&self) -> &str {
+        self.server.public_url.trim_end_matches('/')
+    }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.shutdown_timeout_secs)
+    }
+}
+
+/// overwrite `setting` with `value`, parsed the way the TOML file would be
+fn set_parsed<T>(setting: &mut T, source: &str, value: &str, errors: &mut Vec<String>)
+where
+    T: FromStr,
+{
+    match value.parse() {
+        Ok(value) => *setting = value,
+        Err(_) => errors.push(format!("{}: `{}` is not a valid value", source, value)),
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(StorageBackend::Memory),
+            "file" => Ok(StorageBackend::File),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn defaults_match_the_old_hard_coded_values() {
+        let config = Config::load(&Flags::default(), env(&[])).unwrap();
+        assert_eq!(
+            "127.0.0.1:3000".parse::<SocketAddr>().unwrap(),
+            config.bind_addr()
+        );
+        assert_eq!(vec!["http://localhost:8080"], config.server.allowed_origins);
+        assert_eq!(StorageBackend::Memory, config.storage.backend);
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let dir = std::env::temp_dir().join(format!("post-server-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(
+            &path,
+            "[server]\nbind = \"0.0.0.0:4000\"\n[limits]\nfeed_size = 5\n[logging]\nlevel = \"warn\"\n",
+        )
+        .unwrap();
+
+        let flags = Flags {
+            config: Some(path),
+            log_level: Some("debug".to_string()),
+            ..Default::default()
+        };
+        let config = Config::load(
+            &flags,
+            env(&[
+                ("POST_SERVER_FEED_SIZE", "7"),
+                ("POST_SERVER_REPORT_THRESHOLD", "0"),
+                ("POST_SERVER_LOG_LEVEL", "error"),
+                (
+                    "POST_SERVER_ALLOWED_ORIGINS",
+                    "https://a.example, https://b.example",
+                ),
+            ]),
+        )
+        .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // file
+        assert_eq!("0.0.0.0:4000", config.server.bind);
+        // env over file
+        assert_eq!(7, config.limits.feed_size);
+        assert_eq!(0, config.moderation.report_threshold);
+        assert_eq!(
+            vec!["https://a.example", "https://b.example"],
+            config.server.allowed_origins
+        );
+        // flags over env
+        assert_eq!("debug", config.logging.level);
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let flags = Flags {
+            bind: Some("localhost".to_string()),
+            public_url: Some("posts.example".to_string()),
+            storage: Some("file".to_string()),
+            allowed_origins: vec!["localhost:8080".to_string()],
+            ..Default::default()
+        };
+        let err = Config::load(
+            &flags,
+            env(&[
+                ("POST_SERVER_EVENT_BUFFER", "lots"),
+                ("POST_SERVER_LOG_FORMAT", "xml"),
+            ]),
+        )
+        .unwrap_err();
+
+        assert_eq!(6, err.errors.len(), "{}", err);
+        assert!(err.errors.iter().any(|e| e.starts_with("server.bind")));
+        assert!(err
+            .errors
+            .iter()
+            .any(|e| e.starts_with("server.public_url")));
+        assert!(err
+            .errors
+            .iter()
+            .any(|e| e.starts_with("server.allowed_origins")));
+        assert!(err.errors.iter().any(|e| e.starts_with("storage.path")));
+        assert!(err
+            .errors
+            .iter()
+            .any(|e| e.starts_with("POST_SERVER_EVENT_BUFFER")));
+        assert!(err
+            .errors
+            .iter()
+            .any(|e| e.starts_with("POST_SERVER_LOG_FORMAT")));
+    }
+
+    #[test]
+    fn printed_config_parses_back() {
+        let mut config = Config::default();
+        config.storage.backend = StorageBackend::File;
+        config.storage.path = Some(PathBuf::from("posts.json"));
+        assert_eq!(config, Config::parse(&config.to_toml()).unwrap());
+        assert!(Config::parse("[server]\nport = 3000").is_err());
+    }
+
+    #[test]
+    fn rate_limits_name_real_routes() {
+        let config = Config::parse(
+            "[rate_limit.routes.\"/addPost\"]\nwrite = { per_minute = 0, burst = 5 }\n[rate_limit.routes.\"/nope\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "rate_limit.routes: `/nope` is not a route",
+                "rate_limit.routes.\"/addPost\".write: per_minute and burst must be at least 1",
+            ],
+            config.validate()
+        );
+    }
+
+    #[test]
+    fn attachment_types_allow_wildcards() {
+        let config = Config::parse(
+            "[attachments]\nallowed_types = [\"image/*\", \"text/plain\", \"nonsense\"]\n",
+        )
+        .unwrap();
+        assert!(config.attachments.allows("image/webp"));
+        assert!(config.attachments.allows("text/plain"));
+        assert!(!config.attachments.allows("text/html"));
+        assert!(!config.attachments.allows("imagex/png"));
+        assert_eq!(
+            vec!["attachments.allowed_types: `nonsense` is not a MIME type like `image/png` or `image/*`"],
+            config.validate()
+        );
+    }
+
+    #[test]
+    fn filters_are_checked() {
+        let config = Config::parse(
+            "[filters.blocked_words]\npatterns = [\"cas*no\", \"two words\", \"**\"]\n[filters.links]\naction = \"allow\"\n[filters.spam]\nreject_score = 1.5\n",
+        )
+        .unwrap();
+        assert_eq!(FilterAction::Allow, config.filters.links.action);
+        assert_eq!(FilterAction::Reject, config.filters.duplicates.action);
+        assert_eq!(
+            vec![
+                "filters.blocked_words.patterns: `two words` is not a word, with `*` and `?` as wildcards",
+                "filters.blocked_words.patterns: `**` is not a word, with `*` and `?` as wildcards",
+                "filters.spam.reject_score: must be from 0 to 1",
+            ],
+            config.validate()
+        );
+        assert!(Config::parse("[filters.links]\naction = \"delete\"\n").is_err());
+    }
+
+    #[test]
+    fn moderators_need_api_keys() {
+        let config = Config::parse(
+            "[auth]\napi_keys = { \"k1\" = \"mod\", \"k 2\" = \"ann\", \"k3\" = \"\" }\nmoderators = [\"mod\", \"bob\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                "auth.api_keys: keys must be non-empty and have no whitespace",
+                "auth.api_keys: `` is not a user name",
+                "auth.moderators: `bob` has no API key to sign in with",
+            ],
+            config.validate()
+        );
+    }
+
+    #[test]
+    fn module_levels_join_the_filter() {
+        let config = Config::parse(
+            "[logging]\nlevel = \"warn\"\n[logging.modules]\n\"post_server::webhooks\" = \"debug\"\nhyper = \"loud\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            "warn,hyper=loud,post_server::webhooks=debug",
+            config.logging.filter()
+        );
+        assert_eq!(
+            vec!["logging.modules.hyper: `loud` is not a level like `debug` or `off`"],
+            config.validate()
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Drafts Module
+//!
+//! drafts are posts an author is still working on: only the author sees
+//! them, and they are not listed with the posts until published, either
+//! by hand or by the scheduler once their `publish_at` time comes. The
+//! author is always the signed in caller, never a name from the request.
+//! Drafts are saved with the store, so scheduled ones survive restarts
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use post_lib::{ApiError, CreateDraftRequest, DraftRequest, UpdateDraftRequest};
+use tokio::{task::JoinHandle, time::MissedTickBehavior};
+
+use crate::{audit, auth::User, config::Config, post_db::PostDb, validation};
+
+/// how often the scheduler looks for drafts that are due
+pub const SCHEDULER_INTERVAL: Duration = Duration::from_secs(1);
+
+/// who the audit log says published scheduled drafts
+pub const SCHEDULER_ACTOR: &str = "scheduler";
+
+/// where the scheduler gets the time from, so tests can set it
+pub trait Clock: Send + Sync {
+    /// seconds since the unix epoch
+    fn now(&self) -> u64;
+}
+
+/// SystemClock struct - the real time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+/// ManualClock struct - a clock that only moves when told to
+#[derive(Default)]
+pub struct ManualClock(AtomicU64);
+
+impl ManualClock {
+    pub fn new(now: u64) -> Self {
+        ManualClock(AtomicU64::new(now))
+    }
+
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        self.0.fetch_add(seconds, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// publish every draft that is due by `clock`, returning the new posts' ids
+pub fn publish_due(post_db: &Mutex<PostDb>, clock: &dyn Clock) -> Vec<u64> {
+    let published = audit::as_actor(SCHEDULER_ACTOR, || {
+        PostDb::lock(post_db).unwrap().publish_due(clock.now())
+    });
+    if !published.is_empty() {
+        tracing::info!(?published, "published scheduled drafts");
+    }
+    published
+}
+
+/// publish due drafts every `interval`, starting with any that came due
+/// while the server was down
+pub fn spawn_scheduler(
+    post_db: Arc<Mutex<PostDb>>,
+    clock: Arc<dyn Clock>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticks.tick().await;
+            publish_due(&post_db, clock.as_ref());
+        }
+    })
+}
+
+/// Save A Draft
+///
+/// a `publish_at` time has the scheduler publish the draft then
+#[utoipa::path(
+    post,
+    path = "/addDraft",
+    tag = "drafts",
+    request_body = CreateDraftRequest,
+    responses(
+        (status = 200, description = "the new draft", body = Draft),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 422, description = "the content is invalid", body = ApiError)
+    )
+)]
+pub async fn new_draft_handler(
+    User(author): User,
+    Json(payload): Json<CreateDraftRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let content = valid_content(&payload.content, &config)?;
+    let response = PostDb::lock(&post_db).unwrap().create_draft(
+        author,
+        content,
+        payload.format,
+        payload.publish_at,
+    );
+    Ok::<_, (StatusCode, Json<ApiError>)>((StatusCode::OK, Json(response.value)))
+}
+
+/// Update A Draft
+///
+/// replaces the content and publish time; leaving out `publish_at`
+/// unschedules the draft
+#[utoipa::path(
+    post,
+    path = "/updateDraft",
+    tag = "drafts",
+    request_body = UpdateDraftRequest,
+    responses(
+        (status = 200, description = "the updated draft", body = Draft),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 417, description = "the caller has no draft with that id", body = ApiError),
+        (status = 422, description = "the content is invalid", body = ApiError)
+    )
+)]
+pub async fn update_draft_handler(
+    User(author): User,
+    Json(payload): Json<UpdateDraftRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let content = valid_content(&payload.content, &config)?;
+    let response = PostDb::lock(&post_db).unwrap().update_draft(
+        payload.draft_id,
+        &author,
+        content,
+        payload.publish_at,
+    );
+    crate::response_handler(response, || draft_not_found(payload.draft_id))
+}
+
+/// Get The Caller's Drafts
+#[utoipa::path(
+    get,
+    path = "/drafts",
+    tag = "drafts",
+    responses(
+        (status = 200, description = "the caller's drafts, oldest first", body = [Draft]),
+        (status = 401, description = "not signed in", body = ApiError)
+    )
+)]
+pub async fn drafts_handler(
+    User(author): User,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let drafts = PostDb::lock(&post_db).unwrap().drafts(&author);
+    (StatusCode::OK, Json(drafts))
+}
+
+/// Publish A Draft Now
+///
+/// the content filters see the post like any other; a draft they reject
+/// is kept
+#[utoipa::path(
+    post,
+    path = "/publishDraft",
+    tag = "drafts",
+    request_body = DraftRequest,
+    responses(
+        (status = 200, description = "id of the new post", body = u64),
+        (status = 202, description = "id of the new post, held for a moderator", body = u64),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 417, description = "the caller has no draft with that id", body = ApiError),
+        (status = 422, description = "a filter rejected the content", body = ApiError)
+    )
+)]
+pub async fn publish_draft_handler(
+    User(author): User,
+    Json(payload): Json<DraftRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let response = PostDb::lock(&post_db)
+        .unwrap()
+        .publish_draft(payload.draft_id, &author);
+    match response.value {
+        Some(submission) => crate::submitted("content", submission),
+        None => Err((
+            StatusCode::EXPECTATION_FAILED,
+            crate::error_body(draft_not_found(payload.draft_id)),
+        )),
+    }
+}
+
+/// Delete A Draft
+#[utoipa::path(
+    post,
+    path = "/deleteDraft",
+    tag = "drafts",
+    request_body = DraftRequest,
+    responses(
+        (status = 200, description = "id of the deleted draft", body = u64),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 417, description = "the caller has no draft with that id", body = ApiError)
+    )
+)]
+pub async fn delete_draft_handler(
+    User(author): User,
+    Json(payload): Json<DraftRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let response = PostDb::lock(&post_db)
+        .unwrap()
+        .delete_draft(payload.draft_id, &author);
+    crate::response_handler(response, || draft_not_found(payload.draft_id))
+}
+
+fn valid_content(content: &str, config: &Config) -> Result<String, (StatusCode, Json<ApiError>)> {
+    validation::content("content", content, &config.limits.content).map_err(crate::invalid_request)
+}
+
+/// drafts of other authors are reported missing too, keeping them private
+fn draft_not_found(draft_id: u64) -> ApiError {
+    ApiError::not_found(format!("no draft with id {}", draft_id))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drafts_are_published_when_due() {
+        let post_db = Mutex::new(PostDb::new());
+        let clock = ManualClock::new(100);
+        {
+            let mut post_db = post_db.lock().unwrap();
+            post_db.create_draft(
+                "ann".to_string(),
+                "later".to_string(),
+                Default::default(),
+                Some(160),
+            );
+            post_db.create_draft(
+                "ann".to_string(),
+                "sooner".to_string(),
+                Default::default(),
+                Some(130),
+            );
+            post_db.create_draft(
+                "ann".to_string(),
+                "someday".to_string(),
+                Default::default(),
+                None,
+            );
+        }
+
+        assert!(publish_due(&post_db, &clock).is_empty());
+        clock.advance(30);
+        assert_eq!(vec![1], publish_due(&post_db, &clock));
+        clock.set(1000);
+        assert_eq!(vec![2], publish_due(&post_db, &clock));
+
+        let post_db = post_db.lock().unwrap();
+        let contents: Vec<String> = post_db
+            .get_posts()
+            .into_iter()
+            .map(|post| post.content)
+            .collect();
+        assert_eq!(vec!["sooner", "later"], contents);
+        assert_eq!(1, post_db.drafts("ann").len());
+    }
+
+    #[tokio::test]
+    async fn scheduler_follows_the_clock() {
+        let post_db = Arc::new(Mutex::new(PostDb::new()));
+        post_db.lock().unwrap().create_draft(
+            "ann".to_string(),
+            "on time".to_string(),
+            Default::default(),
+            Some(50),
+        );
+        let clock = Arc::new(ManualClock::new(0));
+        let scheduler = spawn_scheduler(post_db.clone(), clock.clone(), Duration::from_millis(10));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(post_db.lock().unwrap().posts.is_empty());
+
+        clock.set(50);
+        for _ in 0..50 {
+            if !post_db.lock().unwrap().posts.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        scheduler.abort();
+        assert_eq!("on time", post_db.lock().unwrap().posts[0].content);
+    }
+}
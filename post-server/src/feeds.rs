@@ -0,0 +1,288 @@
+//! Feeds Module
+//!
+//! Atom and RSS renderings of the most recent posts
+//!
+//! posts have no boards or tags, so only the global feeds exist
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::post_db::{Post, PostDb};
+
+/// number of posts included in a feed by default
+pub const FEED_SIZE: usize = 20;
+
+/// longest title taken from the first line of a post
+const TITLE_LENGTH: usize = 80;
+
+/// A rendered feed, ready for conditional GET handling
+pub struct Feed {
+    pub body: String,
+    pub etag: String,
+    pub last_modified: u64,
+}
+
+/// Feed implementation
+impl Feed {
+    fn new(body: String, last_modified: u64) -> Self {
+        let etag = format!(
+            "\"{}\"",
+            hex::encode(&Sha256::digest(body.as_bytes())[..16])
+        );
+        Feed {
+            body,
+            etag,
+            last_modified,
+        }
+    }
+
+    /// the `Last-Modified` header value
+    pub fn last_modified_header(&self) -> String {
+        httpdate::fmt_http_date(system_time(self.last_modified))
+    }
+
+    /// whether a request with these conditional headers already has this feed
+    ///
+    /// `If-None-Match` takes precedence over `If-Modified-Since`
+    pub fn is_fresh(&self, if_none_match: Option<&str>, if_modified_since: Option<&str>) -> bool {
+        if let Some(if_none_match) = if_none_match {
+            return if_none_match
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == self.etag);
+        }
+        match if_modified_since.and_then(|date| httpdate::parse_http_date(date).ok()) {
+            Some(since) => system_time(self.last_modified) <= since,
+            None => false,
+        }
+    }
+}
+
+/// the `size` posts that go in a feed, most recently changed first
+fn recent_posts(post_db: &PostDb, size: usize) -> Vec<Post> {
+    let mut posts = post_db.get_posts();
+    posts.sort_by(|a, b| {
+        b.updated_at
+            .cmp(&a.updated_at)
+            .then(b.post_id.cmp(&a.post_id))
+    });
+    posts.truncate(size);
+    posts
+}
+
+/// render an Atom 1.0 feed of `size` posts, with links relative to `base_url`
+pub fn atom(post_db: &PostDb, base_url: &str, size: usize) -> Feed {
+    let posts = recent_posts(post_db, size);
+    let updated = posts
+        .iter()
+        .map(|post| post.updated_at)
+        .max()
+        .unwrap_or(post_db.last_modified);
+
+    let mut body = String::new();
+    body.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    body.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    body.push_str("  <title>Posts</title>\n");
+    body.push_str(&format!(
+        "  <id>{}</id>\n",
+        escape_xml(&format!("{}/feed.atom", base_url))
+    ));
+    body.push_str(&format!(
+        "  <link rel=\"self\" href=\"{}\"/>\n",
+        escape_xml(&format!("{}/feed.atom", base_url))
+    ));
+    body.push_str(&format!("  <updated>{}</updated>\n", rfc3339(updated)));
+
+    for post in posts {
+        let link = escape_xml(&post_url(base_url, &post));
+        body.push_str("  <entry>\n");
+        body.push_str(&format!("    <id>{}</id>\n", link));
+        body.push_str(&format!(
+            "    <title>{}</title>\n",
+            escape_xml(&title(&post))
+        ));
+        body.push_str(&format!("    <link href=\"{}\"/>\n", link));
+        body.push_str(&format!(
+            "    <published>{}</published>\n",
+            rfc3339(post.created_at)
+        ));
+        body.push_str(&format!(
+            "    <updated>{}</updated>\n",
+            rfc3339(post.updated_at)
+        ));
+        body.push_str("    <author><name>anonymous</name></author>\n");
+        body.push_str(&match &post.content_html {
+            Some(html) => format!(
+                "    <content type=\"html\">{}</content>\n",
+                escape_xml(html)
+            ),
+            None => format!(
+                "    <content type=\"text\">{}</content>\n",
+                escape_xml(&post.content)
+            ),
+        });
+        body.push_str("  </entry>\n");
+    }
+    body.push_str("</feed>\n");
+
+    Feed::new(body, post_db.last_modified)
+}
+
+/// render an RSS 2.0 feed of `size` posts, with links relative to `base_url`
+pub fn rss(post_db: &PostDb, base_url: &str, size: usize) -> Feed {
+    let posts = recent_posts(post_db, size);
+
+    let mut body = String::new();
+    body.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    body.push_str("<rss version=\"2.0\">\n");
+    body.push_str("  <channel>\n");
+    body.push_str("    <title>Posts</title>\n");
+    body.push_str(&format!("    <link>{}</link>\n", escape_xml(base_url)));
+    body.push_str("    <description>Recent posts</description>\n");
+    body.push_str(&format!(
+        "    <lastBuildDate>{}</lastBuildDate>\n",
+        httpdate::fmt_http_date(system_time(post_db.last_modified))
+    ));
+
+    for post in posts {
+        let link = escape_xml(&post_url(base_url, &post));
+        body.push_str("    <item>\n");
+        body.push_str(&format!(
+            "      <title>{}</title>\n",
+            escape_xml(&title(&post))
+        ));
+        body.push_str(&format!("      <link>{}</link>\n", link));
+        body.push_str(&format!(
+            "      <guid isPermaLink=\"true\">{}</guid>\n",
+            link
+        ));
+        body.push_str(&format!(
+            "      <pubDate>{}</pubDate>\n",
+            httpdate::fmt_http_date(system_time(post.created_at))
+        ));
+        body.push_str(&format!(
+            "      <description>{}</description>\n",
+            // RSS readers treat the description as escaped HTML
+            escape_xml(post.content_html.as_ref().unwrap_or(&post.content))
+        ));
+        body.push_str("    </item>\n");
+    }
+    body.push_str("  </channel>\n");
+    body.push_str("</rss>\n");
+
+    Feed::new(body, post_db.last_modified)
+}
+
+/// the permanent url of a post, also used as its feed id
+fn post_url(base_url: &str, post: &Post) -> String {
+    format!("{}/post/{}", base_url, post.post_id)
+}
+
+/// posts have no title, so use the start of the first line
+fn title(post: &Post) -> String {
+    let first_line = post.content.lines().next().unwrap_or_default().trim();
+    if first_line.chars().count() > TITLE_LENGTH {
+        let truncated: String = first_line.chars().take(TITLE_LENGTH).collect();
+        format!("{}…", truncated.trim_end())
+    } else {
+        first_line.to_string()
+    }
+}
+
+fn system_time(seconds: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+fn rfc3339(seconds: u64) -> String {
+    humantime::format_rfc3339_seconds(system_time(seconds)).to_string()
+}
+
+/// escape text for use in xml content and attributes, dropping
+/// characters xml 1.0 does not allow at all
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if (c as u32) < 0x20 || c == '\u{FFFE}' || c == '\u{FFFF}' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::post_db::ContentFormat;
+
+    #[test]
+    fn escapes_markup_and_control_characters() {
+        assert_eq!(
+            "&lt;b&gt;&quot;fish&quot; &amp; &apos;chips&apos;&lt;/b&gt;",
+            escape_xml("<b>\"fish\" & 'chips'</b>\u{0}")
+        );
+    }
+
+    #[test]
+    fn feeds_contain_escaped_posts() {
+        let mut db = PostDb::new();
+        db.create_post("first post".to_string());
+        db.create_post("<script>alert(1)</script>".to_string());
+
+        let feed = atom(&db, "http://localhost:3000", FEED_SIZE);
+        assert!(feed.body.contains("<id>http://localhost:3000/post/1</id>"));
+        assert!(feed.body.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+        assert!(!feed.body.contains("<script>"));
+
+        let feed = rss(&db, "http://localhost:3000", FEED_SIZE);
+        assert_eq!(2, feed.body.matches("<item>").count());
+        assert!(!feed.body.contains("<script>"));
+    }
+
+    #[test]
+    fn markdown_posts_carry_their_html() {
+        let mut db = PostDb::new();
+        db.create_post_as("*hi*".to_string(), ContentFormat::Markdown);
+
+        let feed = atom(&db, "http://localhost:3000", FEED_SIZE);
+        assert!(feed.body.contains(
+            "<content type=\"html\">&lt;p&gt;&lt;em&gt;hi&lt;/em&gt;&lt;/p&gt;\n</content>"
+        ));
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let mut db = PostDb::new();
+        db.create_post("first post".to_string());
+        let feed = atom(&db, "http://localhost:3000", FEED_SIZE);
+
+        assert!(!feed.is_fresh(None, None));
+        assert!(feed.is_fresh(Some(&feed.etag), None));
+        assert!(feed.is_fresh(Some(&format!("W/{}", feed.etag)), None));
+        assert!(!feed.is_fresh(Some("\"stale\""), Some(&feed.last_modified_header())));
+        assert!(feed.is_fresh(None, Some(&feed.last_modified_header())));
+        assert!(!feed.is_fresh(None, Some("Thu, 01 Jan 1970 00:00:00 GMT")));
+
+        db.create_post("second post".to_string());
+        let changed = atom(&db, "http://localhost:3000", FEED_SIZE);
+        assert!(!changed.is_fresh(Some(&feed.etag), None));
+    }
+
+    #[test]
+    fn titles_use_the_first_line() {
+        let mut db = PostDb::new();
+        db.create_post(format!("{}\nsecond line", "a".repeat(100)));
+        let post = db.get_posts().remove(0);
+
+        let title = title(&post);
+        assert_eq!(TITLE_LENGTH + 1, title.chars().count());
+        assert!(!title.contains("second line"));
+    }
+}
@@ -0,0 +1,514 @@
+//! Filters Module
+//!
+//! content filters that see every new post, and every edit, before it is
+//! stored, each letting it through, holding it for a moderator or
+//! rejecting it. The
+//! built-in filters are configured under `[filters]`: blocked words,
+//! too many links, the same content posted again, and a naive Bayes spam
+//! score learned from what moderators hide, delete and dismiss
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use post_lib::FieldError;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{FilterAction, FiltersConfig},
+    post_db::Post,
+};
+
+/// what a filter makes of a new post
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Allow,
+    /// store the post hidden, waiting in the moderation queue
+    Hold(String),
+    /// refuse the post, saying why
+    Reject(String),
+}
+
+/// a new post as the filters see it
+pub struct Candidate<'a> {
+    pub content: &'a str,
+    /// seconds since the unix epoch
+    pub now: u64,
+    /// every stored post, hidden ones included
+    pub posts: &'a [Post],
+    pub spam: &'a SpamModel,
+}
+
+/// a check run on every new post
+pub trait ContentFilter: Send + Sync {
+    /// short name, used as the error code when the filter rejects a post
+    fn name(&self) -> &'static str;
+
+    fn check(&self, candidate: &Candidate<'_>) -> Verdict;
+}
+
+/// Flag struct - which filter stopped a post, and why
+#[derive(Debug, Clone, PartialEq)]
+pub struct Flag {
+    pub filter: &'static str,
+    pub reason: String,
+}
+
+/// Flag implementation
+impl Flag {
+    /// the flag as a validation problem with `field`
+    pub fn field_error(&self, field: &str) -> FieldError {
+        FieldError {
+            field: field.to_string(),
+            code: self.filter.to_string(),
+            message: self.reason.clone(),
+        }
+    }
+}
+
+/// what the whole chain makes of a new post
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decision {
+    Allow,
+    Hold(Flag),
+    Reject(Flag),
+}
+
+/// Filters struct - the chain of filters and the spam model they share
+#[derive(Default)]
+pub struct Filters {
+    chain: Vec<Box<dyn ContentFilter>>,
+    spam: SpamModel,
+}
+
+/// Filters implementation
+impl Filters {
+    /// replace the chain, keeping what the spam model has learned
+    pub fn set_chain(&mut self, chain: Vec<Box<dyn ContentFilter>>) {
+        self.chain = chain;
+    }
+
+    /// run every filter over `content`; any rejection wins, otherwise the
+    /// first filter holding the post does
+    pub fn check(&self, content: &str, now: u64, posts: &[Post]) -> Decision {
+        let candidate = Candidate {
+            content,
+            now,
+            posts,
+            spam: &self.spam,
+        };
+        let mut decision = Decision::Allow;
+        for filter in &self.chain {
+            let flag = |reason| Flag {
+                filter: filter.name(),
+                reason,
+            };
+            match filter.check(&candidate) {
+                Verdict::Allow => {}
+                Verdict::Hold(reason) => {
+                    if decision == Decision::Allow {
+                        decision = Decision::Hold(flag(reason));
+                    }
+                }
+                Verdict::Reject(reason) => return Decision::Reject(flag(reason)),
+            }
+        }
+        decision
+    }
+
+    /// teach the spam model that a moderator found `content` to be spam or not
+    pub fn train(&mut self, content: &str, spam: bool) {
+        self.spam.train(content, spam);
+    }
+
+    pub fn spam_model(&self) -> &SpamModel {
+        &self.spam
+    }
+
+    /// replace the spam model with a saved one
+    pub fn restore_spam_model(&mut self, spam: SpamModel) {
+        self.spam = spam;
+    }
+}
+
+/// the filters `config` asks for, in the order they run; filters whose
+/// action is `allow` are left out
+pub fn chain(config: &FiltersConfig) -> Vec<Box<dyn ContentFilter>> {
+    let mut chain: Vec<Box<dyn ContentFilter>> = vec![];
+    let blocked = &config.blocked_words;
+    if blocked.action != FilterAction::Allow && !blocked.patterns.is_empty() {
+        chain.push(Box::new(BlockedWords::new(
+            &blocked.patterns,
+            blocked.action,
+        )));
+    }
+    if config.links.action != FilterAction::Allow {
+        chain.push(Box::new(LinkLimit {
+            max: config.links.max,
+            action: config.links.action,
+        }));
+    }
+    if config.duplicates.action != FilterAction::Allow && config.duplicates.window_secs > 0 {
+        chain.push(Box::new(Duplicates {
+            window_secs: config.duplicates.window_secs,
+            action: config.duplicates.action,
+        }));
+    }
+    chain.push(Box::new(SpamScore {
+        hold_score: config.spam.hold_score,
+        reject_score: config.spam.reject_score,
+        min_trained: config.spam.min_trained,
+    }));
+    chain
+}
+
+/// the verdict `action` calls for
+fn verdict(action: FilterAction, reason: String) -> Verdict {
+    match action {
+        FilterAction::Allow => Verdict::Allow,
+        FilterAction::Hold => Verdict::Hold(reason),
+        FilterAction::Reject => Verdict::Reject(reason),
+    }
+}
+
+/// the lowercased words of `content`: runs of letters and digits
+fn words(content: &str) -> impl Iterator<Item = String> + '_ {
+    content
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+/// BlockedWords struct - words no post may contain, as wildcard patterns
+/// where `*` stands for any run of characters and `?` for exactly one
+pub struct BlockedWords {
+    patterns: Vec<Vec<char>>,
+    action: FilterAction,
+}
+
+/// BlockedWords implementation
+impl BlockedWords {
+    pub fn new(patterns: &[String], action: FilterAction) -> Self {
+        BlockedWords {
+            patterns: patterns
+                .iter()
+                .map(|pattern| pattern.to_lowercase().chars().collect())
+                .collect(),
+            action,
+        }
+    }
+}
+
+impl ContentFilter for BlockedWords {
+    fn name(&self) -> &'static str {
+        "blocked_word"
+    }
+
+    fn check(&self, candidate: &Candidate<'_>) -> Verdict {
+        for word in words(candidate.content) {
+            let chars: Vec<char> = word.chars().collect();
+            if self
+                .patterns
+                .iter()
+                .any(|pattern| wildcard_match(pattern, &chars))
+            {
+                return verdict(self.action, format!("must not contain `{}`", word));
+            }
+        }
+        Verdict::Allow
+    }
+}
+
+/// whether `word` matches `pattern`, where `*` stands for any run of
+/// characters and `?` for exactly one
+fn wildcard_match(pattern: &[char], word: &[char]) -> bool {
+    let (mut p, mut w) = (0, 0);
+    // where the last `*` was, and how much of the word it has taken so far
+    let mut star: Option<(usize, usize)> = None;
+    while w < word.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, w));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == word[w] => {
+                p += 1;
+                w += 1;
+            }
+            _ => match star {
+                Some((star_p, star_w)) => {
+                    star = Some((star_p, star_w + 1));
+                    p = star_p + 1;
+                    w = star_w + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// LinkLimit struct - at most `max` links per post
+pub struct LinkLimit {
+    pub max: usize,
+    pub action: FilterAction,
+}
+
+impl ContentFilter for LinkLimit {
+    fn name(&self) -> &'static str {
+        "too_many_links"
+    }
+
+    fn check(&self, candidate: &Candidate<'_>) -> Verdict {
+        let links = count_links(candidate.content);
+        if links > self.max {
+            verdict(
+                self.action,
+                format!("must have at most {} links, not {}", self.max, links),
+            )
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+/// how many http(s) urls and `www.` addresses `content` holds, including
+/// markdown links and autolinks
+fn count_links(content: &str) -> usize {
+    content
+        .to_lowercase()
+        .split(|c: char| c.is_whitespace() || "()<>[]\"'".contains(c))
+        .filter(|word| {
+            word.starts_with("http://") || word.starts_with("https://") || word.starts_with("www.")
+        })
+        .count()
+}
+
+/// Duplicates struct - no post may repeat one made in the last `window_secs`
+pub struct Duplicates {
+    pub window_secs: u64,
+    pub action: FilterAction,
+}
+
+impl ContentFilter for Duplicates {
+    fn name(&self) -> &'static str {
+        "duplicate"
+    }
+
+    fn check(&self, candidate: &Candidate<'_>) -> Verdict {
+        let content = normalize(candidate.content);
+        let repeated = candidate.posts.iter().find(|post| {
+            candidate.now.saturating_sub(post.created_at) < self.window_secs
+                && normalize(&post.content) == content
+        });
+        match repeated {
+            Some(post) => verdict(
+                self.action,
+                format!(
+                    "repeats post {} from the last {}s",
+                    post.post_id, self.window_secs
+                ),
+            ),
+            None => Verdict::Allow,
+        }
+    }
+}
+
+/// content compared ignoring case and how whitespace is laid out
+fn normalize(content: &str) -> String {
+    content
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// SpamScore struct - holds or rejects posts the spam model is sure enough
+/// about, once it has learned from `min_trained` posts of each kind
+pub struct SpamScore {
+    pub hold_score: f64,
+    pub reject_score: f64,
+    pub min_trained: u64,
+}
+
+impl ContentFilter for SpamScore {
+    fn name(&self) -> &'static str {
+        "spam"
+    }
+
+    fn check(&self, candidate: &Candidate<'_>) -> Verdict {
+        let spam = candidate.spam;
+        if spam.spam_posts < self.min_trained || spam.ham_posts < self.min_trained {
+            return Verdict::Allow;
+        }
+        let Some(score) = spam.score(candidate.content) else {
+            return Verdict::Allow;
+        };
+        let reason = format!("looks like spam, scoring {:.2}", score);
+        if score > self.reject_score {
+            Verdict::Reject(reason)
+        } else if score > self.hold_score {
+            Verdict::Hold(reason)
+        } else {
+            Verdict::Allow
+        }
+    }
+}
+
+/// SpamModel struct - in how many spam and other posts each word was seen
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct SpamModel {
+    pub spam_posts: u64,
+    pub ham_posts: u64,
+    pub spam_words: BTreeMap<String, u64>,
+    pub ham_words: BTreeMap<String, u64>,
+}
+
+/// SpamModel implementation
+impl SpamModel {
+    /// whether nothing has been learned yet
+    pub fn is_empty(&self) -> bool {
+        self.spam_posts == 0 && self.ham_posts == 0
+    }
+
+    /// count the words of `content` as seen in one more spam or other post
+    pub fn train(&mut self, content: &str, spam: bool) {
+        let (posts, counts) = if spam {
+            (&mut self.spam_posts, &mut self.spam_words)
+        } else {
+            (&mut self.ham_posts, &mut self.ham_words)
+        };
+        *posts += 1;
+        for word in words(content).collect::<BTreeSet<_>>() {
+            *counts.entry(word).or_default() += 1;
+        }
+    }
+
+    /// how likely `content` is spam, from 0 to 1, going by the words it
+    /// shares with posts learned from; `None` until both kinds were learned
+    pub fn score(&self, content: &str) -> Option<f64> {
+        if self.spam_posts == 0 || self.ham_posts == 0 {
+            return None;
+        }
+        let (spam_posts, ham_posts) = (self.spam_posts as f64, self.ham_posts as f64);
+        let mut log_odds = (spam_posts / ham_posts).ln();
+        for word in words(content).collect::<BTreeSet<_>>() {
+            let spam = self.spam_words.get(&word).copied().unwrap_or_default();
+            let ham = self.ham_words.get(&word).copied().unwrap_or_default();
+            // words never seen say nothing either way
+            if spam + ham == 0 {
+                continue;
+            }
+            // add-one smoothing, so one unseen side doesn't decide alone
+            let in_spam = (spam as f64 + 1.0) / (spam_posts + 2.0);
+            let in_ham = (ham as f64 + 1.0) / (ham_posts + 2.0);
+            log_odds += (in_spam / in_ham).ln();
+        }
+        Some(1.0 / (1.0 + (-log_odds).exp()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::FiltersConfig;
+
+    fn post(post_id: u64, content: &str, created_at: u64) -> Post {
+        Post {
+            post_id,
+            content: content.to_string(),
+            created_at,
+            ..Default::default()
+        }
+    }
+
+    fn check(filters: &Filters, content: &str, posts: &[Post]) -> Decision {
+        filters.check(content, 1000, posts)
+    }
+
+    #[test]
+    fn wildcards_match_whole_words() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        assert!(wildcard_match(&chars("cas*no"), &chars("casino")));
+        assert!(wildcard_match(&chars("cas*no"), &chars("casno")));
+        assert!(wildcard_match(&chars("v?agra"), &chars("v1agra")));
+        assert!(wildcard_match(&chars("*coin"), &chars("bitcoin")));
+        assert!(wildcard_match(&chars("a*b*c"), &chars("aXbYbZc")));
+        assert!(!wildcard_match(&chars("*coin"), &chars("coins")));
+        assert!(!wildcard_match(&chars("v?agra"), &chars("vagra")));
+        assert!(!wildcard_match(&chars("spam"), &chars("spammer")));
+    }
+
+    #[test]
+    fn blocked_words_links_and_duplicates() {
+        let mut config = FiltersConfig::default();
+        config.blocked_words.patterns = vec!["Cas*no".to_string()];
+        config.links.max = 1;
+        let mut filters = Filters::default();
+        filters.set_chain(chain(&config));
+
+        assert_eq!(Decision::Allow, check(&filters, "a casual post", &[]));
+        assert_eq!(
+            Decision::Reject(Flag {
+                filter: "blocked_word",
+                reason: "must not contain `casino`".to_string()
+            }),
+            check(&filters, "best CASINO in town", &[])
+        );
+
+        let links = "see [this](https://a.example) and www.b.example";
+        assert_eq!(2, count_links(links));
+        let Decision::Hold(flag) = check(&filters, links, &[]) else {
+            panic!("two links should be held");
+        };
+        assert_eq!("too_many_links", flag.filter);
+
+        let posts = [post(1, "Hello   World", 990), post(2, "old news", 100)];
+        let Decision::Reject(flag) = check(&filters, "hello world", &posts) else {
+            panic!("a repeat should be rejected");
+        };
+        assert_eq!("repeats post 1 from the last 60s", flag.reason);
+        assert_eq!(Decision::Allow, check(&filters, "old news", &posts));
+
+        // a rejection wins over an earlier hold
+        let Decision::Reject(flag) = check(&filters, links, &[post(3, links, 995)]) else {
+            panic!("a repeat should be rejected");
+        };
+        assert_eq!("duplicate", flag.filter);
+    }
+
+    #[test]
+    fn spam_score_learns_from_moderators() {
+        let mut config = FiltersConfig::default();
+        config.spam.min_trained = 2;
+        config.spam.reject_score = 0.99;
+        let mut filters = Filters::default();
+        filters.set_chain(chain(&config));
+
+        filters.train("buy cheap watches now", true);
+        assert_eq!(
+            Decision::Allow,
+            check(&filters, "buy cheap watches now", &[])
+        );
+        filters.train("cheap pills, buy now", true);
+        filters.train("lunch at noon?", false);
+        filters.train("the build is green again", false);
+
+        let Decision::Hold(flag) = check(&filters, "cheap watches, buy", &[]) else {
+            panic!("spam should be held");
+        };
+        assert_eq!("spam", flag.filter);
+        assert_eq!(Decision::Allow, check(&filters, "lunch is green", &[]));
+        assert_eq!(Decision::Allow, check(&filters, "nothing seen before", &[]));
+
+        let model = filters.spam_model().clone();
+        assert_eq!((2, 2), (model.spam_posts, model.ham_posts));
+        assert_eq!(Some(&2), model.spam_words.get("cheap"));
+        let mut restored = Filters::default();
+        restored.set_chain(chain(&config));
+        restored.restore_spam_model(model);
+        assert_eq!(
+            check(&filters, "cheap watches, buy", &[]),
+            check(&restored, "cheap watches, buy", &[])
+        );
+    }
+}
@@ -0,0 +1,594 @@
+//! GraphQL Module
+//!
+//! a GraphQL schema over the same PostDb the REST handlers use, with
+//! queries, mutations mirroring the REST writes and subscriptions to
+//! post change events and notifications. Requests carry the signed in
+//! [`Caller`], if any, as context data: over HTTP from the
+//! `Authorization` header, over websockets from the upgrade request or
+//! the `Authorization` field of the `connection_init` payload. Posts have no
+//! replies, authors or tags in the store, so the schema has none either
+
+use std::sync::{Arc, Mutex};
+
+use async_graphql::{
+    http::{graphiql_source, WsMessage, ALL_WEBSOCKET_PROTOCOLS},
+    Context, Data, Error, ErrorExtensions, InputObject, Object, Result, Schema, SimpleObject,
+    Subscription,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse, SecWebsocketProtocol};
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Extension, TypedHeader,
+    },
+    http::HeaderMap,
+    response::{Html, IntoResponse},
+};
+use futures::{SinkExt, Stream, StreamExt};
+use post_lib::{FieldError, NewPoll};
+use tokio_stream::wrappers::BroadcastStream;
+
+use crate::{
+    auth::{Auth, Caller},
+    config::ContentLimits,
+    metrics::METRICS,
+    post_db::{
+        self, ContentFormat, Draft, Edit, Notification, Post, PostDb, PostEvent, PostEventKind,
+        Reaction, Submission,
+    },
+    shutdown::{Shutdown, SHUTDOWN_REASON},
+    validation,
+};
+
+/// largest page `posts` will return
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// The GraphQL schema type
+pub type PostSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// build the schema, backed by the given PostDb
+pub fn schema(post_db: Arc<Mutex<PostDb>>, content_limits: ContentLimits) -> PostSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(post_db)
+        .data(content_limits)
+        .finish()
+}
+
+/// normalize and check new content like the REST handlers do
+fn valid_content(ctx: &Context<'_>, content: &str) -> Result<String> {
+    validation::content("content", content, ctx.data_unchecked::<ContentLimits>()).map_err(
+        |fields| {
+            let problems: Vec<String> = fields
+                .iter()
+                .map(|field| format!("{}: {}", field.field, field.message))
+                .collect();
+            Error::new(problems.join("; ")).extend_with(|_, e| e.set("code", "invalid"))
+        },
+    )
+}
+
+/// check a reaction's emoji like the REST handlers do
+fn valid_emoji(emoji: &str) -> Result<String> {
+    validation::reaction(emoji).map_err(invalid)
+}
+
+fn invalid(fields: Vec<FieldError>) -> Error {
+    let problems: Vec<String> = fields
+        .iter()
+        .map(|field| format!("{}: {}", field.field, field.message))
+        .collect();
+    Error::new(problems.join("; ")).extend_with(|_, e| e.set("code", "invalid"))
+}
+
+/// the signed in caller, like the REST handlers' [`crate::auth::User`]
+fn caller<'a>(ctx: &Context<'a>) -> Result<&'a Caller> {
+    ctx.data_opt::<Caller>().ok_or_else(|| {
+        Error::new("sign in with an `Authorization: Bearer` API key")
+            .extend_with(|_, e| e.set("code", "unauthorized"))
+    })
+}
+
+/// the signed in caller, if a moderator, like the REST handlers'
+/// [`crate::auth::Moderator`]
+fn moderator<'a>(ctx: &Context<'a>) -> Result<&'a Caller> {
+    let caller = caller(ctx)?;
+    if !caller.moderator {
+        return Err(Error::new("only moderators may do this")
+            .extend_with(|_, e| e.set("code", "forbidden")));
+    }
+    Ok(caller)
+}
+
+fn post_db<'a>(ctx: &Context<'a>) -> &'a Arc<Mutex<PostDb>> {
+    ctx.data_unchecked::<Arc<Mutex<PostDb>>>()
+}
+
+/// filters for the `posts` query
+#[derive(InputObject, Default)]
+pub struct PostFilter {
+    /// only posts whose content contains this text, ignoring case
+    pub contains: Option<String>,
+    /// only posts changed at or after this time, in seconds since the unix epoch
+    pub updated_since: Option<u64>,
+}
+
+impl PostFilter {
+    fn matches(&self, post: &Post) -> bool {
+        let contains = self
+            .contains
+            .as_ref()
+            .is_none_or(|text| post.content.to_lowercase().contains(&text.to_lowercase()));
+        let updated_since = self
+            .updated_since
+            .is_none_or(|since| post.updated_at >= since);
+        contains && updated_since
+    }
+}
+
+/// One page of posts
+#[derive(SimpleObject)]
+pub struct PostPage {
+    /// posts matching the filter, before paging
+    pub total_count: usize,
+    pub has_next_page: bool,
+    pub items: Vec<Post>,
+}
+
+/// Query root
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// a single post by id
+    async fn post(&self, ctx: &Context<'_>, post_id: u64) -> Option<Post> {
+        PostDb::lock(post_db(ctx)).unwrap().get_post(post_id).value
+    }
+
+    /// who reacted to a post and with what, ordered by emoji then user
+    async fn reactions(&self, ctx: &Context<'_>, post_id: u64) -> Result<Vec<Reaction>> {
+        PostDb::lock(post_db(ctx))
+            .unwrap()
+            .reactions(post_id)
+            .value
+            .ok_or_else(|| not_found(post_id))
+    }
+
+    /// the caller's notifications, newest first, like `GET /notifications`
+    async fn notifications(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] unread_only: bool,
+    ) -> Result<Vec<Notification>> {
+        let user = &caller(ctx)?.user;
+        Ok(PostDb::lock(post_db(ctx))
+            .unwrap()
+            .notifications(user, unread_only))
+    }
+
+    /// the signed in caller's drafts, oldest first, like `GET /drafts`
+    async fn drafts(&self, ctx: &Context<'_>) -> Result<Vec<Draft>> {
+        let author = &caller(ctx)?.user;
+        Ok(PostDb::lock(post_db(ctx)).unwrap().drafts(author))
+    }
+
+    /// a page of posts, oldest first
+    async fn posts(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] offset: usize,
+        #[graphql(default = 20)] limit: usize,
+        filter: Option<PostFilter>,
+    ) -> PostPage {
+        let filter = filter.unwrap_or_default();
+        let matching: Vec<Post> = PostDb::lock(post_db(ctx))
+            .unwrap()
+            .get_posts()
+            .into_iter()
+            .filter(|post| filter.matches(post))
+            .collect();
+
+        let total_count = matching.len();
+        let items: Vec<Post> = matching
+            .into_iter()
+            .skip(offset)
+            .take(limit.min(MAX_PAGE_SIZE))
+            .collect();
+        PostPage {
+            total_count,
+            has_next_page: offset + items.len() < total_count,
+            items,
+        }
+    }
+}
+
+/// Mutation root
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// create a post, like `POST /addPost`, in plain text unless `format`
+    /// says otherwise, asking `poll` if given; a post the content filters
+    /// hold comes back `hidden`
+    async fn create_post(
+        &self,
+        ctx: &Context<'_>,
+        content: String,
+        format: Option<ContentFormat>,
+        poll: Option<NewPoll>,
+    ) -> Result<Post> {
+        let content = valid_content(ctx, &content)?;
+        let poll = poll
+            .map(|poll| validation::poll(&poll, post_db::now()).map_err(invalid))
+            .transpose()?;
+        let mut post_db = PostDb::lock(post_db(ctx)).unwrap();
+        let submission = post_db
+            .create_post_with_poll(content, format.unwrap_or_default(), poll)
+            .value;
+        submitted(&post_db, submission)
+    }
+
+    /// replace the content of a post, like `POST /updatePost`; the content
+    /// filters see the new content, and a held edit returns the post hidden
+    async fn update_post(&self, ctx: &Context<'_>, post_id: u64, content: String) -> Result<Post> {
+        let content = valid_content(ctx, &content)?;
+        let mut post_db = PostDb::lock(post_db(ctx)).unwrap();
+        match post_db.update_post(post_id, content).value {
+            Edit::Updated(_) | Edit::Held(_) => post_db
+                .posts
+                .iter()
+                .find(|post| post.post_id == post_id)
+                .cloned()
+                .ok_or_else(|| not_found(post_id)),
+            Edit::Rejected(flag) => Err(invalid(vec![flag.field_error("content")])),
+            Edit::Missing => Err(not_found(post_id)),
+            Edit::Locked => Err(Error::new(format!("post {} is locked", post_id))
+                .extend_with(|_, e| e.set("code", "locked"))),
+        }
+    }
+
+    /// save a draft, like `POST /addDraft`
+    async fn create_draft(
+        &self,
+        ctx: &Context<'_>,
+        content: String,
+        format: Option<ContentFormat>,
+        publish_at: Option<u64>,
+    ) -> Result<Draft> {
+        let author = caller(ctx)?.user.clone();
+        let content = valid_content(ctx, &content)?;
+        Ok(PostDb::lock(post_db(ctx))
+            .unwrap()
+            .create_draft(author, content, format.unwrap_or_default(), publish_at)
+            .value)
+    }
+
+    /// replace a draft's content and publish time, like `POST /updateDraft`
+    async fn update_draft(
+        &self,
+        ctx: &Context<'_>,
+        draft_id: u64,
+        content: String,
+        publish_at: Option<u64>,
+    ) -> Result<Draft> {
+        let author = &caller(ctx)?.user;
+        let content = valid_content(ctx, &content)?;
+        PostDb::lock(post_db(ctx))
+            .unwrap()
+            .update_draft(draft_id, author, content, publish_at)
+            .value
+            .ok_or_else(|| draft_not_found(draft_id))
+    }
+
+    /// publish a draft now, like `POST /publishDraft`, returning the new
+    /// post; a draft the content filters reject is kept
+    async fn publish_draft(&self, ctx: &Context<'_>, draft_id: u64) -> Result<Post> {
+        let author = &caller(ctx)?.user;
+        let mut post_db = PostDb::lock(post_db(ctx)).unwrap();
+        let submission = post_db
+            .publish_draft(draft_id, author)
+            .value
+            .ok_or_else(|| draft_not_found(draft_id))?;
+        submitted(&post_db, submission)
+    }
+
+    /// delete a draft, like `POST /deleteDraft`, returning its id
+    async fn delete_draft(&self, ctx: &Context<'_>, draft_id: u64) -> Result<u64> {
+        let author = &caller(ctx)?.user;
+        PostDb::lock(post_db(ctx))
+            .unwrap()
+            .delete_draft(draft_id, author)
+            .value
+            .ok_or_else(|| draft_not_found(draft_id))
+    }
+
+    /// pin or lock a post, like `POST /admin/updatePostFlags`; only
+    /// moderators may
+    async fn update_post_flags(
+        &self,
+        ctx: &Context<'_>,
+        post_id: u64,
+        pinned: Option<bool>,
+        locked: Option<bool>,
+    ) -> Result<Post> {
+        moderator(ctx)?;
+        PostDb::lock(post_db(ctx))
+            .unwrap()
+            .set_post_flags(post_id, pinned, locked)
+            .value
+            .ok_or_else(|| not_found(post_id))
+    }
+
+    /// react to a post as the caller, like `POST /addReaction`
+    async fn add_reaction(&self, ctx: &Context<'_>, post_id: u64, emoji: String) -> Result<Post> {
+        let user = caller(ctx)?.user.clone();
+        let emoji = valid_emoji(&emoji)?;
+        let mut post_db = PostDb::lock(post_db(ctx)).unwrap();
+        post_db
+            .add_reaction(post_id, user, emoji)
+            .value
+            .ok_or_else(|| not_found(post_id))?;
+        post_db
+            .get_post(post_id)
+            .value
+            .ok_or_else(|| not_found(post_id))
+    }
+
+    /// take back the caller's reaction, like `POST /removeReaction`
+    async fn remove_reaction(
+        &self,
+        ctx: &Context<'_>,
+        post_id: u64,
+        emoji: String,
+    ) -> Result<Post> {
+        let user = caller(ctx)?.user.clone();
+        let emoji = valid_emoji(&emoji)?;
+        let mut post_db = PostDb::lock(post_db(ctx)).unwrap();
+        post_db
+            .remove_reaction(post_id, user, emoji)
+            .value
+            .ok_or_else(|| not_found(post_id))?;
+        post_db
+            .get_post(post_id)
+            .value
+            .ok_or_else(|| not_found(post_id))
+    }
+
+    /// vote in a post's poll as the caller, like `POST /vote`, replacing
+    /// their earlier vote; no choices takes the vote back
+    async fn vote(&self, ctx: &Context<'_>, post_id: u64, choices: Vec<u32>) -> Result<Post> {
+        let user = caller(ctx)?.user.clone();
+        let mut post_db = PostDb::lock(post_db(ctx)).unwrap();
+        let poll = post_db.poll(post_id).ok_or_else(|| no_poll(post_id))?;
+        if !poll.is_open(post_db::now()) {
+            return Err(
+                Error::new(format!("the poll on post {} is closed", post_id))
+                    .extend_with(|_, e| e.set("code", "closed")),
+            );
+        }
+        let choices = validation::vote(&poll, &choices).map_err(invalid)?;
+        post_db
+            .vote(post_id, user, choices)
+            .value
+            .ok_or_else(|| no_poll(post_id))?;
+        post_db
+            .get_post(post_id)
+            .value
+            .ok_or_else(|| not_found(post_id))
+    }
+
+    /// mark the caller's notifications read, all of them unless
+    /// `notificationIds` says which, like `POST /markNotificationsRead`,
+    /// returning how many were unread
+    async fn mark_notifications_read(
+        &self,
+        ctx: &Context<'_>,
+        notification_ids: Option<Vec<u64>>,
+    ) -> Result<u64> {
+        let user = &caller(ctx)?.user;
+        Ok(PostDb::lock(post_db(ctx))
+            .unwrap()
+            .mark_notifications_read(user, notification_ids.as_deref()))
+    }
+
+    /// delete a post, like `POST /deletePost/:id`, returning its id
+    async fn delete_post(&self, ctx: &Context<'_>, post_id: u64) -> Result<u64> {
+        PostDb::lock(post_db(ctx))
+            .unwrap()
+            .delete_post(post_id)
+            .value
+            .ok_or_else(|| not_found(post_id))
+    }
+}
+
+/// the post just created, hidden if the content filters held it, or why
+/// they rejected it
+fn submitted(post_db: &PostDb, submission: Submission) -> Result<Post> {
+    match submission {
+        Submission::Rejected(flag) => Err(invalid(vec![flag.field_error("content")])),
+        submission => {
+            let post_id = submission.post_id().unwrap_or_default();
+            post_db
+                .posts
+                .iter()
+                .find(|post| post.post_id == post_id)
+                .cloned()
+                .ok_or_else(|| Error::new("post vanished after creation"))
+        }
+    }
+}
+
+fn not_found(post_id: u64) -> Error {
+    Error::new(format!("no post with id {}", post_id))
+}
+
+fn no_poll(post_id: u64) -> Error {
+    Error::new(format!("no poll on post {}", post_id))
+}
+
+fn draft_not_found(draft_id: u64) -> Error {
+    Error::new(format!("no draft with id {}", draft_id))
+}
+
+/// Subscription root
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// live post changes, optionally only of the given kinds
+    async fn post_changes(
+        &self,
+        ctx: &Context<'_>,
+        kinds: Option<Vec<PostEventKind>>,
+    ) -> impl Stream<Item = PostEvent> {
+        let (_, receiver) = PostDb::lock(post_db(ctx)).unwrap().subscribe(None);
+        BroadcastStream::new(receiver)
+            .take_while(|event| futures::future::ready(event.is_ok()))
+            .filter_map(move |event| {
+                let event = event.ok().filter(|event| {
+                    kinds
+                        .as_ref()
+                        .is_none_or(|kinds| kinds.contains(&event.kind))
+                });
+                futures::future::ready(event)
+            })
+    }
+
+    /// the caller's new notifications, and ones marked read, as they happen
+    async fn notifications(&self, ctx: &Context<'_>) -> Result<impl Stream<Item = Notification>> {
+        let user = caller(ctx)?.user.to_lowercase();
+        let receiver = PostDb::lock(post_db(ctx))
+            .unwrap()
+            .subscribe_notifications();
+        Ok(BroadcastStream::new(receiver)
+            .take_while(|notification| futures::future::ready(notification.is_ok()))
+            .filter_map(move |notification| {
+                futures::future::ready(notification.ok().filter(|n| n.user == user))
+            }))
+    }
+}
+
+/// Execute GraphQL Queries And Mutations
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(content = String, description = "a GraphQL request", content_type = "application/json"),
+    responses((status = 200, description = "the GraphQL response", content_type = "application/json"))
+)]
+pub async fn graphql_handler(
+    headers: HeaderMap,
+    Extension(schema): Extension<PostSchema>,
+    Extension(auth): Extension<Arc<Auth>>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let mut request = request.into_inner();
+    if let Some(caller) = auth.caller(&headers) {
+        request = request.data(caller);
+    }
+    schema.execute(request).await.into()
+}
+
+/// GraphiQL Page
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    tag = "graphql",
+    responses((status = 200, description = "GraphiQL page", content_type = "text/html"))
+)]
+pub async fn graphiql_handler() -> impl IntoResponse {
+    Html(graphiql_source("/graphql", Some("/graphql/ws")))
+}
+
+/// GraphQL Subscriptions Over WebSocket
+#[utoipa::path(
+    get,
+    path = "/graphql/ws",
+    tag = "graphql",
+    responses((status = 101, description = "upgraded to a `graphql-ws` or `graphql-transport-ws` websocket"))
+)]
+pub async fn graphql_ws_handler(
+    ws: WebSocketUpgrade,
+    headers: HeaderMap,
+    Extension(schema): Extension<PostSchema>,
+    Extension(auth): Extension<Arc<Auth>>,
+    Extension(shutdown): Extension<Shutdown>,
+    TypedHeader(protocol): TypedHeader<SecWebsocketProtocol>,
+) -> impl IntoResponse {
+    let caller = auth.caller(&headers);
+    ws.protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .on_upgrade(move |socket| {
+            graphql_subscription(socket, schema, protocol, shutdown, auth, caller)
+        })
+}
+
+/// the caller named by a `connection_init` payload's `Authorization`
+/// field, since browsers cannot set headers on websockets, else the one
+/// the upgrade request signed in as
+fn subscription_caller(
+    auth: &Auth,
+    payload: &serde_json::Value,
+    upgrade_caller: Option<Caller>,
+) -> Option<Caller> {
+    let key = payload
+        .get("Authorization")
+        .or_else(|| payload.get("authorization"))
+        .and_then(|value| value.as_str())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match key {
+        Some(key) => auth.caller_with_key(key.trim()),
+        None => upgrade_caller,
+    }
+}
+
+/// run the GraphQL websocket protocol over `socket`, like
+/// `async_graphql_axum::graphql_subscription`, but closing the socket
+/// with `1001 Going Away` when the server shuts down
+async fn graphql_subscription(
+    socket: WebSocket,
+    schema: PostSchema,
+    protocol: SecWebsocketProtocol,
+    shutdown: Shutdown,
+    auth: Arc<Auth>,
+    caller: Option<Caller>,
+) {
+    let _connection = METRICS.stream_guard("graphql_ws");
+    let (mut sink, stream) = socket.split();
+    let input = stream
+        .take_while(|message| futures::future::ready(message.is_ok()))
+        .filter_map(|message| {
+            futures::future::ready(match message {
+                Ok(message @ (Message::Text(_) | Message::Binary(_))) => Some(message.into_data()),
+                _ => None,
+            })
+        });
+
+    let initializer = move |payload: serde_json::Value| {
+        let mut data = Data::default();
+        if let Some(caller) = subscription_caller(&auth, &payload, caller) {
+            data.insert(caller);
+        }
+        futures::future::ready(Ok(data))
+    };
+    let output = async_graphql::http::WebSocket::with_data(schema, input, initializer, protocol.0)
+        .map(|message| match message {
+            WsMessage::Text(text) => Message::Text(text),
+            WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                code,
+                reason: reason.into(),
+            })),
+        })
+        .take_until(shutdown.wait());
+    tokio::pin!(output);
+
+    while let Some(message) = output.next().await {
+        let _ = sink.send(message).await;
+    }
+    if shutdown.is_triggered() {
+        let _ = sink
+            .send(Message::Close(Some(CloseFrame {
+                code: 1001,
+                reason: SHUTDOWN_REASON.into(),
+            })))
+            .await;
+    }
+}
@@ -0,0 +1,195 @@
+//! Health Module
+//!
+//! `/healthz` answers whenever the process is running; `/readyz` only
+//! when the store is reachable, saved posts have been restored, the
+//! background tasks are running and the server is not shutting down
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use post_lib::{HealthCheck, HealthReport, HealthStatus};
+use tokio::task::{AbortHandle, JoinHandle};
+
+use crate::{
+    post_db::PostDb,
+    shutdown::{Shutdown, SHUTDOWN_REASON},
+};
+
+/// Health struct - what readiness depends on besides the store itself
+pub struct Health {
+    started: Instant,
+    restored: AtomicBool,
+    tasks: Mutex<Vec<(String, AbortHandle)>>,
+}
+
+/// Health default implementation
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Health implementation
+impl Health {
+    pub fn new() -> Self {
+        Health {
+            started: Instant::now(),
+            restored: AtomicBool::new(false),
+            tasks: Mutex::new(vec![]),
+        }
+    }
+
+    /// record that saved posts, if any, are loaded into the store
+    pub fn mark_restored(&self) {
+        self.restored.store(true, Ordering::SeqCst);
+    }
+
+    /// have readiness depend on `task` running
+    pub fn track_task<T>(&self, name: &str, task: &JoinHandle<T>) {
+        self.tasks
+            .lock()
+            .unwrap()
+            .push((name.to_string(), task.abort_handle()));
+    }
+
+    pub fn liveness(&self) -> HealthReport {
+        HealthReport::new(vec![check(
+            "process",
+            true,
+            format!("up for {}s", self.started.elapsed().as_secs()),
+        )])
+    }
+
+    pub fn readiness(&self, post_db: &Mutex<PostDb>, shutdown: &Shutdown) -> HealthReport {
+        let mut checks = vec![
+            match PostDb::lock(post_db) {
+                Ok(post_db) => check("store", true, format!("{} posts", post_db.posts.len())),
+                Err(_) => check("store", false, "the post store lock is poisoned"),
+            },
+            if self.restored.load(Ordering::SeqCst) {
+                check("restore", true, "saved posts loaded")
+            } else {
+                check("restore", false, "still loading saved posts")
+            },
+        ];
+        for (name, task) in self.tasks.lock().unwrap().iter() {
+            checks.push(if task.is_finished() {
+                check(name, false, "stopped")
+            } else {
+                check(name, true, "running")
+            });
+        }
+        checks.push(if shutdown.is_triggered() {
+            check("shutdown", false, SHUTDOWN_REASON)
+        } else {
+            check("shutdown", true, "accepting requests")
+        });
+        HealthReport::new(checks)
+    }
+}
+
+fn check(name: &str, ok: bool, detail: impl Into<String>) -> HealthCheck {
+    HealthCheck {
+        name: name.to_string(),
+        status: if ok {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Unavailable
+        },
+        detail: Some(detail.into()),
+    }
+}
+
+/// 200 for a healthy report, 503 otherwise
+fn report_response(report: HealthReport) -> (StatusCode, Json<HealthReport>) {
+    let status = match report.status {
+        HealthStatus::Ok => StatusCode::OK,
+        HealthStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    (status, Json(report))
+}
+
+/// Check The Process Is Alive
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "operations",
+    responses((status = 200, description = "the process is running", body = HealthReport))
+)]
+pub async fn healthz_handler(Extension(health): Extension<Arc<Health>>) -> impl IntoResponse {
+    report_response(health.liveness())
+}
+
+/// Check The Server Is Ready For Traffic
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "operations",
+    responses(
+        (status = 200, description = "every check passed", body = HealthReport),
+        (status = 503, description = "at least one check failed", body = HealthReport)
+    )
+)]
+pub async fn readyz_handler(
+    Extension(health): Extension<Arc<Health>>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(shutdown): Extension<Shutdown>,
+) -> impl IntoResponse {
+    report_response(health.readiness(&post_db, &shutdown))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn failing(report: &HealthReport) -> Vec<&str> {
+        report
+            .checks
+            .iter()
+            .filter(|check| check.status == HealthStatus::Unavailable)
+            .map(|check| check.name.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn ready_once_restored_with_tasks_running() {
+        let health = Health::new();
+        let post_db = Mutex::new(PostDb::new());
+        let shutdown = Shutdown::new();
+        assert_eq!(
+            vec!["restore"],
+            failing(&health.readiness(&post_db, &shutdown))
+        );
+
+        health.mark_restored();
+        let task = tokio::spawn(futures::future::pending::<()>());
+        health.track_task("dispatcher", &task);
+        let report = health.readiness(&post_db, &shutdown);
+        assert_eq!(HealthStatus::Ok, report.status, "{:?}", report);
+
+        task.abort();
+        let _ = task.await;
+        assert_eq!(
+            vec!["dispatcher"],
+            failing(&health.readiness(&post_db, &shutdown))
+        );
+    }
+
+    #[test]
+    fn not_ready_while_shutting_down() {
+        let health = Health::new();
+        health.mark_restored();
+        let shutdown = Shutdown::new();
+        shutdown.trigger();
+
+        let report = health.readiness(&Mutex::new(PostDb::new()), &shutdown);
+        assert_eq!(vec!["shutdown"], failing(&report));
+        assert_eq!(HealthStatus::Ok, health.liveness().status);
+    }
+}
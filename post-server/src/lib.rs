@@ -1,81 +1,938 @@
+pub mod attachments;
+pub mod audit;
+pub mod auth;
+pub mod config;
+pub mod drafts;
+pub mod feeds;
+pub mod filters;
+pub mod graphql;
+pub mod health;
+pub mod markdown;
+pub mod mentions;
+pub mod metrics;
+pub mod openapi;
 mod post_db;
+pub mod rate_limit;
+pub mod request_id;
+mod routes;
+pub mod shutdown;
+pub mod storage;
+pub mod validation;
+pub mod webhooks;
 
-use std::sync::{Arc, Mutex};
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
 
 use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
-    response::IntoResponse,
+    extract::{Extension, Path, Query},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
     Json,
 };
+use futures::stream::{self, Stream, StreamExt};
+use tokio_stream::wrappers::BroadcastStream;
 
-pub use post_db::{PostDb, PostDbResponse, PostDbStatus};
-use post_lib::{CreatePostRequest, UpdatePostRequest};
-use serde::Serialize;
+use auth::{Moderator, User};
+use config::Config;
+use metrics::METRICS;
+pub use post_db::{
+    Edit, Post, PostDb, PostDbResponse, PostDbStatus, PostEvent, PostEventKind, Submission,
+};
+use post_lib::{
+    ApiError, AuditAction, CreatePostRequest, FieldError, MarkNotificationsReadRequest,
+    ModerateRequest, Notification, PostFlagsRequest, ReactionRequest, ReportRequest,
+    UpdatePostRequest, VoteRequest,
+};
+pub use routes::{app, app_with_config, route_table, RouteTable};
+use serde::{Deserialize, Serialize};
+use shutdown::Shutdown;
+use utoipa::IntoParams;
+use webhooks::{CreateWebhookRequest, WebhookRegistry};
 
 /// Get All Posts
-pub async fn get_all_posts_handler<'a>(
+#[utoipa::path(
+    get,
+    path = "/posts",
+    tag = "posts",
+    responses((status = 200, description = "every post, pinned posts first", body = [Post]))
+)]
+pub async fn get_all_posts_handler(
     Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
 ) -> impl IntoResponse {
-    let posts = post_db.lock().unwrap().get_posts();
+    let posts = PostDb::lock(&post_db).unwrap().get_posts();
     (StatusCode::OK, Json(posts))
 }
 
 /// Get Post By ID
+#[utoipa::path(
+    get,
+    path = "/post/{id}",
+    tag = "posts",
+    params(("id" = u64, Path, description = "post id")),
+    responses(
+        (status = 200, description = "the post", body = Post),
+        (status = 417, description = "no post with that id", body = ApiError)
+    )
+)]
 pub async fn get_post_handler(
     Path(id): Path<u64>,
     Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
 ) -> impl IntoResponse {
-    let response = post_db.lock().unwrap().get_post(id);
-    response_handler(response)
+    let response = PostDb::lock(&post_db).unwrap().get_post(id);
+    response_handler(response, || post_not_found(id))
 }
 
 /// Create New Post
+///
+/// the content filters see the post first, and may hold it for a
+/// moderator or reject it
+#[utoipa::path(
+    post,
+    path = "/addPost",
+    tag = "posts",
+    request_body = CreatePostRequest,
+    responses(
+        (status = 200, description = "id of the new post", body = u64),
+        (status = 202, description = "id of the new post, held for a moderator", body = u64),
+        (status = 417, description = "the post store is unavailable", body = ApiError),
+        (status = 422, description = "the content or poll is invalid, or a filter rejected it", body = ApiError)
+    )
+)]
 pub async fn new_post_handler(
     Json(payload): Json<CreatePostRequest>,
     Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(config): Extension<Arc<Config>>,
 ) -> impl IntoResponse {
-    let post_db_lock = post_db.lock();
+    let content = validation::content("content", &payload.content, &config.limits.content);
+    let poll = payload
+        .poll
+        .as_ref()
+        .map(|poll| validation::poll(poll, post_db::now()))
+        .transpose();
+    let (content, poll) = match (content, poll) {
+        (Ok(content), Ok(poll)) => (content, poll),
+        (content, poll) => {
+            let mut fields = content.err().unwrap_or_default();
+            fields.extend(poll.err().unwrap_or_default());
+            return Err(invalid_request(fields));
+        }
+    };
+    let post_db_lock = PostDb::lock(&post_db);
     match post_db_lock {
         Ok(mut post_db) => {
-            let response = post_db.create_post(payload.content);
-            response_handler(response)
+            let response = post_db.create_post_with_poll(content, payload.format, poll);
+            submitted("content", response.value)
         }
         Err(e) => {
-            eprintln!("error getting db lock: {}", e);
-            response_handler(PostDbResponse {
-                status: PostDbStatus::Err,
-                value: 0,
-            })
+            tracing::error!("error getting db lock: {}", e);
+            response_handler(
+                PostDbResponse {
+                    status: PostDbStatus::Err,
+                    value: 0,
+                },
+                || ApiError::internal("the post store is unavailable"),
+            )
         }
     }
 }
 
 /// Update Post By ID (update content)
+///
+/// the content filters see the new content like a new post's; a held edit
+/// hides the post until a moderator looks at it
+#[utoipa::path(
+    post,
+    path = "/updatePost",
+    tag = "posts",
+    request_body = UpdatePostRequest,
+    responses(
+        (status = 200, description = "id of the updated post", body = u64),
+        (status = 202, description = "id of the updated post, held for a moderator", body = u64),
+        (status = 417, description = "no post with that id, or it is hidden", body = ApiError),
+        (status = 422, description = "the content is invalid, or a filter rejected it", body = ApiError),
+        (status = 423, description = "the post is locked", body = ApiError)
+    )
+)]
 pub async fn update_post_handler(
     Json(payload): Json<UpdatePostRequest>,
     Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(config): Extension<Arc<Config>>,
 ) -> impl IntoResponse {
-    let response = post_db
-        .lock()
+    let content = match validation::content(
+        "updated_content",
+        &payload.updated_content,
+        &config.limits.content,
+    ) {
+        Ok(content) => content,
+        Err(fields) => return Err(invalid_request(fields)),
+    };
+    let response = PostDb::lock(&post_db)
         .unwrap()
-        .update_post(payload.post_id, payload.updated_content);
-    response_handler(response)
+        .update_post(payload.post_id, content);
+    edited(payload.post_id, response.value)
 }
 
 /// Delete Post By ID
+#[utoipa::path(
+    post,
+    path = "/deletePost/{id}",
+    tag = "posts",
+    params(("id" = u64, Path, description = "post id")),
+    responses(
+        (status = 200, description = "id of the deleted post", body = u64),
+        (status = 417, description = "no post with that id", body = ApiError)
+    )
+)]
 pub async fn delete_post_handler(
     Path(id): Path<u64>,
     Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
 ) -> impl IntoResponse {
-    let response = post_db.lock().unwrap().delete_post(id);
-    response_handler(response)
+    let response = PostDb::lock(&post_db).unwrap().delete_post(id);
+    response_handler(response, || post_not_found(id))
+}
+
+/// Pin Or Lock A Post
+///
+/// pinned posts are listed first, in the order they were pinned, and
+/// locked posts refuse edits; flags left out of the request stay as they are
+#[utoipa::path(
+    post,
+    path = "/admin/updatePostFlags",
+    tag = "moderation",
+    request_body = PostFlagsRequest,
+    responses(
+        (status = 200, description = "the post with its new flags", body = Post),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError),
+        (status = 417, description = "no post with that id, or it is hidden", body = ApiError)
+    )
+)]
+pub async fn update_post_flags_handler(
+    _moderator: Moderator,
+    Json(payload): Json<PostFlagsRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let response = PostDb::lock(&post_db).unwrap().set_post_flags(
+        payload.post_id,
+        payload.pinned,
+        payload.locked,
+    );
+    response_handler(response, || post_not_found(payload.post_id))
+}
+
+/// Report A Post To The Moderators
+///
+/// the reporter is the signed in user, and each user's first report of a
+/// post counts; once `moderation.report_threshold` users have reported it,
+/// the post is hidden until a moderator looks at it
+#[utoipa::path(
+    post,
+    path = "/posts/{id}/report",
+    tag = "moderation",
+    params(("id" = u64, Path, description = "post id")),
+    request_body = ReportRequest,
+    responses(
+        (status = 200, description = "the user's report", body = Report),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 417, description = "no post with that id, or it is hidden", body = ApiError),
+        (status = 422, description = "the reason is invalid", body = ApiError)
+    )
+)]
+pub async fn report_post_handler(
+    User(user): User,
+    Path(id): Path<u64>,
+    Json(payload): Json<ReportRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let reason = validation::report(&payload.reason).map_err(invalid_request)?;
+    let response = PostDb::lock(&post_db).unwrap().report(
+        id,
+        user,
+        reason,
+        config.moderation.report_threshold,
+    );
+    response_handler(response, || post_not_found(id))
+}
+
+/// Get The Moderation Queue
+#[utoipa::path(
+    get,
+    path = "/admin/reports",
+    tag = "moderation",
+    responses(
+        (status = 200, description = "every post with open reports, hidden or not, most reported first", body = [ReportedPost]),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError)
+    )
+)]
+pub async fn moderation_queue_handler(
+    _moderator: Moderator,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let queue = PostDb::lock(&post_db).unwrap().reported_posts();
+    (StatusCode::OK, Json(queue))
+}
+
+/// Act On A Post
+///
+/// dismiss the reports against a post, showing it again if it was hidden,
+/// hide it, delete it or warn a user about it; any action closes the
+/// post's open reports and is added to the moderation log under the signed
+/// in moderator's name
+#[utoipa::path(
+    post,
+    path = "/admin/moderate",
+    tag = "moderation",
+    request_body = ModerateRequest,
+    responses(
+        (status = 200, description = "the new moderation log entry", body = ModerationEntry),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError),
+        (status = 417, description = "no post with that id", body = ApiError),
+        (status = 422, description = "the user or note is invalid", body = ApiError)
+    )
+)]
+pub async fn moderate_handler(
+    Moderator(moderator): Moderator,
+    Json(payload): Json<ModerateRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let request = validation::moderation(&payload).map_err(invalid_request)?;
+    let response = PostDb::lock(&post_db).unwrap().moderate(
+        request.post_id,
+        moderator,
+        request.action,
+        request.user,
+        request.note,
+    );
+    response_handler(response, || post_not_found(payload.post_id))
+}
+
+/// optional filter for the moderation log
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ModerationLogFilter {
+    /// only show entries about this post
+    pub post_id: Option<u64>,
+}
+
+/// Get The Moderation Log
+#[utoipa::path(
+    get,
+    path = "/admin/moderationLog",
+    tag = "moderation",
+    params(ModerationLogFilter),
+    responses(
+        (status = 200, description = "what moderators and reports did, oldest first", body = [ModerationEntry]),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError)
+    )
+)]
+pub async fn moderation_log_handler(
+    _moderator: Moderator,
+    Query(filter): Query<ModerationLogFilter>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let log = PostDb::lock(&post_db)
+        .unwrap()
+        .moderation_log(filter.post_id);
+    (StatusCode::OK, Json(log))
+}
+
+/// Add A Reaction To A Post
+///
+/// the reaction is the signed in user's; reacting again with the same emoji leaves the counts unchanged
+#[utoipa::path(
+    post,
+    path = "/addReaction",
+    tag = "reactions",
+    request_body = ReactionRequest,
+    responses(
+        (status = 200, description = "the post's reaction counts, most used first", body = [ReactionCount]),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 417, description = "no post with that id, or it is hidden", body = ApiError),
+        (status = 422, description = "the emoji is invalid", body = ApiError)
+    )
+)]
+pub async fn add_reaction_handler(
+    User(user): User,
+    Json(payload): Json<ReactionRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let emoji = validation::reaction(&payload.emoji).map_err(invalid_request)?;
+    let response = PostDb::lock(&post_db)
+        .unwrap()
+        .add_reaction(payload.post_id, user, emoji);
+    response_handler(response, || post_not_found(payload.post_id))
+}
+
+/// Remove A Reaction From A Post
+///
+/// only the signed in user's own reaction is removed; removing a reaction that was never added leaves the counts unchanged
+#[utoipa::path(
+    post,
+    path = "/removeReaction",
+    tag = "reactions",
+    request_body = ReactionRequest,
+    responses(
+        (status = 200, description = "the post's reaction counts, most used first", body = [ReactionCount]),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 417, description = "no post with that id, or it is hidden", body = ApiError),
+        (status = 422, description = "the emoji is invalid", body = ApiError)
+    )
+)]
+pub async fn remove_reaction_handler(
+    User(user): User,
+    Json(payload): Json<ReactionRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let emoji = validation::reaction(&payload.emoji).map_err(invalid_request)?;
+    let response = PostDb::lock(&post_db)
+        .unwrap()
+        .remove_reaction(payload.post_id, user, emoji);
+    response_handler(response, || post_not_found(payload.post_id))
+}
+
+/// Get Who Reacted To A Post
+#[utoipa::path(
+    get,
+    path = "/post/{id}/reactions",
+    tag = "reactions",
+    params(("id" = u64, Path, description = "post id")),
+    responses(
+        (status = 200, description = "every reaction, ordered by emoji then user", body = [Reaction]),
+        (status = 417, description = "no post with that id, or it is hidden", body = ApiError)
+    )
+)]
+pub async fn post_reactions_handler(
+    Path(id): Path<u64>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let response = PostDb::lock(&post_db).unwrap().reactions(id);
+    response_handler(response, || post_not_found(id))
+}
+
+/// Vote In A Post's Poll
+///
+/// the voter is the signed in user; voting again replaces their earlier
+/// choices, and sending no choices takes the vote back; votes are taken
+/// until the poll closes
+#[utoipa::path(
+    post,
+    path = "/vote",
+    tag = "polls",
+    request_body = VoteRequest,
+    responses(
+        (status = 200, description = "the poll with its new results", body = Poll),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 409, description = "the poll is closed", body = ApiError),
+        (status = 417, description = "no post with that id, it is hidden or it has no poll", body = ApiError),
+        (status = 422, description = "the choices are invalid", body = ApiError)
+    )
+)]
+pub async fn vote_handler(
+    User(user): User,
+    Json(payload): Json<VoteRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let mut post_db = PostDb::lock(&post_db).unwrap();
+    let Some(poll) = post_db.poll(payload.post_id) else {
+        return Err((
+            StatusCode::EXPECTATION_FAILED,
+            error_body(no_poll(payload.post_id)),
+        ));
+    };
+    if !poll.is_open(post_db::now()) {
+        return Err(poll_closed(payload.post_id));
+    }
+    let choices = validation::vote(&poll, &payload.choices).map_err(invalid_request)?;
+    let response = post_db.vote(payload.post_id, user, choices);
+    response_handler(response, || no_poll(payload.post_id))
+}
+
+/// which of the caller's notifications to list
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NotificationFilter {
+    /// only list notifications not yet marked read
+    #[serde(default)]
+    pub unread: bool,
+}
+
+/// Get Your Notifications
+///
+/// the notifications of the signed in caller
+#[utoipa::path(
+    get,
+    path = "/notifications",
+    tag = "notifications",
+    params(NotificationFilter),
+    responses(
+        (status = 200, description = "the caller's notifications, newest first", body = [Notification]),
+        (status = 401, description = "not signed in", body = ApiError)
+    )
+)]
+pub async fn notifications_handler(
+    User(user): User,
+    Query(filter): Query<NotificationFilter>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let notifications = PostDb::lock(&post_db)
+        .unwrap()
+        .notifications(&user, filter.unread);
+    (StatusCode::OK, Json(notifications))
+}
+
+/// Mark Notifications Read
+///
+/// leaving out `notification_ids` marks all of the caller's notifications read
+#[utoipa::path(
+    post,
+    path = "/markNotificationsRead",
+    tag = "notifications",
+    request_body = MarkNotificationsReadRequest,
+    responses(
+        (status = 200, description = "how many unread notifications were marked read", body = u64),
+        (status = 401, description = "not signed in", body = ApiError)
+    )
+)]
+pub async fn mark_notifications_read_handler(
+    User(user): User,
+    Json(payload): Json<MarkNotificationsReadRequest>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let marked = PostDb::lock(&post_db)
+        .unwrap()
+        .mark_notifications_read(&user, payload.notification_ids.as_deref());
+    (StatusCode::OK, Json(marked))
+}
+
+/// Stream Your Notifications (Server-Sent Events)
+///
+/// sends a `notification` event for each new notification of the signed
+/// in caller and a `notification_read` event for each one marked read.
+/// Nothing is replayed; list `/notifications` after connecting to catch
+/// up. When the server shuts down the stream ends with a `shutdown` event.
+#[utoipa::path(
+    get,
+    path = "/notifications/events",
+    tag = "notifications",
+    responses(
+        (status = 200, description = "`text/event-stream` of the caller's notifications", body = Notification, content_type = "text/event-stream"),
+        (status = 401, description = "not signed in", body = ApiError)
+    )
+)]
+pub async fn notification_events_handler(
+    User(user): User,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(shutdown): Extension<Shutdown>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, Json<ApiError>)> {
+    let user = user.to_lowercase();
+    let receiver = PostDb::lock(&post_db).unwrap().subscribe_notifications();
+
+    let closing = shutdown.clone();
+    let connection = METRICS.stream_guard("sse");
+    let stream = BroadcastStream::new(receiver)
+        .take_while(|notification| futures::future::ready(notification.is_ok()))
+        .filter_map(move |notification| {
+            futures::future::ready(notification.ok().filter(|n| n.user == user))
+        })
+        .map(move |notification| {
+            // counted as open for as long as the stream exists
+            let _ = &connection;
+            Ok(notification_event(&notification))
+        })
+        .take_until(shutdown.wait())
+        .chain(
+            stream::once(async move { closing.is_triggered() })
+                .filter_map(|closed| futures::future::ready(closed.then(|| Ok(shutdown_event())))),
+        );
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// Convert a Notification into an SSE event
+fn notification_event(notification: &Notification) -> Event {
+    Event::default()
+        .id(notification.notification_id.to_string())
+        .event(if notification.read {
+            "notification_read"
+        } else {
+            "notification"
+        })
+        .json_data(notification)
+        .unwrap()
+}
+
+/// Stream Post Change Events (Server-Sent Events)
+///
+/// a `Last-Event-ID` header replays the buffered events after that id
+/// before switching to live events. A client that falls too far behind
+/// has its stream closed so it can reconnect and replay what it missed.
+/// When the server shuts down the stream ends with a `shutdown` event.
+#[utoipa::path(
+    get,
+    path = "/events",
+    tag = "events",
+    params(("Last-Event-ID" = Option<u64>, Header, description = "replay buffered events after this id")),
+    responses((status = 200, description = "`text/event-stream` of post changes", body = PostEvent, content_type = "text/event-stream"))
+)]
+pub async fn events_handler(
+    headers: HeaderMap,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(shutdown): Extension<Shutdown>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+
+    let (backlog, receiver) = PostDb::lock(&post_db).unwrap().subscribe(last_event_id);
+
+    let live = BroadcastStream::new(receiver)
+        .take_while(|event| futures::future::ready(event.is_ok()))
+        .filter_map(|event| futures::future::ready(event.ok()));
+
+    let closing = shutdown.clone();
+    let connection = METRICS.stream_guard("sse");
+    let stream = stream::iter(backlog)
+        .chain(live)
+        .map(move |event| {
+            // counted as open for as long as the stream exists
+            let _ = &connection;
+            Ok(sse_event(&event))
+        })
+        .take_until(shutdown.wait())
+        .chain(
+            stream::once(async move { closing.is_triggered() })
+                .filter_map(|closed| futures::future::ready(closed.then(|| Ok(shutdown_event())))),
+        );
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// Convert a PostEvent into an SSE event
+fn sse_event(event: &PostEvent) -> Event {
+    Event::default()
+        .id(event.event_id.to_string())
+        .event(event.kind.name())
+        .json_data(event)
+        .unwrap()
+}
+
+/// The last event on a stream closed by shutdown
+fn shutdown_event() -> Event {
+    Event::default()
+        .event("shutdown")
+        .data(shutdown::SHUTDOWN_REASON)
+}
+
+/// Get Atom Feed Of Recent Posts
+#[utoipa::path(
+    get,
+    path = "/feed.atom",
+    tag = "feeds",
+    responses(
+        (status = 200, description = "Atom feed", body = String, content_type = "application/atom+xml"),
+        (status = 304, description = "unchanged since `If-None-Match` / `If-Modified-Since`")
+    )
+)]
+pub async fn atom_feed_handler(
+    headers: HeaderMap,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let feed = feeds::atom(
+        &PostDb::lock(&post_db).unwrap(),
+        config.public_url(),
+        config.limits.feed_size,
+    );
+    feed_response(feed, "application/atom+xml; charset=utf-8", &headers)
+}
+
+/// Get RSS Feed Of Recent Posts
+#[utoipa::path(
+    get,
+    path = "/feed.rss",
+    tag = "feeds",
+    responses(
+        (status = 200, description = "RSS feed", body = String, content_type = "application/rss+xml"),
+        (status = 304, description = "unchanged since `If-None-Match` / `If-Modified-Since`")
+    )
+)]
+pub async fn rss_feed_handler(
+    headers: HeaderMap,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+    Extension(config): Extension<Arc<Config>>,
+) -> impl IntoResponse {
+    let feed = feeds::rss(
+        &PostDb::lock(&post_db).unwrap(),
+        config.public_url(),
+        config.limits.feed_size,
+    );
+    feed_response(feed, "application/rss+xml; charset=utf-8", &headers)
+}
+
+/// Answer a feed request, honoring `If-None-Match` and `If-Modified-Since`
+fn feed_response(
+    feed: feeds::Feed,
+    content_type: &'static str,
+    request_headers: &HeaderMap,
+) -> (StatusCode, HeaderMap, String) {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ETAG, HeaderValue::from_str(&feed.etag).unwrap());
+    headers.insert(
+        header::LAST_MODIFIED,
+        HeaderValue::from_str(&feed.last_modified_header()).unwrap(),
+    );
+
+    let if_none_match = request_headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok());
+    let if_modified_since = request_headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok());
+    if feed.is_fresh(if_none_match, if_modified_since) {
+        return (StatusCode::NOT_MODIFIED, headers, String::new());
+    }
+
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    (StatusCode::OK, headers, feed.body)
+}
+
+/// List Webhooks
+#[utoipa::path(
+    get,
+    path = "/admin/webhooks",
+    tag = "webhooks",
+    responses(
+        (status = 200, description = "every webhook subscription", body = [Webhook]),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError)
+    )
+)]
+pub async fn list_webhooks_handler(
+    _moderator: Moderator,
+    Extension(webhooks): Extension<Arc<Mutex<WebhookRegistry>>>,
+) -> impl IntoResponse {
+    let webhooks = webhooks.lock().unwrap().webhooks.clone();
+    (StatusCode::OK, Json(webhooks))
+}
+
+/// Create New Webhook
+///
+/// the url must be an absolute `http` or `https` url
+#[utoipa::path(
+    post,
+    path = "/admin/webhooks",
+    tag = "webhooks",
+    request_body = CreateWebhookRequest,
+    responses(
+        (status = 200, description = "id of the new webhook", body = u64),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError),
+        (status = 422, description = "the url is invalid", body = ApiError)
+    )
+)]
+pub async fn new_webhook_handler(
+    _moderator: Moderator,
+    Json(payload): Json<CreateWebhookRequest>,
+    Extension(webhooks): Extension<Arc<Mutex<WebhookRegistry>>>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let url = validation::webhook_url(&payload.url).map_err(invalid_request)?;
+    let mut webhooks = webhooks.lock().unwrap();
+    let webhook_id = webhooks.add_webhook(CreateWebhookRequest { url, ..payload });
+    let webhook = webhooks
+        .webhooks
+        .iter()
+        .find(|webhook| webhook.webhook_id == webhook_id);
+    PostDb::lock(&post_db).unwrap().audit_webhook(
+        AuditAction::CreateWebhook,
+        webhook_id,
+        None,
+        webhook,
+    );
+    Ok::<_, (StatusCode, Json<ApiError>)>((StatusCode::OK, Json(webhook_id)))
+}
+
+/// Delete Webhook By ID
+#[utoipa::path(
+    post,
+    path = "/admin/deleteWebhook/{id}",
+    tag = "webhooks",
+    params(("id" = u64, Path, description = "webhook id")),
+    responses(
+        (status = 200, description = "id of the deleted webhook", body = u64),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError),
+        (status = 417, description = "no webhook with that id", body = ApiError)
+    )
+)]
+pub async fn delete_webhook_handler(
+    _moderator: Moderator,
+    Path(id): Path<u64>,
+    Extension(webhooks): Extension<Arc<Mutex<WebhookRegistry>>>,
+    Extension(post_db): Extension<Arc<Mutex<PostDb>>>,
+) -> impl IntoResponse {
+    let mut webhooks = webhooks.lock().unwrap();
+    let webhook = webhooks
+        .webhooks
+        .iter()
+        .find(|webhook| webhook.webhook_id == id)
+        .cloned();
+    match webhooks.remove_webhook(id) {
+        Some(id) => {
+            PostDb::lock(&post_db).unwrap().audit_webhook(
+                AuditAction::DeleteWebhook,
+                id,
+                webhook.as_ref(),
+                None,
+            );
+            Ok((StatusCode::OK, Json(id)))
+        }
+        None => Err((
+            StatusCode::EXPECTATION_FAILED,
+            error_body(ApiError::not_found(format!("no webhook with id {}", id))),
+        )),
+    }
+}
+
+/// optional filter for the webhook delivery log and dead letters
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct WebhookFilter {
+    /// only show entries for this webhook
+    pub webhook_id: Option<u64>,
+}
+
+/// Get Webhook Delivery Log
+#[utoipa::path(
+    get,
+    path = "/admin/webhookDeliveries",
+    tag = "webhooks",
+    params(WebhookFilter),
+    responses(
+        (status = 200, description = "delivery attempts, oldest first", body = [DeliveryAttempt]),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError)
+    )
+)]
+pub async fn webhook_deliveries_handler(
+    _moderator: Moderator,
+    Query(filter): Query<WebhookFilter>,
+    Extension(webhooks): Extension<Arc<Mutex<WebhookRegistry>>>,
+) -> impl IntoResponse {
+    let deliveries = webhooks.lock().unwrap().deliveries(filter.webhook_id);
+    (StatusCode::OK, Json(deliveries))
+}
+
+/// Get Webhook Dead Letters
+#[utoipa::path(
+    get,
+    path = "/admin/webhookDeadLetters",
+    tag = "webhooks",
+    params(WebhookFilter),
+    responses(
+        (status = 200, description = "the latest deliveries that ran out of retries, oldest first", body = [DeadLetter]),
+        (status = 401, description = "not signed in", body = ApiError),
+        (status = 403, description = "not a moderator", body = ApiError)
+    )
+)]
+pub async fn webhook_dead_letters_handler(
+    _moderator: Moderator,
+    Query(filter): Query<WebhookFilter>,
+    Extension(webhooks): Extension<Arc<Mutex<WebhookRegistry>>>,
+) -> impl IntoResponse {
+    let dead_letters = webhooks.lock().unwrap().dead_letters(filter.webhook_id);
+    (StatusCode::OK, Json(dead_letters))
 }
 
 /// Handle the response
-fn response_handler<T: Serialize>(response: PostDbResponse<T>) -> impl IntoResponse {
+fn response_handler<T: Serialize>(
+    response: PostDbResponse<T>,
+    error: impl FnOnce() -> ApiError,
+) -> Result<(StatusCode, Json<T>), (StatusCode, Json<ApiError>)> {
     match response.status {
-        PostDbStatus::Ok => (StatusCode::OK, Json(response.value)),
-        PostDbStatus::Err => (StatusCode::EXPECTATION_FAILED, Json(response.value)),
+        PostDbStatus::Ok => Ok((StatusCode::OK, Json(response.value))),
+        PostDbStatus::Err => Err((StatusCode::EXPECTATION_FAILED, error_body(error()))),
     }
 }
+
+/// an error body tagged with the id of the request being handled
+fn error_body(error: ApiError) -> Json<ApiError> {
+    Json(ApiError {
+        request_id: request_id::current(),
+        ..error
+    })
+}
+
+/// the response to a new post: `200` with its id, `202` if the content
+/// filters held it, and `422` naming the filter that rejected it
+fn submitted(
+    field: &str,
+    submission: Submission,
+) -> Result<(StatusCode, Json<u64>), (StatusCode, Json<ApiError>)> {
+    match submission {
+        Submission::Published(post_id) => Ok((StatusCode::OK, Json(post_id))),
+        Submission::Held(post_id) => Ok((StatusCode::ACCEPTED, Json(post_id))),
+        Submission::Rejected(flag) => Err(invalid_request(vec![flag.field_error(field)])),
+    }
+}
+
+/// the response to an edit: `200` with the post's id, `202` if a filter
+/// held it, `417` if there is no such post, `422` if a filter rejected it
+/// and `423` if it is locked
+fn edited(
+    post_id: u64,
+    edit: Edit,
+) -> Result<(StatusCode, Json<u64>), (StatusCode, Json<ApiError>)> {
+    match edit {
+        Edit::Updated(post_id) => Ok((StatusCode::OK, Json(post_id))),
+        Edit::Held(post_id) => Ok((StatusCode::ACCEPTED, Json(post_id))),
+        Edit::Rejected(flag) => Err(invalid_request(vec![flag.field_error("updated_content")])),
+        Edit::Missing => Err((
+            StatusCode::EXPECTATION_FAILED,
+            error_body(post_not_found(post_id)),
+        )),
+        Edit::Locked => Err(post_locked(post_id)),
+    }
+}
+
+fn invalid_request(fields: Vec<FieldError>) -> (StatusCode, Json<ApiError>) {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        error_body(ApiError::invalid(fields)),
+    )
+}
+
+fn post_not_found(post_id: u64) -> ApiError {
+    ApiError::not_found(format!("no post with id {}", post_id))
+}
+
+fn no_poll(post_id: u64) -> ApiError {
+    ApiError::not_found(format!("no poll on post {}", post_id))
+}
+
+fn poll_closed(post_id: u64) -> (StatusCode, Json<ApiError>) {
+    (
+        StatusCode::CONFLICT,
+        error_body(ApiError::closed(format!(
+            "the poll on post {} is closed",
+            post_id
+        ))),
+    )
+}
+
+fn post_locked(post_id: u64) -> (StatusCode, Json<ApiError>) {
+    (
+        StatusCode::LOCKED,
+        error_body(ApiError::locked(format!("post {} is locked", post_id))),
+    )
+}
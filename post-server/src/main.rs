@@ -1,48 +1,159 @@
-use std::sync::{Arc, Mutex};
-
-use axum::{
-    routing::{get, post},
-    AddExtensionLayer, Router,
+use std::{
+    process,
+    sync::{Arc, Mutex},
 };
 
-use hyper::{header::CONTENT_TYPE, Method};
-use tower_http::cors::{CorsLayer, Origin};
-
+use clap::Parser;
+use hyper::server::conn::AddrIncoming;
 use post_server::{
-    delete_post_handler, get_all_posts_handler, get_post_handler, new_post_handler,
-    update_post_handler, PostDb,
+    app_with_config,
+    attachments::{spawn_cleaner, BlobStore},
+    config::{Config, Flags, LogFormat, StorageBackend},
+    drafts::{spawn_scheduler, SystemClock, SCHEDULER_INTERVAL},
+    filters,
+    health::Health,
+    shutdown::{self, Drain, Shutdown},
+    storage,
+    webhooks::{spawn_dispatcher, WebhookRegistry},
+    PostDb,
 };
+use tracing_subscriber::EnvFilter;
 
 /// The main application entry point
 #[tokio::main]
 async fn main() {
-    let db = create_post_db();
-    let app = app(db);
-    let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 3000));
-
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    let flags = Flags::parse();
+    let config = match Config::load(&flags, |name| std::env::var(name).ok()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(2);
+        }
+    };
+    if flags.print_config {
+        print!("{}", config.to_toml());
+        return;
+    }
+    init_logging(&config);
+
+    let db = match create_post_db(&config) {
+        Ok(db) => db,
+        Err(e) => {
+            tracing::error!("could not open the post store: {}", e);
+            process::exit(1);
+        }
+    };
+    let health = Arc::new(Health::new());
+    health.mark_restored();
+
+    let blobs = match BlobStore::open(config.attachments.dir.clone()) {
+        Ok(blobs) => Arc::new(blobs),
+        Err(e) => {
+            tracing::error!(
+                "could not open the attachment store in {}: {}",
+                config.attachments.dir.display(),
+                e
+            );
+            process::exit(1);
+        }
+    };
+    let cleaner = spawn_cleaner(db.clone(), blobs.clone());
+    health.track_task("attachment_cleaner", &cleaner);
+
+    let scheduler = spawn_scheduler(db.clone(), Arc::new(SystemClock), SCHEDULER_INTERVAL);
+    health.track_task("scheduler", &scheduler);
+
+    let webhooks = create_webhook_registry();
+    let dispatcher = spawn_dispatcher(db.clone(), webhooks.clone());
+    health.track_task("webhook_dispatcher", &dispatcher);
+    let persister = match (config.storage.backend, &config.storage.path) {
+        (StorageBackend::File, Some(path)) => {
+            let persister = storage::spawn_persister(db.clone(), path.clone());
+            health.track_task("persister", &persister);
+            let audit_path = storage::audit_path(path);
+            let audit_writer = storage::spawn_audit_writer(db.clone(), audit_path.clone());
+            health.track_task("audit_writer", &audit_writer);
+            Some((persister, path.clone(), audit_writer, audit_path))
+        }
+        _ => None,
+    };
+
+    let addr = config.bind_addr();
+    let incoming = match AddrIncoming::bind(&addr) {
+        Ok(incoming) => incoming,
+        Err(e) => {
+            tracing::error!("could not listen on {}: {}", addr, e);
+            process::exit(1);
+        }
+    };
+    let drain_timeout = config.shutdown_timeout();
+    let shutdown = Shutdown::new();
+    let app = app_with_config(
+        config,
+        db.clone(),
+        webhooks,
+        shutdown.clone(),
+        health,
+        blobs,
+    );
+
+    let trigger = shutdown.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        tracing::info!("shutting down, draining in-flight requests");
+        trigger.trigger();
+    });
+
+    tracing::info!("listening on {}", addr);
+    match shutdown::serve(incoming, app, shutdown, drain_timeout).await {
+        Ok(Drain::Complete) => tracing::info!("all requests finished"),
+        Ok(Drain::TimedOut) => tracing::warn!(
+            "requests still running after {}s, stopping anyway",
+            drain_timeout.as_secs()
+        ),
+        Err(e) => tracing::error!("server error: {}", e),
+    }
+
+    // nothing gets published after the final save
+    scheduler.abort();
+
+    // write the final state, making sure the persister isn't mid-write
+    if let Some((persister, path, audit_writer, audit_path)) = persister {
+        audit_writer.abort();
+        let _ = audit_writer.await;
+        if let Err(e) = storage::write_audit_log(&db, &audit_path) {
+            tracing::error!(
+                "could not append to audit log {}: {}",
+                audit_path.display(),
+                e
+            );
+        }
+        persister.abort();
+        let _ = persister.await;
+        let snapshot = storage::Snapshot::of(&db.lock().unwrap());
+        match snapshot.save(&path).await {
+            Ok(()) => tracing::info!("saved posts to {}", path.display()),
+            Err(e) => tracing::error!("could not save posts to {}: {}", path.display(), e),
+        }
+    }
 }
 
-fn create_post_db() -> Arc<Mutex<PostDb>> {
-    Arc::new(Mutex::new(Default::default()))
+fn init_logging(config: &Config) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(config.logging.filter()))
+        .with_writer(std::io::stderr);
+    match config.logging.format {
+        LogFormat::Pretty => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
 }
 
-fn app(db: Arc<Mutex<PostDb>>) -> Router {
-    let cors = CorsLayer::new()
-        .allow_methods(vec![Method::GET, Method::POST, Method::OPTIONS])
-        .allow_origin(Origin::exact("http://localhost:8080".parse().unwrap()))
-        .allow_credentials(false)
-        .allow_headers(vec![CONTENT_TYPE]);
-
-    Router::new()
-        .route("/posts", get(get_all_posts_handler))
-        .route("/post/:id", get(get_post_handler))
-        .route("/addPost", post(new_post_handler))
-        .route("/updatePost", post(update_post_handler))
-        .route("/deletePost/:id", post(delete_post_handler))
-        .layer(cors)
-        .layer(AddExtensionLayer::new(db))
+fn create_post_db(config: &Config) -> std::io::Result<Arc<Mutex<PostDb>>> {
+    let mut db = storage::open(&config.storage, config.limits.event_buffer)?;
+    db.set_filters(filters::chain(&config.filters));
+    Ok(Arc::new(Mutex::new(db)))
+}
+
+fn create_webhook_registry() -> Arc<Mutex<WebhookRegistry>> {
+    Arc::new(Mutex::new(Default::default()))
 }
@@ -0,0 +1,109 @@
+//! Markdown Module
+//!
+//! CommonMark post content rendered to HTML and sanitized against an
+//! allowlist, so clients can insert `content_html` as is
+
+use std::collections::HashSet;
+
+use ammonia::Builder;
+use once_cell::sync::Lazy;
+use pulldown_cmark::{html, Options, Parser};
+
+use crate::post_db::ContentFormat;
+
+/// the elements rendered markdown may keep; everything else is removed
+const ALLOWED_TAGS: &[&str] = &[
+    "a",
+    "blockquote",
+    "br",
+    "code",
+    "del",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "hr",
+    "li",
+    "ol",
+    "p",
+    "pre",
+    "strong",
+    "table",
+    "tbody",
+    "td",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+static SANITIZER: Lazy<Builder<'static>> = Lazy::new(|| {
+    let mut builder = Builder::empty();
+    builder
+        .tags(ALLOWED_TAGS.iter().copied().collect())
+        .tag_attributes(
+            [("a", ["href", "title"].into_iter().collect())]
+                .into_iter()
+                .collect(),
+        )
+        .url_schemes(
+            ["http", "https", "mailto"]
+                .into_iter()
+                .collect::<HashSet<_>>(),
+        )
+        .link_rel(Some("nofollow noopener noreferrer"));
+    builder
+});
+
+/// render CommonMark to sanitized HTML
+pub fn render(markdown: &str) -> String {
+    let mut unsafe_html = String::new();
+    let options = Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH;
+    html::push_html(&mut unsafe_html, Parser::new_ext(markdown, options));
+    SANITIZER.clean(&unsafe_html).to_string()
+}
+
+/// the `content_html` for content written in `format`
+pub fn render_content(content: &str, format: ContentFormat) -> Option<String> {
+    match format {
+        ContentFormat::Plain => None,
+        ContentFormat::Markdown => Some(render(content)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn renders_commonmark() {
+        assert_eq!(
+            "<ul>\n<li>one</li>\n<li><a href=\"https://example.com\" rel=\"nofollow noopener noreferrer\">two</a></li>\n</ul>\n<pre><code>let x = 1;\n</code></pre>\n",
+            render("- one\n- [two](https://example.com)\n\n```rust\nlet x = 1;\n```\n")
+        );
+    }
+
+    #[test]
+    fn strips_unsafe_html() {
+        let html = render(
+            "<script>alert(1)</script>\n\n[x](javascript:alert(1)) <img src=x onerror=alert(1)> <b onclick=\"alert(1)\">bold</b>",
+        );
+        assert!(!html.contains("script"), "{}", html);
+        assert!(!html.contains("javascript"), "{}", html);
+        assert!(!html.contains("onerror"), "{}", html);
+        assert!(!html.contains("onclick"), "{}", html);
+        assert!(!html.contains("<img"), "{}", html);
+    }
+
+    #[test]
+    fn plain_content_has_no_html() {
+        assert_eq!(None, render_content("*plain*", ContentFormat::Plain));
+        assert_eq!(
+            Some("<p><em>marked</em></p>\n".to_string()),
+            render_content("*marked*", ContentFormat::Markdown)
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Mentions Module
+//!
+//! `@name` mentions in post content, whose users get notified
+
+use std::collections::BTreeSet;
+
+/// longest user name a mention can carry; longer ones are ignored
+pub const MAX_MENTION_CHARS: usize = 64;
+
+fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '.' || c == '-'
+}
+
+/// every user mentioned in `content`, lowercased
+///
+/// a mention is an `@` that doesn't follow a name character, so email
+/// addresses don't count, then letters, digits, `_`, `.` or `-`; a
+/// trailing `.` or `-` is punctuation rather than part of the name
+pub fn parse(content: &str) -> BTreeSet<String> {
+    let mut mentions = BTreeSet::new();
+    let mut prev = None;
+    for (index, c) in content.char_indices() {
+        if c == '@' && !prev.is_some_and(is_name_char) {
+            let rest = &content[index + 1..];
+            let end = rest.find(|c| !is_name_char(c)).unwrap_or(rest.len());
+            let name = rest[..end].trim_end_matches(['.', '-']);
+            let starts_well = name
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphanumeric() || c == '_');
+            if starts_well && name.chars().count() <= MAX_MENTION_CHARS {
+                mentions.insert(name.to_lowercase());
+            }
+        }
+        prev = Some(c);
+    }
+    mentions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(content: &str) -> Vec<String> {
+        parse(content).into_iter().collect()
+    }
+
+    #[test]
+    fn finds_mentions() {
+        assert_eq!(
+            vec!["alice", "bob.smith", "carol_1"],
+            names("@alice, ask @Bob.Smith. cc (@carol_1) and @alice again")
+        );
+        assert_eq!(vec!["zoë"], names("thanks @Zoë-"));
+    }
+
+    #[test]
+    fn ignores_what_is_not_a_mention() {
+        assert!(names("mail alice@example.com").is_empty());
+        assert!(names("a lone @ sign, @. and @-dash").is_empty());
+        assert!(names(&format!("@{}", "a".repeat(MAX_MENTION_CHARS + 1))).is_empty());
+    }
+}
@@ -0,0 +1,239 @@
+//! Metrics Module
+//!
+//! Prometheus metrics for requests, store operations and live streams,
+//! kept in one process-wide registry and served at `/metrics`
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    body::{Body, BoxBody},
+    http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode},
+    response::IntoResponse,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tower::Service;
+
+/// Every metric the server exports
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+/// Metrics struct - the registry and the metrics registered in it
+pub struct Metrics {
+    pub registry: Registry,
+    /// labels: method, route, status
+    pub http_requests: IntCounterVec,
+    /// labels: method, route, status
+    pub http_request_duration: HistogramVec,
+    /// labels: operation
+    pub store_operation_duration: HistogramVec,
+    pub store_lock_wait: Histogram,
+    pub posts: IntGauge,
+    /// labels: kind (`sse` or `graphql_ws`)
+    pub streaming_connections: IntGaugeVec,
+}
+
+/// Metrics implementation
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("post_server".to_string()), None)
+            .expect("valid registry prefix");
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests answered"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "time to produce an HTTP response",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let store_operation_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "store_operation_duration_seconds",
+                "time spent in each PostDb operation",
+            )
+            .buckets(prometheus::exponential_buckets(0.000_001, 4.0, 12).unwrap()),
+            &["operation"],
+        )
+        .unwrap();
+        let store_lock_wait = Histogram::with_opts(
+            HistogramOpts::new(
+                "store_lock_wait_seconds",
+                "time spent waiting for the PostDb lock",
+            )
+            .buckets(prometheus::exponential_buckets(0.000_001, 4.0, 12).unwrap()),
+        )
+        .unwrap();
+        let posts = IntGauge::new("posts", "posts currently stored").unwrap();
+        let streaming_connections = IntGaugeVec::new(
+            Opts::new(
+                "streaming_connections",
+                "open SSE and websocket connections",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(store_operation_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(store_lock_wait.clone()))
+            .unwrap();
+        registry.register(Box::new(posts.clone())).unwrap();
+        registry
+            .register(Box::new(streaming_connections.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            store_operation_duration,
+            store_lock_wait,
+            posts,
+            streaming_connections,
+        }
+    }
+
+    /// times a store operation until the returned timer is dropped
+    pub fn store_timer(&self, operation: &str) -> HistogramTimer {
+        self.store_operation_duration
+            .with_label_values(&[operation])
+            .start_timer()
+    }
+
+    /// counts a streaming connection as open until the guard is dropped
+    pub fn stream_guard(&self, kind: &str) -> StreamGuard {
+        let gauge = self.streaming_connections.with_label_values(&[kind]);
+        gauge.inc();
+        StreamGuard { gauge }
+    }
+
+    /// the metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding never fails");
+        String::from_utf8(buffer).expect("text encoding is utf-8")
+    }
+}
+
+/// StreamGuard struct - decrements its streaming gauge when dropped
+pub struct StreamGuard {
+    gauge: IntGauge,
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+/// RouteMetrics struct - a route's service, counting and timing its requests
+#[derive(Clone)]
+pub struct RouteMetrics<S> {
+    inner: S,
+    route: String,
+}
+
+/// RouteMetrics implementation
+impl<S> RouteMetrics<S> {
+    pub fn new(inner: S, route: &str) -> Self {
+        RouteMetrics {
+            inner,
+            route: route.to_string(),
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for RouteMetrics<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let method = request.method().to_string();
+        let route = self.route.clone();
+        let start = Instant::now();
+        let response = self.inner.call(request);
+
+        Box::pin(async move {
+            let response = response.await?;
+            let status = response.status().as_u16().to_string();
+            let labels = [method.as_str(), route.as_str(), status.as_str()];
+            METRICS.http_requests.with_label_values(&labels).inc();
+            METRICS
+                .http_request_duration
+                .with_label_values(&labels)
+                .observe(start.elapsed().as_secs_f64());
+            Ok(response)
+        })
+    }
+}
+
+/// Get Prometheus Metrics
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "operations",
+    responses((status = 200, description = "metrics in the Prometheus text format", body = String, content_type = "text/plain"))
+)]
+pub async fn metrics_handler() -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4; charset=utf-8"),
+    );
+    (StatusCode::OK, headers, METRICS.render())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stream_guard_tracks_open_streams() {
+        let gauge = METRICS.streaming_connections.with_label_values(&["test"]);
+        let guard = METRICS.stream_guard("test");
+        let second = METRICS.stream_guard("test");
+        assert_eq!(2, gauge.get());
+        drop(guard);
+        drop(second);
+        assert_eq!(0, gauge.get());
+    }
+
+    #[test]
+    fn renders_text_format() {
+        drop(METRICS.store_timer("render_test"));
+        let text = METRICS.render();
+        assert!(text.contains("# TYPE post_server_store_operation_duration_seconds histogram"));
+        assert!(text.contains("operation=\"render_test\""));
+    }
+}
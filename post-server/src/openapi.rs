@@ -0,0 +1,169 @@
+//! OpenAPI Module
+//!
+//! an OpenAPI 3 document built from the handler annotations and the
+//! `post_lib` request types, plus a Swagger UI page to browse it
+
+use axum::{
+    response::{Html, IntoResponse},
+    Json,
+};
+use post_lib::{
+    ApiError, CreateDraftRequest, CreatePostRequest, DraftRequest, FieldError, HealthCheck,
+    HealthReport, HealthStatus, MarkNotificationsReadRequest, ModerateRequest, NewPoll,
+    Notification, NotificationKind, PollOption, PostFlagsRequest, ReactionRequest, ReportRequest,
+    UpdateDraftRequest, UpdatePostRequest, VoteRequest,
+};
+use utoipa::OpenApi;
+
+use crate::{
+    post_db::{
+        Attachment, AuditAction, AuditEntry, ContentFormat, Draft, ModerationAction,
+        ModerationEntry, Poll, Post, PostEvent, PostEventKind, Reaction, ReactionCount, Report,
+        ReportedPost,
+    },
+    webhooks::{CreateWebhookRequest, DeadLetter, DeliveryAttempt, DeliveryStatus, Webhook},
+};
+
+/// The OpenAPI document for every route in [`crate::route_table`]
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "post-server",
+        description = "REST API for managing posts in a message board"
+    ),
+    paths(
+        crate::get_all_posts_handler,
+        crate::get_post_handler,
+        crate::new_post_handler,
+        crate::update_post_handler,
+        crate::delete_post_handler,
+        crate::drafts::new_draft_handler,
+        crate::drafts::update_draft_handler,
+        crate::drafts::drafts_handler,
+        crate::drafts::publish_draft_handler,
+        crate::drafts::delete_draft_handler,
+        crate::attachments::add_attachment_handler,
+        crate::attachments::attachment_handler,
+        crate::attachments::thumbnail_handler,
+        crate::add_reaction_handler,
+        crate::remove_reaction_handler,
+        crate::post_reactions_handler,
+        crate::vote_handler,
+        crate::notifications_handler,
+        crate::mark_notifications_read_handler,
+        crate::notification_events_handler,
+        crate::events_handler,
+        crate::update_post_flags_handler,
+        crate::report_post_handler,
+        crate::moderation_queue_handler,
+        crate::moderate_handler,
+        crate::moderation_log_handler,
+        crate::audit::audit_log_handler,
+        crate::audit::audit_export_handler,
+        crate::atom_feed_handler,
+        crate::rss_feed_handler,
+        crate::list_webhooks_handler,
+        crate::new_webhook_handler,
+        crate::delete_webhook_handler,
+        crate::webhook_deliveries_handler,
+        crate::webhook_dead_letters_handler,
+        crate::graphql::graphql_handler,
+        crate::graphql::graphiql_handler,
+        crate::graphql::graphql_ws_handler,
+        crate::health::healthz_handler,
+        crate::health::readyz_handler,
+        crate::metrics::metrics_handler,
+        openapi_handler,
+        docs_handler,
+    ),
+    components(schemas(
+        Post,
+        ContentFormat,
+        CreatePostRequest,
+        UpdatePostRequest,
+        PostFlagsRequest,
+        Draft,
+        CreateDraftRequest,
+        UpdateDraftRequest,
+        DraftRequest,
+        Attachment,
+        ReactionRequest,
+        Reaction,
+        ReactionCount,
+        Poll,
+        PollOption,
+        NewPoll,
+        VoteRequest,
+        ReportRequest,
+        Report,
+        ReportedPost,
+        ModerateRequest,
+        ModerationAction,
+        ModerationEntry,
+        AuditAction,
+        AuditEntry,
+        Notification,
+        NotificationKind,
+        MarkNotificationsReadRequest,
+        ApiError,
+        FieldError,
+        PostEvent,
+        PostEventKind,
+        Webhook,
+        CreateWebhookRequest,
+        DeliveryAttempt,
+        DeliveryStatus,
+        DeadLetter,
+        HealthReport,
+        HealthCheck,
+        HealthStatus,
+    ))
+)]
+pub struct ApiDoc;
+
+/// Swagger UI pointed at `/openapi.json`; its script and stylesheet are
+/// not bundled but loaded from unpkg.com by the browser, so the page needs
+/// internet access and trusts that CDN. `/openapi.json` itself is served
+/// locally and works with any OpenAPI viewer
+const DOCS_PAGE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8" />
+    <title>post-server API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+</head>
+<body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+    <script>
+        window.onload = () => {
+            window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+        };
+    </script>
+</body>
+</html>
+"##;
+
+/// Get OpenAPI Document
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "docs",
+    responses((status = 200, description = "this document", content_type = "application/json"))
+)]
+pub async fn openapi_handler() -> impl IntoResponse {
+    Json(ApiDoc::openapi())
+}
+
+/// Browse The API Documentation
+///
+/// Swagger UI, loaded by the browser from unpkg.com
+#[utoipa::path(
+    get,
+    path = "/docs",
+    tag = "docs",
+    responses((status = 200, description = "Swagger UI page", content_type = "text/html"))
+)]
+pub async fn docs_handler() -> Html<&'static str> {
+    Html(DOCS_PAGE)
+}
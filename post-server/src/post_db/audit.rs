@@ -0,0 +1,157 @@
+//! Audit
+//!
+//! the append-only log of every change made to a post, a draft or a
+//! webhook, with a broadcast channel announcing new entries so they can
+//! be written to the audit file
+
+use tokio::sync::broadcast;
+
+use super::{AuditEntry, AuditQuery};
+
+/// AuditLog struct - every entry, oldest first
+pub struct AuditLog {
+    next_entry_id: u64,
+    entries: Vec<AuditEntry>,
+    /// the id of the latest entry known to be in the audit file
+    written: u64,
+    sender: broadcast::Sender<u64>,
+}
+
+/// AuditLog default implementation
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// AuditLog implementation
+impl AuditLog {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(64);
+        AuditLog {
+            next_entry_id: 1,
+            entries: vec![],
+            written: 0,
+            sender,
+        }
+    }
+
+    /// replace the log with one read back from the audit file, keeping
+    /// one entry per id
+    pub fn restore(&mut self, mut entries: Vec<AuditEntry>) {
+        entries.sort_by_key(|entry| entry.entry_id);
+        entries.dedup_by_key(|entry| entry.entry_id);
+        self.written = entries.last().map_or(0, |entry| entry.entry_id);
+        self.next_entry_id = self.written + 1;
+        self.entries = entries;
+    }
+
+    /// add an entry, giving it the next id; entries are never changed or
+    /// removed
+    pub fn record(&mut self, entry: AuditEntry) -> AuditEntry {
+        let entry = AuditEntry {
+            entry_id: self.next_entry_id,
+            ..entry
+        };
+        self.next_entry_id += 1;
+        self.entries.push(entry.clone());
+        // an error only means nobody is listening right now
+        let _ = self.sender.send(entry.entry_id);
+        entry
+    }
+
+    /// the entries passing `query`, oldest first
+    pub fn query(&self, query: &AuditQuery) -> Vec<AuditEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| query.matches(entry))
+            .cloned()
+            .collect()
+    }
+
+    /// the entries not yet written to the audit file, oldest first
+    pub fn unwritten(&self) -> Vec<AuditEntry> {
+        let start = self
+            .entries
+            .partition_point(|entry| entry.entry_id <= self.written);
+        self.entries[start..].to_vec()
+    }
+
+    /// note that every entry up to `entry_id` is in the audit file
+    pub fn mark_written(&mut self, entry_id: u64) {
+        self.written = self.written.max(entry_id);
+    }
+
+    /// the ids of new entries, as they are added
+    pub fn subscribe(&self) -> broadcast::Receiver<u64> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::post_db::AuditAction;
+
+    #[test]
+    fn entries_are_appended_and_filtered() {
+        let mut log = AuditLog::new();
+        let mut receiver = log.subscribe();
+        let record = |log: &mut AuditLog, actor: &str, action, post_id, created_at| {
+            log.record(AuditEntry {
+                entry_id: 0,
+                actor: actor.to_string(),
+                action,
+                post_id: Some(post_id),
+                draft_id: None,
+                webhook_id: None,
+                before: None,
+                after: None,
+                request_id: None,
+                created_at,
+            })
+        };
+        record(&mut log, "ann", AuditAction::Create, 1, 10);
+        record(&mut log, "mod", AuditAction::Hide, 1, 20);
+        record(&mut log, "ann", AuditAction::Create, 2, 30);
+        assert_eq!(3, std::iter::from_fn(|| receiver.try_recv().ok()).count());
+
+        let ids_of = |entries: &[AuditEntry]| -> Vec<u64> {
+            entries.iter().map(|entry| entry.entry_id).collect()
+        };
+        let ids = |query: AuditQuery| ids_of(&log.query(&query));
+        assert_eq!(vec![1, 2, 3], ids(AuditQuery::default()));
+        assert_eq!(
+            vec![1, 3],
+            ids(AuditQuery {
+                actor: Some("ann".to_string()),
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            vec![2],
+            ids(AuditQuery {
+                post_id: Some(1),
+                since: Some(15),
+                until: Some(25),
+                ..Default::default()
+            })
+        );
+
+        // only entries after the last one written are left to write
+        assert_eq!(3, log.unwritten().len());
+        log.mark_written(2);
+        assert_eq!(vec![3], ids_of(&log.unwritten()));
+
+        // ids carry on after a restore, which counts as written
+        let mut saved = log.query(&AuditQuery::default());
+        saved.push(saved[0].clone());
+        let mut restored = AuditLog::new();
+        restored.restore(saved);
+        assert_eq!(3, restored.query(&AuditQuery::default()).len());
+        assert!(restored.unwritten().is_empty());
+        let entry = record(&mut restored, "ann", AuditAction::Delete, 2, 40);
+        assert_eq!(4, entry.entry_id);
+        assert_eq!(vec![4], ids_of(&restored.unwritten()));
+    }
+}
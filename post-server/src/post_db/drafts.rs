@@ -0,0 +1,248 @@
+//! Drafts
+//!
+//! unpublished posts, only handed out to their authors, with a broadcast
+//! channel announcing every change so the store can be saved
+
+use std::collections::BTreeMap;
+
+use tokio::sync::broadcast;
+
+use super::{ContentFormat, Draft};
+
+/// Drafts struct - every unpublished draft, by id
+pub struct Drafts {
+    next_draft_id: u64,
+    drafts: BTreeMap<u64, Draft>,
+    sender: broadcast::Sender<u64>,
+}
+
+/// Drafts default implementation
+impl Default for Drafts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drafts implementation
+impl Drafts {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(64);
+        Drafts {
+            next_draft_id: 1,
+            drafts: BTreeMap::new(),
+            sender,
+        }
+    }
+
+    /// replace every draft with saved ones
+    pub fn restore(&mut self, drafts: Vec<Draft>) {
+        self.next_draft_id = drafts
+            .iter()
+            .map(|draft| draft.draft_id + 1)
+            .max()
+            .unwrap_or(1);
+        self.drafts = drafts
+            .into_iter()
+            .map(|draft| (draft.draft_id, draft))
+            .collect();
+    }
+
+    /// never hand out ids below `next_draft_id`, ones used before a restart
+    pub fn skip_ids_before(&mut self, next_draft_id: u64) {
+        self.next_draft_id = self.next_draft_id.max(next_draft_id);
+    }
+
+    /// the id the next draft will get
+    pub fn next_draft_id(&self) -> u64 {
+        self.next_draft_id
+    }
+
+    /// save a new draft
+    pub fn create(
+        &mut self,
+        author: String,
+        content: String,
+        format: ContentFormat,
+        publish_at: Option<u64>,
+        now: u64,
+    ) -> Draft {
+        let draft = Draft {
+            draft_id: self.next_draft_id,
+            author,
+            content,
+            format,
+            publish_at,
+            created_at: now,
+            updated_at: now,
+        };
+        self.next_draft_id += 1;
+        self.drafts.insert(draft.draft_id, draft.clone());
+        self.changed(draft.draft_id);
+        draft
+    }
+
+    /// replace the content and publish time of one of `author`'s drafts
+    pub fn update(
+        &mut self,
+        draft_id: u64,
+        author: &str,
+        content: String,
+        publish_at: Option<u64>,
+        now: u64,
+    ) -> Option<Draft> {
+        let draft = self
+            .drafts
+            .get_mut(&draft_id)
+            .filter(|draft| draft.author == author)?;
+        draft.content = content;
+        draft.publish_at = publish_at;
+        draft.updated_at = now;
+        let draft = draft.clone();
+        self.changed(draft_id);
+        Some(draft)
+    }
+
+    /// one of `author`'s drafts
+    pub fn get(&self, draft_id: u64, author: &str) -> Option<Draft> {
+        self.drafts
+            .get(&draft_id)
+            .filter(|draft| draft.author == author)
+            .cloned()
+    }
+
+    /// `author`'s drafts, oldest first
+    pub fn list(&self, author: &str) -> Vec<Draft> {
+        self.drafts
+            .values()
+            .filter(|draft| draft.author == author)
+            .cloned()
+            .collect()
+    }
+
+    /// take one of `author`'s drafts out of the store
+    pub fn remove(&mut self, draft_id: u64, author: &str) -> Option<Draft> {
+        if self.drafts.get(&draft_id)?.author != author {
+            return None;
+        }
+        let draft = self.drafts.remove(&draft_id);
+        self.changed(draft_id);
+        draft
+    }
+
+    /// return a draft taken out by [`Drafts::remove`] or
+    /// [`Drafts::take_due`], keeping its id
+    pub fn put_back(&mut self, draft: Draft) {
+        let draft_id = draft.draft_id;
+        self.drafts.insert(draft_id, draft);
+        self.changed(draft_id);
+    }
+
+    /// take out every draft due by `now`, earliest publish time first
+    pub fn take_due(&mut self, now: u64) -> Vec<Draft> {
+        let mut due: Vec<Draft> = self
+            .drafts
+            .values()
+            .filter(|draft| draft.publish_at.is_some_and(|publish_at| publish_at <= now))
+            .cloned()
+            .collect();
+        due.sort_by_key(|draft| (draft.publish_at, draft.draft_id));
+        for draft in &due {
+            self.drafts.remove(&draft.draft_id);
+            self.changed(draft.draft_id);
+        }
+        due
+    }
+
+    /// every draft, for saving
+    pub fn all(&self) -> Vec<Draft> {
+        self.drafts.values().cloned().collect()
+    }
+
+    /// the ids of drafts as they change
+    pub fn subscribe(&self) -> broadcast::Receiver<u64> {
+        self.sender.subscribe()
+    }
+
+    fn changed(&self, draft_id: u64) {
+        // an error only means nobody is listening right now
+        let _ = self.sender.send(draft_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drafts_belong_to_their_authors() {
+        let mut drafts = Drafts::new();
+        let draft = drafts.create(
+            "ann".to_string(),
+            "first".to_string(),
+            ContentFormat::Plain,
+            None,
+            10,
+        );
+        drafts.create(
+            "bob".to_string(),
+            "second".to_string(),
+            ContentFormat::Plain,
+            None,
+            10,
+        );
+
+        assert_eq!(vec![draft.clone()], drafts.list("ann"));
+        assert!(drafts
+            .update(draft.draft_id, "bob", "stolen".to_string(), None, 20)
+            .is_none());
+        let updated = drafts
+            .update(draft.draft_id, "ann", "edited".to_string(), Some(30), 20)
+            .unwrap();
+        assert_eq!(
+            ("edited", Some(30), 20),
+            (
+                updated.content.as_str(),
+                updated.publish_at,
+                updated.updated_at
+            )
+        );
+
+        assert!(drafts.remove(draft.draft_id, "bob").is_none());
+        assert_eq!(Some(updated), drafts.remove(draft.draft_id, "ann"));
+        assert!(drafts.list("ann").is_empty());
+    }
+
+    #[test]
+    fn due_drafts_are_taken_in_publish_order() {
+        let mut drafts = Drafts::new();
+        for publish_at in [Some(30), None, Some(20), Some(50)] {
+            drafts.create(
+                "ann".to_string(),
+                "soon".to_string(),
+                ContentFormat::Plain,
+                publish_at,
+                0,
+            );
+        }
+        let ids = |due: Vec<Draft>| due.iter().map(|draft| draft.draft_id).collect::<Vec<_>>();
+        assert!(drafts.take_due(10).is_empty());
+        assert_eq!(vec![3, 1], ids(drafts.take_due(30)));
+        assert!(drafts.take_due(30).is_empty());
+
+        let mut restored = Drafts::new();
+        restored.restore(drafts.all());
+        assert_eq!(vec![2, 4], ids(restored.list("ann")));
+        assert_eq!(
+            5,
+            restored
+                .create(
+                    "ann".to_string(),
+                    String::new(),
+                    ContentFormat::Plain,
+                    None,
+                    0
+                )
+                .draft_id
+        );
+    }
+}
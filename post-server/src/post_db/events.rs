@@ -0,0 +1,175 @@
+//! Post Change Events
+//!
+//! every change made through the PostDb is recorded as a PostEvent,
+//! kept in a bounded ring buffer for replay and broadcast to live subscribers
+
+use std::collections::VecDeque;
+
+use tokio::sync::broadcast;
+
+use super::{Post, PostEvent, PostEventKind, Reaction};
+
+/// number of events kept around for `Last-Event-ID` replay
+pub const EVENT_BUFFER_CAPACITY: usize = 256;
+
+/// EventLog struct - a ring buffer of recent events plus a broadcast channel
+pub struct EventLog {
+    next_event_id: u64,
+    capacity: usize,
+    buffer: VecDeque<PostEvent>,
+    sender: broadcast::Sender<PostEvent>,
+}
+
+/// EventLog default implementation
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new(EVENT_BUFFER_CAPACITY)
+    }
+}
+
+/// EventLog implementation
+impl EventLog {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        EventLog {
+            next_event_id: 1,
+            capacity,
+            buffer: VecDeque::with_capacity(capacity),
+            sender,
+        }
+    }
+
+    /// the id of the latest event, 0 before the first one
+    pub fn last_event_id(&self) -> u64 {
+        self.next_event_id - 1
+    }
+
+    /// carry on numbering events after `last_event_id`, as saved before a
+    /// restart, so clients replaying with `Last-Event-ID` never see an id
+    /// used twice
+    pub fn restore(&mut self, last_event_id: u64) {
+        self.next_event_id = self.next_event_id.max(last_event_id + 1);
+    }
+
+    /// record a new event, dropping the oldest one if the buffer is full
+    pub fn push(&mut self, kind: PostEventKind, post_id: u64, post: Option<Post>) -> PostEvent {
+        self.push_event(kind, post_id, post, None)
+    }
+
+    /// record a reaction being added or removed
+    pub fn push_reaction(
+        &mut self,
+        kind: PostEventKind,
+        post: Post,
+        reaction: Reaction,
+    ) -> PostEvent {
+        self.push_event(kind, post.post_id, Some(post), Some(reaction))
+    }
+
+    fn push_event(
+        &mut self,
+        kind: PostEventKind,
+        post_id: u64,
+        post: Option<Post>,
+        reaction: Option<Reaction>,
+    ) -> PostEvent {
+        let event = PostEvent {
+            event_id: self.next_event_id,
+            kind,
+            post_id,
+            // only moderators get to see hidden posts
+            post: post.filter(|post| !post.hidden),
+            reaction,
+        };
+        self.next_event_id += 1;
+
+        if self.buffer.len() == self.capacity {
+            self.buffer.pop_front();
+        }
+        if self.capacity > 0 {
+            self.buffer.push_back(event.clone());
+        }
+
+        // an error only means nobody is listening right now
+        let _ = self.sender.send(event.clone());
+        event
+    }
+
+    /// subscribe to live events
+    ///
+    /// returns the buffered events newer than `last_event_id` (nothing if
+    /// `None`) together with a receiver for everything after them
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Vec<PostEvent>, broadcast::Receiver<PostEvent>) {
+        let backlog = match last_event_id {
+            Some(last_event_id) => self
+                .buffer
+                .iter()
+                .filter(|event| event.event_id > last_event_id)
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        (backlog, self.sender.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn buffer_is_bounded() {
+        let mut log = EventLog::new(2);
+        log.push(PostEventKind::Created, 1, None);
+        log.push(PostEventKind::Created, 2, None);
+        log.push(PostEventKind::Deleted, 1, None);
+
+        let (backlog, _) = log.subscribe(Some(0));
+        let ids: Vec<u64> = backlog.iter().map(|event| event.event_id).collect();
+        assert_eq!(vec![2, 3], ids);
+    }
+
+    #[test]
+    fn replay_after_last_event_id() {
+        let mut log = EventLog::default();
+        log.push(PostEventKind::Created, 1, None);
+        log.push(PostEventKind::Updated, 1, None);
+        log.push(PostEventKind::Deleted, 1, None);
+
+        let (backlog, _) = log.subscribe(Some(1));
+        let kinds: Vec<PostEventKind> = backlog.iter().map(|event| event.kind).collect();
+        assert_eq!(vec![PostEventKind::Updated, PostEventKind::Deleted], kinds);
+
+        let (backlog, _) = log.subscribe(None);
+        assert!(backlog.is_empty());
+    }
+
+    #[test]
+    fn ids_carry_on_after_a_restore() {
+        let mut log = EventLog::default();
+        assert_eq!(0, log.last_event_id());
+        log.restore(41);
+        let event = log.push(PostEventKind::Created, 1, None);
+        assert_eq!(42, event.event_id);
+        assert_eq!(42, log.last_event_id());
+
+        // an older saved id never moves the numbering back
+        log.restore(7);
+        assert_eq!(43, log.push(PostEventKind::Deleted, 1, None).event_id);
+    }
+
+    #[test]
+    fn subscribers_receive_new_events() {
+        let mut log = EventLog::default();
+        let (_, mut receiver) = log.subscribe(None);
+
+        log.push(PostEventKind::Created, 7, None);
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(7, event.post_id);
+        assert_eq!(PostEventKind::Created, event.kind);
+    }
+}
@@ -2,14 +2,43 @@
 //!
 //! this is a simple container for posts
 
+mod audit;
+mod drafts;
+mod events;
+mod moderation;
+mod notifications;
+mod polls;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    sync::{LockResult, Mutex, MutexGuard},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub use post_lib::{
+    Attachment, AuditAction, AuditEntry, AuditQuery, ContentFormat, Draft, ModerationAction,
+    ModerationEntry, Notification, NotificationKind, Poll, Post, PostEvent, PostEventKind,
+    Reaction, ReactionCount, Report, ReportedPost,
+};
 use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast;
 
-/// Post struct
-#[derive(Debug, Serialize, Clone)]
-pub struct Post {
-    post_id: u64,
-    content: String,
-}
+use crate::{
+    filters::{ContentFilter, Decision, Filters, Flag, SpamModel},
+    markdown, mentions,
+    metrics::METRICS,
+    request_id,
+    webhooks::Webhook,
+};
+use audit::AuditLog;
+use drafts::Drafts;
+use events::EventLog;
+pub use events::EVENT_BUFFER_CAPACITY;
+use moderation::Moderation;
+use notifications::Notifications;
+pub use polls::Vote;
+use polls::Votes;
 
 /// PostDb struct - just a list of Posts
 ///
@@ -24,13 +53,26 @@ pub struct Post {
 ///
 /// let result = db.create_post("some content".to_string());
 /// assert!(result.status == PostDbStatus::Ok);
-/// assert!(result.value == 1);
+/// assert!(result.value.post_id() == Some(1));
 ///
 /// let result = db.get_post(1);
 /// assert!(result.status == PostDbStatus::Ok);
 /// ```
 pub struct PostDb {
     pub posts: Vec<Post>,
+    pub last_modified: u64,
+    /// never handed out again, even once the post is deleted
+    next_post_id: u64,
+    events: EventLog,
+    /// who reacted to each post, by post id then emoji
+    reactions: BTreeMap<u64, BTreeMap<String, BTreeSet<String>>>,
+    votes: Votes,
+    next_attachment_id: u64,
+    notifications: Notifications,
+    drafts: Drafts,
+    moderation: Moderation,
+    filters: Filters,
+    audit: AuditLog,
 }
 
 /// Status returned as part of the response
@@ -40,6 +82,40 @@ pub enum PostDbStatus {
     Err,
 }
 
+/// what became of a new post once the content filters had seen it
+#[derive(PartialEq, Debug)]
+pub enum Submission {
+    Published(u64),
+    /// stored hidden, waiting in the moderation queue
+    Held(u64),
+    Rejected(Flag),
+}
+
+/// Submission implementation
+impl Submission {
+    /// the new post's id, unless it was rejected
+    pub fn post_id(&self) -> Option<u64> {
+        match self {
+            Submission::Published(post_id) | Submission::Held(post_id) => Some(*post_id),
+            Submission::Rejected(_) => None,
+        }
+    }
+}
+
+/// what became of an edit to a post
+#[derive(PartialEq, Debug)]
+pub enum Edit {
+    Updated(u64),
+    /// no post with that id, or it is hidden
+    Missing,
+    /// a moderator locked the post against edits
+    Locked,
+    /// saved, but hidden and waiting in the moderation queue
+    Held(u64),
+    /// not saved, the content filters having refused the new content
+    Rejected(Flag),
+}
+
 /// Response that contains the status and any returned values
 #[derive(Serialize)]
 pub struct PostDbResponse<T> {
@@ -57,33 +133,308 @@ impl Default for PostDb {
 /// PostDb implementation
 impl PostDb {
     pub fn new() -> Self {
-        PostDb { posts: vec![] }
+        Self::with_event_capacity(EVENT_BUFFER_CAPACITY)
+    }
+
+    /// an empty PostDb keeping `capacity` events for replay
+    pub fn with_event_capacity(capacity: usize) -> Self {
+        PostDb {
+            posts: vec![],
+            last_modified: now(),
+            next_post_id: 1,
+            events: EventLog::new(capacity),
+            reactions: BTreeMap::new(),
+            votes: Votes::new(),
+            next_attachment_id: 1,
+            notifications: Notifications::new(),
+            drafts: Drafts::new(),
+            moderation: Moderation::new(),
+            filters: Filters::default(),
+            audit: AuditLog::new(),
+        }
+    }
+
+    /// run every new post through `chain` from now on
+    ///
+    /// see [`crate::filters::chain`]
+    pub fn set_filters(&mut self, chain: Vec<Box<dyn ContentFilter>>) {
+        self.filters.set_chain(chain);
+    }
+
+    /// replace the posts with ones loaded from storage, without emitting events
+    pub fn restore(&mut self, posts: Vec<Post>) {
+        self.last_modified = posts
+            .iter()
+            .map(|post| post.updated_at)
+            .max()
+            .unwrap_or(self.last_modified);
+        self.next_post_id = posts.iter().map(|post| post.post_id + 1).max().unwrap_or(1);
+        self.next_attachment_id = posts
+            .iter()
+            .flat_map(|post| &post.attachments)
+            .map(|attachment| attachment.attachment_id + 1)
+            .max()
+            .unwrap_or(1);
+        self.posts = posts;
+        self.reactions.clear();
+        self.votes = Votes::new();
+        METRICS.posts.set(self.posts.len() as i64);
+    }
+
+    /// replace the reactions with ones loaded from storage, without
+    /// emitting events; reactions to unknown posts are dropped
+    pub fn restore_reactions(&mut self, reactions: Vec<(u64, Reaction)>) {
+        self.reactions.clear();
+        for (post_id, reaction) in reactions {
+            if self.posts.iter().any(|post| post.post_id == post_id) {
+                self.reactions
+                    .entry(post_id)
+                    .or_default()
+                    .entry(reaction.emoji)
+                    .or_default()
+                    .insert(reaction.user);
+            }
+        }
+        for index in 0..self.posts.len() {
+            self.posts[index].reactions = self.reaction_counts(self.posts[index].post_id);
+        }
+    }
+
+    /// replace the poll votes with ones loaded from storage, without
+    /// emitting events; votes in unknown polls are dropped
+    pub fn restore_votes(&mut self, votes: Vec<(u64, Vote)>) {
+        let votes = votes
+            .into_iter()
+            .filter(|(post_id, _)| {
+                self.posts
+                    .iter()
+                    .any(|post| post.post_id == *post_id && post.poll.is_some())
+            })
+            .collect();
+        self.votes.restore(votes);
+        for post in self.posts.iter_mut() {
+            if let Some(poll) = post.poll.as_mut() {
+                self.votes.tally(post.post_id, poll);
+            }
+        }
+    }
+
+    /// replace the notifications with ones loaded from storage;
+    /// notifications about unknown posts are dropped
+    pub fn restore_notifications(&mut self, notifications: Vec<Notification>) {
+        let notifications = notifications
+            .into_iter()
+            .filter(|notification| self.has_post(notification.post_id))
+            .collect();
+        self.notifications.restore(notifications);
+    }
+
+    /// replace the drafts with ones loaded from storage
+    pub fn restore_drafts(&mut self, drafts: Vec<Draft>) {
+        self.drafts.restore(drafts);
+    }
+
+    /// replace the open reports and the moderation log with ones loaded
+    /// from storage; reports against unknown posts are dropped, but the
+    /// log is kept whole
+    pub fn restore_moderation(&mut self, reports: Vec<Report>, log: Vec<ModerationEntry>) {
+        let reports = reports
+            .into_iter()
+            .filter(|report| self.has_post(report.post_id))
+            .collect();
+        self.moderation.restore(reports, log);
     }
 
-    /// return all posts from the database
+    /// replace the audit log with one loaded from storage; entries about
+    /// deleted posts are kept, and the ids of posts and drafts they name are
+    /// not handed out again
+    pub fn restore_audit_log(&mut self, entries: Vec<AuditEntry>) {
+        let next_post_id = entries
+            .iter()
+            .filter_map(|entry| entry.post_id)
+            .map(|post_id| post_id + 1)
+            .max()
+            .unwrap_or(1);
+        let next_draft_id = entries
+            .iter()
+            .filter_map(|entry| entry.draft_id)
+            .map(|draft_id| draft_id + 1)
+            .max()
+            .unwrap_or(1);
+        self.restore_next_ids(next_post_id, next_draft_id);
+        self.audit.restore(entries);
+    }
+
+    /// hand out post and draft ids from the ones saved before a restart at
+    /// the earliest, so deleting the newest doesn't free its id
+    pub fn restore_next_ids(&mut self, next_post_id: u64, next_draft_id: u64) {
+        self.next_post_id = self.next_post_id.max(next_post_id);
+        self.drafts.skip_ids_before(next_draft_id);
+    }
+
+    /// the ids the next post and draft will get, for saving alongside them
+    pub fn next_ids(&self) -> (u64, u64) {
+        (self.next_post_id, self.drafts.next_draft_id())
+    }
+
+    /// number new events after `last_event_id`, the latest one saved
+    /// before a restart
+    pub fn restore_last_event_id(&mut self, last_event_id: u64) {
+        self.events.restore(last_event_id);
+    }
+
+    /// the id of the latest post change event, for saving alongside the posts
+    pub fn last_event_id(&self) -> u64 {
+        self.events.last_event_id()
+    }
+
+    /// replace what the spam filter has learned with what was saved
+    pub fn restore_spam_model(&mut self, spam: SpamModel) {
+        self.filters.restore_spam_model(spam);
+    }
+
+    /// lock the shared PostDb, recording how long the lock took to get
+    pub fn lock(post_db: &Mutex<PostDb>) -> LockResult<MutexGuard<'_, PostDb>> {
+        let start = Instant::now();
+        let guard = post_db.lock();
+        METRICS
+            .store_lock_wait
+            .observe(start.elapsed().as_secs_f64());
+        guard
+    }
+
+    /// subscribe to post change events
+    ///
+    /// see [`EventLog::subscribe`]
+    pub fn subscribe(
+        &self,
+        last_event_id: Option<u64>,
+    ) -> (Vec<PostEvent>, broadcast::Receiver<PostEvent>) {
+        self.events.subscribe(last_event_id)
+    }
+
+    /// subscribe to draft changes, which are private and so never
+    /// show up as post change events
+    pub fn subscribe_drafts(&self) -> broadcast::Receiver<u64> {
+        self.drafts.subscribe()
+    }
+
+    /// subscribe to new notifications, and to notifications being marked read
+    pub fn subscribe_notifications(&self) -> broadcast::Receiver<Notification> {
+        self.notifications.subscribe()
+    }
+
+    /// subscribe to changes to reports and the moderation log, which only
+    /// moderators see and so never show up as post change events
+    pub fn subscribe_moderation(&self) -> broadcast::Receiver<u64> {
+        self.moderation.subscribe()
+    }
+
+    /// subscribe to new audit log entries
+    pub fn subscribe_audit(&self) -> broadcast::Receiver<u64> {
+        self.audit.subscribe()
+    }
+
+    /// return all posts from the database but hidden ones, pinned posts
+    /// first in the order they were pinned
+    #[tracing::instrument(level = "debug", skip_all)]
     pub fn get_posts(&self) -> Vec<Post> {
-        self.posts.clone()
+        let _timer = METRICS.store_timer("get_posts");
+        let mut posts: Vec<Post> = self
+            .posts
+            .iter()
+            .filter(|post| !post.hidden)
+            .cloned()
+            .collect();
+        // stable, so unpinned posts keep their order
+        posts.sort_by_key(|post| (post.pinned_at.is_none(), post.pinned_at));
+        posts
+    }
+
+    /// create a new plain text post
+    pub fn create_post(&mut self, content: String) -> PostDbResponse<Submission> {
+        self.create_post_as(content, ContentFormat::Plain)
+    }
+
+    /// create a new post written in `format`
+    pub fn create_post_as(
+        &mut self,
+        content: String,
+        format: ContentFormat,
+    ) -> PostDbResponse<Submission> {
+        self.create_post_with_poll(content, format, None)
     }
 
-    /// create a new post
-    pub fn create_post(&mut self, content: String) -> PostDbResponse<u64> {
-        let id: u64 = self.get_post_id((self.posts.len() + 1).try_into().unwrap());
+    /// create a new post written in `format`, asking `poll` if given
+    ///
+    /// the content filters see the post first: rejected posts are not
+    /// stored, and held ones are stored hidden, with an open report from
+    /// the filter so they show up in the moderation queue. Held posts
+    /// emit no event and notify nobody they mention
+    #[tracing::instrument(level = "debug", skip(self, content, poll))]
+    pub fn create_post_with_poll(
+        &mut self,
+        content: String,
+        format: ContentFormat,
+        poll: Option<Poll>,
+    ) -> PostDbResponse<Submission> {
+        let _timer = METRICS.store_timer("create_post");
+        let created_at = now();
+        let held = match self.filters.check(&content, created_at, &self.posts) {
+            Decision::Allow => None,
+            Decision::Hold(flag) => Some(flag),
+            Decision::Reject(flag) => {
+                tracing::info!(filter = flag.filter, reason = %flag.reason, "rejected a post");
+                return PostDbResponse {
+                    status: PostDbStatus::Err,
+                    value: Submission::Rejected(flag),
+                };
+            }
+        };
+        let id = self.next_post_id;
+        self.next_post_id += 1;
+        let mentioned = mentions::parse(&content);
         let post = Post {
+            content_html: markdown::render_content(&content, format),
             content,
+            format,
             post_id: id,
+            reactions: vec![],
+            attachments: vec![],
+            poll,
+            pinned_at: None,
+            locked: false,
+            hidden: held.is_some(),
+            created_at,
+            updated_at: created_at,
         };
 
-        self.posts.push(post);
+        self.posts.push(post.clone());
+        METRICS.posts.set(self.posts.len() as i64);
+        self.audit(AuditAction::Create, id, None, Some(&post));
+        if let Some(flag) = held {
+            tracing::info!(post_id = id, filter = flag.filter, reason = %flag.reason, "held a post");
+            self.hold(id, flag, created_at);
+            return PostDbResponse {
+                status: PostDbStatus::Ok,
+                value: Submission::Held(id),
+            };
+        }
+        self.last_modified = created_at;
+        self.events.push(PostEventKind::Created, id, Some(post));
+        self.notify_mentioned(mentioned, id, created_at);
         PostDbResponse {
             status: PostDbStatus::Ok,
-            value: id,
+            value: Submission::Published(id),
         }
     }
 
-    /// get a post by id
+    /// get a post by id, unless it is hidden
+    #[tracing::instrument(level = "debug", skip(self))]
     pub fn get_post(&self, id: u64) -> PostDbResponse<Option<Post>> {
+        let _timer = METRICS.store_timer("get_post");
         for post in self.posts.clone().into_iter() {
-            if post.post_id == id {
+            if post.post_id == id && !post.hidden {
                 return PostDbResponse {
                     status: PostDbStatus::Ok,
                     value: Some(post),
@@ -97,51 +448,1010 @@ impl PostDb {
     }
 
     /// delete a post by id
+    #[tracing::instrument(level = "debug", skip(self))]
     pub fn delete_post(&mut self, id: u64) -> PostDbResponse<Option<u64>> {
-        for (post_index, post) in self.posts.clone().into_iter().enumerate() {
-            if post.post_id == id {
-                let found_post = self.posts.remove(post_index);
-                return PostDbResponse {
+        let _timer = METRICS.store_timer("delete_post");
+        match self.remove_post(id) {
+            Some(found_post) => {
+                self.audit(AuditAction::Delete, id, Some(&found_post), None);
+                PostDbResponse {
                     status: PostDbStatus::Ok,
                     value: Some(found_post.post_id),
-                };
+                }
             }
-        }
-        PostDbResponse {
-            status: PostDbStatus::Err,
-            value: None,
+            None => PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            },
         }
     }
 
-    /// update a post by id with updated content
-    pub fn update_post(&mut self, id: u64, updated_content: String) -> PostDbResponse<Option<u64>> {
+    /// update a post by id with updated content, unless it is locked
+    ///
+    /// hidden posts are reported missing, so edits to them are neither
+    /// broadcast nor notify anyone. The content filters see the new content
+    /// as they see a new post's: rejected edits are not saved, and held ones
+    /// are saved but hide the post until a moderator looks at it
+    #[tracing::instrument(level = "debug", skip(self, updated_content))]
+    pub fn update_post(&mut self, id: u64, updated_content: String) -> PostDbResponse<Edit> {
+        let _timer = METRICS.store_timer("update_post");
         for (index, post) in self.posts.clone().iter_mut().enumerate() {
-            if post.post_id == id {
+            if post.post_id == id && !post.hidden {
+                if post.locked {
+                    return PostDbResponse {
+                        status: PostDbStatus::Err,
+                        value: Edit::Locked,
+                    };
+                }
+                let updated_at = now();
+                // a post does not repeat itself
+                let others: Vec<Post> = self
+                    .posts
+                    .iter()
+                    .filter(|other| other.post_id != id)
+                    .cloned()
+                    .collect();
+                let held = match self.filters.check(&updated_content, updated_at, &others) {
+                    Decision::Allow => None,
+                    Decision::Hold(flag) => Some(flag),
+                    Decision::Reject(flag) => {
+                        tracing::info!(post_id = id, filter = flag.filter, reason = %flag.reason, "rejected an edit");
+                        return PostDbResponse {
+                            status: PostDbStatus::Err,
+                            value: Edit::Rejected(flag),
+                        };
+                    }
+                };
+                // only people mentioned for the first time hear about an edit
+                let mut mentioned = mentions::parse(&updated_content);
+                for already in mentions::parse(&post.content) {
+                    mentioned.remove(&already);
+                }
+                let before = self.posts[index].clone();
+                self.posts[index].content_html =
+                    markdown::render_content(&updated_content, post.format);
                 self.posts[index].content = updated_content;
+                self.posts[index].updated_at = updated_at;
+                self.last_modified = updated_at;
+                if let Some(flag) = held {
+                    tracing::info!(post_id = id, filter = flag.filter, reason = %flag.reason, "held an edit");
+                    self.set_hidden(id, true);
+                    let held_post = self.posts[index].clone();
+                    self.audit(AuditAction::Update, id, Some(&before), Some(&held_post));
+                    self.hold(id, flag, updated_at);
+                    return PostDbResponse {
+                        status: PostDbStatus::Ok,
+                        value: Edit::Held(id),
+                    };
+                }
+                let updated_post = self.posts[index].clone();
+                self.audit(AuditAction::Update, id, Some(&before), Some(&updated_post));
+                self.events
+                    .push(PostEventKind::Updated, id, Some(updated_post));
+                self.notify_mentioned(mentioned, id, updated_at);
                 return PostDbResponse {
                     status: PostDbStatus::Ok,
-                    value: Some(id),
+                    value: Edit::Updated(id),
                 };
             }
         }
         PostDbResponse {
             status: PostDbStatus::Err,
-            value: None,
+            value: Edit::Missing,
         }
     }
 
-    /// get the next available post id
-    /// based on the number of posts
-    fn get_post_id(&self, id: u64) -> u64 {
-        for post in self.posts.clone().into_iter() {
-            if post.post_id == id {
-                return self.get_post_id(id + 1);
+    /// whether a post with this id exists
+    pub fn has_post(&self, id: u64) -> bool {
+        self.posts.iter().any(|post| post.post_id == id)
+    }
+
+    /// whether a post with this id exists and is not hidden
+    pub fn is_visible(&self, id: u64) -> bool {
+        self.posts
+            .iter()
+            .any(|post| post.post_id == id && !post.hidden)
+    }
+
+    /// whether a moderator locked the post with this id against edits
+    pub fn is_locked(&self, id: u64) -> bool {
+        self.posts
+            .iter()
+            .any(|post| post.post_id == id && post.locked)
+    }
+
+    /// pin or unpin and lock or unlock a post, leaving a flag that is
+    /// `None` as it is; pinning a pinned post keeps its pin time
+    ///
+    /// hidden posts are reported missing
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn set_post_flags(
+        &mut self,
+        id: u64,
+        pinned: Option<bool>,
+        locked: Option<bool>,
+    ) -> PostDbResponse<Option<Post>> {
+        let _timer = METRICS.store_timer("set_post_flags");
+        let Some(post) = self
+            .posts
+            .iter_mut()
+            .find(|post| post.post_id == id && !post.hidden)
+        else {
+            return PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            };
+        };
+        let before = post.clone();
+        match pinned {
+            Some(true) if post.pinned_at.is_none() => post.pinned_at = Some(now()),
+            Some(false) => post.pinned_at = None,
+            _ => {}
+        }
+        if let Some(locked) = locked {
+            post.locked = locked;
+        }
+        let post = post.clone();
+        if (post.pinned_at, post.locked) != (before.pinned_at, before.locked) {
+            // the content is unchanged, but listings are not
+            self.last_modified = now();
+            self.audit(AuditAction::Flag, id, Some(&before), Some(&post));
+            self.events
+                .push(PostEventKind::Updated, id, Some(post.clone()));
+        }
+        PostDbResponse {
+            status: PostDbStatus::Ok,
+            value: Some(post),
+        }
+    }
+
+    /// attach an uploaded file to a post, giving it the next attachment id;
+    /// hidden posts are reported missing
+    #[tracing::instrument(level = "debug", skip(self, attachment), fields(sha256 = %attachment.sha256))]
+    pub fn add_attachment(
+        &mut self,
+        post_id: u64,
+        attachment: Attachment,
+    ) -> PostDbResponse<Option<Attachment>> {
+        let _timer = METRICS.store_timer("add_attachment");
+        let Some(post) = self
+            .posts
+            .iter_mut()
+            .find(|post| post.post_id == post_id && !post.hidden)
+        else {
+            return PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            };
+        };
+        let attachment = Attachment {
+            attachment_id: self.next_attachment_id,
+            ..attachment
+        };
+        let before = post.clone();
+        self.next_attachment_id += 1;
+        post.attachments.push(attachment.clone());
+        post.updated_at = now();
+        self.last_modified = post.updated_at;
+        let updated_post = post.clone();
+        self.audit(
+            AuditAction::Attach,
+            post_id,
+            Some(&before),
+            Some(&updated_post),
+        );
+        self.events
+            .push(PostEventKind::Updated, post_id, Some(updated_post));
+        PostDbResponse {
+            status: PostDbStatus::Ok,
+            value: Some(attachment),
+        }
+    }
+
+    /// get an attachment by id, whichever post it is on, unless that post
+    /// is hidden
+    pub fn get_attachment(&self, attachment_id: u64) -> PostDbResponse<Option<Attachment>> {
+        let attachment = self
+            .posts
+            .iter()
+            .filter(|post| !post.hidden)
+            .flat_map(|post| &post.attachments)
+            .find(|attachment| attachment.attachment_id == attachment_id)
+            .cloned();
+        PostDbResponse {
+            status: if attachment.is_some() {
+                PostDbStatus::Ok
+            } else {
+                PostDbStatus::Err
+            },
+            value: attachment,
+        }
+    }
+
+    /// the content hashes of every attachment still on a post
+    pub fn attachment_hashes(&self) -> HashSet<String> {
+        self.posts
+            .iter()
+            .flat_map(|post| &post.attachments)
+            .map(|attachment| attachment.sha256.clone())
+            .collect()
+    }
+
+    /// react to a post with `emoji` as `user`, returning the post's new counts
+    ///
+    /// reacting twice with the same emoji changes nothing, and hidden posts
+    /// are reported missing
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn add_reaction(
+        &mut self,
+        post_id: u64,
+        user: String,
+        emoji: String,
+    ) -> PostDbResponse<Option<Vec<ReactionCount>>> {
+        let _timer = METRICS.store_timer("add_reaction");
+        if !self.is_visible(post_id) {
+            return PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            };
+        }
+        let added = self
+            .reactions
+            .entry(post_id)
+            .or_default()
+            .entry(emoji.clone())
+            .or_default()
+            .insert(user.clone());
+        self.reaction_changed(
+            added,
+            PostEventKind::ReactionAdded,
+            post_id,
+            Reaction { emoji, user },
+        )
+    }
+
+    /// take back `user`'s `emoji` reaction to a post, returning the post's new counts
+    ///
+    /// removing a reaction that was never made changes nothing, and hidden
+    /// posts are reported missing
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn remove_reaction(
+        &mut self,
+        post_id: u64,
+        user: String,
+        emoji: String,
+    ) -> PostDbResponse<Option<Vec<ReactionCount>>> {
+        let _timer = METRICS.store_timer("remove_reaction");
+        if !self.is_visible(post_id) {
+            return PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            };
+        }
+        let mut removed = false;
+        if let Some(emojis) = self.reactions.get_mut(&post_id) {
+            if let Some(users) = emojis.get_mut(&emoji) {
+                removed = users.remove(&user);
+                if users.is_empty() {
+                    emojis.remove(&emoji);
+                }
+            }
+            if emojis.is_empty() {
+                self.reactions.remove(&post_id);
+            }
+        }
+        self.reaction_changed(
+            removed,
+            PostEventKind::ReactionRemoved,
+            post_id,
+            Reaction { emoji, user },
+        )
+    }
+
+    /// who reacted to a post and with what, ordered by emoji then user;
+    /// hidden posts are reported missing
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn reactions(&self, post_id: u64) -> PostDbResponse<Option<Vec<Reaction>>> {
+        let _timer = METRICS.store_timer("reactions");
+        if !self.is_visible(post_id) {
+            return PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            };
+        }
+        PostDbResponse {
+            status: PostDbStatus::Ok,
+            value: Some(self.reactions_to(post_id)),
+        }
+    }
+
+    /// every reaction held, hidden posts' too, for saving alongside the posts
+    pub fn all_reactions(&self) -> Vec<(u64, Reaction)> {
+        self.posts
+            .iter()
+            .flat_map(|post| {
+                let post_id = post.post_id;
+                self.reactions_to(post_id)
+                    .into_iter()
+                    .map(move |reaction| (post_id, reaction))
+            })
+            .collect()
+    }
+
+    /// who reacted to a post and with what, hidden or not, for saving
+    /// alongside the posts
+    pub fn reactions_to(&self, post_id: u64) -> Vec<Reaction> {
+        self.reactions
+            .get(&post_id)
+            .into_iter()
+            .flatten()
+            .flat_map(|(emoji, users)| {
+                users.iter().map(|user| Reaction {
+                    emoji: emoji.clone(),
+                    user: user.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// the poll a post asks, if the post exists, is not hidden and has one
+    pub fn poll(&self, post_id: u64) -> Option<Poll> {
+        self.posts
+            .iter()
+            .find(|post| post.post_id == post_id && !post.hidden)
+            .and_then(|post| post.poll.clone())
+    }
+
+    /// vote in a post's poll as `user`, replacing any earlier vote, and
+    /// return the poll's results; no choices takes the vote back
+    ///
+    /// the choices are expected to be checked against the poll already, and
+    /// hidden posts are reported missing
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn vote(
+        &mut self,
+        post_id: u64,
+        user: String,
+        choices: BTreeSet<u32>,
+    ) -> PostDbResponse<Option<Poll>> {
+        let _timer = METRICS.store_timer("vote");
+        let Some(post) = self
+            .posts
+            .iter_mut()
+            .find(|post| post.post_id == post_id && !post.hidden && post.poll.is_some())
+        else {
+            return PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            };
+        };
+        let before = post.clone();
+        let poll = post.poll.as_mut().expect("only posts with polls are found");
+        if self.votes.cast(post_id, user, choices) {
+            self.votes.tally(post_id, poll);
+            let post = post.clone();
+            self.last_modified = now();
+            self.audit(AuditAction::Vote, post_id, Some(&before), Some(&post));
+            self.events
+                .push(PostEventKind::PollVoted, post_id, Some(post));
+        }
+        PostDbResponse {
+            status: PostDbStatus::Ok,
+            value: self.poll(post_id),
+        }
+    }
+
+    /// who voted for what in a post's poll, ordered by user
+    pub fn votes(&self, post_id: u64) -> Vec<Vote> {
+        self.votes.of(post_id)
+    }
+
+    /// a user's notifications, newest first
+    ///
+    /// user names are matched ignoring case, as mentions are
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn notifications(&self, user: &str, unread_only: bool) -> Vec<Notification> {
+        let _timer = METRICS.store_timer("notifications");
+        self.notifications.list(&user.to_lowercase(), unread_only)
+    }
+
+    /// mark the given notifications of a user read, or all of them when
+    /// `notification_ids` is `None`, returning how many were unread
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn mark_notifications_read(&mut self, user: &str, notification_ids: Option<&[u64]>) -> u64 {
+        let _timer = METRICS.store_timer("mark_notifications_read");
+        self.notifications
+            .mark_read(&user.to_lowercase(), notification_ids)
+    }
+
+    /// every notification held, for saving alongside the posts
+    pub fn all_notifications(&self) -> Vec<Notification> {
+        self.notifications.all()
+    }
+
+    /// save a draft for `author`, to be published at `publish_at` if given
+    #[tracing::instrument(level = "debug", skip(self, content))]
+    pub fn create_draft(
+        &mut self,
+        author: String,
+        content: String,
+        format: ContentFormat,
+        publish_at: Option<u64>,
+    ) -> PostDbResponse<Draft> {
+        let _timer = METRICS.store_timer("create_draft");
+        let draft = self
+            .drafts
+            .create(author, content, format, publish_at, now());
+        self.audit_draft(
+            AuditAction::CreateDraft,
+            draft.draft_id,
+            None,
+            None,
+            Some(&draft),
+        );
+        PostDbResponse {
+            status: PostDbStatus::Ok,
+            value: draft,
+        }
+    }
+
+    /// replace the content and publish time of one of `author`'s drafts
+    #[tracing::instrument(level = "debug", skip(self, content))]
+    pub fn update_draft(
+        &mut self,
+        draft_id: u64,
+        author: &str,
+        content: String,
+        publish_at: Option<u64>,
+    ) -> PostDbResponse<Option<Draft>> {
+        let _timer = METRICS.store_timer("update_draft");
+        let before = self.drafts.get(draft_id, author);
+        let draft = self
+            .drafts
+            .update(draft_id, author, content, publish_at, now());
+        if let Some(draft) = &draft {
+            self.audit_draft(
+                AuditAction::UpdateDraft,
+                draft_id,
+                None,
+                before.as_ref(),
+                Some(draft),
+            );
+        }
+        PostDbResponse {
+            status: if draft.is_some() {
+                PostDbStatus::Ok
+            } else {
+                PostDbStatus::Err
+            },
+            value: draft,
+        }
+    }
+
+    /// `author`'s drafts, oldest first
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn drafts(&self, author: &str) -> Vec<Draft> {
+        let _timer = METRICS.store_timer("drafts");
+        self.drafts.list(author)
+    }
+
+    /// throw away one of `author`'s drafts
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn delete_draft(&mut self, draft_id: u64, author: &str) -> PostDbResponse<Option<u64>> {
+        let _timer = METRICS.store_timer("delete_draft");
+        match self.drafts.remove(draft_id, author) {
+            Some(draft) => {
+                self.audit_draft(AuditAction::DeleteDraft, draft_id, None, Some(&draft), None);
+                PostDbResponse {
+                    status: PostDbStatus::Ok,
+                    value: Some(draft_id),
+                }
+            }
+            None => PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            },
+        }
+    }
+
+    /// publish one of `author`'s drafts now, through the content filters;
+    /// a rejected draft is kept so its author can fix it
+    ///
+    /// the value is `None` if the author has no such draft
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn publish_draft(
+        &mut self,
+        draft_id: u64,
+        author: &str,
+    ) -> PostDbResponse<Option<Submission>> {
+        let _timer = METRICS.store_timer("publish_draft");
+        let Some(draft) = self.drafts.remove(draft_id, author) else {
+            return PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            };
+        };
+        let submission = self
+            .create_post_as(draft.content.clone(), draft.format)
+            .value;
+        match submission.post_id() {
+            Some(post_id) => self.audit_draft(
+                AuditAction::PublishDraft,
+                draft_id,
+                Some(post_id),
+                Some(&draft),
+                None,
+            ),
+            None => self.drafts.put_back(draft),
+        }
+        PostDbResponse {
+            status: PostDbStatus::Ok,
+            value: Some(submission),
+        }
+    }
+
+    /// publish every draft whose time has come by `now`, earliest first,
+    /// returning the new posts' ids, held ones included
+    ///
+    /// drafts the content filters reject are kept without a publish time,
+    /// so their authors can fix them instead of them being retried forever
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn publish_due(&mut self, now: u64) -> Vec<u64> {
+        let _timer = METRICS.store_timer("publish_due");
+        let mut published = vec![];
+        for draft in self.drafts.take_due(now) {
+            match self
+                .create_post_as(draft.content.clone(), draft.format)
+                .value
+            {
+                Submission::Rejected(flag) => {
+                    tracing::warn!(
+                        draft_id = draft.draft_id,
+                        filter = flag.filter,
+                        "kept a scheduled draft the filters rejected"
+                    );
+                    let kept = Draft {
+                        publish_at: None,
+                        ..draft.clone()
+                    };
+                    self.audit_draft(
+                        AuditAction::UpdateDraft,
+                        draft.draft_id,
+                        None,
+                        Some(&draft),
+                        Some(&kept),
+                    );
+                    self.drafts.put_back(kept);
+                }
+                submission => {
+                    let post_id = submission.post_id();
+                    self.audit_draft(
+                        AuditAction::PublishDraft,
+                        draft.draft_id,
+                        post_id,
+                        Some(&draft),
+                        None,
+                    );
+                    published.extend(post_id);
+                }
+            }
+        }
+        published
+    }
+
+    /// every draft held, for saving alongside the posts
+    pub fn all_drafts(&self) -> Vec<Draft> {
+        self.drafts.all()
+    }
+
+    /// report a post as `user`, hiding it once `threshold` users have
+    /// reported it; a threshold of 0 never hides posts
+    ///
+    /// hidden posts cannot be reported, as nobody but moderators sees them
+    #[tracing::instrument(level = "debug", skip(self, reason))]
+    pub fn report(
+        &mut self,
+        post_id: u64,
+        user: String,
+        reason: String,
+        threshold: u64,
+    ) -> PostDbResponse<Option<Report>> {
+        let _timer = METRICS.store_timer("report");
+        if !self
+            .posts
+            .iter()
+            .any(|post| post.post_id == post_id && !post.hidden)
+        {
+            return PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            };
+        }
+        let at = now();
+        let (report, count) = self.moderation.report(post_id, user, reason, at);
+        if threshold > 0 && count >= threshold {
+            let before = self
+                .posts
+                .iter()
+                .find(|post| post.post_id == post_id)
+                .cloned();
+            self.set_hidden(post_id, true);
+            let after = self
+                .posts
+                .iter()
+                .find(|post| post.post_id == post_id)
+                .cloned();
+            crate::audit::as_actor(SYSTEM_ACTOR, || {
+                self.audit(AuditAction::Hide, post_id, before.as_ref(), after.as_ref())
+            });
+            self.moderation.record(
+                post_id,
+                None,
+                ModerationAction::Hide,
+                None,
+                Some(format!("reported by {} users", count)),
+                at,
+            );
+        }
+        PostDbResponse {
+            status: PostDbStatus::Ok,
+            value: Some(report),
+        }
+    }
+
+    /// the moderation queue: every post with open reports, hidden or not,
+    /// most reported first and then longest waiting first
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn reported_posts(&self) -> Vec<ReportedPost> {
+        let _timer = METRICS.store_timer("reported_posts");
+        let mut queue: Vec<ReportedPost> = self
+            .moderation
+            .reported()
+            .into_iter()
+            .filter_map(|post_id| {
+                let post = self.posts.iter().find(|post| post.post_id == post_id)?;
+                let reports = self.moderation.of(post_id);
+                Some(ReportedPost {
+                    post: post.clone(),
+                    report_count: reports.len() as u64,
+                    reports,
+                })
+            })
+            .collect();
+        queue.sort_by_key(|reported| {
+            (
+                std::cmp::Reverse(reported.report_count),
+                reported.reports[0].report_id,
+            )
+        });
+        queue
+    }
+
+    /// act on a post as `moderator`, closing its open reports and adding
+    /// the action to the moderation log
+    ///
+    /// dismissing shows a hidden post again, and warning notifies `user`,
+    /// which is expected to be given for warnings only. Hiding and
+    /// deleting teach the spam filter the post's content is spam, and
+    /// dismissing that it is not
+    #[tracing::instrument(level = "debug", skip(self, note))]
+    pub fn moderate(
+        &mut self,
+        post_id: u64,
+        moderator: String,
+        action: ModerationAction,
+        user: Option<String>,
+        note: Option<String>,
+    ) -> PostDbResponse<Option<ModerationEntry>> {
+        let _timer = METRICS.store_timer("moderate");
+        if !self.has_post(post_id) {
+            return PostDbResponse {
+                status: PostDbStatus::Err,
+                value: None,
+            };
+        }
+        let at = now();
+        let before = self
+            .posts
+            .iter()
+            .find(|post| post.post_id == post_id)
+            .cloned();
+        let content = before
+            .as_ref()
+            .map(|post| post.content.clone())
+            .unwrap_or_default();
+        match action {
+            ModerationAction::Dismiss => {
+                self.filters.train(&content, false);
+                self.set_hidden(post_id, false);
+            }
+            ModerationAction::Hide => {
+                self.filters.train(&content, true);
+                self.set_hidden(post_id, true);
+            }
+            ModerationAction::Delete => {
+                self.filters.train(&content, true);
+                self.remove_post(post_id);
+            }
+            ModerationAction::Warn => {
+                if let Some(user) = &user {
+                    self.notifications.notify(
+                        &user.to_lowercase(),
+                        NotificationKind::Warning,
+                        post_id,
+                        at,
+                    );
+                }
+            }
+        }
+        let after = self
+            .posts
+            .iter()
+            .find(|post| post.post_id == post_id)
+            .cloned();
+        let audit_action = match action {
+            ModerationAction::Dismiss
+                if before.as_ref().is_some_and(|post| post.hidden)
+                    && after.as_ref().is_some_and(|post| !post.hidden) =>
+            {
+                AuditAction::Restore
             }
+            ModerationAction::Dismiss => AuditAction::Dismiss,
+            ModerationAction::Hide => AuditAction::Hide,
+            ModerationAction::Delete => AuditAction::Delete,
+            ModerationAction::Warn => AuditAction::Warn,
+        };
+        self.audit(audit_action, post_id, before.as_ref(), after.as_ref());
+        self.moderation.resolve(post_id);
+        let entry = self
+            .moderation
+            .record(post_id, Some(moderator), action, user, note, at);
+        PostDbResponse {
+            status: PostDbStatus::Ok,
+            value: Some(entry),
+        }
+    }
+
+    /// the moderation log, oldest first, only about `post_id` if given
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn moderation_log(&self, post_id: Option<u64>) -> Vec<ModerationEntry> {
+        let _timer = METRICS.store_timer("moderation_log");
+        self.moderation.log(post_id)
+    }
+
+    /// every open report, for saving alongside the posts
+    pub fn all_reports(&self) -> Vec<Report> {
+        self.moderation.all_reports()
+    }
+
+    /// the audit log entries not yet written to the audit file, oldest first
+    pub fn unwritten_audit_entries(&self) -> Vec<AuditEntry> {
+        self.audit.unwritten()
+    }
+
+    /// note that every audit log entry up to `entry_id` is in the audit file
+    pub fn mark_audit_written(&mut self, entry_id: u64) {
+        self.audit.mark_written(entry_id);
+    }
+
+    /// the audit log entries passing `query`, oldest first
+    #[tracing::instrument(level = "debug", skip(self))]
+    pub fn audit_log(&self, query: &AuditQuery) -> Vec<AuditEntry> {
+        let _timer = METRICS.store_timer("audit_log");
+        self.audit.query(query)
+    }
+
+    /// what the spam filter has learned, for saving alongside the posts
+    pub fn spam_model(&self) -> &SpamModel {
+        self.filters.spam_model()
+    }
+
+    /// take a post out of the store along with its reactions, votes,
+    /// notifications and reports, emitting an event
+    fn remove_post(&mut self, id: u64) -> Option<Post> {
+        let post_index = self.posts.iter().position(|post| post.post_id == id)?;
+        let found_post = self.posts.remove(post_index);
+        self.reactions.remove(&id);
+        self.votes.remove_post(id);
+        self.notifications.remove_post(id);
+        self.moderation.resolve(id);
+        METRICS.posts.set(self.posts.len() as i64);
+        self.last_modified = now();
+        self.events.push(PostEventKind::Deleted, id, None);
+        Some(found_post)
+    }
+
+    /// add a change to a post to the audit log
+    fn audit(
+        &mut self,
+        action: AuditAction,
+        post_id: u64,
+        before: Option<&Post>,
+        after: Option<&Post>,
+    ) {
+        self.record_audit(AuditEntry {
+            post_id: Some(post_id),
+            ..audit_entry(action, before.map(audit_hash), after.map(audit_hash))
+        });
+    }
+
+    /// add a change to a draft to the audit log, naming the post it
+    /// became if it was published
+    fn audit_draft(
+        &mut self,
+        action: AuditAction,
+        draft_id: u64,
+        post_id: Option<u64>,
+        before: Option<&Draft>,
+        after: Option<&Draft>,
+    ) {
+        self.record_audit(AuditEntry {
+            post_id,
+            draft_id: Some(draft_id),
+            ..audit_entry(action, before.map(audit_hash), after.map(audit_hash))
+        });
+    }
+
+    /// add a change to a webhook to the audit log; webhooks live outside
+    /// the store, so their handlers call this themselves
+    pub fn audit_webhook(
+        &mut self,
+        action: AuditAction,
+        webhook_id: u64,
+        before: Option<&Webhook>,
+        after: Option<&Webhook>,
+    ) {
+        self.record_audit(AuditEntry {
+            webhook_id: Some(webhook_id),
+            ..audit_entry(action, before.map(audit_hash), after.map(audit_hash))
+        });
+    }
+
+    /// add `entry` to the audit log as made by the current request's
+    /// caller, or by `system` outside of one
+    fn record_audit(&mut self, entry: AuditEntry) {
+        self.audit.record(AuditEntry {
+            actor: crate::audit::current_actor().unwrap_or_else(|| SYSTEM_ACTOR.to_string()),
+            request_id: request_id::current(),
+            created_at: now(),
+            ..entry
+        });
+    }
+
+    /// put a post the filters held into the moderation queue, reported by
+    /// the filter, and log that it was hidden
+    fn hold(&mut self, post_id: u64, flag: Flag, at: u64) {
+        self.moderation.report(
+            post_id,
+            format!("filter:{}", flag.filter),
+            flag.reason.clone(),
+            at,
+        );
+        self.moderation.record(
+            post_id,
+            None,
+            ModerationAction::Hide,
+            None,
+            Some(format!(
+                "held by the {} filter: {}",
+                flag.filter, flag.reason
+            )),
+            at,
+        );
+    }
+
+    /// hide or show a post, emitting an event if that changed anything
+    fn set_hidden(&mut self, post_id: u64, hidden: bool) {
+        let Some(post) = self.posts.iter_mut().find(|post| post.post_id == post_id) else {
+            return;
+        };
+        if post.hidden == hidden {
+            return;
+        }
+        post.hidden = hidden;
+        let post = post.clone();
+        // the content is unchanged, but listings are not
+        self.last_modified = now();
+        if hidden {
+            self.events.push(PostEventKind::Hidden, post_id, None);
+        } else {
+            self.events
+                .push(PostEventKind::Unhidden, post_id, Some(post));
         }
-        id
+    }
+
+    fn notify_mentioned(&mut self, mentioned: BTreeSet<String>, post_id: u64, at: u64) {
+        for user in mentioned {
+            self.notifications
+                .notify(&user, NotificationKind::Mention, post_id, at);
+        }
+    }
+
+    /// refresh a post's counts after a reaction change, emitting an event
+    /// only if something actually changed
+    fn reaction_changed(
+        &mut self,
+        changed: bool,
+        kind: PostEventKind,
+        post_id: u64,
+        reaction: Reaction,
+    ) -> PostDbResponse<Option<Vec<ReactionCount>>> {
+        let counts = self.reaction_counts(post_id);
+        if changed {
+            let post = self
+                .posts
+                .iter_mut()
+                .find(|post| post.post_id == post_id)
+                .expect("reactions are only changed on existing posts");
+            let before = post.clone();
+            post.reactions = counts.clone();
+            let post = post.clone();
+            self.last_modified = now();
+            let action = match kind {
+                PostEventKind::ReactionAdded => AuditAction::React,
+                _ => AuditAction::Unreact,
+            };
+            self.audit(action, post_id, Some(&before), Some(&post));
+            self.events.push_reaction(kind, post, reaction);
+        }
+        PostDbResponse {
+            status: PostDbStatus::Ok,
+            value: Some(counts),
+        }
+    }
+
+    /// how many users used each emoji on a post, most used first
+    fn reaction_counts(&self, post_id: u64) -> Vec<ReactionCount> {
+        let mut counts: Vec<ReactionCount> = self
+            .reactions
+            .get(&post_id)
+            .into_iter()
+            .flatten()
+            .map(|(emoji, users)| ReactionCount {
+                emoji: emoji.clone(),
+                count: users.len() as u64,
+            })
+            .collect();
+        // emoji order is kept among equal counts, the map being sorted
+        counts.sort_by_key(|count| std::cmp::Reverse(count.count));
+        counts
+    }
+}
+
+/// who the audit log says made changes no request asked for
+const SYSTEM_ACTOR: &str = "system";
+
+/// hex SHA-256 of a post's, draft's or webhook's JSON, as kept in the
+/// audit log
+fn audit_hash(value: &impl Serialize) -> String {
+    let json = serde_json::to_vec(value).expect("audited values serialize");
+    hex::encode(Sha256::digest(json))
+}
+
+/// an audit log entry about nothing yet, made by nobody yet
+fn audit_entry(action: AuditAction, before: Option<String>, after: Option<String>) -> AuditEntry {
+    AuditEntry {
+        entry_id: 0,
+        actor: String::new(),
+        action,
+        post_id: None,
+        draft_id: None,
+        webhook_id: None,
+        before,
+        after,
+        request_id: None,
+        created_at: 0,
     }
 }
 
+/// the current time in seconds since the unix epoch
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -179,7 +1489,7 @@ mod test {
 
         let response = db.create_post("post content".to_string());
         assert_eq!(PostDbStatus::Ok, response.status);
-        assert_eq!(response.value, 1);
+        assert_eq!(response.value, Submission::Published(1));
         let response = db.get_post(1);
         assert_eq!(PostDbStatus::Ok, response.status);
         if let Some(post) = response.value {
@@ -188,7 +1498,7 @@ mod test {
 
         let response = db.create_post("post content 2".to_string());
         assert_eq!(PostDbStatus::Ok, response.status);
-        assert_eq!(response.value, 2);
+        assert_eq!(response.value, Submission::Published(2));
         let response = db.get_post(2);
         assert_eq!(PostDbStatus::Ok, response.status);
         if let Some(post) = response.value {
@@ -203,14 +1513,79 @@ mod test {
 
         let response = db.create_post("post content".to_string());
         assert_eq!(PostDbStatus::Ok, response.status);
-        let created_post_id = response.value;
+        let created_post_id = response.value.post_id().unwrap();
         assert_eq!(created_post_id, 1);
 
         let response = db.update_post(created_post_id, "post content updated".to_string());
         assert_eq!(PostDbStatus::Ok, response.status);
-        if let Some(updated_post_id) = response.value {
-            assert_eq!(created_post_id, updated_post_id);
-        }
+        assert_eq!(Edit::Updated(created_post_id), response.value);
+
+        let response = db.update_post(2, "no such post".to_string());
+        assert_eq!(PostDbStatus::Err, response.status);
+        assert_eq!(Edit::Missing, response.value);
+    }
+
+    #[test]
+    fn locked_posts_refuse_edits() {
+        let mut db = PostDb::new();
+        db.create_post("post content".to_string());
+        db.set_post_flags(1, None, Some(true));
+        let (_, mut receiver) = db.subscribe(None);
+
+        let response = db.update_post(1, "post content updated".to_string());
+        assert_eq!(PostDbStatus::Err, response.status);
+        assert_eq!(Edit::Locked, response.value);
+        assert_eq!("post content", db.posts[0].content);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn hidden_posts_refuse_changes() {
+        let mut db = PostDb::new();
+        let poll = Poll {
+            question: "lunch?".to_string(),
+            options: vec![post_lib::PollOption {
+                text: "pizza".to_string(),
+                votes: 0,
+            }],
+            ..Default::default()
+        };
+        db.create_post_with_poll("post content".to_string(), ContentFormat::Plain, Some(poll));
+        let upload = Attachment {
+            attachment_id: 0,
+            file_name: "app.log".to_string(),
+            content_type: "text/plain".to_string(),
+            size: 3,
+            sha256: "aa".to_string(),
+            thumbnail: false,
+        };
+        db.add_attachment(1, upload.clone());
+        db.add_reaction(1, "ann".to_string(), "👍".to_string());
+        db.moderate(1, "mod".to_string(), ModerationAction::Hide, None, None);
+        let (_, mut receiver) = db.subscribe(None);
+
+        let response = db.update_post(1, "post content, @bob".to_string());
+        assert_eq!(Edit::Missing, response.value);
+        assert_eq!(None, db.set_post_flags(1, Some(true), None).value);
+        assert_eq!(None, db.add_attachment(1, upload).value);
+        assert_eq!(None, db.get_attachment(1).value);
+        let reaction = || ("ann".to_string(), "🎉".to_string());
+        assert_eq!(None, db.add_reaction(1, reaction().0, reaction().1).value);
+        assert_eq!(
+            None,
+            db.remove_reaction(1, reaction().0, reaction().1).value
+        );
+        assert_eq!(None, db.reactions(1).value);
+        assert_eq!(
+            None,
+            db.vote(1, "ann".to_string(), BTreeSet::from([0])).value
+        );
+
+        assert_eq!("post content", db.posts[0].content);
+        assert!(db.notifications("bob", false).is_empty());
+        assert!(receiver.try_recv().is_err());
+        // what the post already had is still saved
+        assert_eq!(1, db.all_reactions().len());
     }
 
     #[test]
@@ -220,7 +1595,7 @@ mod test {
 
         let response = db.create_post("post content".to_string());
         assert_eq!(PostDbStatus::Ok, response.status);
-        let created_post_id = response.value;
+        let created_post_id = response.value.post_id().unwrap();
         assert_eq!(created_post_id, 1);
 
         let response = db.delete_post(created_post_id);
@@ -229,4 +1604,525 @@ mod test {
             assert_eq!(1, removed_id);
         }
     }
+
+    #[test]
+    fn changes_emit_events() {
+        let mut db = PostDb::new();
+        let (_, mut receiver) = db.subscribe(None);
+
+        db.create_post("post content".to_string());
+        db.update_post(1, "post content updated".to_string());
+        db.delete_post(1);
+        db.delete_post(1);
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(PostEventKind::Created, event.kind);
+        assert_eq!("post content", event.post.unwrap().content);
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(PostEventKind::Updated, event.kind);
+        assert_eq!("post content updated", event.post.unwrap().content);
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(PostEventKind::Deleted, event.kind);
+        assert!(event.post.is_none());
+
+        // deleting a missing post changes nothing
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn reactions_are_counted_per_user() {
+        let mut db = PostDb::new();
+        db.create_post("post content".to_string());
+        let react = |db: &mut PostDb, user: &str, emoji: &str| {
+            db.add_reaction(1, user.to_string(), emoji.to_string())
+                .value
+                .unwrap()
+        };
+
+        react(&mut db, "ann", "👍");
+        react(&mut db, "bob", "🎉");
+        react(&mut db, "bob", "👍");
+        // reacting twice with the same emoji counts once
+        let counts = react(&mut db, "ann", "👍");
+        assert_eq!(
+            vec![
+                ReactionCount {
+                    emoji: "👍".to_string(),
+                    count: 2
+                },
+                ReactionCount {
+                    emoji: "🎉".to_string(),
+                    count: 1
+                },
+            ],
+            counts
+        );
+        assert_eq!(counts, db.get_post(1).value.unwrap().reactions);
+
+        db.remove_reaction(1, "bob".to_string(), "🎉".to_string());
+        db.remove_reaction(1, "bob".to_string(), "🎉".to_string());
+        assert_eq!(
+            vec![
+                Reaction {
+                    emoji: "👍".to_string(),
+                    user: "ann".to_string()
+                },
+                Reaction {
+                    emoji: "👍".to_string(),
+                    user: "bob".to_string()
+                },
+            ],
+            db.reactions(1).value.unwrap()
+        );
+
+        let response = db.add_reaction(2, "ann".to_string(), "👍".to_string());
+        assert_eq!(PostDbStatus::Err, response.status);
+
+        db.delete_post(1);
+        assert!(db.all_reactions().is_empty());
+    }
+
+    #[test]
+    fn only_reaction_changes_emit_events() {
+        let mut db = PostDb::new();
+        db.create_post("post content".to_string());
+        let (_, mut receiver) = db.subscribe(None);
+
+        db.add_reaction(1, "ann".to_string(), "👍".to_string());
+        db.add_reaction(1, "ann".to_string(), "👍".to_string());
+        db.remove_reaction(1, "ann".to_string(), "👍".to_string());
+        db.remove_reaction(1, "ann".to_string(), "👍".to_string());
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(PostEventKind::ReactionAdded, event.kind);
+        assert_eq!(1, event.post.unwrap().reactions[0].count);
+        assert_eq!("ann", event.reaction.unwrap().user);
+
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(PostEventKind::ReactionRemoved, event.kind);
+        assert!(event.post.unwrap().reactions.is_empty());
+
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn attachments_get_their_own_ids() {
+        let mut db = PostDb::new();
+        db.create_post("first".to_string());
+        db.create_post("second".to_string());
+        let upload = |sha256: &str| Attachment {
+            attachment_id: 0,
+            file_name: "app.log".to_string(),
+            content_type: "text/plain".to_string(),
+            size: 3,
+            sha256: sha256.to_string(),
+            thumbnail: false,
+        };
+
+        assert_eq!(
+            1,
+            db.add_attachment(1, upload("aa"))
+                .value
+                .unwrap()
+                .attachment_id
+        );
+        assert_eq!(
+            2,
+            db.add_attachment(2, upload("aa"))
+                .value
+                .unwrap()
+                .attachment_id
+        );
+        assert_eq!(
+            3,
+            db.add_attachment(2, upload("bb"))
+                .value
+                .unwrap()
+                .attachment_id
+        );
+        assert_eq!(PostDbStatus::Err, db.add_attachment(3, upload("cc")).status);
+        assert_eq!("bb", db.get_attachment(3).value.unwrap().sha256);
+
+        db.delete_post(2);
+        assert_eq!(PostDbStatus::Err, db.get_attachment(3).status);
+        assert_eq!(HashSet::from(["aa".to_string()]), db.attachment_hashes());
+
+        // restored posts keep ids unique
+        let posts = db.get_posts();
+        db.restore(posts);
+        assert_eq!(
+            2,
+            db.add_attachment(1, upload("dd"))
+                .value
+                .unwrap()
+                .attachment_id
+        );
+    }
+
+    #[test]
+    fn polls_count_one_vote_per_user() {
+        let mut db = PostDb::new();
+        db.create_post("no poll here".to_string());
+        let poll = Poll {
+            question: "lunch?".to_string(),
+            options: vec![
+                post_lib::PollOption {
+                    text: "pizza".to_string(),
+                    votes: 0,
+                },
+                post_lib::PollOption {
+                    text: "sushi".to_string(),
+                    votes: 0,
+                },
+            ],
+            ..Default::default()
+        };
+        db.create_post_with_poll("vote please".to_string(), ContentFormat::Plain, Some(poll));
+        let (_, mut receiver) = db.subscribe(None);
+        let vote = |db: &mut PostDb, user: &str, choice: u32| {
+            db.vote(2, user.to_string(), BTreeSet::from([choice]))
+                .value
+                .unwrap()
+        };
+
+        vote(&mut db, "ann", 0);
+        vote(&mut db, "bob", 0);
+        // changing a vote moves it
+        let poll = vote(&mut db, "ann", 1);
+        assert_eq!(
+            (1, 1, 2),
+            (poll.options[0].votes, poll.options[1].votes, poll.voters)
+        );
+        assert_eq!(Some(poll), db.get_post(2).value.unwrap().poll);
+        vote(&mut db, "ann", 1);
+
+        let kinds: Vec<PostEventKind> = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|event| event.kind)
+            .collect();
+        assert_eq!(vec![PostEventKind::PollVoted; 3], kinds);
+        assert_eq!(
+            PostDbStatus::Err,
+            db.vote(1, "ann".to_string(), BTreeSet::from([0])).status
+        );
+
+        // saved votes are counted again on restore
+        let votes: Vec<(u64, Vote)> = db.votes(2).into_iter().map(|vote| (2, vote)).collect();
+        let posts = db.get_posts();
+        db.restore(posts);
+        db.restore_votes(votes);
+        assert_eq!(2, db.poll(2).unwrap().voters);
+
+        db.delete_post(2);
+        assert!(db.votes(2).is_empty());
+    }
+
+    #[test]
+    fn reports_hide_posts_past_the_threshold() {
+        let mut db = PostDb::new();
+        db.create_post("buy now".to_string());
+        db.create_post("hello".to_string());
+        let (_, mut receiver) = db.subscribe(None);
+        let report = |db: &mut PostDb, post_id: u64, user: &str| {
+            db.report(post_id, user.to_string(), "spam".to_string(), 2)
+        };
+
+        report(&mut db, 2, "ann");
+        report(&mut db, 1, "ann");
+        // reporting twice counts once
+        report(&mut db, 1, "ann");
+        assert!(!db.get_post(1).value.unwrap().hidden);
+        report(&mut db, 1, "bob");
+        assert_eq!(
+            vec![2],
+            db.get_posts()
+                .iter()
+                .map(|post| post.post_id)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(PostDbStatus::Err, db.get_post(1).status);
+        assert_eq!(PostDbStatus::Err, report(&mut db, 1, "carol").status);
+
+        // hidden posts stay in the queue, most reported first
+        let queue = db.reported_posts();
+        assert_eq!(
+            vec![(1, 2, true), (2, 1, false)],
+            queue
+                .iter()
+                .map(|reported| (
+                    reported.post.post_id,
+                    reported.report_count,
+                    reported.post.hidden
+                ))
+                .collect::<Vec<_>>()
+        );
+        let log = db.moderation_log(None);
+        assert_eq!(
+            (None, ModerationAction::Hide, Some("reported by 2 users")),
+            (
+                log[0].moderator.as_deref(),
+                log[0].action,
+                log[0].note.as_deref()
+            )
+        );
+
+        let moderate = |db: &mut PostDb, post_id: u64, action: ModerationAction| {
+            db.moderate(
+                post_id,
+                "mod".to_string(),
+                action,
+                Some("Ann".to_string()),
+                None,
+            )
+        };
+        moderate(&mut db, 1, ModerationAction::Dismiss);
+        assert!(!db.get_post(1).value.unwrap().hidden);
+        moderate(&mut db, 2, ModerationAction::Warn);
+        assert!(db.reported_posts().is_empty());
+        assert_eq!(
+            NotificationKind::Warning,
+            db.notifications("ann", false)[0].kind
+        );
+        moderate(&mut db, 2, ModerationAction::Delete);
+        assert!(!db.has_post(2));
+        assert_eq!(
+            PostDbStatus::Err,
+            moderate(&mut db, 2, ModerationAction::Hide).status
+        );
+        assert_eq!(
+            vec![
+                ModerationAction::Hide,
+                ModerationAction::Dismiss,
+                ModerationAction::Warn,
+                ModerationAction::Delete
+            ],
+            db.moderation_log(None)
+                .iter()
+                .map(|entry| entry.action)
+                .collect::<Vec<_>>()
+        );
+
+        // hiding never shows the post to subscribers
+        let events: Vec<(PostEventKind, bool)> = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|event| (event.kind, event.post.is_some()))
+            .collect();
+        assert_eq!(
+            vec![
+                (PostEventKind::Hidden, false),
+                (PostEventKind::Unhidden, true),
+                (PostEventKind::Deleted, false)
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn filters_hold_and_reject_new_posts() {
+        let mut config = crate::config::FiltersConfig::default();
+        config.blocked_words.patterns = vec!["cas*no".to_string()];
+        config.links.max = 0;
+        let mut db = PostDb::new();
+        db.set_filters(crate::filters::chain(&config));
+        let (_, mut receiver) = db.subscribe(None);
+
+        let response = db.create_post("try my casino".to_string());
+        assert_eq!(PostDbStatus::Err, response.status);
+        let Submission::Rejected(flag) = response.value else {
+            panic!("a blocked word should be rejected");
+        };
+        assert_eq!("blocked_word", flag.filter);
+        assert!(db.posts.is_empty());
+
+        // held posts are stored hidden, quietly, and wait in the queue
+        let response = db.create_post("@ann see https://a.example".to_string());
+        assert_eq!(Submission::Held(1), response.value);
+        assert!(db.get_posts().is_empty());
+        assert!(receiver.try_recv().is_err());
+        assert!(db.notifications("ann", false).is_empty());
+        let queue = db.reported_posts();
+        assert_eq!(1, queue.len());
+        assert_eq!("filter:too_many_links", queue[0].reports[0].user);
+        assert_eq!(
+            Some("held by the too_many_links filter: must have at most 0 links, not 1"),
+            db.moderation_log(Some(1))[0].note.as_deref()
+        );
+
+        db.moderate(1, "mod".to_string(), ModerationAction::Dismiss, None, None);
+        assert_eq!(1, db.get_posts().len());
+        assert_eq!(1, db.spam_model().ham_posts);
+
+        // drafts the filters reject are kept, without a publish time
+        let draft = db
+            .create_draft(
+                "ann".to_string(),
+                "casino".to_string(),
+                ContentFormat::Plain,
+                Some(5),
+            )
+            .value;
+        assert!(db.publish_due(10).is_empty());
+        assert_eq!(None, db.drafts("ann")[0].publish_at);
+        let Some(Submission::Rejected(_)) = db.publish_draft(draft.draft_id, "ann").value else {
+            panic!("a blocked word should be rejected");
+        };
+        assert_eq!(1, db.drafts("ann").len());
+    }
+
+    #[test]
+    fn filters_hold_and_reject_edits() {
+        let mut config = crate::config::FiltersConfig::default();
+        config.blocked_words.patterns = vec!["cas*no".to_string()];
+        config.links.max = 0;
+        let mut db = PostDb::new();
+        db.set_filters(crate::filters::chain(&config));
+        db.create_post("hello".to_string());
+        let (_, mut receiver) = db.subscribe(None);
+
+        // an edit repeating the post itself is no duplicate
+        assert_eq!(
+            Edit::Updated(1),
+            db.update_post(1, "Hello".to_string()).value
+        );
+        assert_eq!(PostEventKind::Updated, receiver.try_recv().unwrap().kind);
+
+        let response = db.update_post(1, "try my casino".to_string());
+        assert_eq!(PostDbStatus::Err, response.status);
+        let Edit::Rejected(flag) = response.value else {
+            panic!("a blocked word should be rejected");
+        };
+        assert_eq!("blocked_word", flag.filter);
+        assert_eq!("Hello", db.posts[0].content);
+
+        // held edits hide the post, quietly, until a moderator looks at it
+        let response = db.update_post(1, "@ann see https://a.example".to_string());
+        assert_eq!(Edit::Held(1), response.value);
+        assert!(db.get_posts().is_empty());
+        let event = receiver.try_recv().unwrap();
+        assert_eq!((PostEventKind::Hidden, None), (event.kind, event.post));
+        assert!(receiver.try_recv().is_err());
+        assert!(db.notifications("ann", false).is_empty());
+        assert_eq!(
+            "filter:too_many_links",
+            db.reported_posts()[0].reports[0].user
+        );
+    }
+
+    #[test]
+    fn changes_are_audited() {
+        let mut db = PostDb::new();
+        let mut receiver = db.subscribe_audit();
+        crate::audit::as_actor("ip:10.0.0.1", || {
+            db.create_post("hello".to_string());
+            db.update_post(1, "hello there".to_string());
+            db.set_post_flags(1, None, Some(false));
+            // logged as who asked, not as the user the request names
+            db.add_reaction(1, "ann".to_string(), "👍".to_string());
+        });
+        crate::audit::as_actor("mod", || {
+            db.moderate(1, "mod".to_string(), ModerationAction::Hide, None, None);
+            db.moderate(1, "mod".to_string(), ModerationAction::Dismiss, None, None);
+        });
+        db.delete_post(1);
+
+        let log = db.audit_log(&AuditQuery::default());
+        let entries: Vec<(AuditAction, &str)> = log
+            .iter()
+            .map(|entry| (entry.action, entry.actor.as_str()))
+            .collect();
+        // the unchanged flags are not logged
+        assert_eq!(
+            vec![
+                (AuditAction::Create, "ip:10.0.0.1"),
+                (AuditAction::Update, "ip:10.0.0.1"),
+                (AuditAction::React, "ip:10.0.0.1"),
+                (AuditAction::Hide, "mod"),
+                (AuditAction::Restore, "mod"),
+                (AuditAction::Delete, "system"),
+            ],
+            entries
+        );
+        assert_eq!(6, std::iter::from_fn(|| receiver.try_recv().ok()).count());
+
+        // each entry's before is the previous entry's after
+        assert_eq!(None, log[0].before);
+        for pair in log.windows(2) {
+            assert_eq!(pair[0].after, pair[1].before);
+            assert_eq!(64, pair[1].before.as_ref().unwrap().len());
+        }
+        assert_eq!(None, log[5].after);
+
+        let query = AuditQuery {
+            action: Some(AuditAction::Hide),
+            ..Default::default()
+        };
+        assert_eq!(vec![log[3].clone()], db.audit_log(&query));
+
+        // the deleted post's id is not handed out again
+        assert_eq!(Some(2), db.create_post("again".to_string()).value.post_id());
+    }
+
+    #[test]
+    fn mentioned_users_are_notified_once() {
+        let mut db = PostDb::new();
+        let mut receiver = db.subscribe_notifications();
+        db.create_post("hi @alice and @Bob".to_string());
+        db.update_post(1, "hi @alice, @carol".to_string());
+        db.update_post(1, "hi @alice, @carol".to_string());
+        db.create_post("mail me at dave@example.com".to_string());
+
+        let mentioned: Vec<(String, u64)> = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|notification| (notification.user, notification.post_id))
+            .collect();
+        assert_eq!(
+            vec![
+                ("alice".to_string(), 1),
+                ("bob".to_string(), 1),
+                ("carol".to_string(), 1)
+            ],
+            mentioned
+        );
+        assert_eq!(1, db.notifications("alice", true).len());
+        assert_eq!(1, db.mark_notifications_read("alice", None));
+        assert!(db.notifications("alice", true).is_empty());
+
+        db.delete_post(1);
+        assert!(db.all_notifications().is_empty());
+    }
+
+    #[test]
+    fn pinned_posts_are_listed_first() {
+        let mut db = PostDb::new();
+        for content in ["first", "second", "third", "fourth"] {
+            db.create_post(content.to_string());
+        }
+        let (_, mut receiver) = db.subscribe(None);
+        db.set_post_flags(3, Some(true), None);
+        db.posts[2].pinned_at = Some(10);
+        db.set_post_flags(2, Some(true), Some(true));
+        // already pinned, so it keeps its place
+        db.set_post_flags(3, Some(true), None);
+        assert_eq!(
+            vec![3, 2, 1, 4],
+            db.get_posts()
+                .iter()
+                .map(|post| post.post_id)
+                .collect::<Vec<_>>()
+        );
+        assert!(db.is_locked(2));
+        assert!(!db.is_locked(3));
+
+        let post = db.set_post_flags(2, Some(false), None).value.unwrap();
+        assert_eq!((None, true), (post.pinned_at, post.locked));
+        assert_eq!(
+            PostDbStatus::Err,
+            db.set_post_flags(9, None, Some(true)).status
+        );
+
+        // only changes emit events
+        let changed: Vec<u64> = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|event| event.post_id)
+            .collect();
+        assert_eq!(vec![3, 2, 2], changed);
+    }
 }
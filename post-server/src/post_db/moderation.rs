@@ -0,0 +1,224 @@
+//! Moderation
+//!
+//! reports waiting for a moderator and the log of what was done about
+//! them, with a broadcast channel announcing every change so the store
+//! can be saved
+
+use std::collections::BTreeMap;
+
+use tokio::sync::broadcast;
+
+use super::{ModerationAction, ModerationEntry, Report};
+
+/// Moderation struct - open reports, by post id then user, and the log
+pub struct Moderation {
+    next_report_id: u64,
+    reports: BTreeMap<u64, BTreeMap<String, Report>>,
+    next_entry_id: u64,
+    log: Vec<ModerationEntry>,
+    sender: broadcast::Sender<u64>,
+}
+
+/// Moderation default implementation
+impl Default for Moderation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Moderation implementation
+impl Moderation {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(64);
+        Moderation {
+            next_report_id: 1,
+            reports: BTreeMap::new(),
+            next_entry_id: 1,
+            log: vec![],
+            sender,
+        }
+    }
+
+    /// replace the open reports and the log with saved ones
+    pub fn restore(&mut self, reports: Vec<Report>, log: Vec<ModerationEntry>) {
+        self.next_report_id = reports
+            .iter()
+            .map(|report| report.report_id + 1)
+            .max()
+            .unwrap_or(1);
+        self.reports.clear();
+        for report in reports {
+            self.reports
+                .entry(report.post_id)
+                .or_default()
+                .insert(report.user.clone(), report);
+        }
+        self.next_entry_id = log
+            .iter()
+            .map(|entry| entry.entry_id + 1)
+            .max()
+            .unwrap_or(1);
+        self.log = log;
+        self.log.sort_by_key(|entry| entry.entry_id);
+    }
+
+    /// report a post as `user`, returning the report and how many users
+    /// have reported the post; a user reporting the post again keeps
+    /// their first report
+    pub fn report(
+        &mut self,
+        post_id: u64,
+        user: String,
+        reason: String,
+        now: u64,
+    ) -> (Report, u64) {
+        let reports = self.reports.entry(post_id).or_default();
+        let report = match reports.get(&user) {
+            Some(report) => report.clone(),
+            None => {
+                let report = Report {
+                    report_id: self.next_report_id,
+                    post_id,
+                    user: user.clone(),
+                    reason,
+                    created_at: now,
+                };
+                self.next_report_id += 1;
+                reports.insert(user, report.clone());
+                // an error only means nobody is listening right now
+                let _ = self.sender.send(post_id);
+                report
+            }
+        };
+        (report, reports.len() as u64)
+    }
+
+    /// the open reports against a post, oldest first
+    pub fn of(&self, post_id: u64) -> Vec<Report> {
+        let mut reports: Vec<Report> = self
+            .reports
+            .get(&post_id)
+            .into_iter()
+            .flat_map(|reports| reports.values().cloned())
+            .collect();
+        reports.sort_by_key(|report| report.report_id);
+        reports
+    }
+
+    /// the ids of every post with open reports
+    pub fn reported(&self) -> Vec<u64> {
+        self.reports.keys().copied().collect()
+    }
+
+    /// close a post's open reports, as after a moderator acted on it
+    pub fn resolve(&mut self, post_id: u64) {
+        if self.reports.remove(&post_id).is_some() {
+            let _ = self.sender.send(post_id);
+        }
+    }
+
+    /// add an entry to the log; entries are never changed or removed
+    pub fn record(
+        &mut self,
+        post_id: u64,
+        moderator: Option<String>,
+        action: ModerationAction,
+        user: Option<String>,
+        note: Option<String>,
+        now: u64,
+    ) -> ModerationEntry {
+        let entry = ModerationEntry {
+            entry_id: self.next_entry_id,
+            post_id,
+            moderator,
+            action,
+            user,
+            note,
+            created_at: now,
+        };
+        self.next_entry_id += 1;
+        self.log.push(entry.clone());
+        let _ = self.sender.send(post_id);
+        entry
+    }
+
+    /// the log, oldest first, only about `post_id` if given
+    pub fn log(&self, post_id: Option<u64>) -> Vec<ModerationEntry> {
+        self.log
+            .iter()
+            .filter(|entry| post_id.is_none_or(|post_id| entry.post_id == post_id))
+            .cloned()
+            .collect()
+    }
+
+    /// every open report, for saving
+    pub fn all_reports(&self) -> Vec<Report> {
+        self.reports
+            .values()
+            .flat_map(|reports| reports.values().cloned())
+            .collect()
+    }
+
+    /// the ids of posts whose reports or log changed, as they change
+    pub fn subscribe(&self) -> broadcast::Receiver<u64> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_count_once_per_user() {
+        let mut moderation = Moderation::new();
+        let mut receiver = moderation.subscribe();
+        let report = |moderation: &mut Moderation, user: &str, reason: &str| {
+            moderation.report(1, user.to_string(), reason.to_string(), 10)
+        };
+
+        assert_eq!(1, report(&mut moderation, "ann", "spam").1);
+        let (again, count) = report(&mut moderation, "ann", "still spam");
+        assert_eq!(("spam", 1), (again.reason.as_str(), count));
+        assert_eq!(2, report(&mut moderation, "bob", "rude").1);
+        assert_eq!(
+            vec![1, 2],
+            moderation
+                .of(1)
+                .iter()
+                .map(|report| report.report_id)
+                .collect::<Vec<_>>()
+        );
+
+        moderation.resolve(1);
+        moderation.record(1, None, ModerationAction::Hide, None, None, 20);
+        assert!(moderation.reported().is_empty());
+        // two new reports, the resolve and the log entry
+        assert_eq!(4, std::iter::from_fn(|| receiver.try_recv().ok()).count());
+
+        // ids carry on after a restore
+        let mut restored = Moderation::new();
+        restored.restore(
+            vec![report(&mut moderation, "carol", "spam").0],
+            moderation.log(None),
+        );
+        assert_eq!(1, restored.of(1).len());
+        assert_eq!(
+            4,
+            restored
+                .report(2, "ann".to_string(), "x".to_string(), 0)
+                .0
+                .report_id
+        );
+        let entry = restored.record(
+            2,
+            Some("mod".to_string()),
+            ModerationAction::Dismiss,
+            None,
+            None,
+            30,
+        );
+        assert_eq!(2, entry.entry_id);
+        assert_eq!(vec![entry], restored.log(Some(2)));
+    }
+}
@@ -0,0 +1,193 @@
+//! Notifications
+//!
+//! per-user notifications, kept newest last and capped per user, with a
+//! broadcast channel carrying new ones, and ones marked read, to live
+//! subscribers
+
+use std::collections::BTreeMap;
+
+use tokio::sync::broadcast;
+
+use super::{Notification, NotificationKind};
+
+/// notifications kept per user; the oldest go first
+pub const NOTIFICATIONS_PER_USER: usize = 1000;
+
+/// Notifications struct - every user's notifications
+pub struct Notifications {
+    next_notification_id: u64,
+    by_user: BTreeMap<String, Vec<Notification>>,
+    sender: broadcast::Sender<Notification>,
+}
+
+/// Notifications default implementation
+impl Default for Notifications {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Notifications implementation
+impl Notifications {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(256);
+        Notifications {
+            next_notification_id: 1,
+            by_user: BTreeMap::new(),
+            sender,
+        }
+    }
+
+    /// replace every notification with saved ones
+    pub fn restore(&mut self, notifications: Vec<Notification>) {
+        self.by_user.clear();
+        self.next_notification_id = 1;
+        for notification in notifications {
+            self.next_notification_id = self
+                .next_notification_id
+                .max(notification.notification_id + 1);
+            self.by_user
+                .entry(notification.user.clone())
+                .or_default()
+                .push(notification);
+        }
+        for notifications in self.by_user.values_mut() {
+            notifications.sort_by_key(|notification| notification.notification_id);
+        }
+    }
+
+    /// notify `user`, dropping their oldest notification if they have too many
+    pub fn notify(
+        &mut self,
+        user: &str,
+        kind: NotificationKind,
+        post_id: u64,
+        created_at: u64,
+    ) -> Notification {
+        let notification = Notification {
+            notification_id: self.next_notification_id,
+            user: user.to_string(),
+            kind,
+            post_id,
+            read: false,
+            created_at,
+        };
+        self.next_notification_id += 1;
+
+        let notifications = self.by_user.entry(user.to_string()).or_default();
+        if notifications.len() == NOTIFICATIONS_PER_USER {
+            notifications.remove(0);
+        }
+        notifications.push(notification.clone());
+        // an error only means nobody is listening right now
+        let _ = self.sender.send(notification.clone());
+        notification
+    }
+
+    /// a user's notifications, newest first
+    pub fn list(&self, user: &str, unread_only: bool) -> Vec<Notification> {
+        self.by_user
+            .get(user)
+            .into_iter()
+            .flatten()
+            .rev()
+            .filter(|notification| !unread_only || !notification.read)
+            .cloned()
+            .collect()
+    }
+
+    /// mark some of a user's notifications read, or all when `ids` is
+    /// `None`, returning how many were unread
+    pub fn mark_read(&mut self, user: &str, ids: Option<&[u64]>) -> u64 {
+        let mut marked = 0;
+        for notification in self.by_user.get_mut(user).into_iter().flatten() {
+            let wanted = ids.is_none_or(|ids| ids.contains(&notification.notification_id));
+            if wanted && !notification.read {
+                notification.read = true;
+                marked += 1;
+                let _ = self.sender.send(notification.clone());
+            }
+        }
+        marked
+    }
+
+    /// drop the notifications pointing at a deleted post
+    pub fn remove_post(&mut self, post_id: u64) {
+        for notifications in self.by_user.values_mut() {
+            notifications.retain(|notification| notification.post_id != post_id);
+        }
+        self.by_user
+            .retain(|_, notifications| !notifications.is_empty());
+    }
+
+    /// every notification, for saving
+    pub fn all(&self) -> Vec<Notification> {
+        self.by_user.values().flatten().cloned().collect()
+    }
+
+    /// new notifications, and ones marked read, as they happen
+    pub fn subscribe(&self) -> broadcast::Receiver<Notification> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn notifications_are_kept_per_user() {
+        let mut notifications = Notifications::new();
+        let mut receiver = notifications.subscribe();
+        notifications.notify("alice", NotificationKind::Mention, 1, 10);
+        notifications.notify("bob", NotificationKind::Mention, 1, 10);
+        notifications.notify("alice", NotificationKind::Mention, 2, 20);
+
+        let listed = notifications.list("alice", false);
+        assert_eq!(
+            vec![3, 1],
+            listed.iter().map(|n| n.notification_id).collect::<Vec<_>>()
+        );
+        assert!(notifications.list("carol", false).is_empty());
+
+        assert_eq!(1, notifications.mark_read("alice", Some(&[1, 2])));
+        assert_eq!(1, notifications.list("alice", true).len());
+        assert_eq!(1, notifications.mark_read("alice", None));
+        assert_eq!(0, notifications.mark_read("alice", None));
+
+        // three new ones, then the two marked read
+        let received: Vec<(u64, bool)> = std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|n| (n.notification_id, n.read))
+            .collect();
+        assert_eq!(
+            vec![(1, false), (2, false), (3, false), (1, true), (3, true)],
+            received
+        );
+
+        notifications.remove_post(1);
+        assert_eq!(
+            vec![3],
+            notifications
+                .all()
+                .iter()
+                .map(|n| n.notification_id)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn oldest_notifications_go_first() {
+        let mut notifications = Notifications::new();
+        for post_id in 0..NOTIFICATIONS_PER_USER as u64 + 1 {
+            notifications.notify("alice", NotificationKind::Mention, post_id, 0);
+        }
+        let listed = notifications.list("alice", false);
+        assert_eq!(NOTIFICATIONS_PER_USER, listed.len());
+        assert_eq!(1, listed.last().unwrap().post_id);
+
+        let mut restored = Notifications::new();
+        restored.restore(notifications.all());
+        let next = restored.notify("bob", NotificationKind::Mention, 0, 0);
+        assert_eq!(NOTIFICATIONS_PER_USER as u64 + 2, next.notification_id);
+    }
+}
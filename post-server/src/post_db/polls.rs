@@ -0,0 +1,142 @@
+//! Polls
+//!
+//! who voted for what in each post's poll; the posts themselves only
+//! carry the totals
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::Poll;
+
+/// One user's current vote in a poll
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Vote {
+    pub user: String,
+    /// indexes into the poll's options, in order
+    pub choices: Vec<u32>,
+}
+
+/// Votes struct - every vote, by post id then user
+#[derive(Default)]
+pub struct Votes {
+    ballots: BTreeMap<u64, BTreeMap<String, BTreeSet<u32>>>,
+}
+
+/// Votes implementation
+impl Votes {
+    pub fn new() -> Self {
+        Votes::default()
+    }
+
+    /// replace every vote with saved ones
+    pub fn restore(&mut self, votes: Vec<(u64, Vote)>) {
+        self.ballots.clear();
+        for (post_id, vote) in votes {
+            self.cast(post_id, vote.user, vote.choices.into_iter().collect());
+        }
+    }
+
+    /// record `user`'s choices in a post's poll, replacing their earlier
+    /// vote, or take the vote back when there are no choices; returns
+    /// whether anything changed
+    pub fn cast(&mut self, post_id: u64, user: String, choices: BTreeSet<u32>) -> bool {
+        let ballots = self.ballots.entry(post_id).or_default();
+        let changed = if choices.is_empty() {
+            ballots.remove(&user).is_some()
+        } else {
+            ballots.insert(user, choices.clone()).as_ref() != Some(&choices)
+        };
+        if ballots.is_empty() {
+            self.ballots.remove(&post_id);
+        }
+        changed
+    }
+
+    /// the votes in a post's poll, ordered by user
+    pub fn of(&self, post_id: u64) -> Vec<Vote> {
+        self.ballots
+            .get(&post_id)
+            .into_iter()
+            .flatten()
+            .map(|(user, choices)| Vote {
+                user: user.clone(),
+                choices: choices.iter().copied().collect(),
+            })
+            .collect()
+    }
+
+    /// forget the votes of a deleted post
+    pub fn remove_post(&mut self, post_id: u64) {
+        self.ballots.remove(&post_id);
+    }
+
+    /// count the votes of a post into its poll's results
+    pub fn tally(&self, post_id: u64, poll: &mut Poll) {
+        let ballots = self.ballots.get(&post_id);
+        for option in poll.options.iter_mut() {
+            option.votes = 0;
+        }
+        for choice in ballots
+            .into_iter()
+            .flat_map(|ballots| ballots.values().flatten())
+        {
+            if let Some(option) = poll.options.get_mut(*choice as usize) {
+                option.votes += 1;
+            }
+        }
+        poll.voters = ballots.map_or(0, |ballots| ballots.len() as u64);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use post_lib::PollOption;
+
+    fn poll() -> Poll {
+        Poll {
+            question: "lunch?".to_string(),
+            options: ["pizza", "sushi", "salad"]
+                .into_iter()
+                .map(|text| PollOption {
+                    text: text.to_string(),
+                    votes: 0,
+                })
+                .collect(),
+            multiple: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn votes_replace_earlier_ones() {
+        let mut votes = Votes::new();
+        assert!(votes.cast(1, "ann".to_string(), BTreeSet::from([0, 2])));
+        assert!(votes.cast(1, "bob".to_string(), BTreeSet::from([0])));
+        assert!(!votes.cast(1, "bob".to_string(), BTreeSet::from([0])));
+        assert!(votes.cast(1, "bob".to_string(), BTreeSet::from([1])));
+
+        let mut tallied = poll();
+        votes.tally(1, &mut tallied);
+        let counts: Vec<u64> = tallied.options.iter().map(|option| option.votes).collect();
+        assert_eq!(vec![1, 1, 1], counts);
+        assert_eq!(2, tallied.voters);
+
+        // no choices takes the vote back, once
+        assert!(votes.cast(1, "ann".to_string(), BTreeSet::new()));
+        assert!(!votes.cast(1, "ann".to_string(), BTreeSet::new()));
+        assert_eq!(
+            vec![Vote {
+                user: "bob".to_string(),
+                choices: vec![1]
+            }],
+            votes.of(1)
+        );
+
+        votes.remove_post(1);
+        votes.tally(1, &mut tallied);
+        assert_eq!(0, tallied.voters);
+        assert!(tallied.options.iter().all(|option| option.votes == 0));
+    }
+}
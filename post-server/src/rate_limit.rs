@@ -0,0 +1,438 @@
+//! Rate Limit Module
+//!
+//! token buckets per client, keyed by API key, user or address; reads
+//! and writes draw from separate buckets, and a route with its own limits
+//! gets buckets of its own. Clients over their limit get a `429`.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    body::{boxed, Body, BoxBody},
+    extract::ConnectInfo,
+    http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode},
+    response::IntoResponse,
+};
+use post_lib::ApiError;
+use tower::Service;
+
+use crate::config::{Limit, RateLimitConfig};
+
+/// bucket count above which full, and so forgettable, buckets are dropped
+const SWEEP_THRESHOLD: usize = 10_000;
+
+pub const RATELIMIT_LIMIT: &str = "ratelimit-limit";
+pub const RATELIMIT_REMAINING: &str = "ratelimit-remaining";
+pub const RATELIMIT_RESET: &str = "ratelimit-reset";
+
+/// Which bucket a request draws from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/// Access implementation
+impl Access {
+    pub fn of(method: &Method) -> Self {
+        match *method {
+            Method::GET | Method::HEAD | Method::OPTIONS => Access::Read,
+            _ => Access::Write,
+        }
+    }
+}
+
+/// Quota struct - the outcome of drawing a token from a client's bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quota {
+    pub allowed: bool,
+    /// the bucket size
+    pub limit: u32,
+    /// whole tokens left after this request
+    pub remaining: u32,
+    /// seconds until the bucket is full again
+    pub reset_secs: u64,
+    /// seconds until the next token, when not allowed
+    pub retry_after_secs: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Bucket implementation
+impl Bucket {
+    fn refill(&mut self, limit: Limit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate(limit)).min(limit.burst as f64);
+        self.updated = now;
+    }
+}
+
+/// tokens per second
+fn rate(limit: Limit) -> f64 {
+    limit.per_minute as f64 / 60.0
+}
+
+/// the bucket scope and limit for `route`: its own, or the shared defaults
+fn bucket_limit<'a>(config: &RateLimitConfig, route: &'a str, access: Access) -> (&'a str, Limit) {
+    let own = config.routes.get(route).and_then(|limits| match access {
+        Access::Read => limits.read,
+        Access::Write => limits.write,
+    });
+    match (own, access) {
+        (Some(limit), _) => (route, limit),
+        (None, Access::Read) => ("*", config.read),
+        (None, Access::Write) => ("*", config.write),
+    }
+}
+
+/// (client, route or `*` for the shared defaults, access)
+type BucketKey = (String, String, Access);
+
+/// RateLimiter struct - the configured limits and every client's buckets
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    buckets: HashMap<BucketKey, Bucket>,
+    sweep_at: usize,
+}
+
+/// RateLimiter implementation
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                sweep_at: SWEEP_THRESHOLD,
+            }),
+        }
+    }
+
+    /// who is asking: the bearer API key, else the user named by the
+    /// configured header, else the client address
+    pub fn client_key<B>(&self, request: &Request<B>) -> String {
+        let headers = request.headers();
+        let header_value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+
+        if let Some(key) = header_value(header::AUTHORIZATION.as_str())
+            .and_then(|value| value.strip_prefix("Bearer "))
+        {
+            return format!("key:{}", key);
+        }
+        if let Some(user) = self.config.user_header.as_deref().and_then(header_value) {
+            return format!("user:{}", user);
+        }
+        self.client_addr(request)
+    }
+
+    /// the client's address, forwarded for it by a trusted proxy or else
+    /// the peer's
+    pub fn client_addr<B>(&self, request: &Request<B>) -> String {
+        let forwarded = request
+            .headers()
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty() && self.config.trust_forwarded_for)
+            .and_then(|value| value.split(',').next())
+            .map(|addr| addr.trim().to_string());
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string());
+        format!(
+            "ip:{}",
+            forwarded.or(peer).unwrap_or_else(|| "unknown".to_string())
+        )
+    }
+
+    /// take a token for `client` calling `route`; `None` when the route
+    /// isn't limited
+    pub fn check(&self, client: &str, route: &str, access: Access, now: Instant) -> Option<Quota> {
+        if !self.config.enabled || self.config.exempt_routes.iter().any(|r| r == route) {
+            return None;
+        }
+        let (scope, limit) = bucket_limit(&self.config, route, access);
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.buckets.len() >= buckets.sweep_at {
+            buckets.sweep(&self.config, now);
+        }
+        let bucket = buckets
+            .buckets
+            .entry((client.to_string(), scope.to_string(), access))
+            .or_insert(Bucket {
+                tokens: limit.burst as f64,
+                updated: now,
+            });
+        bucket.refill(limit, now);
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        let secs_until =
+            |tokens: f64| ((tokens - bucket.tokens).max(0.0) / rate(limit)).ceil() as u64;
+        Some(Quota {
+            allowed,
+            limit: limit.burst,
+            remaining: bucket.tokens.floor() as u32,
+            reset_secs: secs_until(limit.burst as f64),
+            retry_after_secs: if allowed { 0 } else { secs_until(1.0) },
+        })
+    }
+}
+
+/// Buckets implementation
+impl Buckets {
+    /// forget buckets that have refilled, as a new bucket would be the same
+    fn sweep(&mut self, config: &RateLimitConfig, now: Instant) {
+        self.buckets.retain(|(_, scope, access), bucket| {
+            let (_, limit) = bucket_limit(config, scope, *access);
+            bucket.refill(limit, now);
+            bucket.tokens < limit.burst as f64
+        });
+        self.sweep_at = (self.buckets.len() * 2).max(SWEEP_THRESHOLD);
+    }
+}
+
+fn quota_headers(headers: &mut HeaderMap, quota: &Quota) {
+    headers.insert(RATELIMIT_LIMIT, HeaderValue::from(quota.limit));
+    headers.insert(RATELIMIT_REMAINING, HeaderValue::from(quota.remaining));
+    headers.insert(RATELIMIT_RESET, HeaderValue::from(quota.reset_secs));
+}
+
+fn limited_response(quota: &Quota) -> Response<BoxBody> {
+    let mut headers = HeaderMap::new();
+    quota_headers(&mut headers, quota);
+    headers.insert(
+        header::RETRY_AFTER,
+        HeaderValue::from(quota.retry_after_secs),
+    );
+    let error = ApiError::new(
+        "rate_limited",
+        format!("too many requests, retry in {}s", quota.retry_after_secs),
+    );
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        headers,
+        crate::error_body(error),
+    )
+        .into_response()
+        .map(boxed)
+}
+
+/// RouteRateLimit struct - a route's service, limited by the
+/// [`RateLimiter`] found in the request extensions
+#[derive(Clone)]
+pub struct RouteRateLimit<S> {
+    inner: S,
+    route: String,
+}
+
+/// RouteRateLimit implementation
+impl<S> RouteRateLimit<S> {
+    pub fn new(inner: S, route: &str) -> Self {
+        RouteRateLimit {
+            inner,
+            route: route.to_string(),
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for RouteRateLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let quota = request
+            .extensions()
+            .get::<Arc<RateLimiter>>()
+            .and_then(|limiter| {
+                limiter.check(
+                    &limiter.client_key(&request),
+                    &self.route,
+                    Access::of(request.method()),
+                    Instant::now(),
+                )
+            });
+
+        match quota {
+            Some(quota) if !quota.allowed => {
+                tracing::warn!(route = %self.route, "rate limited");
+                Box::pin(futures::future::ready(Ok(limited_response(&quota))))
+            }
+            _ => {
+                let response = self.inner.call(request);
+                Box::pin(async move {
+                    let mut response = response.await?;
+                    if let Some(quota) = quota {
+                        quota_headers(response.headers_mut(), &quota);
+                    }
+                    Ok(response)
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::RouteLimits;
+    use std::time::Duration;
+
+    fn limiter() -> RateLimiter {
+        let mut config = RateLimitConfig {
+            write: Limit {
+                per_minute: 60,
+                burst: 2,
+            },
+            ..Default::default()
+        };
+        config.routes.insert(
+            "/addPost".to_string(),
+            RouteLimits {
+                read: None,
+                write: Some(Limit {
+                    per_minute: 6,
+                    burst: 1,
+                }),
+            },
+        );
+        RateLimiter::new(config)
+    }
+
+    #[test]
+    fn buckets_empty_and_refill() {
+        let limiter = limiter();
+        let start = Instant::now();
+        let check = |secs| {
+            limiter
+                .check(
+                    "ip:1",
+                    "/updatePost",
+                    Access::Write,
+                    start + Duration::from_secs(secs),
+                )
+                .unwrap()
+        };
+
+        assert_eq!(1, check(0).remaining);
+        assert_eq!(0, check(0).remaining);
+        let limited = check(0);
+        assert!(!limited.allowed);
+        assert_eq!(1, limited.retry_after_secs);
+        assert_eq!(2, limited.reset_secs);
+
+        // one token a second
+        assert!(check(1).allowed);
+        assert!(!check(1).allowed);
+    }
+
+    #[test]
+    fn routes_clients_and_access_are_separate() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        // /addPost has its own bucket of one
+        assert!(
+            limiter
+                .check("ip:1", "/addPost", Access::Write, now)
+                .unwrap()
+                .allowed
+        );
+        let limited = limiter
+            .check("ip:1", "/addPost", Access::Write, now)
+            .unwrap();
+        assert!(!limited.allowed);
+        assert_eq!(10, limited.retry_after_secs);
+
+        // which leaves the shared write bucket, reads and other clients alone
+        assert!(
+            limiter
+                .check("ip:1", "/updatePost", Access::Write, now)
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            limiter
+                .check("ip:1", "/addPost", Access::Read, now)
+                .unwrap()
+                .allowed
+        );
+        assert!(
+            limiter
+                .check("ip:2", "/addPost", Access::Write, now)
+                .unwrap()
+                .allowed
+        );
+
+        assert_eq!(None, limiter.check("ip:1", "/healthz", Access::Read, now));
+    }
+
+    #[test]
+    fn keys_by_api_key_then_user_then_address() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            user_header: Some("x-forwarded-user".to_string()),
+            ..Default::default()
+        });
+        let request = |headers: &[(&str, &str)]| {
+            let mut builder = Request::builder();
+            for (name, value) in headers {
+                builder = builder.header(*name, *value);
+            }
+            let mut request = builder.body(()).unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 5000))));
+            request
+        };
+
+        assert_eq!(
+            "key:secret",
+            limiter.client_key(&request(&[
+                ("authorization", "Bearer secret"),
+                ("x-forwarded-user", "ann")
+            ]))
+        );
+        assert_eq!(
+            "user:ann",
+            limiter.client_key(&request(&[("x-forwarded-user", "ann")]))
+        );
+        // X-Forwarded-For is ignored unless trusted
+        assert_eq!(
+            "ip:10.0.0.1",
+            limiter.client_key(&request(&[("x-forwarded-for", "203.0.113.9")]))
+        );
+    }
+}
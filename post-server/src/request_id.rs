@@ -0,0 +1,144 @@
+//! Request Id Module
+//!
+//! every request runs inside a `request` span carrying an id, taken from
+//! the caller's `X-Request-Id` header or generated, which is echoed in the
+//! response header and in error bodies
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request, Response},
+};
+use tower::{Layer, Service};
+use tracing::Instrument;
+
+/// header carrying the request id, in both directions
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// longest caller-supplied id that is kept rather than replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// the id of the request being handled, if called from inside one
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// the caller's request id if it is usable, otherwise a new one
+fn request_id<B>(request: &Request<B>) -> String {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// RequestIdLayer struct - wraps the router in [`RequestId`]
+#[derive(Clone, Copy, Default)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestId<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestId { inner }
+    }
+}
+
+/// RequestId struct - runs each request in a span with its request id
+#[derive(Clone)]
+pub struct RequestId<S> {
+    inner: S,
+}
+
+impl<S, B> Service<Request<Body>> for RequestId<S>
+where
+    S: Service<Request<Body>, Response = Response<B>, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<B>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let id = request_id(&request);
+        let span = tracing::info_span!(
+            "request",
+            request_id = %id,
+            method = %request.method(),
+            path = %request.uri().path(),
+        );
+        let header = HeaderValue::from_str(&id).expect("request ids are valid header values");
+        let start = Instant::now();
+        let response = REQUEST_ID.scope(id, self.inner.call(request));
+
+        Box::pin(
+            async move {
+                let mut response = response.await?;
+                tracing::info!(
+                    status = response.status().as_u16(),
+                    latency_ms = start.elapsed().as_millis() as u64,
+                    "request finished"
+                );
+                response.headers_mut().insert(REQUEST_ID_HEADER, header);
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tower::ServiceExt;
+
+    async fn echo_current(_: Request<Body>) -> Result<Response<String>, Infallible> {
+        Ok(Response::new(current().unwrap_or_default()))
+    }
+
+    #[tokio::test]
+    async fn propagates_the_callers_id() {
+        let response = RequestIdLayer
+            .layer(tower::service_fn(echo_current))
+            .oneshot(
+                Request::builder()
+                    .header(REQUEST_ID_HEADER, "from-the-caller")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!("from-the-caller", response.headers()[REQUEST_ID_HEADER]);
+        assert_eq!("from-the-caller", response.body());
+    }
+
+    #[tokio::test]
+    async fn generates_an_id_when_missing() {
+        let response = RequestIdLayer
+            .layer(tower::service_fn(echo_current))
+            .oneshot(Request::new(Body::empty()))
+            .await
+            .unwrap();
+        let id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert_eq!(36, id.len());
+        assert_eq!(id, response.body());
+        assert_eq!(None, current());
+    }
+}
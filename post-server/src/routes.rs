@@ -0,0 +1,294 @@
+//! Routes Module
+//!
+//! the route table for the application, recording each path with the
+//! methods its handlers are registered for, so the OpenAPI document can be
+//! checked against it
+
+use std::{
+    convert::Infallible,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{Body, BoxBody},
+    handler::Handler,
+    http::{Request, Response},
+    routing::{self, MethodRouter},
+    AddExtensionLayer, Router,
+};
+use hyper::{
+    header::{
+        HeaderName, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_RANGE, CONTENT_TYPE,
+        IF_MODIFIED_SINCE, IF_NONE_MATCH, RANGE, RETRY_AFTER,
+    },
+    Method,
+};
+use tower::Service;
+use tower_http::cors::{self, AnyOr, CorsLayer, Origin};
+
+use crate::{
+    add_reaction_handler, atom_feed_handler,
+    attachments::{
+        add_attachment_handler, attachment_handler, thumbnail_handler, BlobStore,
+        MULTIPART_OVERHEAD,
+    },
+    audit::{audit_export_handler, audit_log_handler, AuditLayer},
+    auth::Auth,
+    config::Config,
+    delete_post_handler, delete_webhook_handler,
+    drafts::{
+        delete_draft_handler, drafts_handler, new_draft_handler, publish_draft_handler,
+        update_draft_handler,
+    },
+    events_handler, get_all_posts_handler, get_post_handler,
+    graphql::{self, graphiql_handler, graphql_handler, graphql_ws_handler},
+    health::{healthz_handler, readyz_handler, Health},
+    list_webhooks_handler, mark_notifications_read_handler,
+    metrics::{metrics_handler, RouteMetrics},
+    moderate_handler, moderation_log_handler, moderation_queue_handler, new_post_handler,
+    new_webhook_handler, notification_events_handler, notifications_handler,
+    openapi::{docs_handler, openapi_handler},
+    post_reactions_handler,
+    rate_limit::{
+        RateLimiter, RouteRateLimit, RATELIMIT_LIMIT, RATELIMIT_REMAINING, RATELIMIT_RESET,
+    },
+    remove_reaction_handler, report_post_handler,
+    request_id::{RequestIdLayer, REQUEST_ID_HEADER},
+    rss_feed_handler,
+    shutdown::Shutdown,
+    update_post_flags_handler, update_post_handler,
+    validation::BodyLimitLayer,
+    vote_handler, webhook_dead_letters_handler, webhook_deliveries_handler,
+    webhooks::WebhookRegistry,
+    PostDb,
+};
+
+/// RouteTable struct - a Router plus the (method, path) pairs registered on it
+pub struct RouteTable {
+    router: Router,
+    routes: Vec<(Method, String)>,
+}
+
+/// RouteTable implementation
+impl RouteTable {
+    pub fn new() -> Self {
+        RouteTable {
+            router: Router::new(),
+            routes: vec![],
+        }
+    }
+
+    /// register `handler` for `GET` requests to `path`
+    pub fn get<H, T>(self, path: &str, handler: H) -> Self
+    where
+        H: Handler<Body, T>,
+        T: Send + 'static,
+    {
+        self.route(path, &[Method::GET], routing::get(handler))
+    }
+
+    /// register `handler` for `POST` requests to `path`
+    pub fn post<H, T>(self, path: &str, handler: H) -> Self
+    where
+        H: Handler<Body, T>,
+        T: Send + 'static,
+    {
+        self.route(path, &[Method::POST], routing::post(handler))
+    }
+
+    /// register `get` for `GET` and `post` for `POST` requests to `path`
+    pub fn get_and_post<G, GT, P, PT>(self, path: &str, get: G, post: P) -> Self
+    where
+        G: Handler<Body, GT>,
+        GT: Send + 'static,
+        P: Handler<Body, PT>,
+        PT: Send + 'static,
+    {
+        self.route(
+            path,
+            &[Method::GET, Method::POST],
+            routing::get(get).post(post),
+        )
+    }
+
+    /// register `service` at `path`, recording the methods it answers
+    /// and counting its requests under the `path` route label; requests
+    /// are rate limited once a [`RateLimiter`] is added to the router.
+    /// Only the helpers above call it, with the methods they route, so
+    /// the recorded methods are always the ones the service answers
+    fn route<H, T, F>(
+        mut self,
+        path: &str,
+        methods: &[Method],
+        service: MethodRouter<H, Body, T, F>,
+    ) -> Self
+    where
+        MethodRouter<H, Body, T, F>: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>
+            + Clone
+            + Send
+            + 'static,
+        <MethodRouter<H, Body, T, F> as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        let service = RouteRateLimit::new(service, path);
+        self.router = self.router.route(path, RouteMetrics::new(service, path));
+        self.routes.extend(
+            methods
+                .iter()
+                .map(|method| (method.clone(), path.to_string())),
+        );
+        self
+    }
+
+    /// the (method, path) pairs registered so far, in axum path syntax
+    pub fn routes(&self) -> &[(Method, String)] {
+        &self.routes
+    }
+
+    pub fn into_router(self) -> Router {
+        self.router
+    }
+}
+
+/// RouteTable default implementation
+impl Default for RouteTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// every route the server answers
+pub fn route_table() -> RouteTable {
+    RouteTable::new()
+        .get("/posts", get_all_posts_handler)
+        .get("/post/:id", get_post_handler)
+        .post("/addPost", new_post_handler)
+        .post("/updatePost", update_post_handler)
+        .post("/deletePost/:id", delete_post_handler)
+        .post("/addDraft", new_draft_handler)
+        .post("/updateDraft", update_draft_handler)
+        .get("/drafts", drafts_handler)
+        .post("/publishDraft", publish_draft_handler)
+        .post("/deleteDraft", delete_draft_handler)
+        .post("/addAttachment/:id", add_attachment_handler)
+        .get("/attachment/:id", attachment_handler)
+        .get("/attachment/:id/thumbnail", thumbnail_handler)
+        .post("/addReaction", add_reaction_handler)
+        .post("/removeReaction", remove_reaction_handler)
+        .get("/post/:id/reactions", post_reactions_handler)
+        .post("/vote", vote_handler)
+        .post("/posts/:id/report", report_post_handler)
+        .get("/notifications", notifications_handler)
+        .post("/markNotificationsRead", mark_notifications_read_handler)
+        .get("/notifications/events", notification_events_handler)
+        .get("/events", events_handler)
+        .get("/feed.atom", atom_feed_handler)
+        .get("/feed.rss", rss_feed_handler)
+        .post("/admin/updatePostFlags", update_post_flags_handler)
+        .get("/admin/reports", moderation_queue_handler)
+        .post("/admin/moderate", moderate_handler)
+        .get("/admin/moderationLog", moderation_log_handler)
+        .get("/admin/auditLog", audit_log_handler)
+        .get("/admin/auditLog.ndjson", audit_export_handler)
+        .get_and_post(
+            "/admin/webhooks",
+            list_webhooks_handler,
+            new_webhook_handler,
+        )
+        .post("/admin/deleteWebhook/:id", delete_webhook_handler)
+        .get("/admin/webhookDeliveries", webhook_deliveries_handler)
+        .get("/admin/webhookDeadLetters", webhook_dead_letters_handler)
+        .get_and_post("/graphql", graphiql_handler, graphql_handler)
+        .get("/graphql/ws", graphql_ws_handler)
+        .get("/healthz", healthz_handler)
+        .get("/readyz", readyz_handler)
+        .get("/metrics", metrics_handler)
+        .get("/openapi.json", openapi_handler)
+        .get("/docs", docs_handler)
+}
+
+/// The application router with the default [`Config`]
+pub fn app(db: Arc<Mutex<PostDb>>, webhooks: Arc<Mutex<WebhookRegistry>>) -> Router {
+    let health = Health::new();
+    health.mark_restored();
+    let config = Config::default();
+    let blobs = Arc::new(BlobStore::new(config.attachments.dir.clone()));
+    app_with_config(
+        config,
+        db,
+        webhooks,
+        Shutdown::new(),
+        Arc::new(health),
+        blobs,
+    )
+}
+
+/// The application router, with CORS, shared state and request ids
+pub fn app_with_config(
+    config: Config,
+    db: Arc<Mutex<PostDb>>,
+    webhooks: Arc<Mutex<WebhookRegistry>>,
+    shutdown: Shutdown,
+    health: Arc<Health>,
+    blobs: Arc<BlobStore>,
+) -> Router {
+    let origin: AnyOr<Origin> = if config.server.allowed_origins.iter().any(|o| o == "*") {
+        cors::any().into()
+    } else {
+        Origin::list(
+            config
+                .server
+                .allowed_origins
+                .iter()
+                .map(|origin| origin.parse().expect("validated origin")),
+        )
+        .into()
+    };
+    let cors = CorsLayer::new()
+        .allow_methods(vec![Method::GET, Method::POST, Method::OPTIONS])
+        .allow_origin(origin)
+        .allow_credentials(false)
+        .allow_headers(vec![
+            AUTHORIZATION,
+            CONTENT_TYPE,
+            IF_MODIFIED_SINCE,
+            IF_NONE_MATCH,
+            RANGE,
+            HeaderName::from_static("last-event-id"),
+            HeaderName::from_static(REQUEST_ID_HEADER),
+        ])
+        .expose_headers(vec![
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static(RATELIMIT_LIMIT),
+            HeaderName::from_static(RATELIMIT_REMAINING),
+            HeaderName::from_static(RATELIMIT_RESET),
+            RETRY_AFTER,
+            CONTENT_RANGE,
+            CONTENT_DISPOSITION,
+        ]);
+
+    let schema = graphql::schema(db.clone(), config.limits.content);
+    let auth = Arc::new(Auth::new(&config.auth));
+    let rate_limiter = Arc::new(RateLimiter::new(config.rate_limit.clone()));
+    let body_limit = BodyLimitLayer::new(config.limits.max_body_bytes).prefix_limit(
+        "/addAttachment/",
+        usize::try_from(config.attachments.max_bytes)
+            .unwrap_or(usize::MAX)
+            .saturating_add(MULTIPART_OVERHEAD),
+    );
+
+    route_table()
+        .into_router()
+        .layer(cors)
+        .layer(AddExtensionLayer::new(schema))
+        .layer(AddExtensionLayer::new(Arc::new(config)))
+        .layer(AddExtensionLayer::new(auth.clone()))
+        .layer(AddExtensionLayer::new(shutdown))
+        .layer(AddExtensionLayer::new(health))
+        .layer(AddExtensionLayer::new(rate_limiter.clone()))
+        .layer(AddExtensionLayer::new(db))
+        .layer(AddExtensionLayer::new(webhooks))
+        .layer(AddExtensionLayer::new(blobs))
+        .layer(body_limit)
+        .layer(AuditLayer::new(auth, rate_limiter))
+        .layer(RequestIdLayer)
+}
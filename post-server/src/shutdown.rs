@@ -0,0 +1,139 @@
+//! Shutdown Module
+//!
+//! graceful shutdown: once triggered the server stops accepting
+//! connections, live streams end with a close reason, and in-flight
+//! requests get a bounded time to finish
+
+use std::{future::Future, net::SocketAddr, time::Duration};
+
+use axum::{Router, Server};
+use hyper::server::conn::AddrIncoming;
+use tokio::sync::watch;
+
+/// why live streams are closed when the server stops
+pub const SHUTDOWN_REASON: &str = "server shutting down";
+
+/// Shutdown struct - a cloneable handle to trigger or await shutdown
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: std::sync::Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+}
+
+/// Shutdown default implementation
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shutdown implementation
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        Shutdown {
+            sender: std::sync::Arc::new(sender),
+            receiver,
+        }
+    }
+
+    /// start shutting down; later calls do nothing
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// resolves once shutdown has been triggered
+    pub fn wait(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut receiver = self.receiver.clone();
+        async move {
+            // an error means every sender is gone, so shutdown can never come
+            if receiver.wait_for(|triggered| *triggered).await.is_err() {
+                futures::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// How the in-flight requests finished
+#[derive(Debug, PartialEq, Eq)]
+pub enum Drain {
+    /// every connection closed on its own
+    Complete,
+    /// connections were still open when the drain timeout ran out
+    TimedOut,
+}
+
+/// serve `router` until `shutdown` is triggered, then stop accepting
+/// connections and give in-flight requests `drain_timeout` to finish
+pub async fn serve(
+    incoming: AddrIncoming,
+    router: Router,
+    shutdown: Shutdown,
+    drain_timeout: Duration,
+) -> Result<Drain, hyper::Error> {
+    let server = Server::builder(incoming)
+        .serve(router.into_make_service_with_connect_info::<SocketAddr, _>())
+        .with_graceful_shutdown(shutdown.wait());
+    tokio::pin!(server);
+
+    tokio::select! {
+        result = &mut server => return result.map(|_| Drain::Complete),
+        _ = shutdown.wait() => {}
+    }
+    match tokio::time::timeout(drain_timeout, server).await {
+        Ok(result) => result.map(|_| Drain::Complete),
+        Err(_) => Ok(Drain::TimedOut),
+    }
+}
+
+/// resolves on SIGINT (ctrl-c) or, on unix, SIGTERM
+pub async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = futures::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn wait_resolves_after_trigger() {
+        let shutdown = Shutdown::new();
+        let waiting = tokio::spawn(shutdown.wait());
+        assert!(!shutdown.is_triggered());
+
+        shutdown.clone().trigger();
+        tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(shutdown.is_triggered());
+
+        // waiting after the fact resolves straight away
+        tokio::time::timeout(Duration::from_secs(1), shutdown.wait())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,610 @@
+//! Storage Module
+//!
+//! the file backend keeps posts in memory as usual and writes a JSON
+//! snapshot after every change, loading it again on startup. The audit log
+//! is kept apart, in an NDJSON file next to the snapshot that is only ever
+//! appended to
+
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+
+use crate::{
+    config::{StorageBackend, StorageConfig},
+    filters::SpamModel,
+    post_db::{
+        AuditEntry, Draft, ModerationEntry, Notification, Post, PostDb, Reaction, Report, Vote,
+    },
+};
+
+/// Snapshot struct - the contents of the snapshot file
+#[derive(Serialize, Deserialize, Default)]
+pub struct Snapshot {
+    pub posts: Vec<StoredPost>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notifications: Vec<Notification>,
+    /// unpublished drafts, including ones waiting for their publish time
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drafts: Vec<Draft>,
+    /// reports no moderator has acted on yet
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reports: Vec<Report>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub moderation_log: Vec<ModerationEntry>,
+    /// what the spam filter has learned from moderators
+    #[serde(default, skip_serializing_if = "SpamModel::is_empty")]
+    pub spam_model: SpamModel,
+    /// the audit log as older versions kept it, only read to move it into
+    /// the audit file
+    #[serde(default, skip_serializing)]
+    pub audit_log: Vec<AuditEntry>,
+    /// the id of the latest post change event, so ids carry on after a
+    /// restart instead of starting again at 1
+    #[serde(default)]
+    pub last_event_id: u64,
+    /// the ids the next post and draft get, so deleted ones are not
+    /// handed out again after a restart
+    #[serde(default)]
+    pub next_post_id: u64,
+    #[serde(default)]
+    pub next_draft_id: u64,
+}
+
+/// a post plus the timestamps its JSON representation leaves out
+#[derive(Serialize, Deserialize)]
+pub struct StoredPost {
+    #[serde(flatten)]
+    pub post: Post,
+    pub created_at: u64,
+    pub updated_at: u64,
+    /// who reacted to the post, since the post only carries counts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reacted: Vec<Reaction>,
+    /// who voted for what in the post's poll, since the poll only carries totals
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub voted: Vec<Vote>,
+}
+
+/// Snapshot implementation
+impl Snapshot {
+    pub fn of(post_db: &PostDb) -> Self {
+        let (next_post_id, next_draft_id) = post_db.next_ids();
+        Snapshot {
+            posts: post_db
+                .posts
+                .iter()
+                .map(|post| StoredPost {
+                    post: post.clone(),
+                    created_at: post.created_at,
+                    updated_at: post.updated_at,
+                    reacted: post_db.reactions_to(post.post_id),
+                    voted: post_db.votes(post.post_id),
+                })
+                .collect(),
+            notifications: post_db.all_notifications(),
+            drafts: post_db.all_drafts(),
+            reports: post_db.all_reports(),
+            moderation_log: post_db.moderation_log(None),
+            spam_model: post_db.spam_model().clone(),
+            audit_log: vec![],
+            last_event_id: post_db.last_event_id(),
+            next_post_id,
+            next_draft_id,
+        }
+    }
+
+    /// every saved reaction, by post id
+    pub fn reactions(&self) -> Vec<(u64, Reaction)> {
+        self.posts
+            .iter()
+            .flat_map(|stored| {
+                let post_id = stored.post.post_id;
+                stored
+                    .reacted
+                    .iter()
+                    .map(move |reaction| (post_id, reaction.clone()))
+            })
+            .collect()
+    }
+
+    /// every saved poll vote, by post id
+    pub fn votes(&self) -> Vec<(u64, Vote)> {
+        self.posts
+            .iter()
+            .flat_map(|stored| {
+                let post_id = stored.post.post_id;
+                stored.voted.iter().map(move |vote| (post_id, vote.clone()))
+            })
+            .collect()
+    }
+
+    pub fn into_posts(self) -> Vec<Post> {
+        self.posts
+            .into_iter()
+            .map(|stored| Post {
+                created_at: stored.created_at,
+                updated_at: stored.updated_at,
+                ..stored.post
+            })
+            .collect()
+    }
+
+    /// read a snapshot, treating a missing file as empty
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Snapshot::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// write the snapshot next to `path` and move it into place, so a
+    /// crash mid-write never leaves a truncated file behind
+    pub async fn save(&self, path: &Path) -> io::Result<()> {
+        let bytes = serde_json::to_vec_pretty(self)?;
+        let tmp = tmp_path(path);
+        tokio::fs::write(&tmp, bytes).await?;
+        tokio::fs::rename(&tmp, path).await
+    }
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// the audit file kept next to the snapshot at `path`, `posts.json`
+/// having `posts.audit.ndjson`
+pub fn audit_path(path: &Path) -> PathBuf {
+    path.with_extension("audit.ndjson")
+}
+
+/// read the audit file, treating a missing file as empty
+///
+/// a line cut short by a crash mid-write is skipped, and ended so that
+/// entries appended after it start on a line of their own
+pub fn load_audit_log(path: &Path) -> io::Result<Vec<AuditEntry>> {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    if !bytes.is_empty() && !bytes.ends_with(b"\n") {
+        OpenOptions::new()
+            .append(true)
+            .open(path)?
+            .write_all(b"\n")?;
+    }
+    let mut entries = vec![];
+    for (number, line) in bytes.split(|&byte| byte == b'\n').enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_slice(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => tracing::warn!("skipping line {} of {}: {}", number + 1, path.display(), e),
+        }
+    }
+    Ok(entries)
+}
+
+/// append `entries` to the audit file, one per line, and wait for them to
+/// reach the disk
+fn append_audit_entries(path: &Path, entries: &[AuditEntry]) -> io::Result<()> {
+    let mut lines = vec![];
+    for entry in entries {
+        serde_json::to_writer(&mut lines, entry)?;
+        lines.push(b'\n');
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(&lines)?;
+    file.sync_data()
+}
+
+/// append the audit log entries not yet in the audit file at `path`
+///
+/// this blocks rather than awaiting, so aborting the task calling it can't
+/// leave entries written but not marked as such, to be written twice
+pub fn write_audit_log(post_db: &Mutex<PostDb>, path: &Path) -> io::Result<()> {
+    let entries = post_db.lock().unwrap().unwritten_audit_entries();
+    let Some(last) = entries.last() else {
+        return Ok(());
+    };
+    append_audit_entries(path, &entries)?;
+    post_db.lock().unwrap().mark_audit_written(last.entry_id);
+    Ok(())
+}
+
+/// create the PostDb for the configured backend, restoring saved posts
+pub fn open(config: &StorageConfig, event_capacity: usize) -> io::Result<PostDb> {
+    let mut post_db = PostDb::with_event_capacity(event_capacity);
+    if let (StorageBackend::File, Some(path)) = (config.backend, &config.path) {
+        let mut snapshot = Snapshot::load(path)?;
+        let reactions = snapshot.reactions();
+        let votes = snapshot.votes();
+        let notifications = std::mem::take(&mut snapshot.notifications);
+        let drafts = std::mem::take(&mut snapshot.drafts);
+        let reports = std::mem::take(&mut snapshot.reports);
+        let moderation_log = std::mem::take(&mut snapshot.moderation_log);
+        let spam_model = std::mem::take(&mut snapshot.spam_model);
+        let last_event_id = snapshot.last_event_id;
+        let next_ids = (snapshot.next_post_id, snapshot.next_draft_id);
+        let audit_path = audit_path(path);
+        let mut audit_log = load_audit_log(&audit_path)?;
+        if audit_log.is_empty() && !snapshot.audit_log.is_empty() {
+            audit_log = std::mem::take(&mut snapshot.audit_log);
+            append_audit_entries(&audit_path, &audit_log)?;
+            tracing::info!(
+                "moved {} audit log entries into {}",
+                audit_log.len(),
+                audit_path.display()
+            );
+        }
+        post_db.restore(snapshot.into_posts());
+        post_db.restore_reactions(reactions);
+        post_db.restore_votes(votes);
+        post_db.restore_notifications(notifications);
+        post_db.restore_drafts(drafts);
+        post_db.restore_moderation(reports, moderation_log);
+        post_db.restore_spam_model(spam_model);
+        post_db.restore_audit_log(audit_log);
+        post_db.restore_next_ids(next_ids.0, next_ids.1);
+        post_db.restore_last_event_id(last_event_id);
+    }
+    Ok(post_db)
+}
+
+/// save a snapshot after every change, for the file backend
+///
+/// changes arriving while a snapshot is written are folded into the next one;
+/// notifications being marked read, draft changes, and reports and
+/// moderation, which is also what teaches the spam filter, count as changes
+/// too
+pub fn spawn_persister(post_db: Arc<Mutex<PostDb>>, path: PathBuf) -> JoinHandle<()> {
+    let (mut receiver, mut notifications, mut drafts, mut moderation) = {
+        let post_db = post_db.lock().unwrap();
+        (
+            post_db.subscribe(None).1,
+            post_db.subscribe_notifications(),
+            post_db.subscribe_drafts(),
+            post_db.subscribe_moderation(),
+        )
+    };
+    tokio::spawn(async move {
+        loop {
+            let received = tokio::select! {
+                received = receiver.recv() => received.map(drop),
+                received = notifications.recv() => received.map(drop),
+                received = drafts.recv() => received.map(drop),
+                received = moderation.recv() => received.map(drop),
+            };
+            match received {
+                Ok(()) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+            while receiver.try_recv().is_ok() {}
+            while notifications.try_recv().is_ok() {}
+            while drafts.try_recv().is_ok() {}
+            while moderation.try_recv().is_ok() {}
+
+            let snapshot = Snapshot::of(&post_db.lock().unwrap());
+            if let Err(e) = snapshot.save(&path).await {
+                tracing::error!("error saving snapshot to {}: {}", path.display(), e);
+            }
+        }
+    })
+}
+
+/// append new audit log entries to the audit file at `path` as they are
+/// added, for the file backend
+///
+/// entries that could not be written are tried again with the next ones
+pub fn spawn_audit_writer(post_db: Arc<Mutex<PostDb>>, path: PathBuf) -> JoinHandle<()> {
+    let mut receiver = post_db.lock().unwrap().subscribe_audit();
+    tokio::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+            while receiver.try_recv().is_ok() {}
+            if let Err(e) = write_audit_log(&post_db, &path) {
+                tracing::error!("error appending to audit log {}: {}", path.display(), e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use post_lib::{AuditQuery, Poll, PollOption};
+    use std::time::Duration;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("post-server-{}-{}.json", name, std::process::id()))
+    }
+
+    #[test]
+    fn snapshot_keeps_timestamps() {
+        let mut post_db = PostDb::new();
+        post_db.create_post("first".to_string());
+        post_db.posts[0].created_at = 10;
+        post_db.posts[0].updated_at = 20;
+
+        let json = serde_json::to_string(&Snapshot::of(&post_db)).unwrap();
+        let posts = serde_json::from_str::<Snapshot>(&json)
+            .unwrap()
+            .into_posts();
+        assert_eq!(post_db.posts, posts);
+        assert_eq!(20, posts[0].updated_at);
+    }
+
+    #[tokio::test]
+    async fn file_backend_survives_restart() {
+        let path = temp_path("restart");
+        let config = StorageConfig {
+            backend: StorageBackend::File,
+            path: Some(path.clone()),
+        };
+
+        let post_db = Arc::new(Mutex::new(open(&config, 16).unwrap()));
+        let persister = spawn_persister(post_db.clone(), path.clone());
+        post_db.lock().unwrap().create_post("first".to_string());
+        post_db.lock().unwrap().create_post("second".to_string());
+        post_db.lock().unwrap().delete_post(1);
+        post_db
+            .lock()
+            .unwrap()
+            .add_reaction(2, "ann".to_string(), "👍".to_string());
+        post_db
+            .lock()
+            .unwrap()
+            .update_post(2, "second, cc @bo".to_string());
+        post_db.lock().unwrap().mark_notifications_read("bo", None);
+        post_db.lock().unwrap().create_draft(
+            "ann".to_string(),
+            "later".to_string(),
+            Default::default(),
+            Some(u64::MAX),
+        );
+
+        let mut restored = vec![];
+        let mut reactions = vec![];
+        let mut notifications = vec![];
+        let mut drafts = vec![];
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let reopened = open(&config, 16).unwrap();
+            restored = reopened.posts.clone();
+            reactions = reopened.all_reactions();
+            notifications = reopened.all_notifications();
+            drafts = reopened.drafts("ann");
+            if restored.len() == 1
+                && restored[0].post_id == 2
+                && !reactions.is_empty()
+                && notifications
+                    .first()
+                    .is_some_and(|notification| notification.read)
+                && !drafts.is_empty()
+            {
+                break;
+            }
+        }
+        persister.abort();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(1, restored.len());
+        assert_eq!(2, restored[0].post_id);
+        assert_eq!("second, cc @bo", restored[0].content);
+        assert_eq!(1, notifications.len());
+        assert_eq!("bo", notifications[0].user);
+        assert!(notifications[0].read);
+        assert_eq!(1, drafts.len());
+        assert_eq!(Some(u64::MAX), drafts[0].publish_at);
+        assert_eq!(1, restored[0].reactions[0].count);
+        assert_eq!(
+            vec![(
+                2,
+                Reaction {
+                    emoji: "👍".to_string(),
+                    user: "ann".to_string()
+                }
+            )],
+            reactions
+        );
+    }
+
+    #[tokio::test]
+    async fn poll_votes_are_saved() {
+        let path = temp_path("votes");
+        let config = StorageConfig {
+            backend: StorageBackend::File,
+            path: Some(path.clone()),
+        };
+        let mut post_db = PostDb::new();
+        let poll = Poll {
+            question: "lunch?".to_string(),
+            options: vec![
+                PollOption {
+                    text: "pizza".to_string(),
+                    votes: 0,
+                },
+                PollOption {
+                    text: "sushi".to_string(),
+                    votes: 0,
+                },
+            ],
+            ..Default::default()
+        };
+        post_db.create_post_with_poll("vote please".to_string(), Default::default(), Some(poll));
+        post_db.vote(1, "ann".to_string(), [1].into());
+        post_db.vote(1, "bob".to_string(), [1].into());
+        Snapshot::of(&post_db).save(&path).await.unwrap();
+
+        let mut reopened = open(&config, 16).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(post_db.poll(1), reopened.poll(1));
+        assert_eq!(post_db.votes(1), reopened.votes(1));
+
+        // a changed vote still counts once
+        let poll = reopened
+            .vote(1, "ann".to_string(), [0].into())
+            .value
+            .unwrap();
+        assert_eq!(
+            (1, 1, 2),
+            (poll.options[0].votes, poll.options[1].votes, poll.voters)
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_and_moderation_log_are_saved() {
+        let path = temp_path("moderation");
+        let config = StorageConfig {
+            backend: StorageBackend::File,
+            path: Some(path.clone()),
+        };
+        let mut post_db = PostDb::new();
+        post_db.create_post("buy now".to_string());
+        post_db.create_post("hello".to_string());
+        post_db.report(1, "ann".to_string(), "spam".to_string(), 2);
+        post_db.report(1, "bob".to_string(), "spam".to_string(), 2);
+        post_db.report(2, "ann".to_string(), "boring".to_string(), 2);
+        Snapshot::of(&post_db).save(&path).await.unwrap();
+
+        let mut reopened = open(&config, 16).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(reopened.get_post(1).value.is_none());
+        assert_eq!(post_db.reported_posts(), reopened.reported_posts());
+        assert_eq!(post_db.moderation_log(None), reopened.moderation_log(None));
+
+        // new reports and log entries carry on from the saved ids
+        let report = reopened
+            .report(2, "carol".to_string(), "boring".to_string(), 0)
+            .value
+            .unwrap();
+        assert_eq!(4, report.report_id);
+        let entry = reopened
+            .moderate(
+                1,
+                "mod".to_string(),
+                post_lib::ModerationAction::Dismiss,
+                None,
+                None,
+            )
+            .value
+            .unwrap();
+        assert_eq!(2, entry.entry_id);
+
+        // what the dismissal taught the spam filter is saved too
+        Snapshot::of(&reopened).save(&path).await.unwrap();
+        let again = open(&config, 16).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(1, again.spam_model().ham_posts);
+        assert_eq!(reopened.spam_model(), again.spam_model());
+    }
+
+    #[tokio::test]
+    async fn audit_log_is_appended_to_its_own_file() {
+        let path = temp_path("audit");
+        let audit = audit_path(&path);
+        let config = StorageConfig {
+            backend: StorageBackend::File,
+            path: Some(path.clone()),
+        };
+        let post_db = Mutex::new(PostDb::new());
+        post_db.lock().unwrap().create_post("hello".to_string());
+        write_audit_log(&post_db, &audit).unwrap();
+        post_db.lock().unwrap().delete_post(1);
+        write_audit_log(&post_db, &audit).unwrap();
+        write_audit_log(&post_db, &audit).unwrap();
+        let snapshot = Snapshot::of(&post_db.lock().unwrap());
+        snapshot.save(&path).await.unwrap();
+        assert!(!std::fs::read_to_string(&path)
+            .unwrap()
+            .contains("audit_log"));
+
+        let all = AuditQuery::default();
+        let saved = post_db.lock().unwrap().audit_log(&all);
+        assert_eq!(2, saved.len());
+        assert_eq!(saved, load_audit_log(&audit).unwrap());
+
+        // entries about deleted posts stay, and neither entry nor post ids
+        // are handed out again
+        let reopened = Mutex::new(open(&config, 16).unwrap());
+        assert_eq!(saved, reopened.lock().unwrap().audit_log(&all));
+        let created = reopened.lock().unwrap().create_post("again".to_string());
+        assert_eq!(Some(2), created.value.post_id());
+        assert_eq!(3, reopened.lock().unwrap().audit_log(&all)[2].entry_id);
+
+        // a line cut short by a crash is skipped, and later entries are
+        // still read back
+        OpenOptions::new()
+            .append(true)
+            .open(&audit)
+            .unwrap()
+            .write_all(b"{\"entry_id\":")
+            .unwrap();
+        assert_eq!(2, load_audit_log(&audit).unwrap().len());
+        write_audit_log(&reopened, &audit).unwrap();
+        let entries = load_audit_log(&audit).unwrap();
+        assert_eq!(reopened.lock().unwrap().audit_log(&all), entries);
+
+        // the snapshot alone remembers post ids too
+        std::fs::remove_file(&audit).unwrap();
+        let mut without_log = open(&config, 16).unwrap();
+        assert_eq!(
+            Some(2),
+            without_log.create_post("again".to_string()).value.post_id()
+        );
+
+        // a log saved inside the snapshot is moved into the audit file
+        let mut json = serde_json::to_value(&snapshot).unwrap();
+        json["audit_log"] = serde_json::to_value(&saved).unwrap();
+        std::fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+        let migrated = open(&config, 16).unwrap();
+        let moved = load_audit_log(&audit);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&audit);
+        assert_eq!(saved, migrated.audit_log(&all));
+        assert_eq!(saved, moved.unwrap());
+    }
+
+    #[tokio::test]
+    async fn event_ids_carry_on_after_restart() {
+        let path = temp_path("events");
+        let config = StorageConfig {
+            backend: StorageBackend::File,
+            path: Some(path.clone()),
+        };
+        let mut post_db = PostDb::new();
+        post_db.create_post("hello".to_string());
+        post_db.update_post(1, "hello again".to_string());
+        Snapshot::of(&post_db).save(&path).await.unwrap();
+
+        let mut reopened = open(&config, 16).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(2, reopened.last_event_id());
+        let (_, mut receiver) = reopened.subscribe(None);
+        reopened.delete_post(1);
+        assert_eq!(3, receiver.try_recv().unwrap().event_id);
+    }
+
+    #[test]
+    fn memory_backend_ignores_path() {
+        let config = StorageConfig {
+            backend: StorageBackend::Memory,
+            path: Some(temp_path("unused")),
+        };
+        assert!(open(&config, 16).unwrap().posts.is_empty());
+    }
+}
@@ -0,0 +1,707 @@
+//! Validation Module
+//!
+//! checks on post content, configured under `[limits]`, on user names,
+//! reactions, polls, votes, reports, moderation and webhook urls, and a layer refusing
+//! request bodies larger than `limits.max_body_bytes`
+
+use std::{
+    collections::BTreeSet,
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{boxed, Body, BoxBody, Bytes},
+    http::{header, Request, Response, StatusCode},
+    response::IntoResponse,
+};
+use futures::stream;
+use hyper::body::HttpBody;
+use post_lib::{ApiError, FieldError, ModerateRequest, ModerationAction, NewPoll, PollOption};
+use tower::{Layer, Service};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::ContentLimits;
+
+/// normalize `content` and check it against `limits`, returning the
+/// normalized content or every problem found with it
+pub fn content(
+    field: &str,
+    content: &str,
+    limits: &ContentLimits,
+) -> Result<String, Vec<FieldError>> {
+    let content: String = content.replace("\r\n", "\n").nfc().collect();
+    let mut errors = vec![];
+    let mut fail = |code: &str, message: String| {
+        errors.push(FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message,
+        })
+    };
+
+    if content.trim().chars().count() < limits.min_chars {
+        fail(
+            "too_short",
+            format!(
+                "must have at least {} characters besides whitespace",
+                limits.min_chars
+            ),
+        );
+    }
+    let chars = content.chars().count();
+    if chars > limits.max_chars {
+        fail(
+            "too_long",
+            format!(
+                "must have at most {} characters, not {}",
+                limits.max_chars, chars
+            ),
+        );
+    }
+    let lines = content.lines().count();
+    if lines > limits.max_lines {
+        fail(
+            "too_many_lines",
+            format!(
+                "must have at most {} lines, not {}",
+                limits.max_lines, lines
+            ),
+        );
+    }
+    if let Some(c) = content
+        .chars()
+        .find(|c| c.is_control() && *c != '\n' && *c != '\t')
+    {
+        fail(
+            "control_character",
+            format!(
+                "must not contain control characters, found U+{:04X}",
+                c as u32
+            ),
+        );
+    }
+
+    if errors.is_empty() {
+        Ok(content)
+    } else {
+        Err(errors)
+    }
+}
+
+/// longest user name a reaction or notification request may carry
+const MAX_USER_CHARS: usize = 64;
+
+/// most code points in a reaction emoji, enough for joined sequences
+/// such as flags and families
+const MAX_EMOJI_CHARS: usize = 16;
+
+/// check a user name given as `field`, returning it normalized
+pub fn user(field: &str, user: &str) -> Result<String, Vec<FieldError>> {
+    let user: String = user.trim().nfc().collect();
+    let errors = user_errors(field, &user);
+    if errors.is_empty() {
+        Ok(user)
+    } else {
+        Err(errors)
+    }
+}
+
+fn user_errors(field: &str, user: &str) -> Vec<FieldError> {
+    let mut errors = vec![];
+    let mut fail = |code: &str, message: String| {
+        errors.push(FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message,
+        })
+    };
+
+    let user_chars = user.chars().count();
+    if user_chars == 0 {
+        fail("too_short", "must not be empty".to_string());
+    } else if user_chars > MAX_USER_CHARS {
+        fail(
+            "too_long",
+            format!("must have at most {} characters", MAX_USER_CHARS),
+        );
+    }
+    if user.chars().any(char::is_control) {
+        fail(
+            "control_character",
+            "must not contain control characters".to_string(),
+        );
+    }
+    errors
+}
+
+/// check the emoji of a reaction, returning it normalized
+pub fn reaction(emoji: &str) -> Result<String, Vec<FieldError>> {
+    let emoji: String = emoji.trim().nfc().collect();
+    let mut errors = vec![];
+    let mut fail = |field: &str, code: &str, message: String| {
+        errors.push(FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message,
+        })
+    };
+
+    let emoji_chars = emoji.chars().count();
+    if emoji_chars == 0 {
+        fail("emoji", "too_short", "must not be empty".to_string());
+    } else if emoji_chars > MAX_EMOJI_CHARS {
+        fail(
+            "emoji",
+            "too_long",
+            format!("must have at most {} characters", MAX_EMOJI_CHARS),
+        );
+    }
+    if emoji
+        .chars()
+        .any(|c| c.is_ascii() || c.is_whitespace() || c.is_control())
+    {
+        fail(
+            "emoji",
+            "not_emoji",
+            "must be a single emoji, not text".to_string(),
+        );
+    }
+
+    if errors.is_empty() {
+        Ok(emoji)
+    } else {
+        Err(errors)
+    }
+}
+
+/// most options a poll may offer
+const MAX_POLL_OPTIONS: usize = 10;
+
+/// longest poll question
+const MAX_QUESTION_CHARS: usize = 300;
+
+/// longest poll option
+const MAX_OPTION_CHARS: usize = 100;
+
+/// check a new poll, returning it normalized with no votes yet; it must
+/// close after `now`, in seconds since the unix epoch, if it closes at all
+pub fn poll(new_poll: &NewPoll, now: u64) -> Result<post_lib::Poll, Vec<FieldError>> {
+    let question: String = new_poll.question.trim().nfc().collect();
+    let options: Vec<String> = new_poll
+        .options
+        .iter()
+        .map(|option| option.trim().nfc().collect())
+        .collect();
+    let mut errors = text_errors("poll.question", &question, MAX_QUESTION_CHARS);
+    let mut fail = |field: &str, code: &str, message: String| {
+        errors.push(FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message,
+        })
+    };
+
+    if options.len() < 2 {
+        fail(
+            "poll.options",
+            "too_few",
+            "must offer at least 2 options".to_string(),
+        );
+    } else if options.len() > MAX_POLL_OPTIONS {
+        fail(
+            "poll.options",
+            "too_many",
+            format!("must offer at most {} options", MAX_POLL_OPTIONS),
+        );
+    }
+    let distinct: BTreeSet<&String> = options.iter().collect();
+    if distinct.len() < options.len() {
+        fail(
+            "poll.options",
+            "duplicate",
+            "must all be different".to_string(),
+        );
+    }
+    if new_poll.closes_at.is_some_and(|closes_at| closes_at <= now) {
+        fail(
+            "poll.closes_at",
+            "in_past",
+            "must be in the future".to_string(),
+        );
+    }
+    for (index, option) in options.iter().enumerate() {
+        errors.extend(text_errors(
+            &format!("poll.options[{}]", index),
+            option,
+            MAX_OPTION_CHARS,
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(post_lib::Poll {
+            question,
+            options: options
+                .into_iter()
+                .map(|text| PollOption { text, votes: 0 })
+                .collect(),
+            multiple: new_poll.multiple,
+            closes_at: new_poll.closes_at,
+            voters: 0,
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+/// check a vote's choices against the poll, returning the distinct choices
+pub fn vote(poll: &post_lib::Poll, choices: &[u32]) -> Result<BTreeSet<u32>, Vec<FieldError>> {
+    let choices: BTreeSet<u32> = choices.iter().copied().collect();
+    let mut errors = vec![];
+    let mut fail = |code: &str, message: String| {
+        errors.push(FieldError {
+            field: "choices".to_string(),
+            code: code.to_string(),
+            message,
+        })
+    };
+
+    if let Some(choice) = choices
+        .iter()
+        .find(|choice| **choice as usize >= poll.options.len())
+    {
+        fail(
+            "unknown_option",
+            format!(
+                "the poll has {} options, so there is no option {}",
+                poll.options.len(),
+                choice
+            ),
+        );
+    }
+    if !poll.multiple && choices.len() > 1 {
+        fail("too_many", "the poll allows a single choice".to_string());
+    }
+
+    if errors.is_empty() {
+        Ok(choices)
+    } else {
+        Err(errors)
+    }
+}
+
+/// longest reason a report may give
+const MAX_REASON_CHARS: usize = 500;
+
+/// longest note a moderator may leave in the moderation log
+const MAX_NOTE_CHARS: usize = 500;
+
+/// check the reason of a report, returning it normalized
+pub fn report(reason: &str) -> Result<String, Vec<FieldError>> {
+    let reason: String = reason.trim().nfc().collect();
+    let errors = text_errors("reason", &reason, MAX_REASON_CHARS);
+    if errors.is_empty() {
+        Ok(reason)
+    } else {
+        Err(errors)
+    }
+}
+
+/// check a moderator's decision, returning it normalized; only `warn`
+/// names a user, which it must, and a blank note counts as none
+pub fn moderation(request: &ModerateRequest) -> Result<ModerateRequest, Vec<FieldError>> {
+    let mut errors = vec![];
+
+    let user = match (request.action, &request.user) {
+        (ModerationAction::Warn, Some(user)) => {
+            let user: String = user.trim().nfc().collect();
+            errors.extend(user_errors("user", &user));
+            Some(user)
+        }
+        (ModerationAction::Warn, None) => {
+            errors.push(FieldError {
+                field: "user".to_string(),
+                code: "required".to_string(),
+                message: "must name the user to warn".to_string(),
+            });
+            None
+        }
+        _ => None,
+    };
+    let note = request
+        .note
+        .as_deref()
+        .map(|note| note.trim().nfc().collect::<String>())
+        .filter(|note| !note.is_empty());
+    if let Some(note) = &note {
+        errors.extend(text_errors("note", note, MAX_NOTE_CHARS));
+    }
+
+    if errors.is_empty() {
+        Ok(ModerateRequest {
+            post_id: request.post_id,
+            action: request.action,
+            user,
+            note,
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+/// check a webhook's url: an absolute `http` or `https` url naming a host
+pub fn webhook_url(url: &str) -> Result<String, Vec<FieldError>> {
+    let url = url.trim();
+    let fail = |code: &str, message: &str| {
+        Err(vec![FieldError {
+            field: "url".to_string(),
+            code: code.to_string(),
+            message: message.to_string(),
+        }])
+    };
+    let Ok(uri) = url.parse::<hyper::Uri>() else {
+        return fail("invalid_url", "must be an absolute url");
+    };
+    if !matches!(uri.scheme_str(), Some("http" | "https")) {
+        return fail("invalid_scheme", "must be an http or https url");
+    }
+    match uri.authority() {
+        Some(authority) if authority.as_str().contains('@') => {
+            fail("invalid_url", "must not carry credentials")
+        }
+        Some(_) if uri.host().is_some_and(|host| !host.is_empty()) => Ok(url.to_string()),
+        _ => fail("invalid_url", "must name a host"),
+    }
+}
+
+/// problems with a required line of text, such as a poll question
+fn text_errors(field: &str, text: &str, max_chars: usize) -> Vec<FieldError> {
+    let mut errors = vec![];
+    let mut fail = |code: &str, message: String| {
+        errors.push(FieldError {
+            field: field.to_string(),
+            code: code.to_string(),
+            message,
+        })
+    };
+
+    let chars = text.chars().count();
+    if chars == 0 {
+        fail("too_short", "must not be empty".to_string());
+    } else if chars > max_chars {
+        fail(
+            "too_long",
+            format!("must have at most {} characters", max_chars),
+        );
+    }
+    if text.chars().any(char::is_control) {
+        fail(
+            "control_character",
+            "must not contain control characters".to_string(),
+        );
+    }
+    errors
+}
+
+/// BodyLimitLayer struct - wraps the router in [`BodyLimit`]
+#[derive(Clone)]
+pub struct BodyLimitLayer {
+    max_bytes: usize,
+    prefix_limits: Vec<(&'static str, usize)>,
+}
+
+/// BodyLimitLayer implementation
+impl BodyLimitLayer {
+    pub fn new(max_bytes: usize) -> Self {
+        BodyLimitLayer {
+            max_bytes,
+            prefix_limits: vec![],
+        }
+    }
+
+    /// allow `max_bytes` instead for paths starting with `prefix`, e.g. uploads
+    pub fn prefix_limit(mut self, prefix: &'static str, max_bytes: usize) -> Self {
+        self.prefix_limits.push((prefix, max_bytes));
+        self
+    }
+}
+
+impl<S> Layer<S> for BodyLimitLayer {
+    type Service = BodyLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BodyLimit {
+            inner,
+            max_bytes: self.max_bytes,
+            prefix_limits: self.prefix_limits.clone(),
+        }
+    }
+}
+
+/// BodyLimit struct - answers `413` to bodies declared larger than the
+/// limit, and cuts off streamed bodies once they pass it
+#[derive(Clone)]
+pub struct BodyLimit<S> {
+    inner: S,
+    max_bytes: usize,
+    prefix_limits: Vec<(&'static str, usize)>,
+}
+
+impl<S> Service<Request<Body>> for BodyLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>, Error = Infallible>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        let path = request.uri().path();
+        let max_bytes = self
+            .prefix_limits
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix))
+            .map_or(self.max_bytes, |(_, max_bytes)| *max_bytes);
+        let declared = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+
+        let request = match declared {
+            Some(length) if length > max_bytes as u64 => {
+                let error = ApiError::new(
+                    "too_large",
+                    format!("request bodies are limited to {} bytes", max_bytes),
+                );
+                let response = (StatusCode::PAYLOAD_TOO_LARGE, crate::error_body(error))
+                    .into_response()
+                    .map(boxed);
+                return Box::pin(futures::future::ready(Ok(response)));
+            }
+            // hyper holds the body to its declared length
+            Some(_) => request,
+            None if request.body().is_end_stream() => request,
+            None => {
+                let (parts, body) = request.into_parts();
+                Request::from_parts(parts, limited(body, max_bytes))
+            }
+        };
+        Box::pin(self.inner.call(request))
+    }
+}
+
+/// `body`, failing once more than `max_bytes` have been read
+fn limited(body: Body, max_bytes: usize) -> Body {
+    let chunks = stream::try_unfold((body, 0), move |(mut body, read)| async move {
+        match body.data().await {
+            None => Ok(None),
+            Some(Ok(chunk)) => {
+                let read = read + chunk.len();
+                if read > max_bytes {
+                    Err(format!("request body is over {} bytes", max_bytes).into())
+                } else {
+                    Ok(Some((chunk, (body, read))))
+                }
+            }
+            Some(Err(e)) => Err(Box::new(e) as Box<dyn std::error::Error + Send + Sync>),
+        }
+    });
+    Body::wrap_stream::<_, Bytes, Box<dyn std::error::Error + Send + Sync>>(chunks)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn codes(result: Result<String, Vec<FieldError>>) -> Vec<String> {
+        result
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.code)
+            .collect()
+    }
+
+    #[test]
+    fn normalizes_valid_content() {
+        let limits = ContentLimits::default();
+        // "e" + combining acute accent becomes a single "é"
+        assert_eq!(
+            "caf\u{e9}\nline two\tend",
+            content("content", "cafe\u{301}\r\nline two\tend", &limits).unwrap()
+        );
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let limits = ContentLimits {
+            min_chars: 1,
+            max_chars: 10,
+            max_lines: 2,
+        };
+        assert_eq!(
+            vec!["too_short"],
+            codes(content("content", " \n ", &limits))
+        );
+        assert_eq!(
+            vec!["too_long", "too_many_lines", "control_character"],
+            codes(content("content", "a\nb\nc\u{7}defghij", &limits))
+        );
+        // length is counted after normalization
+        assert!(content("content", &"e\u{301}".repeat(10), &limits).is_ok());
+    }
+
+    #[test]
+    fn checks_reactions() {
+        assert_eq!("👍🏽", reaction(" 👍🏽 ").unwrap());
+        assert!(reaction("👨‍👩‍👧‍👦").is_ok());
+        let codes = |result: Result<String, Vec<FieldError>>| -> Vec<String> {
+            result
+                .unwrap_err()
+                .into_iter()
+                .map(|error| format!("{}:{}", error.field, error.code))
+                .collect()
+        };
+        assert_eq!(vec!["emoji:not_emoji"], codes(reaction(":+1:")));
+        assert_eq!(vec!["emoji:too_short"], codes(reaction("  ")));
+        assert_eq!("bo", user("user", " bo\n").unwrap());
+        assert_eq!("author", user("author", "b\u{0}o").unwrap_err()[0].field);
+    }
+
+    #[test]
+    fn checks_polls_and_votes() {
+        let new_poll = NewPoll {
+            question: " lunch? ".to_string(),
+            options: vec!["pizza".to_string(), " sushi".to_string()],
+            multiple: false,
+            closes_at: Some(100),
+        };
+        let checked = poll(&new_poll, 99).unwrap();
+        assert_eq!("lunch?", checked.question);
+        assert_eq!("sushi", checked.options[1].text);
+
+        let fields = |errors: Vec<FieldError>| -> Vec<String> {
+            errors
+                .into_iter()
+                .map(|error| format!("{}:{}", error.field, error.code))
+                .collect()
+        };
+        let bad_poll = NewPoll {
+            question: String::new(),
+            options: vec![
+                "pizza".to_string(),
+                "pizza ".to_string(),
+                "\u{7}".to_string(),
+            ],
+            ..new_poll.clone()
+        };
+        assert_eq!(
+            vec![
+                "poll.question:too_short",
+                "poll.options:duplicate",
+                "poll.closes_at:in_past",
+                "poll.options[2]:control_character"
+            ],
+            fields(poll(&bad_poll, 100).unwrap_err())
+        );
+        let one_option = NewPoll {
+            options: vec!["pizza".to_string()],
+            ..new_poll
+        };
+        assert_eq!(
+            vec!["poll.options:too_few"],
+            fields(poll(&one_option, 0).unwrap_err())
+        );
+
+        assert_eq!(BTreeSet::from([1]), vote(&checked, &[1, 1]).unwrap());
+        assert_eq!(
+            vec!["choices:unknown_option", "choices:too_many"],
+            fields(vote(&checked, &[0, 2]).unwrap_err())
+        );
+        let multiple = post_lib::Poll {
+            multiple: true,
+            ..checked
+        };
+        assert!(vote(&multiple, &[0, 1]).is_ok());
+        // no choices takes a vote back
+        assert!(vote(&multiple, &[]).is_ok());
+    }
+
+    #[test]
+    fn checks_reports_and_moderation() {
+        assert_eq!("spam", report(" spam ").unwrap());
+        let fields = |errors: Vec<FieldError>| -> Vec<String> {
+            errors
+                .into_iter()
+                .map(|error| format!("{}:{}", error.field, error.code))
+                .collect()
+        };
+        assert_eq!(
+            vec!["reason:too_long"],
+            fields(report(&"x".repeat(501)).unwrap_err())
+        );
+
+        let warn = ModerateRequest {
+            post_id: 1,
+            action: ModerationAction::Warn,
+            user: Some(" bob".to_string()),
+            note: Some("  ".to_string()),
+        };
+        let checked = moderation(&warn).unwrap();
+        assert_eq!(
+            (Some("bob"), None),
+            (checked.user.as_deref(), checked.note.as_deref())
+        );
+        let no_user = ModerateRequest {
+            user: None,
+            note: Some("line\nbreak".to_string()),
+            ..warn.clone()
+        };
+        assert_eq!(
+            vec!["user:required", "note:control_character"],
+            fields(moderation(&no_user).unwrap_err())
+        );
+        // only warnings name a user
+        let hide = ModerateRequest {
+            action: ModerationAction::Hide,
+            ..warn
+        };
+        assert_eq!(None, moderation(&hide).unwrap().user);
+    }
+
+    #[test]
+    fn checks_webhook_urls() {
+        assert_eq!(
+            "https://chat.example/hook?x=1",
+            webhook_url(" https://chat.example/hook?x=1 ").unwrap()
+        );
+        assert!(webhook_url("http://127.0.0.1:8080/hook").is_ok());
+        let code = |url: &str| webhook_url(url).unwrap_err()[0].code.clone();
+        assert_eq!("invalid_scheme", code("ftp://chat.example/hook"));
+        assert_eq!("invalid_scheme", code("/hook"));
+        assert_eq!("invalid_url", code("not a url"));
+        assert_eq!("invalid_url", code("https://user:pw@chat.example/"));
+    }
+
+    #[tokio::test]
+    async fn streamed_bodies_are_cut_off() {
+        let body = Body::wrap_stream(stream::iter(vec![
+            Ok::<_, std::io::Error>(Bytes::from_static(b"12345")),
+            Ok(Bytes::from_static(b"67890")),
+        ]));
+        assert!(hyper::body::to_bytes(limited(body, 8)).await.is_err());
+
+        let body = Body::from("1234");
+        assert_eq!(
+            &b"1234"[..],
+            hyper::body::to_bytes(limited(body, 8)).await.unwrap()
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Webhook Delivery
+//!
+//! a background task listens for post change events and delivers them to
+//! every interested webhook, signing each payload and retrying with
+//! exponential backoff
+
+use std::sync::{Arc, Mutex};
+
+use hmac::{Hmac, Mac};
+use hyper::{client::HttpConnector, Body, Client, Request};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use sha2::Sha256;
+use tokio::{sync::broadcast::error::RecvError, task::JoinHandle};
+
+use super::{DeadLetter, DeliveryAttempt, DeliveryStatus, Webhook, WebhookRegistry};
+use crate::post_db::{PostDb, PostEvent};
+
+/// header carrying `sha256=<hex hmac of the body>`
+pub const SIGNATURE_HEADER: &str = "x-post-signature";
+
+/// header carrying the event kind
+pub const EVENT_HEADER: &str = "x-post-event";
+
+/// header carrying the delivery id, stable across retries
+pub const DELIVERY_HEADER: &str = "x-post-delivery";
+
+/// the client deliveries are sent with, speaking `https` with the
+/// webpki root certificates as well as plain `http`
+type DeliveryClient = Client<HttpsConnector<HttpConnector>>;
+
+/// sign a payload with the webhook secret, in the format of [`SIGNATURE_HEADER`]
+pub fn sign_payload(secret: &str, payload: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("hmac accepts keys of any length");
+    mac.update(payload);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// spawn the task that fans post events out to webhooks
+pub fn spawn_dispatcher(
+    post_db: Arc<Mutex<PostDb>>,
+    webhooks: Arc<Mutex<WebhookRegistry>>,
+) -> JoinHandle<()> {
+    let (_, mut receiver) = post_db.lock().unwrap().subscribe(None);
+    let client: DeliveryClient = Client::builder().build(
+        HttpsConnectorBuilder::new()
+            .with_webpki_roots()
+            .https_or_http()
+            .enable_http1()
+            .build(),
+    );
+
+    tokio::spawn(async move {
+        loop {
+            let event = match receiver.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("webhook dispatcher lagged, {} events not delivered", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let subscribers = webhooks.lock().unwrap().subscribers(event.kind);
+            for webhook in subscribers {
+                tokio::spawn(deliver(
+                    client.clone(),
+                    webhooks.clone(),
+                    webhook,
+                    event.clone(),
+                ));
+            }
+        }
+    })
+}
+
+/// deliver one event to one webhook, retrying until it succeeds or
+/// the retry policy gives up, at which point it becomes a dead letter;
+/// deliveries to webhooks deleted in the meantime are dropped instead
+#[tracing::instrument(
+    name = "webhook_delivery",
+    skip_all,
+    fields(webhook_id = webhook.webhook_id, event_id = event.event_id)
+)]
+async fn deliver(
+    client: DeliveryClient,
+    webhooks: Arc<Mutex<WebhookRegistry>>,
+    webhook: Webhook,
+    event: PostEvent,
+) {
+    let (delivery_id, policy) = {
+        let mut registry = webhooks.lock().unwrap();
+        (registry.next_delivery_id(), registry.retry_policy.clone())
+    };
+    let payload = serde_json::to_vec(&event).unwrap();
+    let signature = sign_payload(&webhook.secret, &payload);
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+
+        let request = Request::post(&webhook.url)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .header(EVENT_HEADER, event.kind.name())
+            .header(DELIVERY_HEADER, delivery_id.to_string())
+            .body(Body::from(payload.clone()));
+
+        let result = match request {
+            Ok(request) => {
+                match tokio::time::timeout(policy.request_timeout, client.request(request)).await {
+                    Ok(Ok(response)) if response.status().is_success() => {
+                        Ok(response.status().as_u16())
+                    }
+                    Ok(Ok(response)) => Err((
+                        Some(response.status().as_u16()),
+                        format!("endpoint responded with {}", response.status()),
+                    )),
+                    Ok(Err(e)) => Err((None, e.to_string())),
+                    Err(_) => Err((None, "request timed out".to_string())),
+                }
+            }
+            Err(e) => Err((None, e.to_string())),
+        };
+
+        let finished = {
+            let mut registry = webhooks.lock().unwrap();
+            match result {
+                Ok(response_status) => {
+                    registry.record_attempt(DeliveryAttempt {
+                        delivery_id,
+                        webhook_id: webhook.webhook_id,
+                        event_id: event.event_id,
+                        attempt,
+                        status: DeliveryStatus::Delivered,
+                        response_status: Some(response_status),
+                        error: None,
+                    });
+                    true
+                }
+                Err((response_status, error)) => {
+                    tracing::warn!(attempt, "webhook delivery failed: {}", error);
+                    registry.record_attempt(DeliveryAttempt {
+                        delivery_id,
+                        webhook_id: webhook.webhook_id,
+                        event_id: event.event_id,
+                        attempt,
+                        status: DeliveryStatus::Failed,
+                        response_status,
+                        error: Some(error.clone()),
+                    });
+
+                    let exhausted = attempt >= policy.max_attempts;
+                    if exhausted {
+                        registry.record_dead_letter(DeadLetter {
+                            delivery_id,
+                            webhook_id: webhook.webhook_id,
+                            url: webhook.url.clone(),
+                            attempts: attempt,
+                            last_error: error,
+                            event: event.clone(),
+                        });
+                    }
+                    exhausted
+                }
+            }
+        };
+        if finished {
+            return;
+        }
+
+        tokio::time::sleep(policy.backoff(attempt)).await;
+        if !webhooks.lock().unwrap().has_webhook(webhook.webhook_id) {
+            tracing::debug!(attempt, "webhook was deleted, dropping the delivery");
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn signature_is_hex_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            sign_payload("Jefe", b"what do ya want for nothing?")
+        );
+    }
+}
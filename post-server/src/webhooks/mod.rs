@@ -0,0 +1,239 @@
+//! Webhooks Module
+//!
+//! outgoing webhook subscriptions for post change events, along with a
+//! log of delivery attempts and a dead-letter list for deliveries that
+//! ran out of retries
+
+mod delivery;
+
+use std::{collections::VecDeque, time::Duration};
+
+pub use post_lib::{CreateWebhookRequest, DeadLetter, DeliveryAttempt, DeliveryStatus, Webhook};
+
+use crate::post_db::PostEventKind;
+
+pub use delivery::{sign_payload, spawn_dispatcher, SIGNATURE_HEADER};
+
+/// number of delivery attempts kept in the delivery log
+pub const DELIVERY_LOG_CAPACITY: usize = 1000;
+
+/// number of dead letters kept
+pub const DEAD_LETTER_CAPACITY: usize = 1000;
+
+/// How often and how patiently deliveries are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub request_timeout: Duration,
+}
+
+/// RetryPolicy default implementation
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            request_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// the delay before retrying after the given (1-based) attempt,
+    /// doubling each time up to `max_backoff`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff)
+    }
+}
+
+/// WebhookRegistry struct - subscriptions plus delivery bookkeeping
+pub struct WebhookRegistry {
+    pub webhooks: Vec<Webhook>,
+    pub retry_policy: RetryPolicy,
+    deliveries: VecDeque<DeliveryAttempt>,
+    dead_letters: VecDeque<DeadLetter>,
+    next_webhook_id: u64,
+    next_delivery_id: u64,
+}
+
+/// WebhookRegistry default implementation
+impl Default for WebhookRegistry {
+    fn default() -> Self {
+        Self::new(RetryPolicy::default())
+    }
+}
+
+/// WebhookRegistry implementation
+impl WebhookRegistry {
+    pub fn new(retry_policy: RetryPolicy) -> Self {
+        WebhookRegistry {
+            webhooks: vec![],
+            retry_policy,
+            deliveries: VecDeque::new(),
+            dead_letters: VecDeque::new(),
+            next_webhook_id: 1,
+            next_delivery_id: 1,
+        }
+    }
+
+    /// add a new subscription, returning its id
+    pub fn add_webhook(&mut self, request: CreateWebhookRequest) -> u64 {
+        let webhook_id = self.next_webhook_id;
+        self.next_webhook_id += 1;
+        self.webhooks.push(Webhook {
+            webhook_id,
+            url: request.url,
+            secret: request.secret,
+            events: request.events,
+        });
+        webhook_id
+    }
+
+    /// remove a subscription, returning its id if it existed
+    pub fn remove_webhook(&mut self, webhook_id: u64) -> Option<u64> {
+        let index = self
+            .webhooks
+            .iter()
+            .position(|webhook| webhook.webhook_id == webhook_id)?;
+        self.webhooks.remove(index);
+        Some(webhook_id)
+    }
+
+    /// whether the subscription still exists
+    pub fn has_webhook(&self, webhook_id: u64) -> bool {
+        self.webhooks
+            .iter()
+            .any(|webhook| webhook.webhook_id == webhook_id)
+    }
+
+    /// the subscriptions interested in the given kind of event
+    pub fn subscribers(&self, kind: PostEventKind) -> Vec<Webhook> {
+        self.webhooks
+            .iter()
+            .filter(|webhook| webhook.wants(kind))
+            .cloned()
+            .collect()
+    }
+
+    /// hand out an id for a new delivery
+    pub fn next_delivery_id(&mut self) -> u64 {
+        let delivery_id = self.next_delivery_id;
+        self.next_delivery_id += 1;
+        delivery_id
+    }
+
+    /// record a delivery attempt, dropping the oldest one if the log is full
+    pub fn record_attempt(&mut self, attempt: DeliveryAttempt) {
+        if self.deliveries.len() == DELIVERY_LOG_CAPACITY {
+            self.deliveries.pop_front();
+        }
+        self.deliveries.push_back(attempt);
+    }
+
+    /// record a delivery that ran out of retries, dropping the oldest
+    /// one if the list is full
+    pub fn record_dead_letter(&mut self, dead_letter: DeadLetter) {
+        if self.dead_letters.len() == DEAD_LETTER_CAPACITY {
+            self.dead_letters.pop_front();
+        }
+        self.dead_letters.push_back(dead_letter);
+    }
+
+    /// delivery attempts, oldest first, optionally for a single webhook
+    pub fn deliveries(&self, webhook_id: Option<u64>) -> Vec<DeliveryAttempt> {
+        self.deliveries
+            .iter()
+            .filter(|attempt| webhook_id.is_none_or(|id| attempt.webhook_id == id))
+            .cloned()
+            .collect()
+    }
+
+    /// dead letters, oldest first, optionally for a single webhook
+    pub fn dead_letters(&self, webhook_id: Option<u64>) -> Vec<DeadLetter> {
+        self.dead_letters
+            .iter()
+            .filter(|dead_letter| webhook_id.is_none_or(|id| dead_letter.webhook_id == id))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(events: Vec<PostEventKind>) -> CreateWebhookRequest {
+        CreateWebhookRequest {
+            url: "http://localhost/hook".to_string(),
+            secret: "secret".to_string(),
+            events,
+        }
+    }
+
+    #[test]
+    fn add_and_remove_webhooks() {
+        let mut registry = WebhookRegistry::default();
+        assert_eq!(1, registry.add_webhook(request(vec![])));
+        assert_eq!(2, registry.add_webhook(request(vec![])));
+
+        assert_eq!(Some(1), registry.remove_webhook(1));
+        assert_eq!(None, registry.remove_webhook(1));
+        assert_eq!(1, registry.webhooks.len());
+    }
+
+    #[test]
+    fn subscribers_filter_by_event_kind() {
+        let mut registry = WebhookRegistry::default();
+        registry.add_webhook(request(vec![]));
+        registry.add_webhook(request(vec![PostEventKind::Deleted]));
+
+        assert_eq!(1, registry.subscribers(PostEventKind::Created).len());
+        assert_eq!(2, registry.subscribers(PostEventKind::Deleted).len());
+    }
+
+    #[test]
+    fn dead_letters_are_capped() {
+        let mut registry = WebhookRegistry::default();
+        for delivery_id in 0..DEAD_LETTER_CAPACITY as u64 + 2 {
+            registry.record_dead_letter(DeadLetter {
+                delivery_id,
+                webhook_id: 1,
+                url: "http://localhost/hook".to_string(),
+                attempts: 5,
+                last_error: "refused".to_string(),
+                event: crate::post_db::PostEvent {
+                    event_id: delivery_id,
+                    kind: PostEventKind::Created,
+                    post_id: 1,
+                    post: None,
+                    reaction: None,
+                },
+            });
+        }
+        let dead_letters = registry.dead_letters(None);
+        assert_eq!(DEAD_LETTER_CAPACITY, dead_letters.len());
+        assert_eq!(2, dead_letters[0].delivery_id);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            ..Default::default()
+        };
+
+        assert_eq!(Duration::from_secs(1), policy.backoff(1));
+        assert_eq!(Duration::from_secs(2), policy.backoff(2));
+        assert_eq!(Duration::from_secs(4), policy.backoff(3));
+        assert_eq!(Duration::from_secs(5), policy.backoff(4));
+        assert_eq!(Duration::from_secs(5), policy.backoff(40));
+    }
+}
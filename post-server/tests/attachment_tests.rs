@@ -0,0 +1,264 @@
+/// attachment upload and download tests
+use std::{
+    io::Cursor,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use tower::ServiceExt;
+
+use post_server::{
+    app_with_config,
+    attachments::{spawn_cleaner, BlobStore},
+    config::Config,
+    health::Health,
+    shutdown::Shutdown,
+    PostDb,
+};
+
+const BOUNDARY: &str = "attachment-test-boundary";
+
+struct TestApp {
+    app: Router,
+    db: Arc<Mutex<PostDb>>,
+    blobs: Arc<BlobStore>,
+    dir: PathBuf,
+}
+
+impl Drop for TestApp {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn test_app(name: &str, max_bytes: u64) -> TestApp {
+    let dir = std::env::temp_dir().join(format!(
+        "post-server-attachment-tests-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let mut config = Config::default();
+    config.attachments.dir = dir.clone();
+    config.attachments.max_bytes = max_bytes;
+    config.rate_limit.enabled = false;
+
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    db.lock().unwrap().create_post("first".to_string());
+    db.lock().unwrap().create_post("second".to_string());
+    let blobs = Arc::new(BlobStore::open(&dir).unwrap());
+    let app = app_with_config(
+        config,
+        db.clone(),
+        Arc::new(Mutex::new(Default::default())),
+        Shutdown::new(),
+        Arc::new(Health::new()),
+        blobs.clone(),
+    );
+    TestApp {
+        app,
+        db,
+        blobs,
+        dir,
+    }
+}
+
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut bytes = vec![];
+    image::RgbImage::from_pixel(width, height, image::Rgb([200, 30, 30]))
+        .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+        .unwrap();
+    bytes
+}
+
+/// a multipart body with one part per (file name, content type, content)
+fn multipart(files: &[(&str, &str, &[u8])]) -> Vec<u8> {
+    let mut body = vec![];
+    for (file_name, content_type, content) in files {
+        body.extend(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                BOUNDARY, file_name, content_type
+            )
+            .as_bytes(),
+        );
+        body.extend(*content);
+        body.extend(b"\r\n");
+    }
+    body.extend(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    body
+}
+
+async fn upload(app: &Router, post_id: u64, files: &[(&str, &str, &[u8])]) -> (StatusCode, Value) {
+    let body = multipart(files);
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri(format!("/addAttachment/{}", post_id))
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .header(header::CONTENT_LENGTH, body.len())
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+async fn download(
+    app: &Router,
+    uri: &str,
+    range: Option<&str>,
+) -> (StatusCode, header::HeaderMap, Vec<u8>) {
+    let mut request = Request::builder().uri(uri);
+    if let Some(range) = range {
+        request = request.header(header::RANGE, range);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, headers, body.to_vec())
+}
+
+#[tokio::test]
+async fn uploads_download_with_ranges_and_thumbnails() {
+    let test = test_app("round-trip", 1024 * 1024);
+    let image = png(600, 300);
+    let (status, attachments) = upload(
+        &test.app,
+        1,
+        &[
+            ("logs/app.log", "text/plain", b"line one\nline two\n"),
+            ("screen.png", "image/png", &image),
+        ],
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status, "{}", attachments);
+    assert_eq!(attachments[0]["attachment_id"], 1);
+    assert_eq!(attachments[0]["file_name"], "app.log");
+    assert_eq!(attachments[0]["size"], 18);
+    assert_eq!(attachments[0]["thumbnail"], false);
+    assert_eq!(attachments[1]["content_type"], "image/png");
+    assert_eq!(attachments[1]["thumbnail"], true);
+    assert_eq!(2, test.db.lock().unwrap().posts[0].attachments.len());
+
+    let (status, headers, body) = download(&test.app, "/attachment/1", None).await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(b"line one\nline two\n", &body[..]);
+    assert_eq!("text/plain", headers[header::CONTENT_TYPE]);
+    assert_eq!("bytes", headers[header::ACCEPT_RANGES]);
+    assert!(headers[header::CONTENT_DISPOSITION]
+        .to_str()
+        .unwrap()
+        .starts_with("attachment; filename=\"app.log\""));
+
+    let (status, headers, body) = download(&test.app, "/attachment/1", Some("bytes=5-7")).await;
+    assert_eq!(StatusCode::PARTIAL_CONTENT, status);
+    assert_eq!(b"one", &body[..]);
+    assert_eq!("bytes 5-7/18", headers[header::CONTENT_RANGE]);
+
+    let (status, headers, _) = download(&test.app, "/attachment/1", Some("bytes=18-")).await;
+    assert_eq!(StatusCode::RANGE_NOT_SATISFIABLE, status);
+    assert_eq!("bytes */18", headers[header::CONTENT_RANGE]);
+
+    let (status, _, body) = download(&test.app, "/attachment/2/thumbnail", None).await;
+    assert_eq!(StatusCode::OK, status);
+    let thumbnail = image::load_from_memory(&body).unwrap();
+    assert_eq!((256, 128), (thumbnail.width(), thumbnail.height()));
+
+    let (status, _, _) = download(&test.app, "/attachment/1/thumbnail", None).await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+    let (status, _, _) = download(&test.app, "/attachment/9", None).await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+}
+
+#[tokio::test]
+async fn uploads_are_checked() {
+    let test = test_app("checks", 64);
+
+    let (status, body) = upload(&test.app, 1, &[("page.html", "text/html", b"<p>hi</p>")]).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["code"], "type_not_allowed");
+
+    let (status, body) = upload(&test.app, 1, &[("fake.png", "image/png", b"not a png")]).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["code"], "type_mismatch");
+
+    let (status, body) = upload(&test.app, 1, &[("big.txt", "text/plain", &[b'x'; 65])]).await;
+    assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, status);
+    assert_eq!(body["error"], "too_large");
+
+    let (status, body) = upload(&test.app, 1, &[]).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["code"], "missing");
+
+    let (status, body) = upload(&test.app, 7, &[("a.txt", "text/plain", b"a")]).await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+    assert_eq!(body["error"], "not_found");
+
+    test.db.lock().unwrap().set_post_flags(2, None, Some(true));
+    let (status, body) = upload(&test.app, 2, &[("a.txt", "text/plain", b"a")]).await;
+    assert_eq!(StatusCode::LOCKED, status);
+    assert_eq!(body["error"], "locked");
+
+    // nothing was attached, so nothing is left behind once swept
+    assert!(test.db.lock().unwrap().posts[0].attachments.is_empty());
+    test.blobs.sweep(&test.db).unwrap();
+    assert_eq!(
+        0,
+        std::fs::read_dir(test.dir.join("blobs")).unwrap().count()
+    );
+}
+
+#[tokio::test]
+async fn deleted_posts_leave_no_orphans() {
+    let test = test_app("orphans", 1024);
+    let cleaner = spawn_cleaner(test.db.clone(), test.blobs.clone());
+
+    let (_, first) = upload(&test.app, 1, &[("shared.txt", "text/plain", b"shared")]).await;
+    let (_, second) = upload(
+        &test.app,
+        2,
+        &[
+            ("shared.txt", "text/plain", b"shared"),
+            ("own.txt", "text/plain", b"only on post 2"),
+        ],
+    )
+    .await;
+    // identical content is stored once
+    assert_eq!(first[0]["sha256"], second[0]["sha256"]);
+    let blob_count = || std::fs::read_dir(test.dir.join("blobs")).unwrap().count();
+    assert_eq!(2, blob_count());
+
+    test.db.lock().unwrap().delete_post(2);
+    for _ in 0..50 {
+        if blob_count() == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    cleaner.abort();
+
+    assert_eq!(1, blob_count());
+    let (status, _, body) = download(&test.app, "/attachment/1", None).await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(b"shared", &body[..]);
+}
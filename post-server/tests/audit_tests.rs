@@ -0,0 +1,271 @@
+/// audit log tests
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    http::{self, Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use post_server::{
+    app_with_config,
+    attachments::BlobStore,
+    audit::NDJSON_CONTENT_TYPE,
+    config::{AuthConfig, Config, RateLimitConfig},
+    health::Health,
+    shutdown::Shutdown,
+    PostDb,
+};
+
+const ANN_KEY: &str = "s3cret-ann";
+const MODERATOR_KEY: &str = "s3cret-mod";
+
+fn app(db: Arc<Mutex<PostDb>>) -> Router {
+    let health = Health::new();
+    health.mark_restored();
+    let config = Config {
+        auth: AuthConfig {
+            api_keys: [
+                (ANN_KEY.to_string(), "ann".to_string()),
+                (MODERATOR_KEY.to_string(), "mod".to_string()),
+            ]
+            .into(),
+            moderators: vec!["mod".to_string()],
+        },
+        rate_limit: RateLimitConfig {
+            user_header: Some("x-user".to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let blobs = Arc::new(BlobStore::new(config.attachments.dir.clone()));
+    app_with_config(
+        config,
+        db,
+        Arc::new(Mutex::new(Default::default())),
+        Shutdown::new(),
+        Arc::new(health),
+        blobs,
+    )
+}
+
+async fn post(app: &Router, uri: &str, key: &str, request_id: &str, body: Value) -> Value {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri(uri)
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", key))
+                .header("x-request-id", request_id)
+                .header("x-user", "mod")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status(), "{}", uri);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap_or(Value::Null)
+}
+
+async fn get(app: &Router, key: Option<&str>, uri: &str) -> (StatusCode, http::HeaderMap, String) {
+    let mut request = Request::builder().uri(uri);
+    if let Some(key) = key {
+        request = request.header(http::header::AUTHORIZATION, format!("Bearer {}", key));
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, headers, String::from_utf8(body.to_vec()).unwrap())
+}
+
+async fn audit_log(app: &Router, query: &str) -> Vec<Value> {
+    let (status, _, body) = get(
+        app,
+        Some(MODERATOR_KEY),
+        &format!("/admin/auditLog{}", query),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[tokio::test]
+async fn mutations_are_audited_with_their_caller_and_request() {
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    let app = app(db.clone());
+
+    let create = json!({ "content": "hello" });
+    post(&app, "/addPost", ANN_KEY, "req-1", create).await;
+    let update = json!({ "post_id": 1, "updated_content": "hello there" });
+    post(&app, "/updatePost", ANN_KEY, "req-2", update).await;
+    // the user header does not name the actor, and unknown keys are only
+    // fingerprinted
+    let react = json!({ "post_id": 1, "emoji": "👍" });
+    post(&app, "/addReaction", ANN_KEY, "req-3", react).await;
+    let update = json!({ "post_id": 1, "updated_content": "hello again" });
+    post(&app, "/updatePost", "secret", "req-4", update).await;
+    post(&app, "/deletePost/1", MODERATOR_KEY, "req-5", Value::Null).await;
+
+    let log = audit_log(&app, "").await;
+    let entries: Vec<(&str, &str, &str)> = log
+        .iter()
+        .map(|entry| {
+            (
+                entry["action"].as_str().unwrap(),
+                entry["actor"].as_str().unwrap(),
+                entry["request_id"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    let key = entries[3].1;
+    assert!(key.starts_with("key:") && !key.contains("secret"));
+    assert_eq!(
+        vec![
+            ("create", "ann", "req-1"),
+            ("update", "ann", "req-2"),
+            ("react", "ann", "req-3"),
+            ("update", key, "req-4"),
+            ("delete", "mod", "req-5"),
+        ],
+        entries
+    );
+    assert_eq!(log[0]["after"], log[1]["before"]);
+    assert_eq!(Value::Null, log[4]["after"]);
+
+    // reading the log changes nothing, so is not logged
+    assert_eq!(5, audit_log(&app, "").await.len());
+    let filtered = audit_log(&app, "?actor=mod&action=delete").await;
+    assert_eq!(vec![log[4].clone()], filtered);
+    assert!(audit_log(&app, "?post_id=2").await.is_empty());
+
+    let (_, headers, body) = get(
+        &app,
+        Some(MODERATOR_KEY),
+        "/admin/auditLog.ndjson?post_id=1",
+    )
+    .await;
+    assert_eq!(NDJSON_CONTENT_TYPE, headers[http::header::CONTENT_TYPE]);
+    let lines: Vec<Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(log, lines);
+
+    // the deleted post's id is not handed out again
+    let created = post(
+        &app,
+        "/addPost",
+        ANN_KEY,
+        "req-6",
+        json!({ "content": "again" }),
+    )
+    .await;
+    assert_eq!(json!(2), created);
+}
+
+#[tokio::test]
+async fn drafts_and_webhooks_are_audited() {
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    let app = app(db.clone());
+
+    let draft = post(
+        &app,
+        "/addDraft",
+        ANN_KEY,
+        "req-1",
+        json!({ "content": "soon" }),
+    )
+    .await;
+    let draft_id = draft["draft_id"].as_u64().unwrap();
+    let update = json!({ "draft_id": draft_id, "content": "now" });
+    post(&app, "/updateDraft", ANN_KEY, "req-2", update).await;
+    let publish = json!({ "draft_id": draft_id });
+    post(&app, "/publishDraft", ANN_KEY, "req-3", publish).await;
+
+    let webhook = json!({ "url": "https://example.com/hook", "secret": "shh" });
+    let webhook_id = post(&app, "/admin/webhooks", MODERATOR_KEY, "req-4", webhook).await;
+    let uri = format!("/admin/deleteWebhook/{}", webhook_id);
+    post(&app, &uri, MODERATOR_KEY, "req-5", Value::Null).await;
+
+    let log = audit_log(&app, "").await;
+    let entries: Vec<(&str, &str, Value, Value, Value)> = log
+        .iter()
+        .map(|entry| {
+            (
+                entry["action"].as_str().unwrap(),
+                entry["actor"].as_str().unwrap(),
+                entry["draft_id"].clone(),
+                entry["post_id"].clone(),
+                entry["webhook_id"].clone(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        vec![
+            (
+                "create_draft",
+                "ann",
+                json!(draft_id),
+                Value::Null,
+                Value::Null
+            ),
+            (
+                "update_draft",
+                "ann",
+                json!(draft_id),
+                Value::Null,
+                Value::Null
+            ),
+            ("create", "ann", Value::Null, json!(1), Value::Null),
+            (
+                "publish_draft",
+                "ann",
+                json!(draft_id),
+                json!(1),
+                Value::Null
+            ),
+            (
+                "create_webhook",
+                "mod",
+                Value::Null,
+                Value::Null,
+                webhook_id.clone()
+            ),
+            (
+                "delete_webhook",
+                "mod",
+                Value::Null,
+                Value::Null,
+                webhook_id
+            ),
+        ],
+        entries
+    );
+    assert_eq!(log[0]["after"], log[1]["before"]);
+    assert_eq!(log[1]["after"], log[3]["before"]);
+    assert_eq!(log[4]["after"], log[5]["before"]);
+
+    // the post's own entries include the draft it was published from
+    assert_eq!(log[2..4], audit_log(&app, "?post_id=1").await[..]);
+}
+
+#[tokio::test]
+async fn only_moderators_read_the_audit_log() {
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    let app = app(db.clone());
+    for uri in ["/admin/auditLog", "/admin/auditLog.ndjson"] {
+        assert_eq!(StatusCode::UNAUTHORIZED, get(&app, None, uri).await.0);
+        assert_eq!(StatusCode::FORBIDDEN, get(&app, Some(ANN_KEY), uri).await.0);
+        assert_eq!(StatusCode::OK, get(&app, Some(MODERATOR_KEY), uri).await.0);
+    }
+}
@@ -0,0 +1,357 @@
+/// post_lib::client tests, run against the real router over a local socket
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{
+    extract::Extension,
+    http::StatusCode,
+    routing::{get, post},
+    AddExtensionLayer, Json, Router,
+};
+
+use post_lib::{
+    client::{ClientError, EventStream, PostClient},
+    AuditAction, AuditQuery, ContentFormat, ModerateRequest, ModerationAction, NewPoll, PostEvent,
+};
+use post_server::{
+    app, app_with_config,
+    attachments::BlobStore,
+    config::{AuthConfig, Config},
+    health::Health,
+    shutdown::Shutdown,
+    PostDb, PostEventKind,
+};
+
+/// serve `router` on an ephemeral port, returning its base url
+async fn serve(router: Router) -> String {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let server = axum::Server::bind(&addr).serve(router.into_make_service());
+    let base_url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    base_url
+}
+
+fn client(base_url: &str, retries: u32) -> PostClient {
+    PostClient::builder()
+        .base_url(base_url)
+        .retries(retries)
+        .retry_backoff(Duration::from_millis(10))
+        .build()
+        .unwrap()
+}
+
+const CY_KEY: &str = "s3cret-cy";
+const MODERATOR_KEY: &str = "s3cret-mod";
+
+/// a client signing in with `api_key`
+fn client_as(base_url: &str, api_key: &str) -> PostClient {
+    PostClient::builder()
+        .base_url(base_url)
+        .api_key(api_key)
+        .retries(0)
+        .build()
+        .unwrap()
+}
+
+/// the app, with `cy` and the moderator `mod` able to sign in
+fn app_with_users(db: Arc<Mutex<PostDb>>) -> Router {
+    let config = Config {
+        auth: AuthConfig {
+            api_keys: [(CY_KEY, "cy"), (MODERATOR_KEY, "mod")]
+                .into_iter()
+                .map(|(key, user)| (key.to_string(), user.to_string()))
+                .collect(),
+            moderators: vec!["mod".to_string()],
+        },
+        ..Default::default()
+    };
+    let health = Health::new();
+    health.mark_restored();
+    app_with_config(
+        config,
+        db,
+        Arc::new(Mutex::new(Default::default())),
+        Shutdown::new(),
+        Arc::new(health),
+        Arc::new(BlobStore::new(Config::default().attachments.dir)),
+    )
+}
+
+#[tokio::test]
+async fn crud_round_trip() {
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    let base_url = serve(app_with_users(db.clone())).await;
+    let client = client(&base_url, 0);
+    let cy = client_as(&base_url, CY_KEY);
+    let moderator = client_as(&base_url, MODERATOR_KEY);
+
+    let post_id = client.create_post("this is some content").await.unwrap();
+    assert_eq!(1, post_id);
+    assert_eq!(1, db.lock().unwrap().posts.len());
+
+    let post = client.get_post(post_id).await.unwrap();
+    assert_eq!("this is some content", post.content);
+
+    assert_eq!(
+        post_id,
+        client
+            .update_post(post_id, "updated for @cy")
+            .await
+            .unwrap()
+    );
+    let notifications = cy.notifications(true).await.unwrap();
+    assert_eq!(1, notifications.len());
+    assert_eq!(post_id, notifications[0].post_id);
+    let ids = vec![notifications[0].notification_id];
+    assert_eq!(1, cy.mark_notifications_read(Some(ids)).await.unwrap());
+    assert!(cy.notifications(true).await.unwrap().is_empty());
+    match client.notifications(false).await {
+        Err(ClientError::Api { status, error }) => {
+            assert_eq!(401, status);
+            assert_eq!("unauthorized", error.error);
+        }
+        other => panic!("expected an api error, got {:?}", other),
+    }
+    let posts = client.list_posts().await.unwrap();
+    assert_eq!(1, posts.len());
+    assert_eq!("updated for @cy", posts[0].content);
+
+    match client.add_reaction(post_id, "👍").await {
+        Err(ClientError::Api { status, .. }) => assert_eq!(401, status),
+        other => panic!("expected an api error, got {:?}", other),
+    }
+    cy.add_reaction(post_id, "👍").await.unwrap();
+    let counts = moderator.add_reaction(post_id, "👍").await.unwrap();
+    assert_eq!(2, counts[0].count);
+    let counts = cy.remove_reaction(post_id, "👍").await.unwrap();
+    assert_eq!(1, counts[0].count);
+    let reactions = client.reactions(post_id).await.unwrap();
+    assert_eq!(
+        vec!["mod"],
+        reactions
+            .iter()
+            .map(|r| r.user.as_str())
+            .collect::<Vec<_>>()
+    );
+
+    assert_eq!(post_id, client.delete_post(post_id).await.unwrap());
+    assert!(client.list_posts().await.unwrap().is_empty());
+
+    match client.get_post(post_id).await {
+        Err(ClientError::Api { status, error }) => {
+            assert_eq!(417, status);
+            assert_eq!("not_found", error.error);
+            assert_eq!("no post with id 1", error.message);
+        }
+        other => panic!("expected an api error, got {:?}", other),
+    }
+
+    let poll = NewPoll {
+        question: "lunch?".to_string(),
+        options: vec!["pizza".to_string(), "sushi".to_string()],
+        ..Default::default()
+    };
+    let post_id = client
+        .create_post_with_poll("vote please", ContentFormat::Plain, poll)
+        .await
+        .unwrap();
+    cy.vote(post_id, vec![0]).await.unwrap();
+    let poll = cy.vote(post_id, vec![1]).await.unwrap();
+    assert_eq!(
+        (0, 1, 1),
+        (poll.options[0].votes, poll.options[1].votes, poll.voters)
+    );
+    match cy.vote(post_id, vec![2]).await {
+        Err(ClientError::Api { status, error }) => {
+            assert_eq!(422, status);
+            assert_eq!("unknown_option", error.fields[0].code);
+        }
+        other => panic!("expected an api error, got {:?}", other),
+    }
+
+    assert!(matches!(
+        client.report_post(post_id, "spam").await,
+        Err(ClientError::Api { status: 401, .. })
+    ));
+    let report = cy.report_post(post_id, "spam").await.unwrap();
+    assert_eq!("cy", report.user);
+    assert_eq!(report, cy.report_post(post_id, "still spam").await.unwrap());
+    assert!(matches!(
+        client.moderation_queue().await,
+        Err(ClientError::Api { status: 401, .. })
+    ));
+    let queue = moderator.moderation_queue().await.unwrap();
+    assert_eq!((post_id, 1), (queue[0].post.post_id, queue[0].report_count));
+    let entry = moderator
+        .moderate(ModerateRequest {
+            post_id,
+            action: ModerationAction::Hide,
+            user: None,
+            note: Some("spam".to_string()),
+        })
+        .await
+        .unwrap();
+    assert!(client.list_posts().await.unwrap().is_empty());
+    assert!(moderator.moderation_queue().await.unwrap().is_empty());
+    assert_eq!(Some("mod"), entry.moderator.as_deref());
+    assert_eq!(
+        vec![entry],
+        moderator.moderation_log(Some(post_id)).await.unwrap()
+    );
+
+    assert!(matches!(
+        client.audit_log(&AuditQuery::default()).await,
+        Err(ClientError::Api { status: 401, .. })
+    ));
+    let hidden = moderator
+        .audit_log(&AuditQuery {
+            post_id: Some(post_id),
+            action: Some(AuditAction::Hide),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(1, hidden.len());
+    assert_eq!("mod", hidden[0].actor);
+}
+
+#[tokio::test]
+async fn attachment_round_trip() {
+    let dir = std::env::temp_dir().join(format!("post-cli-attachments-{}", std::process::id()));
+    let mut config = Config::default();
+    config.attachments.dir = dir.clone();
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    let base_url = serve(app_with_config(
+        config,
+        db.clone(),
+        Arc::new(Mutex::new(Default::default())),
+        Shutdown::new(),
+        Arc::new(Health::new()),
+        Arc::new(BlobStore::new(&dir)),
+    ))
+    .await;
+    let client = client(&base_url, 0);
+
+    let post_id = client.create_post("see attached").await.unwrap();
+    let attachment = client
+        .upload_attachment(post_id, "notes.txt", None, b"some notes".to_vec())
+        .await
+        .unwrap();
+    assert_eq!("text/plain", attachment.content_type);
+    assert_eq!(
+        b"some notes".to_vec(),
+        client
+            .download_attachment(attachment.attachment_id)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        vec![attachment],
+        client.get_post(post_id).await.unwrap().attachments
+    );
+
+    match client
+        .upload_attachment(post_id, "page.html", Some("text/html"), b"<p>".to_vec())
+        .await
+    {
+        Err(ClientError::Api { status, error }) => {
+            assert_eq!(422, status);
+            assert_eq!("type_not_allowed", error.fields[0].code);
+        }
+        other => panic!("expected an api error, got {:?}", other),
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+async fn flaky_handler(
+    Extension(calls): Extension<Arc<AtomicUsize>>,
+) -> (StatusCode, Json<Vec<u64>>) {
+    // the first two calls fail
+    match calls.fetch_add(1, Ordering::SeqCst) {
+        0 | 1 => (StatusCode::SERVICE_UNAVAILABLE, Json(vec![])),
+        _ => (StatusCode::OK, Json(vec![])),
+    }
+}
+
+async fn unavailable_handler(Extension(calls): Extension<Arc<AtomicUsize>>) -> StatusCode {
+    calls.fetch_add(1, Ordering::SeqCst);
+    StatusCode::SERVICE_UNAVAILABLE
+}
+
+#[tokio::test]
+async fn retries_reads_but_not_writes() {
+    let calls = Arc::new(AtomicUsize::new(0));
+    let router = Router::new()
+        .route("/posts", get(flaky_handler))
+        .route("/addPost", post(unavailable_handler))
+        .layer(AddExtensionLayer::new(calls.clone()));
+    let base_url = serve(router).await;
+
+    let err = client(&base_url, 1).list_posts().await.unwrap_err();
+    assert_eq!(Some(503), err.status());
+    assert_eq!(2, calls.load(Ordering::SeqCst));
+
+    calls.store(0, Ordering::SeqCst);
+    assert!(client(&base_url, 2).list_posts().await.unwrap().is_empty());
+    assert_eq!(3, calls.load(Ordering::SeqCst));
+
+    calls.store(0, Ordering::SeqCst);
+    let err = client(&base_url, 2).create_post("hello").await.unwrap_err();
+    assert_eq!(Some(503), err.status());
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+async fn requests_time_out() {
+    let router = Router::new().route(
+        "/posts",
+        get(|| async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Json(Vec::<u64>::new())
+        }),
+    );
+    let base_url = serve(router).await;
+
+    let client = PostClient::builder()
+        .base_url(base_url)
+        .timeout(Duration::from_millis(100))
+        .retries(0)
+        .build()
+        .unwrap();
+    match client.list_posts().await {
+        Err(ClientError::Http(e)) => assert!(e.is_timeout()),
+        other => panic!("expected a timeout, got {:?}", other),
+    }
+}
+
+async fn next_event(events: &mut EventStream) -> PostEvent {
+    tokio::time::timeout(Duration::from_secs(5), events.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn follows_events() {
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    let base_url = serve(app(db.clone(), Arc::new(Mutex::new(Default::default())))).await;
+    let client = client(&base_url, 0);
+
+    client.create_post("this is some content").await.unwrap();
+    let mut events = client.events(Some(0)).await.unwrap();
+    client.delete_post(1).await.unwrap();
+
+    let created = next_event(&mut events).await;
+    assert_eq!(PostEventKind::Created, created.kind);
+    assert_eq!("this is some content", created.post.unwrap().content);
+    let deleted = next_event(&mut events).await;
+    assert_eq!(PostEventKind::Deleted, deleted.kind);
+    assert_eq!(2, deleted.event_id);
+}
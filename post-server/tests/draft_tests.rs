@@ -0,0 +1,244 @@
+/// draft and scheduled publishing tests
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{self, Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use post_server::{
+    app_with_config,
+    attachments::BlobStore,
+    config::{AuthConfig, Config, StorageBackend, StorageConfig},
+    drafts::{spawn_scheduler, ManualClock},
+    health::Health,
+    shutdown::Shutdown,
+    storage, PostDb,
+};
+
+const ANN_KEY: &str = "s3cret-ann";
+const BOB_KEY: &str = "s3cret-bob";
+
+fn app(db: Arc<Mutex<PostDb>>) -> Router {
+    let health = Health::new();
+    health.mark_restored();
+    let config = Config {
+        auth: AuthConfig {
+            api_keys: [
+                (ANN_KEY.to_string(), "ann".to_string()),
+                (BOB_KEY.to_string(), "bob".to_string()),
+            ]
+            .into(),
+            moderators: vec![],
+        },
+        ..Default::default()
+    };
+    let blobs = Arc::new(BlobStore::new(config.attachments.dir.clone()));
+    app_with_config(
+        config,
+        db,
+        Arc::new(Mutex::new(Default::default())),
+        Shutdown::new(),
+        Arc::new(health),
+        blobs,
+    )
+}
+
+async fn request(
+    app: &Router,
+    method: http::Method,
+    key: Option<&str>,
+    uri: &str,
+    body: Value,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(http::header::CONTENT_TYPE, "application/json");
+    if let Some(key) = key {
+        request = request.header(http::header::AUTHORIZATION, format!("Bearer {}", key));
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+async fn post(app: &Router, key: &str, uri: &str, body: Value) -> (StatusCode, Value) {
+    request(app, http::Method::POST, Some(key), uri, body).await
+}
+
+async fn get(app: &Router, key: Option<&str>, uri: &str) -> Value {
+    let (status, body) = request(app, http::Method::GET, key, uri, Value::Null).await;
+    assert_eq!(StatusCode::OK, status, "{}", body);
+    body
+}
+
+#[tokio::test]
+async fn drafts_stay_private_until_published() {
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    let app = app(db.clone());
+
+    let (status, draft) = post(
+        &app,
+        ANN_KEY,
+        "/addDraft",
+        json!({ "content": "big news, @bob", "publish_at": u64::MAX }),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status, "{}", draft);
+    assert_eq!(draft["draft_id"], 1);
+    assert_eq!(draft["author"], "ann");
+    assert_eq!(draft["publish_at"], u64::MAX);
+
+    assert_eq!(json!([]), get(&app, None, "/posts").await);
+    assert_eq!(
+        1,
+        get(&app, Some(ANN_KEY), "/drafts")
+            .await
+            .as_array()
+            .unwrap()
+            .len()
+    );
+    assert_eq!(json!([]), get(&app, Some(BOB_KEY), "/drafts").await);
+
+    // the author comes from the key, never from the request
+    let (status, _) = request(
+        &app,
+        http::Method::GET,
+        None,
+        "/drafts?author=ann",
+        Value::Null,
+    )
+    .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+    let (status, _) = request(
+        &app,
+        http::Method::POST,
+        None,
+        "/addDraft",
+        json!({ "content": "anonymous" }),
+    )
+    .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+
+    // someone else's draft looks like a missing one
+    let (status, body) = post(
+        &app,
+        BOB_KEY,
+        "/updateDraft",
+        json!({ "draft_id": 1, "content": "mine now" }),
+    )
+    .await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+    assert_eq!(body["message"], "no draft with id 1");
+
+    let (status, draft) = post(
+        &app,
+        ANN_KEY,
+        "/updateDraft",
+        json!({ "draft_id": 1, "content": "bigger news, @bob" }),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(draft["content"], "bigger news, @bob");
+    assert!(draft.get("publish_at").is_none());
+
+    // nobody is notified of a mention in a draft
+    assert!(db.lock().unwrap().notifications("bob", false).is_empty());
+
+    let (status, _) = post(&app, BOB_KEY, "/publishDraft", json!({ "draft_id": 1 })).await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+    let (status, post_id) = post(&app, ANN_KEY, "/publishDraft", json!({ "draft_id": 1 })).await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(json!(1), post_id);
+    assert_eq!(
+        get(&app, None, "/posts").await[0]["content"],
+        "bigger news, @bob"
+    );
+    assert_eq!(json!([]), get(&app, Some(ANN_KEY), "/drafts").await);
+    assert_eq!(1, db.lock().unwrap().notifications("bob", false).len());
+
+    let (status, body) = post(&app, ANN_KEY, "/addDraft", json!({ "content": "" })).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["field"], "content");
+
+    let (_, draft) = post(
+        &app,
+        ANN_KEY,
+        "/addDraft",
+        json!({ "content": "never mind" }),
+    )
+    .await;
+    let (status, _) = post(
+        &app,
+        BOB_KEY,
+        "/deleteDraft",
+        json!({ "draft_id": draft["draft_id"] }),
+    )
+    .await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+    let (status, deleted) = post(
+        &app,
+        ANN_KEY,
+        "/deleteDraft",
+        json!({ "draft_id": draft["draft_id"] }),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(deleted, draft["draft_id"]);
+}
+
+#[tokio::test]
+async fn scheduled_drafts_survive_a_restart() {
+    let path = std::env::temp_dir().join(format!("post-server-drafts-{}.json", std::process::id()));
+    let config = StorageConfig {
+        backend: StorageBackend::File,
+        path: Some(path.clone()),
+    };
+
+    // schedule a draft, then save and stop before it is due
+    {
+        let post_db = Arc::new(Mutex::new(storage::open(&config, 16).unwrap()));
+        post_db.lock().unwrap().create_draft(
+            "ann".to_string(),
+            "happy new year".to_string(),
+            Default::default(),
+            Some(1_000),
+        );
+        let snapshot = storage::Snapshot::of(&post_db.lock().unwrap());
+        snapshot.save(&path).await.unwrap();
+    }
+
+    let post_db = Arc::new(Mutex::new(storage::open(&config, 16).unwrap()));
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(1, post_db.lock().unwrap().drafts("ann").len());
+
+    let clock = Arc::new(ManualClock::new(999));
+    let scheduler = spawn_scheduler(post_db.clone(), clock.clone(), Duration::from_millis(10));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(post_db.lock().unwrap().posts.is_empty());
+
+    clock.advance(1);
+    for _ in 0..50 {
+        if !post_db.lock().unwrap().posts.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    scheduler.abort();
+
+    let post_db = post_db.lock().unwrap();
+    assert_eq!("happy new year", post_db.posts[0].content);
+    assert!(post_db.drafts("ann").is_empty());
+}
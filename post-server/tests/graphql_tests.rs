@@ -0,0 +1,401 @@
+/// GraphQL integration tests
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{self, Request, StatusCode},
+};
+
+use futures::StreamExt;
+use serde_json::{json, Value};
+
+use tower::ServiceExt;
+
+use post_lib::ModerationAction;
+use post_server::{
+    app_with_config,
+    attachments::BlobStore,
+    auth::Caller,
+    config::{AuthConfig, Config, FiltersConfig},
+    filters, graphql,
+    health::Health,
+    shutdown::Shutdown,
+    PostDb,
+};
+
+fn create_post_db() -> Arc<Mutex<PostDb>> {
+    Arc::new(Mutex::new(Default::default()))
+}
+
+const ANN_KEY: &str = "s3cret-ann";
+const MODERATOR_KEY: &str = "s3cret-mod";
+
+/// the app, with `ann` and the moderator `mod` able to sign in
+fn app(db: Arc<Mutex<PostDb>>) -> axum::Router {
+    let config = Config {
+        auth: AuthConfig {
+            api_keys: [
+                (ANN_KEY.to_string(), "ann".to_string()),
+                (MODERATOR_KEY.to_string(), "mod".to_string()),
+            ]
+            .into(),
+            moderators: vec!["mod".to_string()],
+        },
+        ..Default::default()
+    };
+    app_with_config(
+        config,
+        db,
+        Arc::new(Mutex::new(Default::default())),
+        Shutdown::new(),
+        Arc::new(Health::new()),
+        Arc::new(BlobStore::new(Config::default().attachments.dir)),
+    )
+}
+
+async fn graphql_request(db: Arc<Mutex<PostDb>>, query: &str, variables: Value) -> Value {
+    graphql_request_as(db, None, query, variables).await
+}
+
+async fn graphql_request_as(
+    db: Arc<Mutex<PostDb>>,
+    key: Option<&str>,
+    query: &str,
+    variables: Value,
+) -> Value {
+    let mut request = Request::builder()
+        .method(http::Method::POST)
+        .uri("/graphql")
+        .header(http::header::CONTENT_TYPE, "application/json");
+    if let Some(key) = key {
+        request = request.header(http::header::AUTHORIZATION, format!("Bearer {}", key));
+    }
+    let response = app(db)
+        .oneshot(
+            request
+                .body(Body::from(
+                    json!({ "query": query, "variables": variables }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn mutations_share_the_rest_store() {
+    let db = create_post_db();
+
+    let body = graphql_request(
+        db.clone(),
+        "mutation($content: String!) { createPost(content: $content) { postId content } }",
+        json!({ "content": "this is some content" }),
+    )
+    .await;
+    assert_eq!(
+        body["data"]["createPost"],
+        json!({ "postId": 1, "content": "this is some content" })
+    );
+    assert_eq!(1, db.lock().unwrap().posts.len());
+
+    let body = graphql_request(
+        db.clone(),
+        "mutation { updatePost(postId: 1, content: \"updated\") { content } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["data"]["updatePost"]["content"], "updated");
+
+    let flags = "mutation { updatePostFlags(postId: 1, locked: true) { locked pinnedAt } }";
+    let body = graphql_request_as(db.clone(), Some(ANN_KEY), flags, json!({})).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "forbidden");
+    let body = graphql_request_as(db.clone(), Some(MODERATOR_KEY), flags, json!({})).await;
+    assert_eq!(
+        body["data"]["updatePostFlags"],
+        json!({ "locked": true, "pinnedAt": null })
+    );
+    let body = graphql_request(
+        db.clone(),
+        "mutation { updatePost(postId: 1, content: \"again\") { content } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "locked");
+
+    let body = graphql_request(db.clone(), "mutation { deletePost(postId: 1) }", json!({})).await;
+    assert_eq!(body["data"]["deletePost"], 1);
+    assert!(db.lock().unwrap().posts.is_empty());
+
+    let body = graphql_request(db, "mutation { deletePost(postId: 1) }", json!({})).await;
+    assert_eq!(body["errors"][0]["message"], "no post with id 1");
+}
+
+#[tokio::test]
+async fn hidden_posts_are_not_updated() {
+    let db = create_post_db();
+    db.lock().unwrap().create_post("original".to_string());
+    db.lock()
+        .unwrap()
+        .moderate(1, "mod".to_string(), ModerationAction::Hide, None, None);
+    let mut events = db.lock().unwrap().subscribe(None).1;
+
+    let body = graphql_request(
+        db.clone(),
+        "mutation { updatePost(postId: 1, content: \"rewritten\") { content } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["errors"][0]["message"], "no post with id 1");
+    assert_eq!("original", db.lock().unwrap().posts[0].content);
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn filters_see_edits() {
+    let db = create_post_db();
+    let mut config = FiltersConfig::default();
+    config.blocked_words.patterns = vec!["*coin".to_string()];
+    db.lock().unwrap().set_filters(filters::chain(&config));
+    db.lock().unwrap().create_post("original".to_string());
+
+    let body = graphql_request(
+        db.clone(),
+        "mutation { updatePost(postId: 1, content: \"buy Bitcoin\") { content } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "invalid");
+    assert_eq!("original", db.lock().unwrap().posts[0].content);
+}
+
+#[tokio::test]
+async fn posts_query_pages_and_filters() {
+    let db = create_post_db();
+    for content in ["apples", "bananas", "more apples", "cherries", "Apple pie"] {
+        db.lock().unwrap().create_post(content.to_string());
+    }
+
+    let body = graphql_request(
+        db.clone(),
+        "{ posts(offset: 1, limit: 2) { totalCount hasNextPage items { postId } } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(
+        body["data"]["posts"],
+        json!({ "totalCount": 5, "hasNextPage": true, "items": [{ "postId": 2 }, { "postId": 3 }] })
+    );
+
+    let body = graphql_request(
+        db.clone(),
+        "{ posts(filter: { contains: \"apple\" }) { totalCount hasNextPage items { content } } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["data"]["posts"]["totalCount"], 3);
+    assert_eq!(body["data"]["posts"]["hasNextPage"], false);
+    assert_eq!(body["data"]["posts"]["items"][2]["content"], "Apple pie");
+
+    let body = graphql_request(db, "{ post(postId: 4) { content } }", json!({})).await;
+    assert_eq!(body["data"]["post"]["content"], "cherries");
+}
+
+#[tokio::test]
+async fn subscription_streams_changes() {
+    let db = create_post_db();
+    let schema = graphql::schema(db.clone(), Default::default());
+
+    let mut stream = schema.execute_stream(
+        "subscription { postChanges(kinds: [DELETED]) { kind postId post { content } } }",
+    );
+
+    // the subscription only starts listening once it is first polled
+    let next = tokio::spawn(async move { stream.next().await.unwrap() });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    db.lock()
+        .unwrap()
+        .create_post("this is some content".to_string());
+    db.lock().unwrap().delete_post(1);
+
+    let response = tokio::time::timeout(Duration::from_secs(5), next)
+        .await
+        .unwrap()
+        .unwrap();
+    let data = response.data.into_json().unwrap();
+    assert_eq!(
+        data["postChanges"],
+        json!({ "kind": "DELETED", "postId": 1, "post": null })
+    );
+}
+
+#[tokio::test]
+async fn notifications_follow_mentions() {
+    let db = create_post_db();
+    let schema = graphql::schema(db.clone(), Default::default());
+
+    let ann = Caller {
+        user: "ann".to_string(),
+        moderator: false,
+    };
+    let mut stream = schema.execute_stream(
+        async_graphql::Request::new("subscription { notifications { postId read } }").data(ann),
+    );
+    let next = tokio::spawn(async move { stream.next().await.unwrap() });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let body = graphql_request(
+        db.clone(),
+        "mutation { createPost(content: \"hi @Ann\") { postId } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["data"]["createPost"]["postId"], 1);
+
+    let response = tokio::time::timeout(Duration::from_secs(5), next)
+        .await
+        .unwrap()
+        .unwrap();
+    let data = response.data.into_json().unwrap();
+    assert_eq!(data["notifications"], json!({ "postId": 1, "read": false }));
+
+    let body = graphql_request_as(
+        db.clone(),
+        Some(ANN_KEY),
+        "{ notifications(unreadOnly: true) { kind postId } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(
+        body["data"]["notifications"],
+        json!([{ "kind": "MENTION", "postId": 1 }])
+    );
+
+    let body = graphql_request_as(
+        db.clone(),
+        Some(ANN_KEY),
+        "mutation { markNotificationsRead }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["data"]["markNotificationsRead"], 1);
+
+    let body = graphql_request(db.clone(), "{ notifications { postId } }", json!({})).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "unauthorized");
+    let response = schema
+        .execute_stream("subscription { notifications { postId } }")
+        .next()
+        .await
+        .unwrap();
+    let response = serde_json::to_value(response).unwrap();
+    assert_eq!(response["errors"][0]["extensions"]["code"], "unauthorized");
+}
+
+#[tokio::test]
+async fn polls_are_voted_on() {
+    let db = create_post_db();
+
+    let body = graphql_request(
+        db.clone(),
+        "mutation($poll: NewPoll) { createPost(content: \"lunch\", poll: $poll) { postId } }",
+        json!({ "poll": { "question": "where?", "options": ["pizza", "sushi"], "multiple": true } }),
+    )
+    .await;
+    assert_eq!(body["data"]["createPost"]["postId"], 1);
+
+    let body = graphql_request(
+        db.clone(),
+        "mutation { vote(postId: 1, choices: [0]) { postId } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "unauthorized");
+
+    let body = graphql_request_as(
+        db.clone(),
+        Some(ANN_KEY),
+        "mutation { vote(postId: 1, choices: [0, 1]) { poll { voters options { text votes } } } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(
+        body["data"]["vote"]["poll"],
+        json!({
+            "voters": 1,
+            "options": [{ "text": "pizza", "votes": 1 }, { "text": "sushi", "votes": 1 }]
+        })
+    );
+
+    let body = graphql_request_as(
+        db.clone(),
+        Some(ANN_KEY),
+        "mutation { vote(postId: 1, choices: [5]) { postId } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "invalid");
+
+    db.lock().unwrap().posts[0].poll.as_mut().unwrap().closes_at = Some(1);
+    let body = graphql_request_as(
+        db,
+        Some(MODERATOR_KEY),
+        "mutation { vote(postId: 1, choices: [0]) { postId } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "closed");
+}
+
+#[tokio::test]
+async fn reactions_are_the_callers_own() {
+    let db = create_post_db();
+    db.lock().unwrap().create_post("lunch".to_string());
+
+    let add = "mutation { addReaction(postId: 1, emoji: \"👍\") { reactions { emoji count } } }";
+    let body = graphql_request(db.clone(), add, json!({})).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "unauthorized");
+
+    let body = graphql_request_as(db.clone(), Some(ANN_KEY), add, json!({})).await;
+    assert_eq!(
+        body["data"]["addReaction"]["reactions"],
+        json!([{ "emoji": "👍", "count": 1 }])
+    );
+    graphql_request_as(db.clone(), Some(MODERATOR_KEY), add, json!({})).await;
+    let body = graphql_request_as(
+        db.clone(),
+        Some(ANN_KEY),
+        "mutation { removeReaction(postId: 1, emoji: \"👍\") { postId } }",
+        json!({}),
+    )
+    .await;
+    assert_eq!(body["data"]["removeReaction"]["postId"], 1);
+
+    let body = graphql_request(db, "{ reactions(postId: 1) { user } }", json!({})).await;
+    assert_eq!(body["data"]["reactions"], json!([{ "user": "mod" }]));
+}
+
+#[tokio::test]
+async fn serves_graphiql() {
+    let app = app(create_post_db());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/graphql")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(std::str::from_utf8(&body).unwrap().contains("graphiql"));
+}
@@ -0,0 +1,71 @@
+/// health and readiness tests
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use tower::ServiceExt;
+
+use post_lib::{HealthReport, HealthStatus};
+use post_server::{
+    app, app_with_config, attachments::BlobStore, config::Config, health::Health,
+    shutdown::Shutdown,
+};
+
+async fn get_report(app: Router, uri: &str) -> (StatusCode, HealthReport) {
+    let response = app
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn ready_when_started() {
+    let app = app(
+        Arc::new(Mutex::new(Default::default())),
+        Arc::new(Mutex::new(Default::default())),
+    );
+
+    let (status, report) = get_report(app.clone(), "/healthz").await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!("process", report.checks[0].name);
+
+    let (status, report) = get_report(app, "/readyz").await;
+    assert_eq!(StatusCode::OK, status);
+    let names: Vec<_> = report.checks.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(vec!["store", "restore", "shutdown"], names);
+}
+
+#[tokio::test]
+async fn not_ready_during_shutdown() {
+    let shutdown = Shutdown::new();
+    let health = Arc::new(Health::new());
+    health.mark_restored();
+    let app = app_with_config(
+        Config::default(),
+        Arc::new(Mutex::new(Default::default())),
+        Arc::new(Mutex::new(Default::default())),
+        shutdown.clone(),
+        health,
+        Arc::new(BlobStore::new("attachments")),
+    );
+    shutdown.trigger();
+
+    let (status, report) = get_report(app.clone(), "/readyz").await;
+    assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+    assert_eq!(HealthStatus::Unavailable, report.status);
+    let shutting_down = report.checks.iter().find(|c| c.name == "shutdown").unwrap();
+    assert_eq!(
+        Some("server shutting down"),
+        shutting_down.detail.as_deref()
+    );
+
+    // still alive while draining
+    let (status, _) = get_report(app, "/healthz").await;
+    assert_eq!(StatusCode::OK, status);
+}
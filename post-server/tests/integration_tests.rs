@@ -15,8 +15,16 @@ use serde_json::{json, Value};
 
 use tower::ServiceExt;
 
+use hyper::body::HttpBody;
 use post_server::{
-    delete_post_handler, get_all_posts_handler, new_post_handler, update_post_handler, PostDb,
+    add_reaction_handler, atom_feed_handler,
+    auth::Auth,
+    config::{AuthConfig, Config},
+    delete_post_handler, events_handler, get_all_posts_handler, mark_notifications_read_handler,
+    new_post_handler, notification_events_handler, notifications_handler, post_reactions_handler,
+    remove_reaction_handler,
+    shutdown::Shutdown,
+    update_post_flags_handler, update_post_handler, vote_handler, PostDb,
 };
 
 fn create_post_db() -> Arc<Mutex<PostDb>> {
@@ -29,10 +37,54 @@ fn app(db: Arc<Mutex<PostDb>>) -> Router {
         .route("/post/:id", get(get_all_posts_handler))
         .route("/addPost", post(new_post_handler))
         .route("/updatePost", post(update_post_handler))
+        .route("/admin/updatePostFlags", post(update_post_flags_handler))
         .route("/deletePost/:id", post(delete_post_handler))
+        .route("/addReaction", post(add_reaction_handler))
+        .route("/removeReaction", post(remove_reaction_handler))
+        .route("/post/:id/reactions", get(post_reactions_handler))
+        .route("/vote", post(vote_handler))
+        .route("/notifications", get(notifications_handler))
+        .route(
+            "/markNotificationsRead",
+            post(mark_notifications_read_handler),
+        )
+        .route("/notifications/events", get(notification_events_handler))
+        .route("/events", get(events_handler))
+        .route("/feed.atom", get(atom_feed_handler))
+        .layer(AddExtensionLayer::new(Arc::new(Config::default())))
+        .layer(AddExtensionLayer::new(Arc::new(Auth::new(&auth_config()))))
+        .layer(AddExtensionLayer::new(Shutdown::new()))
         .layer(AddExtensionLayer::new(db))
 }
 
+const ALICE_KEY: &str = "s3cret-alice";
+const BOB_KEY: &str = "s3cret-bob";
+const MODERATOR_KEY: &str = "s3cret-mod";
+
+/// the api key of `user<n>`, one of 20 users that only react
+fn user_key(n: u64) -> String {
+    format!("s3cret-user{}", n)
+}
+
+fn auth_config() -> AuthConfig {
+    AuthConfig {
+        api_keys: [
+            (ALICE_KEY, "Alice"),
+            (BOB_KEY, "bob"),
+            (MODERATOR_KEY, "mod"),
+        ]
+        .into_iter()
+        .map(|(key, user)| (key.to_string(), user.to_string()))
+        .chain((0..20).map(|n| (user_key(n), format!("user{}", n))))
+        .collect(),
+        moderators: vec!["mod".to_string()],
+    }
+}
+
+fn bearer(key: &str) -> String {
+    format!("Bearer {}", key)
+}
+
 #[tokio::test]
 async fn new_db_empty() {
     let db = create_post_db();
@@ -80,6 +132,89 @@ async fn create_post() {
     assert_eq!(body, json!(1));
 }
 
+#[tokio::test]
+async fn markdown_posts_come_with_sanitized_html() {
+    let db = create_post_db();
+    let app = app(db.clone());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/addPost")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    json!({
+                        "content": "**bold** <script>alert(1)</script>",
+                        "format": "markdown"
+                    })
+                    .to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let post = serde_json::to_value(&db.lock().unwrap().posts[0]).unwrap();
+    assert_eq!(post["format"], "markdown");
+    assert_eq!(post["content_html"], "<p><strong>bold</strong> </p>\n");
+}
+
+#[tokio::test]
+async fn create_post_rejects_invalid_content() {
+    let db = create_post_db();
+    let app = app(db.clone());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/addPost")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from("{\"content\": \"\"}".to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "invalid");
+    let codes: Vec<_> = body["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| (field["field"].clone(), field["code"].clone()))
+        .collect();
+    assert_eq!(codes, vec![(json!("content"), json!("too_short"))]);
+    assert!(db.lock().unwrap().posts.is_empty());
+}
+
+#[tokio::test]
+async fn oversized_bodies_are_refused() {
+    let app = post_server::app(create_post_db(), Arc::new(Mutex::new(Default::default())));
+    let body =
+        json!({ "content": "x".repeat(Config::default().limits.max_body_bytes) }).to_string();
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/addPost")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .header(http::header::CONTENT_LENGTH, body.len())
+                .body(Body::from(body))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["error"], "too_large");
+}
+
 #[tokio::test]
 async fn update_post() {
     let listener = TcpListener::bind("127.0.0.1:4321".parse::<SocketAddr>().unwrap()).unwrap();
@@ -156,6 +291,216 @@ async fn update_post() {
     assert!(body == json!([{"post_id": 1, "content": "this is some updated content"}]));
 }
 
+async fn post_json(app: Router, uri: &str, body: Value) -> (StatusCode, Value) {
+    post_json_as(app, None, uri, body).await
+}
+
+async fn post_json_as(
+    app: Router,
+    key: Option<&str>,
+    uri: &str,
+    body: Value,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+        .method(http::Method::POST)
+        .uri(uri)
+        .header(http::header::CONTENT_TYPE, "application/json");
+    if let Some(key) = key {
+        request = request.header(http::header::AUTHORIZATION, bearer(key));
+    }
+    let response = app
+        .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn pinned_posts_come_first_and_locked_posts_stay_put() {
+    let db = create_post_db();
+    for content in ["first", "announcement", "third"] {
+        db.lock().unwrap().create_post(content.to_string());
+    }
+    let app = app(db.clone());
+
+    // only moderators set flags
+    let flags = json!({ "post_id": 2, "pinned": true, "locked": true });
+    let (status, _) = post_json(app.clone(), "/admin/updatePostFlags", flags.clone()).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+    let (status, body) = post_json_as(
+        app.clone(),
+        Some(BOB_KEY),
+        "/admin/updatePostFlags",
+        flags.clone(),
+    )
+    .await;
+    assert_eq!(StatusCode::FORBIDDEN, status);
+    assert_eq!(body["error"], "forbidden");
+
+    let (status, post) = post_json_as(
+        app.clone(),
+        Some(MODERATOR_KEY),
+        "/admin/updatePostFlags",
+        flags,
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+    assert!(post["pinned_at"].is_u64());
+    assert_eq!(post["locked"], true);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/posts")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let posts: Value = serde_json::from_slice(&body).unwrap();
+    let ids: Vec<&Value> = posts
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|post| &post["post_id"])
+        .collect();
+    assert_eq!(vec![&json!(2), &json!(1), &json!(3)], ids);
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/updatePost",
+        json!({ "post_id": 2, "updated_content": "edited" }),
+    )
+    .await;
+    assert_eq!(StatusCode::LOCKED, status);
+    assert_eq!(body["error"], "locked");
+    assert_eq!("announcement", db.lock().unwrap().posts[1].content);
+
+    // unlocking leaves the pin alone
+    let (_, post) = post_json_as(
+        app.clone(),
+        Some(MODERATOR_KEY),
+        "/admin/updatePostFlags",
+        json!({ "post_id": 2, "locked": false }),
+    )
+    .await;
+    assert!(post["pinned_at"].is_u64());
+    assert!(post.get("locked").is_none());
+    let (status, _) = post_json(
+        app.clone(),
+        "/updatePost",
+        json!({ "post_id": 2, "updated_content": "edited" }),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+
+    let (status, body) = post_json_as(
+        app,
+        Some(MODERATOR_KEY),
+        "/admin/updatePostFlags",
+        json!({ "post_id": 9, "pinned": true }),
+    )
+    .await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+    assert_eq!(body["error"], "not_found");
+}
+
+#[tokio::test]
+async fn polls_take_one_vote_per_user_until_closed() {
+    let db = create_post_db();
+    let app = app(db.clone());
+
+    let (status, body) = post_json(
+        app.clone(),
+        "/addPost",
+        json!({
+            "content": "where to?",
+            "poll": { "question": "lunch?", "options": ["pizza"], "closes_at": 1 }
+        }),
+    )
+    .await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    let fields: Vec<&Value> = body["fields"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| &field["field"])
+        .collect();
+    assert_eq!(vec!["poll.options", "poll.closes_at"], fields);
+
+    let (status, post_id) = post_json(
+        app.clone(),
+        "/addPost",
+        json!({
+            "content": "where to?",
+            "poll": { "question": "lunch?", "options": ["pizza", "sushi", "salad"] }
+        }),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+
+    let vote = |key: &'static str, choices: Value| {
+        post_json_as(
+            app.clone(),
+            Some(key),
+            "/vote",
+            json!({ "post_id": post_id, "choices": choices }),
+        )
+    };
+    // voters must sign in, so nobody votes for someone else
+    let (status, _) = post_json(
+        app.clone(),
+        "/vote",
+        json!({ "post_id": post_id, "choices": [1] }),
+    )
+    .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+    vote(ALICE_KEY, json!([0])).await;
+    vote(BOB_KEY, json!([0])).await;
+    // a second vote replaces the first
+    let (status, poll) = vote(ALICE_KEY, json!([2])).await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(
+        json!({
+            "question": "lunch?",
+            "options": [
+                { "text": "pizza", "votes": 1 },
+                { "text": "sushi", "votes": 0 },
+                { "text": "salad", "votes": 1 }
+            ],
+            "voters": 2
+        }),
+        poll
+    );
+    let (_, post) = get_json(app.clone(), "/posts").await;
+    assert_eq!(poll, post[0]["poll"]);
+
+    let (status, body) = vote(ALICE_KEY, json!([0, 1])).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["code"], "too_many");
+
+    db.lock().unwrap().create_post("no poll".to_string());
+    let (status, body) = post_json_as(
+        app.clone(),
+        Some(ALICE_KEY),
+        "/vote",
+        json!({ "post_id": 2, "choices": [0] }),
+    )
+    .await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+    assert_eq!(body["message"], "no poll on post 2");
+
+    db.lock().unwrap().posts[0].poll.as_mut().unwrap().closes_at = Some(1);
+    let (status, body) = vote(MODERATOR_KEY, json!([1])).await;
+    assert_eq!(StatusCode::CONFLICT, status);
+    assert_eq!(body["error"], "closed");
+    assert_eq!(2, db.lock().unwrap().poll(1).unwrap().voters);
+}
+
 #[tokio::test]
 async fn delete_post() {
     let listener = TcpListener::bind("127.0.0.1:4322".parse::<SocketAddr>().unwrap()).unwrap();
@@ -220,3 +565,344 @@ async fn delete_post() {
     let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
     assert_eq!(&body[..], b"[]");
 }
+
+/// add or remove a reaction to post 1 as the user `key` signs in as
+async fn react(app: Router, uri: &str, key: &str, emoji: &str) -> (StatusCode, Value) {
+    post_json_as(app, Some(key), uri, json!({ "post_id": 1, "emoji": emoji })).await
+}
+
+#[tokio::test]
+async fn concurrent_reactions_are_counted_once_per_user() {
+    let db = create_post_db();
+    db.lock()
+        .unwrap()
+        .create_post("this is some content".to_string());
+    let app = app(db.clone());
+
+    // every user reacts twice with 👍, and the even ones also with 🎉
+    let requests = (0..40).map(|n| {
+        let app = app.clone();
+        tokio::spawn(async move {
+            let key = user_key(n % 20);
+            let emoji = if n % 2 == 0 && n < 20 { "🎉" } else { "👍" };
+            react(app.clone(), "/addReaction", &key, "👍").await;
+            react(app, "/addReaction", &key, emoji).await
+        })
+    });
+    for request in futures::future::join_all(requests).await {
+        assert_eq!(StatusCode::OK, request.unwrap().0);
+    }
+
+    let (status, counts) = react(app.clone(), "/removeReaction", &user_key(0), "🎉").await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(
+        counts,
+        json!([{ "emoji": "👍", "count": 20 }, { "emoji": "🎉", "count": 9 }])
+    );
+    assert_eq!(counts, json!(db.lock().unwrap().posts[0].reactions));
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/post/1/reactions")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let reactions: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(29, reactions.as_array().unwrap().len());
+    // ordered by emoji code point, then user
+    assert_eq!(reactions[0], json!({ "emoji": "🎉", "user": "user10" }));
+
+    let (status, body) = react(app.clone(), "/addReaction", &user_key(0), "+1").await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["code"], "not_emoji");
+
+    // reactions are the signed in caller's own, so nobody reacts for someone else
+    let (status, _) = post_json(
+        app,
+        "/addReaction",
+        json!({ "post_id": 1, "user": "user0", "emoji": "🎉" }),
+    )
+    .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+}
+
+/// read from a streaming body until `needle` shows up
+async fn read_until<B>(body: &mut B, needle: &str) -> String
+where
+    B: HttpBody<Data = axum::body::Bytes> + Unpin,
+    B::Error: std::fmt::Debug,
+{
+    let mut received = String::new();
+    while !received.contains(needle) {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.data())
+            .await
+            .expect("timed out waiting for event")
+            .unwrap()
+            .unwrap();
+        received.push_str(std::str::from_utf8(&chunk).unwrap());
+    }
+    received
+}
+
+#[tokio::test]
+async fn events_stream_live_changes() {
+    let db = create_post_db();
+    let app = app(db.clone());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/events")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[http::header::CONTENT_TYPE],
+        "text/event-stream"
+    );
+
+    db.lock()
+        .unwrap()
+        .create_post("this is some content".to_string());
+    db.lock().unwrap().delete_post(1);
+
+    let mut body = response.into_body();
+    let received = read_until(&mut body, "event:deleted").await;
+    assert!(received.contains("event:created\n"));
+    assert!(received.contains("id:1\n"));
+    assert!(received.contains("\"content\":\"this is some content\""));
+    assert!(received.contains("event:deleted\n"));
+    assert!(received.contains("id:2\n"));
+}
+
+#[tokio::test]
+async fn events_stream_reactions() {
+    let db = create_post_db();
+    db.lock()
+        .unwrap()
+        .create_post("this is some content".to_string());
+    let app = app(db.clone());
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/events")
+                .header("last-event-id", "1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    react(app.clone(), "/addReaction", BOB_KEY, "👍").await;
+    react(app, "/removeReaction", BOB_KEY, "👍").await;
+
+    let mut body = response.into_body();
+    let received = read_until(&mut body, "event:reaction_removed").await;
+    assert!(received.contains("event:reaction_added\n"));
+    assert!(received.contains("\"reactions\":[{\"emoji\":\"👍\",\"count\":1}]"));
+    assert!(received.contains("\"reaction\":{\"emoji\":\"👍\",\"user\":\"bob\"}"));
+}
+
+async fn get_json(app: Router, uri: &str) -> (StatusCode, Value) {
+    get_json_as(app, None, uri).await
+}
+
+async fn get_json_as(app: Router, key: Option<&str>, uri: &str) -> (StatusCode, Value) {
+    let mut request = Request::builder().uri(uri);
+    if let Some(key) = key {
+        request = request.header(http::header::AUTHORIZATION, bearer(key));
+    }
+    let response = app
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn mentions_notify_users() {
+    let db = create_post_db();
+    let app = app(db.clone());
+    let stream = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/notifications/events")
+                .header(http::header::AUTHORIZATION, bearer(ALICE_KEY))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, stream.status());
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/addPost")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"content": "@bob, meet @alice"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+
+    let (status, notifications) =
+        get_json_as(app.clone(), Some(ALICE_KEY), "/notifications?unread=true").await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, notifications.as_array().unwrap().len());
+    assert_eq!(notifications[0]["post_id"], 1);
+    assert_eq!(notifications[0]["kind"], "mention");
+    assert_eq!(notifications[0]["read"], false);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(http::Method::POST)
+                .uri("/markNotificationsRead")
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .header(http::header::AUTHORIZATION, bearer(ALICE_KEY))
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"1");
+
+    let (_, notifications) =
+        get_json_as(app.clone(), Some(ALICE_KEY), "/notifications?unread=true").await;
+    assert_eq!(json!([]), notifications);
+    let (_, notifications) = get_json_as(app.clone(), Some(BOB_KEY), "/notifications").await;
+    assert_eq!(1, notifications.as_array().unwrap().len());
+    assert_eq!(notifications[0]["user"], "bob");
+
+    let mut body = stream.into_body();
+    let received = read_until(&mut body, "event:notification_read").await;
+    assert!(received.contains("event:notification\n"));
+    assert!(received.contains("\"user\":\"alice\""));
+    assert!(!received.contains("\"user\":\"bob\""));
+
+    // only the signed in user's own notifications, never by name
+    let (status, body) = get_json(app.clone(), "/notifications?user=alice").await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+    assert_eq!(body["error"], "unauthorized");
+    let (status, _) = get_json_as(app, Some("forged"), "/notifications/events").await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+}
+
+#[tokio::test]
+async fn events_replay_after_last_event_id() {
+    let db = create_post_db();
+    db.lock().unwrap().create_post("first".to_string());
+    db.lock().unwrap().create_post("second".to_string());
+    db.lock()
+        .unwrap()
+        .update_post(2, "second updated".to_string());
+
+    let app = app(db);
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/events")
+                .header("Last-Event-ID", "1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let mut body = response.into_body();
+    let received = read_until(&mut body, "event:updated").await;
+    assert!(!received.contains("\"first\""));
+    assert!(!received.contains("id:1\n"));
+    assert!(received.contains("id:2\n"));
+    assert!(received.contains("id:3\n"));
+    assert!(received.contains("\"second updated\""));
+}
+
+#[tokio::test]
+async fn feed_honors_conditional_get() {
+    let db = create_post_db();
+    db.lock().unwrap().create_post("fish & chips".to_string());
+    let app = app(db.clone());
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/feed.atom")
+                .header(http::header::HOST, "board.example")
+                .header("x-forwarded-proto", "https")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[http::header::CONTENT_TYPE],
+        "application/atom+xml; charset=utf-8"
+    );
+    let etag = response.headers()[http::header::ETAG].clone();
+    assert!(response.headers().contains_key(http::header::LAST_MODIFIED));
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body = std::str::from_utf8(&body).unwrap();
+    // links start with `server.public_url`, whatever the headers claim
+    assert!(body.contains("<id>http://localhost:3000/post/1</id>"));
+    assert!(body.contains("fish &amp; chips"));
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/feed.atom")
+                .header(http::header::HOST, "board.example")
+                .header(http::header::IF_NONE_MATCH, etag.clone())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(body.is_empty());
+
+    db.lock().unwrap().create_post("more content".to_string());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/feed.atom")
+                .header(http::header::HOST, "board.example")
+                .header(http::header::IF_NONE_MATCH, etag)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,74 @@
+/// Prometheus metrics tests
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::json;
+use tower::ServiceExt;
+
+use post_server::{app, webhooks::WebhookRegistry};
+
+fn router() -> Router {
+    app(
+        Arc::new(Mutex::new(Default::default())),
+        Arc::new(Mutex::new(WebhookRegistry::default())),
+    )
+}
+
+async fn send(router: &Router, method: Method, uri: &str, body: Body) -> StatusCode {
+    router
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+}
+
+#[tokio::test]
+async fn metrics_cover_requests_and_store() {
+    let router = router();
+    let created = send(
+        &router,
+        Method::POST,
+        "/addPost",
+        Body::from(json!({"content": "counted"}).to_string()),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, created);
+    send(&router, Method::GET, "/post/999", Body::empty()).await;
+
+    let response = router
+        .clone()
+        .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(response.headers()[header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    for expected in [
+        r#"post_server_http_requests_total{method="POST",route="/addPost",status="200"}"#,
+        r#"post_server_http_requests_total{method="GET",route="/post/:id",status="417"}"#,
+        r#"post_server_http_request_duration_seconds_bucket{method="POST",route="/addPost""#,
+        r#"post_server_store_operation_duration_seconds_count{operation="create_post"}"#,
+        r#"post_server_store_operation_duration_seconds_count{operation="get_post"}"#,
+        "post_server_store_lock_wait_seconds_count",
+        "# TYPE post_server_posts gauge",
+    ] {
+        assert!(text.contains(expected), "missing {} in\n{}", expected, text);
+    }
+}
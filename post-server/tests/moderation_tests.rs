@@ -0,0 +1,334 @@
+/// reporting, moderation and content filter tests
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    http::{self, Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use post_server::{
+    app_with_config,
+    attachments::BlobStore,
+    config::{AuthConfig, Config, FiltersConfig, ModerationConfig},
+    filters,
+    health::Health,
+    shutdown::Shutdown,
+    PostDb,
+};
+
+const ANN_KEY: &str = "s3cret-ann";
+const MODERATOR_KEY: &str = "s3cret-mod";
+
+/// the API key of `user1`, `user2` and so on
+fn user_key(user: u64) -> String {
+    format!("s3cret-user{}", user)
+}
+
+fn app(db: Arc<Mutex<PostDb>>) -> Router {
+    let health = Health::new();
+    health.mark_restored();
+    let users = (1..ModerationConfig::default().report_threshold)
+        .map(|user| (user_key(user), format!("user{}", user)));
+    let config = Config {
+        auth: AuthConfig {
+            api_keys: [
+                (ANN_KEY.to_string(), "ann".to_string()),
+                (MODERATOR_KEY.to_string(), "mod".to_string()),
+            ]
+            .into_iter()
+            .chain(users)
+            .collect(),
+            moderators: vec!["mod".to_string()],
+        },
+        ..Default::default()
+    };
+    let blobs = Arc::new(BlobStore::new(config.attachments.dir.clone()));
+    app_with_config(
+        config,
+        db,
+        Arc::new(Mutex::new(Default::default())),
+        Shutdown::new(),
+        Arc::new(health),
+        blobs,
+    )
+}
+
+async fn request(
+    app: &Router,
+    method: http::Method,
+    key: Option<&str>,
+    uri: &str,
+    body: Value,
+) -> (StatusCode, Value) {
+    let mut request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(http::header::CONTENT_TYPE, "application/json");
+    if let Some(key) = key {
+        request = request.header(http::header::AUTHORIZATION, format!("Bearer {}", key));
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::from(body.to_string())).unwrap())
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+async fn post(app: &Router, uri: &str, body: Value) -> (StatusCode, Value) {
+    request(app, http::Method::POST, None, uri, body).await
+}
+
+async fn user_post(app: &Router, key: &str, uri: &str, body: Value) -> (StatusCode, Value) {
+    request(app, http::Method::POST, Some(key), uri, body).await
+}
+
+async fn get(app: &Router, uri: &str) -> Value {
+    let (status, body) = request(app, http::Method::GET, None, uri, Value::Null).await;
+    assert_eq!(StatusCode::OK, status, "{}", body);
+    body
+}
+
+async fn moderator_post(app: &Router, uri: &str, body: Value) -> (StatusCode, Value) {
+    request(app, http::Method::POST, Some(MODERATOR_KEY), uri, body).await
+}
+
+async fn moderator_get(app: &Router, uri: &str) -> Value {
+    let (status, body) = request(
+        app,
+        http::Method::GET,
+        Some(MODERATOR_KEY),
+        uri,
+        Value::Null,
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status, "{}", body);
+    body
+}
+
+#[tokio::test]
+async fn reported_posts_are_hidden_until_moderated() {
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    db.lock()
+        .unwrap()
+        .create_post("buy cheap watches".to_string());
+    db.lock().unwrap().create_post("hello @bob".to_string());
+    let app = app(db.clone());
+    let threshold = ModerationConfig::default().report_threshold;
+
+    // reporters must sign in, so made up names can't add up to a hiding
+    let (status, _) = post(&app, "/posts/1/report", json!({ "reason": "spam" })).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+    let (status, report) = user_post(
+        &app,
+        ANN_KEY,
+        "/posts/1/report",
+        json!({ "reason": " spam " }),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status, "{}", report);
+    assert_eq!(report["reason"], "spam");
+    assert_eq!(report["user"], "ann");
+    for _ in 0..threshold {
+        // a user's later reports don't count
+        user_post(
+            &app,
+            ANN_KEY,
+            "/posts/1/report",
+            json!({ "reason": "spam" }),
+        )
+        .await;
+    }
+    assert_eq!(2, get(&app, "/posts").await.as_array().unwrap().len());
+    for user in 1..threshold {
+        let (status, _) = user_post(
+            &app,
+            &user_key(user),
+            "/posts/1/report",
+            json!({ "reason": "spam" }),
+        )
+        .await;
+        assert_eq!(StatusCode::OK, status);
+    }
+    user_post(
+        &app,
+        ANN_KEY,
+        "/posts/2/report",
+        json!({ "reason": "rude" }),
+    )
+    .await;
+
+    // enough reports hide the post from everyone but moderators
+    let posts = get(&app, "/posts").await;
+    assert_eq!(1, posts.as_array().unwrap().len());
+    assert_eq!(posts[0]["post_id"], 2);
+    let (status, body) = user_post(
+        &app,
+        &user_key(1),
+        "/posts/1/report",
+        json!({ "reason": "spam" }),
+    )
+    .await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+    assert_eq!(body["message"], "no post with id 1");
+
+    // nor can anyone change it, and nothing is broadcast about it
+    let mut events = db.lock().unwrap().subscribe(None).1;
+    let changes = [
+        (
+            "/updatePost",
+            json!({ "post_id": 1, "updated_content": "cheaper watches, @ann" }),
+        ),
+        ("/addReaction", json!({ "post_id": 1, "emoji": "👍" })),
+    ];
+    for (uri, body) in changes {
+        let (status, _) = user_post(&app, ANN_KEY, uri, body).await;
+        assert_eq!(StatusCode::EXPECTATION_FAILED, status, "{}", uri);
+    }
+    assert!(events.try_recv().is_err());
+    assert_eq!("buy cheap watches", db.lock().unwrap().posts[0].content);
+    assert!(db.lock().unwrap().notifications("ann", false).is_empty());
+
+    // only moderators see the queue
+    let (status, _) = request(&app, http::Method::GET, None, "/admin/reports", Value::Null).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+    let (status, _) = request(
+        &app,
+        http::Method::POST,
+        Some(ANN_KEY),
+        "/admin/moderate",
+        json!({ "post_id": 1, "action": "dismiss" }),
+    )
+    .await;
+    assert_eq!(StatusCode::FORBIDDEN, status);
+
+    let queue = moderator_get(&app, "/admin/reports").await;
+    assert_eq!(queue[0]["post"]["post_id"], 1);
+    assert_eq!(queue[0]["post"]["hidden"], true);
+    assert_eq!(queue[0]["report_count"], threshold);
+    assert_eq!(queue[1]["reports"][0]["reason"], "rude");
+
+    let (status, body) = moderator_post(
+        &app,
+        "/admin/moderate",
+        json!({ "post_id": 2, "action": "warn" }),
+    )
+    .await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["code"], "required");
+
+    let (status, entry) = moderator_post(
+        &app,
+        "/admin/moderate",
+        json!({ "post_id": 2, "action": "warn", "user": "bob", "note": "be nice" }),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status, "{}", entry);
+    assert_eq!(entry["user"], "bob");
+    assert_eq!(
+        "warning",
+        db.lock().unwrap().notifications("bob", false)[0]
+            .kind
+            .name()
+    );
+
+    let (status, _) = moderator_post(
+        &app,
+        "/admin/moderate",
+        json!({ "post_id": 1, "action": "dismiss" }),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(2, get(&app, "/posts").await.as_array().unwrap().len());
+    assert_eq!(json!([]), moderator_get(&app, "/admin/reports").await);
+
+    let (status, _) = moderator_post(
+        &app,
+        "/admin/moderate",
+        json!({ "post_id": 9, "action": "hide" }),
+    )
+    .await;
+    assert_eq!(StatusCode::EXPECTATION_FAILED, status);
+
+    let log = moderator_get(&app, "/admin/moderationLog?post_id=1").await;
+    let actions: Vec<(&Value, &Value)> = log
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| (&entry["action"], &entry["moderator"]))
+        .collect();
+    assert_eq!(
+        vec![
+            (&json!("hide"), &Value::Null),
+            (&json!("dismiss"), &json!("mod"))
+        ],
+        actions
+    );
+    assert_eq!(
+        3,
+        moderator_get(&app, "/admin/moderationLog")
+            .await
+            .as_array()
+            .unwrap()
+            .len()
+    );
+}
+
+#[tokio::test]
+async fn filters_hold_and_reject_new_posts() {
+    let mut config = FiltersConfig::default();
+    config.blocked_words.patterns = vec!["*coin".to_string()];
+    config.links.max = 1;
+    let db: Arc<Mutex<PostDb>> = Arc::new(Mutex::new(Default::default()));
+    db.lock().unwrap().set_filters(filters::chain(&config));
+    let app = app(db.clone());
+
+    let (status, body) = post(&app, "/addPost", json!({ "content": "buy Bitcoin" })).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["field"], "content");
+    assert_eq!(body["fields"][0]["code"], "blocked_word");
+
+    let (status, id) = post(&app, "/addPost", json!({ "content": "hello" })).await;
+    assert_eq!((StatusCode::OK, json!(1)), (status, id));
+
+    // edits are filtered too
+    let (status, body) = post(
+        &app,
+        "/updatePost",
+        json!({ "post_id": 1, "updated_content": "hello, buy Bitcoin" }),
+    )
+    .await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["field"], "updated_content");
+    assert_eq!(body["fields"][0]["code"], "blocked_word");
+    assert_eq!("hello", db.lock().unwrap().posts[0].content);
+    let (status, body) = post(&app, "/addPost", json!({ "content": "Hello " })).await;
+    assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+    assert_eq!(body["fields"][0]["code"], "duplicate");
+
+    let (status, id) = post(
+        &app,
+        "/addPost",
+        json!({ "content": "https://a.example https://b.example" }),
+    )
+    .await;
+    assert_eq!((StatusCode::ACCEPTED, json!(2)), (status, id));
+    assert_eq!(1, get(&app, "/posts").await.as_array().unwrap().len());
+    let queue = moderator_get(&app, "/admin/reports").await;
+    assert_eq!(queue[0]["post"]["post_id"], 2);
+    assert_eq!(queue[0]["reports"][0]["user"], "filter:too_many_links");
+
+    let (status, _) = moderator_post(
+        &app,
+        "/admin/moderate",
+        json!({ "post_id": 2, "action": "delete" }),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(1, db.lock().unwrap().spam_model().spam_posts);
+}
@@ -0,0 +1,215 @@
+/// OpenAPI document tests, keeping the spec in step with the router
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+};
+
+use serde_json::Value;
+
+use tower::ServiceExt;
+use utoipa::OpenApi;
+
+use post_server::{app, openapi::ApiDoc, route_table};
+
+/// `/post/:id` in axum syntax is `/post/{id}` in OpenAPI syntax
+fn openapi_path(axum_path: &str) -> String {
+    axum_path
+        .split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(param) => format!("{{{}}}", param),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn spec() -> Value {
+    serde_json::to_value(ApiDoc::openapi()).unwrap()
+}
+
+#[test]
+fn spec_matches_registered_routes() {
+    let registered: BTreeSet<(String, String)> = route_table()
+        .routes()
+        .iter()
+        .map(|(method, path)| (method.as_str().to_lowercase(), openapi_path(path)))
+        .collect();
+
+    let spec = spec();
+    let documented: BTreeSet<(String, String)> = spec["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .flat_map(|(path, operations)| {
+            operations
+                .as_object()
+                .unwrap()
+                .keys()
+                .map(move |method| (method.clone(), path.clone()))
+        })
+        .collect();
+
+    let undocumented: Vec<_> = registered.difference(&documented).collect();
+    let unrouted: Vec<_> = documented.difference(&registered).collect();
+    assert!(
+        undocumented.is_empty(),
+        "routes missing from the spec: {:?}",
+        undocumented
+    );
+    assert!(
+        unrouted.is_empty(),
+        "spec operations with no route: {:?}",
+        unrouted
+    );
+}
+
+#[test]
+fn spec_references_post_lib_types() {
+    let spec = spec();
+    let schemas = &spec["components"]["schemas"];
+    assert!(schemas["CreatePostRequest"]["properties"]["content"].is_object());
+    assert!(schemas["UpdatePostRequest"]["properties"]["updated_content"].is_object());
+    assert_eq!(
+        spec["paths"]["/addPost"]["post"]["requestBody"]["content"]["application/json"]["schema"]
+            ["$ref"],
+        "#/components/schemas/CreatePostRequest"
+    );
+}
+
+#[tokio::test]
+async fn registered_routes_answer_their_methods() {
+    let routes = route_table();
+    for (method, path) in routes.routes() {
+        let app = app(
+            Arc::new(Mutex::new(Default::default())),
+            Arc::new(Mutex::new(Default::default())),
+        );
+        let uri = path.replace(":id", "1");
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method(method.clone())
+                    .uri(&uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_ne!(
+            response.status(),
+            StatusCode::NOT_FOUND,
+            "{} {}",
+            method,
+            uri
+        );
+        assert_ne!(
+            response.status(),
+            StatusCode::METHOD_NOT_ALLOWED,
+            "{} {}",
+            method,
+            uri
+        );
+    }
+}
+
+/// the recorded methods are the only ones a route answers, so the spec
+/// check above compares what the router really serves
+#[tokio::test]
+async fn unregistered_methods_are_not_allowed() {
+    let routes = route_table();
+    for (_, path) in routes.routes() {
+        for method in [Method::GET, Method::POST] {
+            if routes.routes().contains(&(method.clone(), path.clone())) {
+                continue;
+            }
+            let app = app(
+                Arc::new(Mutex::new(Default::default())),
+                Arc::new(Mutex::new(Default::default())),
+            );
+            let uri = path.replace(":id", "1");
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .method(method.clone())
+                        .uri(&uri)
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+
+            assert_eq!(
+                response.status(),
+                StatusCode::METHOD_NOT_ALLOWED,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn serves_spec_and_docs_page() {
+    let app = app(
+        Arc::new(Mutex::new(Default::default())),
+        Arc::new(Mutex::new(Default::default())),
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/openapi.json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert!(body["openapi"].as_str().unwrap().starts_with("3."));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/docs")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(std::str::from_utf8(&body)
+        .unwrap()
+        .contains("/openapi.json"));
+}
+
+/// the JSON schema of every wire type, checked against a snapshot so
+/// changes to the wire format show up in review; run with
+/// `UPDATE_SNAPSHOTS=1` to accept a deliberate change
+#[test]
+fn schemas_match_snapshot() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/snapshots/schemas.json");
+    let schemas = serde_json::to_string_pretty(&spec()["components"]["schemas"]).unwrap() + "\n";
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::write(path, &schemas).unwrap();
+    }
+    let snapshot = std::fs::read_to_string(path).unwrap();
+    assert!(
+        snapshot == schemas,
+        "wire format changed, rerun with UPDATE_SNAPSHOTS=1 if this is intended\n{}",
+        schemas
+    );
+}
@@ -0,0 +1,84 @@
+/// rate limiting tests
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, Response, StatusCode},
+    Router,
+};
+use tower::ServiceExt;
+
+use post_lib::ApiError;
+use post_server::{
+    app_with_config,
+    attachments::BlobStore,
+    config::{Config, Limit, RouteLimits},
+    health::Health,
+    shutdown::Shutdown,
+};
+
+fn limited_app() -> Router {
+    let mut config = Config::default();
+    config.rate_limit.routes.insert(
+        "/addPost".to_string(),
+        RouteLimits {
+            read: None,
+            write: Some(Limit {
+                per_minute: 1,
+                burst: 2,
+            }),
+        },
+    );
+    app_with_config(
+        config,
+        Arc::new(Mutex::new(Default::default())),
+        Arc::new(Mutex::new(Default::default())),
+        Shutdown::new(),
+        Arc::new(Health::new()),
+        Arc::new(BlobStore::new("attachments")),
+    )
+}
+
+async fn add_post(app: &Router, api_key: &str) -> Response<axum::body::BoxBody> {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/addPost")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::AUTHORIZATION, format!("Bearer {}", api_key))
+                .body(Body::from(r#"{"content":"spam"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn writes_over_the_limit_get_429() {
+    let app = limited_app();
+
+    let first = add_post(&app, "bot").await;
+    assert_eq!(StatusCode::OK, first.status());
+    assert_eq!("2", first.headers()["ratelimit-limit"]);
+    assert_eq!("1", first.headers()["ratelimit-remaining"]);
+    assert_eq!(StatusCode::OK, add_post(&app, "bot").await.status());
+
+    let limited = add_post(&app, "bot").await;
+    assert_eq!(StatusCode::TOO_MANY_REQUESTS, limited.status());
+    assert_eq!("60", limited.headers()[header::RETRY_AFTER]);
+    assert_eq!("0", limited.headers()["ratelimit-remaining"]);
+    assert_eq!("120", limited.headers()["ratelimit-reset"]);
+    let body = hyper::body::to_bytes(limited.into_body()).await.unwrap();
+    let error: ApiError = serde_json::from_slice(&body).unwrap();
+    assert_eq!("rate_limited", error.error);
+    assert!(error.request_id.is_some());
+
+    // other keys, and reads, are unaffected
+    assert_eq!(StatusCode::OK, add_post(&app, "person").await.status());
+    let read = app
+        .oneshot(Request::get("/posts").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, read.status());
+}
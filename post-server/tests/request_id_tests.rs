@@ -0,0 +1,49 @@
+/// request id tests
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Router,
+};
+use tower::ServiceExt;
+
+use post_lib::ApiError;
+use post_server::{app, request_id::REQUEST_ID_HEADER, webhooks::WebhookRegistry};
+
+fn router() -> Router {
+    app(
+        Arc::new(Mutex::new(Default::default())),
+        Arc::new(Mutex::new(WebhookRegistry::default())),
+    )
+}
+
+#[tokio::test]
+async fn error_bodies_echo_the_request_id() {
+    let response = router()
+        .oneshot(
+            Request::get("/post/7")
+                .header(REQUEST_ID_HEADER, "trace-me")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::EXPECTATION_FAILED, response.status());
+    assert_eq!("trace-me", response.headers()[REQUEST_ID_HEADER]);
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let error: ApiError = serde_json::from_slice(&body).unwrap();
+    assert_eq!(Some("trace-me".to_string()), error.request_id);
+    assert_eq!("not_found", error.error);
+}
+
+#[tokio::test]
+async fn every_response_gets_a_request_id() {
+    let response = router()
+        .oneshot(Request::get("/posts").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    assert!(!response.headers()[REQUEST_ID_HEADER].is_empty());
+}
@@ -0,0 +1,130 @@
+/// graceful shutdown tests
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    routing::get,
+    Router,
+};
+use hyper::{body::HttpBody, server::conn::AddrIncoming, Client};
+use tower::ServiceExt;
+
+use post_server::{
+    app_with_config,
+    attachments::BlobStore,
+    config::Config,
+    health::Health,
+    shutdown::{self, Drain, Shutdown},
+};
+
+fn slow_router(delay: Duration) -> Router {
+    Router::new().route(
+        "/slow",
+        get(move || async move {
+            tokio::time::sleep(delay).await;
+            "done"
+        }),
+    )
+}
+
+fn bind() -> (AddrIncoming, SocketAddr) {
+    let incoming = AddrIncoming::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let addr = incoming.local_addr();
+    (incoming, addr)
+}
+
+#[tokio::test]
+async fn in_flight_requests_finish() {
+    let (incoming, addr) = bind();
+    let shutdown = Shutdown::new();
+    let server = tokio::spawn(shutdown::serve(
+        incoming,
+        slow_router(Duration::from_millis(300)),
+        shutdown.clone(),
+        Duration::from_secs(5),
+    ));
+
+    let request = tokio::spawn(Client::new().get(format!("http://{}/slow", addr).parse().unwrap()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    shutdown.trigger();
+
+    let response = request.await.unwrap().unwrap();
+    assert_eq!(StatusCode::OK, response.status());
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert_eq!(&body[..], b"done");
+
+    assert_eq!(Drain::Complete, server.await.unwrap().unwrap());
+    // no longer accepting connections
+    assert!(Client::new()
+        .get(format!("http://{}/slow", addr).parse().unwrap())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn drain_gives_up_after_timeout() {
+    let (incoming, addr) = bind();
+    let shutdown = Shutdown::new();
+    let server = tokio::spawn(shutdown::serve(
+        incoming,
+        slow_router(Duration::from_secs(30)),
+        shutdown.clone(),
+        Duration::from_millis(100),
+    ));
+
+    tokio::spawn(Client::new().get(format!("http://{}/slow", addr).parse().unwrap()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    shutdown.trigger();
+
+    let drain = tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .expect("serve should return once the drain timeout runs out");
+    assert_eq!(Drain::TimedOut, drain.unwrap().unwrap());
+}
+
+#[tokio::test]
+async fn event_streams_close_with_reason() {
+    let shutdown = Shutdown::new();
+    let app = app_with_config(
+        Config::default(),
+        Arc::new(Mutex::new(Default::default())),
+        Arc::new(Mutex::new(Default::default())),
+        shutdown.clone(),
+        Arc::new(Health::new()),
+        Arc::new(BlobStore::new("attachments")),
+    );
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/events")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let mut body = response.into_body();
+
+    shutdown.trigger();
+
+    let mut received = String::new();
+    loop {
+        let chunk = tokio::time::timeout(Duration::from_secs(5), body.data())
+            .await
+            .expect("the stream should end after shutdown");
+        match chunk {
+            Some(chunk) => received.push_str(std::str::from_utf8(&chunk.unwrap()).unwrap()),
+            None => break,
+        }
+    }
+    assert!(
+        received.contains("event:shutdown\ndata:server shutting down\n"),
+        "{}",
+        received
+    );
+}
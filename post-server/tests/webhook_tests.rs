@@ -0,0 +1,298 @@
+/// Webhook integration tests, delivering to a local axum receiver
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::Extension,
+    http::{self, HeaderMap, Request, StatusCode},
+    routing::{get, post},
+    AddExtensionLayer, Router,
+};
+
+use serde_json::{json, Value};
+
+use tokio::sync::mpsc;
+use tower::ServiceExt;
+
+use post_server::{
+    auth::Auth,
+    config::AuthConfig,
+    list_webhooks_handler, new_webhook_handler, webhook_dead_letters_handler,
+    webhook_deliveries_handler,
+    webhooks::{sign_payload, spawn_dispatcher, RetryPolicy, WebhookRegistry, SIGNATURE_HEADER},
+    PostDb,
+};
+
+fn create_post_db() -> Arc<Mutex<PostDb>> {
+    Arc::new(Mutex::new(Default::default()))
+}
+
+fn create_webhook_registry() -> Arc<Mutex<WebhookRegistry>> {
+    Arc::new(Mutex::new(WebhookRegistry::new(RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(40),
+        request_timeout: Duration::from_secs(2),
+    })))
+}
+
+fn app(webhooks: Arc<Mutex<WebhookRegistry>>, db: Arc<Mutex<PostDb>>) -> Router {
+    Router::new()
+        .route(
+            "/admin/webhooks",
+            get(list_webhooks_handler).post(new_webhook_handler),
+        )
+        .route("/admin/webhookDeliveries", get(webhook_deliveries_handler))
+        .route(
+            "/admin/webhookDeadLetters",
+            get(webhook_dead_letters_handler),
+        )
+        .layer(AddExtensionLayer::new(webhooks))
+        .layer(AddExtensionLayer::new(db))
+        .layer(AddExtensionLayer::new(Arc::new(Auth::new(&AuthConfig {
+            api_keys: [(MODERATOR_KEY, "mod"), (USER_KEY, "ann")]
+                .into_iter()
+                .map(|(key, user)| (key.to_string(), user.to_string()))
+                .collect(),
+            moderators: vec!["mod".to_string()],
+        }))))
+}
+
+const MODERATOR_KEY: &str = "s3cret-mod";
+const USER_KEY: &str = "s3cret-ann";
+
+type ReceivedSender = mpsc::UnboundedSender<(HeaderMap, Bytes)>;
+
+/// forward whatever was received and answer with the configured status
+async fn hook_handler(
+    headers: HeaderMap,
+    body: Bytes,
+    Extension((sender, status)): Extension<(ReceivedSender, StatusCode)>,
+) -> StatusCode {
+    sender.send((headers, body)).unwrap();
+    status
+}
+
+/// a stand-in webhook endpoint that answers with `status` and
+/// forwards every request it receives
+fn spawn_receiver(status: StatusCode) -> (SocketAddr, mpsc::UnboundedReceiver<(HeaderMap, Bytes)>) {
+    let listener = TcpListener::bind("127.0.0.1:0".parse::<SocketAddr>().unwrap()).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, receiver) = mpsc::unbounded_channel();
+
+    let receiver_app = Router::new()
+        .route("/hook", post(hook_handler))
+        .layer(AddExtensionLayer::new((sender, status)));
+
+    tokio::spawn(async move {
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(receiver_app.into_make_service())
+            .await
+            .unwrap()
+    });
+
+    (addr, receiver)
+}
+
+async fn send(app: Router, request: Request<Body>) -> (StatusCode, Value) {
+    let response = app.oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+fn webhook_request(key: Option<&str>, url: &str, events: Value) -> Request<Body> {
+    let mut request = Request::builder()
+        .method(http::Method::POST)
+        .uri("/admin/webhooks")
+        .header(http::header::CONTENT_TYPE, "application/json");
+    if let Some(key) = key {
+        request = request.header(http::header::AUTHORIZATION, format!("Bearer {}", key));
+    }
+    request
+        .body(Body::from(
+            json!({ "url": url, "secret": "s3cret", "events": events }).to_string(),
+        ))
+        .unwrap()
+}
+
+async fn register_webhook(app: Router, url: String, events: Value) -> Value {
+    let (status, body) = send(app, webhook_request(Some(MODERATOR_KEY), &url, events)).await;
+    assert_eq!(status, StatusCode::OK);
+    body
+}
+
+async fn get_json(app: Router, uri: &str) -> Value {
+    let (status, body) = send(
+        app,
+        Request::builder()
+            .method(http::Method::GET)
+            .uri(uri)
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", MODERATOR_KEY),
+            )
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    body
+}
+
+/// poll the admin api until `check` passes
+async fn wait_for(app: Router, uri: &str, check: impl Fn(&Value) -> bool) -> Value {
+    for _ in 0..100 {
+        let body = get_json(app.clone(), uri).await;
+        if check(&body) {
+            return body;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("timed out waiting on {}", uri);
+}
+
+#[tokio::test]
+async fn delivers_signed_events() {
+    let db = create_post_db();
+    let webhooks = create_webhook_registry();
+    spawn_dispatcher(db.clone(), webhooks.clone());
+    let app = app(webhooks, db.clone());
+
+    let (addr, mut received) = spawn_receiver(StatusCode::OK);
+    let webhook_id = register_webhook(
+        app.clone(),
+        format!("http://{}/hook", addr),
+        json!(["created"]),
+    )
+    .await;
+    assert_eq!(webhook_id, json!(1));
+
+    let listed = get_json(app.clone(), "/admin/webhooks").await;
+    assert_eq!(listed[0]["events"], json!(["created"]));
+    assert!(listed[0].get("secret").is_none());
+
+    db.lock()
+        .unwrap()
+        .create_post("this is some content".to_string());
+    // not subscribed to deletes
+    db.lock().unwrap().delete_post(1);
+
+    let (headers, body) = tokio::time::timeout(Duration::from_secs(5), received.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(headers["x-post-event"], "created");
+    assert_eq!(headers[SIGNATURE_HEADER], sign_payload("s3cret", &body));
+
+    let event: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(event["kind"], "created");
+    assert_eq!(event["post"]["content"], "this is some content");
+
+    let deliveries = wait_for(app, "/admin/webhookDeliveries", |body| {
+        body.as_array().unwrap().len() == 1
+    })
+    .await;
+    assert_eq!(deliveries[0]["status"], "delivered");
+    assert_eq!(deliveries[0]["response_status"], 200);
+    assert!(received.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn failed_deliveries_retry_then_dead_letter() {
+    let db = create_post_db();
+    let webhooks = create_webhook_registry();
+    spawn_dispatcher(db.clone(), webhooks.clone());
+    let app = app(webhooks, db.clone());
+
+    let (addr, mut received) = spawn_receiver(StatusCode::INTERNAL_SERVER_ERROR);
+    register_webhook(app.clone(), format!("http://{}/hook", addr), json!([])).await;
+
+    db.lock()
+        .unwrap()
+        .create_post("this is some content".to_string());
+
+    let dead_letters = wait_for(app.clone(), "/admin/webhookDeadLetters", |body| {
+        !body.as_array().unwrap().is_empty()
+    })
+    .await;
+    assert_eq!(dead_letters[0]["attempts"], 3);
+    assert_eq!(dead_letters[0]["event"]["kind"], "created");
+
+    let deliveries = get_json(app, "/admin/webhookDeliveries?webhook_id=1").await;
+    let attempts: Vec<&Value> = deliveries.as_array().unwrap().iter().collect();
+    assert_eq!(attempts.len(), 3);
+    assert!(attempts.iter().all(|attempt| attempt["status"] == "failed"));
+    assert!(attempts
+        .iter()
+        .all(|attempt| attempt["response_status"] == 500));
+
+    // every retry carries the same delivery id
+    for _ in 0..3 {
+        let (headers, _) = received.recv().await.unwrap();
+        assert_eq!(headers["x-post-delivery"], "1");
+    }
+}
+
+#[tokio::test]
+async fn deleted_webhooks_are_not_retried() {
+    let db = create_post_db();
+    let webhooks = Arc::new(Mutex::new(WebhookRegistry::new(RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(200),
+        max_backoff: Duration::from_millis(200),
+        request_timeout: Duration::from_secs(2),
+    })));
+    spawn_dispatcher(db.clone(), webhooks.clone());
+    let app = app(webhooks.clone(), db.clone());
+
+    let (addr, mut received) = spawn_receiver(StatusCode::INTERNAL_SERVER_ERROR);
+    register_webhook(app.clone(), format!("http://{}/hook", addr), json!([])).await;
+
+    db.lock()
+        .unwrap()
+        .create_post("this is some content".to_string());
+    received.recv().await.unwrap();
+    webhooks.lock().unwrap().remove_webhook(1);
+
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(received.try_recv().is_err());
+    let deliveries = get_json(app.clone(), "/admin/webhookDeliveries").await;
+    assert_eq!(1, deliveries.as_array().unwrap().len());
+    // dropped, not dead-lettered
+    let dead_letters = get_json(app, "/admin/webhookDeadLetters").await;
+    assert!(dead_letters.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn only_moderators_manage_webhooks() {
+    let app = app(create_webhook_registry(), create_post_db());
+    let url = "http://127.0.0.1:9/hook";
+
+    let (status, body) = send(app.clone(), webhook_request(None, url, json!([]))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"], "unauthorized");
+
+    let (status, body) = send(app.clone(), webhook_request(Some(USER_KEY), url, json!([]))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "forbidden");
+
+    for url in [
+        "ftp://127.0.0.1/hook",
+        "/hook",
+        "http://user:pw@127.0.0.1/hook",
+    ] {
+        let (status, _) = send(
+            app.clone(),
+            webhook_request(Some(MODERATOR_KEY), url, json!([])),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY, "{}", url);
+    }
+    assert_eq!(get_json(app, "/admin/webhooks").await, json!([]));
+}